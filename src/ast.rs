@@ -1,43 +1,231 @@
 #![allow(dead_code)]
 
-use crate::ast_common::{BinaryOperator, UnaryOperator};
+use crate::ast_common::{BinaryOperator, Float, UnaryOperator};
+use crate::span::Span;
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Expression {
-  IntegerConstant(i128),
+  // The value and the name of the type it's suffixed with.
+  IntegerConstant(i128, Option<String>),
+  FloatConstant(Float),
+  BooleanConstant(bool),
+  StringConstant(String),
   Local(String),
   BinaryOp(BinaryOperator, Box<(ExpressionCtx, ExpressionCtx)>),
   UnaryOp(UnaryOperator, Box<ExpressionCtx>),
+  Block(Block),
+  // Both branches are always either blocks or, in the case of `else if`,
+  // further if expressions.
+  If {
+    condition: Box<ExpressionCtx>,
+    then_branch: Box<ExpressionCtx>,
+    else_branch: Option<Box<ExpressionCtx>>,
+  },
+  // Repeats until a break. The value of the loop is the value of the break.
+  Loop {
+    body: Block,
+  },
+  Call {
+    callee: Box<ExpressionCtx>,
+    arguments: Vec<ExpressionCtx>,
+  },
+  // The return type is inferred from the body unless it's given.
+  Closure {
+    parameters: Vec<Parameter>,
+    return_type: Option<TypeCtx>,
+    body: Box<ExpressionCtx>,
+  },
+  Array(Vec<ExpressionCtx>),
+  Index {
+    array: Box<ExpressionCtx>,
+    index: Box<ExpressionCtx>,
+  },
+  // `Point { x: 1, y: 2 }`. Every field must be given, in any order.
+  StructLiteral {
+    name: IdentifierCtx,
+    fields: Vec<(IdentifierCtx, ExpressionCtx)>,
+  },
+  Field {
+    value: Box<ExpressionCtx>,
+    field: IdentifierCtx,
+  },
+  // `Shape::Circle`, a variant of an enum. Variants with fields are called
+  // like functions.
+  Path {
+    type_name: IdentifierCtx,
+    variant: IdentifierCtx,
+  },
+  Match {
+    scrutinee: Box<ExpressionCtx>,
+    arms: Vec<MatchArm>,
+  },
+  // `value as type_`, a conversion between numeric types.
+  Cast {
+    value: Box<ExpressionCtx>,
+    type_: TypeCtx,
+  },
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ExpressionCtx(pub Span, pub Expression);
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IdentifierCtx(pub Span, pub String);
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Type {
+  Named(String),
+  // `[T]`, an array of any length.
+  Array(Box<TypeCtx>),
+  // `Pair<i32, bool>`, a generic struct or enum with its type arguments.
+  Applied(String, Vec<TypeCtx>),
+  // `fn(i32, bool) -> i32`, the type of functions and closures. Without a
+  // return type, they return ().
+  Function {
+    parameters: Vec<TypeCtx>,
+    return_type: Option<Box<TypeCtx>>,
+  },
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
-pub struct ExpressionCtx(pub usize, pub Expression);
+pub struct TypeCtx(pub Span, pub Type);
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Pattern {
+  // `_`
+  Wildcard,
+  Binding(String),
+  Integer(i128, Option<String>),
+  Boolean(bool),
+  // `Shape::Rect(w, _)`, or `Shape::Empty` for a variant without fields.
+  Variant {
+    type_name: IdentifierCtx,
+    variant: IdentifierCtx,
+    fields: Vec<PatternCtx>,
+  },
+}
 
 #[derive(Clone, PartialEq, Eq, Debug)]
-pub struct IdentifierCtx(pub usize, pub String);
+pub struct PatternCtx(pub Span, pub Pattern);
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MatchArm {
+  pub pattern: PatternCtx,
+  pub body: ExpressionCtx,
+}
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Statement {
   DeclareVariable {
     name: IdentifierCtx,
     is_mutable: bool,
-    initial_type: Option<IdentifierCtx>,
+    initial_type: Option<TypeCtx>,
     initial_value: ExpressionCtx,
   },
   AssignLocal {
     local: IdentifierCtx,
     value: ExpressionCtx,
   },
-  Block {
-    inner: Vec<StatementCtx>,
+  AssignIndex {
+    array: ExpressionCtx,
+    index: ExpressionCtx,
+    value: ExpressionCtx,
+  },
+  AssignField {
+    target: ExpressionCtx,
+    field: IdentifierCtx,
+    value: ExpressionCtx,
+  },
+  // An expression evaluated only for its side effects.
+  Expression {
+    value: ExpressionCtx,
+  },
+  While {
+    condition: ExpressionCtx,
+    body: Block,
+  },
+  Break {
+    value: Option<ExpressionCtx>,
+  },
+  Continue,
+  Return {
+    value: Option<ExpressionCtx>,
   },
+  // A statement that failed to parse. The error itself is reported by the parser.
+  Error,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StatementCtx(pub Span, pub Statement);
+
+// The trailing expression of a block has no semicolon after it, and it is the
+// value of the whole block.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Block {
+  pub statements: Vec<StatementCtx>,
+  pub result: Option<Box<ExpressionCtx>>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Parameter {
+  pub name: IdentifierCtx,
+  // Inferred from the uses of the parameter if not given.
+  pub type_: Option<TypeCtx>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FunctionDeclaration {
+  pub name: IdentifierCtx,
+  // The names in `<T, U>` after the name of a generic function.
+  pub type_parameters: Vec<IdentifierCtx>,
+  pub parameters: Vec<Parameter>,
+  // None if the function returns ().
+  pub return_type: Option<TypeCtx>,
+  pub body: Block,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
-pub struct StatementCtx(pub usize, pub Statement);
+pub struct FunctionDeclarationCtx(pub Span, pub FunctionDeclaration);
 
 #[derive(Clone, PartialEq, Eq, Debug)]
-pub struct Block(pub Vec<StatementCtx>);
+pub struct FieldDeclaration {
+  pub name: IdentifierCtx,
+  pub type_: TypeCtx,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StructDeclaration {
+  pub name: IdentifierCtx,
+  pub type_parameters: Vec<IdentifierCtx>,
+  pub fields: Vec<FieldDeclaration>,
+}
 
 #[derive(Clone, PartialEq, Eq, Debug)]
-pub struct Program(pub Vec<StatementCtx>);
+pub struct StructDeclarationCtx(pub Span, pub StructDeclaration);
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct VariantDeclaration {
+  pub name: IdentifierCtx,
+  pub fields: Vec<TypeCtx>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EnumDeclaration {
+  pub name: IdentifierCtx,
+  pub type_parameters: Vec<IdentifierCtx>,
+  pub variants: Vec<VariantDeclaration>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EnumDeclarationCtx(pub Span, pub EnumDeclaration);
+
+// Functions, structs and enums are declared at the top level of a program, in
+// between its statements. The statements form an implicit block, whose value
+// is the value of the program.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Program {
+  pub structs: Vec<StructDeclarationCtx>,
+  pub enums: Vec<EnumDeclarationCtx>,
+  pub functions: Vec<FunctionDeclarationCtx>,
+  pub body: Block,
+}
@@ -1,39 +1,121 @@
+use std::fmt;
+
+// A floating point literal. Literals are equal if their bits are, so that the
+// syntax trees containing them can be compared.
+#[derive(Copy, Clone, Debug)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+  fn eq(&self, other: &Float) -> bool {
+    self.0.to_bits() == other.0.to_bits()
+  }
+}
+
+impl Eq for Float {}
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum BinaryOperator {
   Add,
   Mul,
   Sub,
+  Div,
+  Rem,
+  BitAnd,
+  BitOr,
+  BitXor,
+  Shl,
+  Shr,
   Equals,
+  NotEquals,
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+  And,
+  Or,
 }
 
 impl BinaryOperator {
   pub fn get_precedence(&self) -> i32 {
     match self {
-      BinaryOperator::Mul => 3,
-      BinaryOperator::Add | BinaryOperator::Sub => 2,
-      BinaryOperator::Equals => 0,
+      BinaryOperator::Mul | BinaryOperator::Div | BinaryOperator::Rem => 9,
+      BinaryOperator::Add | BinaryOperator::Sub => 8,
+      BinaryOperator::Shl | BinaryOperator::Shr => 7,
+      BinaryOperator::BitAnd => 6,
+      BinaryOperator::BitXor => 5,
+      BinaryOperator::BitOr => 4,
+      BinaryOperator::Equals
+      | BinaryOperator::NotEquals
+      | BinaryOperator::Less
+      | BinaryOperator::LessOrEqual
+      | BinaryOperator::Greater
+      | BinaryOperator::GreaterOrEqual => 3,
+      BinaryOperator::And => 2,
+      BinaryOperator::Or => 1,
     }
   }
 }
 
+impl fmt::Display for BinaryOperator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let symbol = match self {
+      BinaryOperator::Add => "+",
+      BinaryOperator::Mul => "*",
+      BinaryOperator::Sub => "-",
+      BinaryOperator::Div => "/",
+      BinaryOperator::Rem => "%",
+      BinaryOperator::BitAnd => "&",
+      BinaryOperator::BitOr => "|",
+      BinaryOperator::BitXor => "^",
+      BinaryOperator::Shl => "<<",
+      BinaryOperator::Shr => ">>",
+      BinaryOperator::Equals => "==",
+      BinaryOperator::NotEquals => "!=",
+      BinaryOperator::Less => "<",
+      BinaryOperator::LessOrEqual => "<=",
+      BinaryOperator::Greater => ">",
+      BinaryOperator::GreaterOrEqual => ">=",
+      BinaryOperator::And => "&&",
+      BinaryOperator::Or => "||",
+    };
+    write!(f, "{}", symbol)
+  }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum UnaryOperator {
   Negate,
+  // Logical negation of a bool, or the bitwise complement of an integer.
+  Not,
+  BitNot,
+}
+
+impl fmt::Display for UnaryOperator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      UnaryOperator::Negate => write!(f, "-"),
+      UnaryOperator::Not => write!(f, "!"),
+      UnaryOperator::BitNot => write!(f, "~"),
+    }
+  }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Operator {
   Binary(BinaryOperator),
   Unary(UnaryOperator),
+  // `as`, whose right hand side is a type.
+  Cast,
 }
 
 impl Operator {
+  // Unary operators bind tighter than any binary operator, and `as` is in
+  // between, so that `-1 as u8` is `(-1) as u8`.
   pub fn get_precedence(&self) -> i32 {
     match self {
-      Operator::Binary(BinaryOperator::Mul) => 3,
-      Operator::Binary(BinaryOperator::Add) | Operator::Binary(BinaryOperator::Sub) => 2,
-      Operator::Unary(UnaryOperator::Negate) => 1,
-      Operator::Binary(BinaryOperator::Equals) => 0,
+      Operator::Binary(op) => op.get_precedence(),
+      Operator::Cast => 10,
+      Operator::Unary(_) => 11,
     }
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,1467 @@
+// Compiles a type checked program into instructions for a register machine.
+// Each function is a flat list of instructions with jumps for control flow.
+// Its locals and the intermediate values of its expressions are kept in
+// numbered registers, instead of being looked up by id.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast_common::{BinaryOperator, UnaryOperator};
+use crate::interpreter::Value;
+use crate::rast::{
+  CaptureMode, FunctionId, LocalId, LoopId, PrimitiveType, RastBlock, RastExpression,
+  RastExpressionCtx, RastPattern, RastPatternCtx, RastProgram, RastStatement, RastStatementCtx,
+  TypeRef, UserTypeDef, UserTypeId,
+};
+use crate::semantic::SemanticContext;
+use crate::source_map::SourceMap;
+use crate::span::Span;
+use crate::utils::{with_stack, PASS_STACK_SIZE};
+
+pub type Register = u32;
+
+// Other operands are indices into the tables of the program, or jump targets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+  LoadConstant {
+    dst: Register,
+    constant: u32,
+  },
+  LoadUnit {
+    dst: Register,
+  },
+  Move {
+    dst: Register,
+    src: Register,
+  },
+  // Moves a value that is bound to a local, which must not share its arrays
+  // or structs with any other value.
+  Copy {
+    dst: Register,
+    src: Register,
+  },
+  // Locals captured by reference are kept in cells that the closures share.
+  // Every execution of a declaration creates a new cell.
+  LoadCell {
+    dst: Register,
+    cell: Register,
+  },
+  StoreCell {
+    cell: Register,
+    src: Register,
+  },
+  DeclareCell {
+    cell: Register,
+    src: Register,
+  },
+  Jump {
+    target: u32,
+  },
+  JumpIfFalse {
+    condition: Register,
+    target: u32,
+  },
+  Unary {
+    op: UnaryOperator,
+    dst: Register,
+    x: Register,
+  },
+  Binary {
+    op: BinaryOperator,
+    dst: Register,
+    lhs: Register,
+    rhs: Register,
+  },
+  Cast {
+    type_: PrimitiveType,
+    dst: Register,
+    x: Register,
+  },
+  // A function of the program used as a value.
+  Function {
+    dst: Register,
+    function: u32,
+  },
+  // Creates a closure with the captures of the function from the current
+  // frame.
+  Closure {
+    dst: Register,
+    function: u32,
+  },
+  // The arguments of calls, and the values of arrays, structs and variants,
+  // are in consecutive registers starting with the given one.
+  Call {
+    dst: Register,
+    function: u32,
+    arguments: Register,
+  },
+  CallIndirect {
+    dst: Register,
+    callee: Register,
+    arguments: Register,
+    count: u32,
+  },
+  Return {
+    src: Register,
+  },
+  Len {
+    dst: Register,
+    x: Register,
+  },
+  Array {
+    dst: Register,
+    elements: Register,
+    count: u32,
+  },
+  Index {
+    dst: Register,
+    array: Register,
+    index: Register,
+  },
+  // Checks that an index is within the bounds of an array, so that an
+  // assignment fails before its value is evaluated.
+  CheckIndex {
+    array: Register,
+    index: Register,
+  },
+  SetIndex {
+    array: Register,
+    index: Register,
+    src: Register,
+  },
+  Struct {
+    dst: Register,
+    layout: u32,
+    fields: Register,
+  },
+  Field {
+    dst: Register,
+    x: Register,
+    field: u32,
+  },
+  SetField {
+    target: Register,
+    field: u32,
+    src: Register,
+  },
+  Variant {
+    dst: Register,
+    variant: u32,
+    fields: Register,
+  },
+  // Whether a value is the given integer, or variant of an enum.
+  IsInteger {
+    dst: Register,
+    x: Register,
+    integer: u32,
+  },
+  IsVariant {
+    dst: Register,
+    x: Register,
+    variant: u32,
+  },
+  VariantField {
+    dst: Register,
+    x: Register,
+    index: u32,
+  },
+}
+
+// Where a closure finds a capture in the function that creates it. In the
+// closure itself, the captures are the first registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptureSource {
+  pub local_id: LocalId,
+  pub register: Register,
+  pub is_cell: bool,
+}
+
+#[derive(Debug)]
+pub struct BytecodeFunction {
+  // None for the body of the program.
+  pub id: Option<FunctionId>,
+  pub name: String,
+  pub parameter_count: usize,
+  // The registers hold the captures first, then the parameters, then the
+  // other locals and the intermediate values of the function.
+  pub register_count: usize,
+  pub captures: Vec<CaptureSource>,
+  pub code: Vec<Instruction>,
+  // The span of the expression or statement that each instruction is for, to
+  // report runtime errors.
+  pub spans: Vec<Span>,
+}
+
+#[derive(Debug)]
+pub struct BytecodeProgram {
+  // The functions of the program, then the closures, and the body of the
+  // program last.
+  pub functions: Vec<BytecodeFunction>,
+  pub function_indices: HashMap<FunctionId, u32>,
+  pub constants: Vec<Value>,
+  // The values of integer patterns, which have no type of their own.
+  pub integers: Vec<i128>,
+  // The type of each struct literal, and the field that each of its values,
+  // in the order they're written in, is for.
+  pub structs: Vec<(UserTypeId, Vec<usize>)>,
+  // Each field name, and its index in each struct that has it. Fields are
+  // looked up by name, since the type of a value is erased by then.
+  pub fields: Vec<(String, HashMap<UserTypeId, usize>)>,
+  // The type, the index and the number of fields of each variant.
+  pub variants: Vec<(UserTypeId, usize, usize)>,
+}
+
+impl BytecodeProgram {
+  pub fn main(&self) -> usize {
+    self.functions.len() - 1
+  }
+}
+
+struct LoopTarget {
+  id: LoopId,
+  start: usize,
+  // Where the value of a loop expression goes. A while loop has none.
+  dst: Option<Register>,
+  breaks: Vec<usize>,
+}
+
+#[derive(Default)]
+struct FunctionState {
+  registers: HashMap<LocalId, Register>,
+  // Registers from this one on are free. They are allocated like a stack, and
+  // an expression frees the ones it used once it's compiled.
+  next_register: Register,
+  register_count: Register,
+  code: Vec<Instruction>,
+  spans: Vec<Span>,
+  loops: Vec<LoopTarget>,
+}
+
+struct Compiler<'a> {
+  ctx: &'a SemanticContext,
+  functions: Vec<Option<BytecodeFunction>>,
+  function_indices: HashMap<FunctionId, u32>,
+  constants: Vec<Value>,
+  integers: Vec<i128>,
+  structs: Vec<(UserTypeId, Vec<usize>)>,
+  field_names: Vec<String>,
+  variants: Vec<(UserTypeId, usize, usize)>,
+  // Locals captured by reference by some closure.
+  cells: HashSet<LocalId>,
+  state: FunctionState,
+}
+
+impl<'a> Compiler<'a> {
+  fn emit(&mut self, instruction: Instruction, pos: Span) -> usize {
+    self.state.code.push(instruction);
+    self.state.spans.push(pos);
+    self.state.code.len() - 1
+  }
+
+  fn here(&self) -> usize {
+    self.state.code.len()
+  }
+
+  fn patch(&mut self, jump: usize, to: usize) {
+    match &mut self.state.code[jump] {
+      Instruction::Jump { target } | Instruction::JumpIfFalse { target, .. } => *target = to as u32,
+      _ => unreachable!(),
+    }
+  }
+
+  fn allocate(&mut self, count: usize) -> Register {
+    let state = &mut self.state;
+    let first = state.next_register;
+    state.next_register += count as Register;
+    state.register_count = state.register_count.max(state.next_register);
+    first
+  }
+
+  fn register(&mut self, local_id: LocalId) -> Register {
+    match self.state.registers.get(&local_id) {
+      Some(&register) => register,
+      None => {
+        let register = self.allocate(1);
+        self.state.registers.insert(local_id, register);
+        register
+      }
+    }
+  }
+
+  // Whether the values of a local have to be copied when it's bound, which is
+  // when they can hold arrays or structs, or might in a generic function.
+  fn is_copied(&self, local_id: LocalId) -> bool {
+    match self.ctx.locals[&local_id].type_ {
+      TypeRef::Primitive(_) | TypeRef::Never => false,
+      TypeRef::UserType(type_id) => !matches!(
+        self.ctx.resolve_type(type_id).type_def,
+        UserTypeDef::Function { .. }
+      ),
+      TypeRef::Variable(_) | TypeRef::Error => true,
+    }
+  }
+
+  // Makes room for a function, which is compiled later.
+  fn reserve(&mut self, function_id: FunctionId) -> u32 {
+    let index = self.functions.len() as u32;
+    self.functions.push(None);
+    self.function_indices.insert(function_id, index);
+    index
+  }
+
+  // Loads a constant, which is added to the program the first time it's used.
+  // Floats are compared by their bits, so that 0.0 and -0.0 stay apart and
+  // NaN is found again.
+  fn constant(&mut self, dst: Register, value: Value, pos: Span) {
+    let same = |x: &Value| match (x, &value) {
+      (Value::F64(x), Value::F64(y)) => x.to_bits() == y.to_bits(),
+      (x, y) => x == y,
+    };
+    let constant = match self.constants.iter().position(same) {
+      Some(index) => index as u32,
+      None => {
+        self.constants.push(value);
+        self.constants.len() as u32 - 1
+      }
+    };
+    self.emit(Instruction::LoadConstant { dst, constant }, pos);
+  }
+
+  fn field(&mut self, name: &str) -> u32 {
+    match self.field_names.iter().position(|x| x == name) {
+      Some(index) => index as u32,
+      None => {
+        self.field_names.push(name.to_string());
+        self.field_names.len() as u32 - 1
+      }
+    }
+  }
+
+  fn function(&mut self, function_id: FunctionId, body: &RastBlock) -> BytecodeFunction {
+    let function = &self.ctx.functions[&function_id];
+    let outer = std::mem::take(&mut self.state);
+
+    // The captures and the parameters come first, in order. Parameters are
+    // immutable, so they are never captured by reference.
+    let captures = function
+      .captures
+      .iter()
+      .map(|capture| CaptureSource {
+        local_id: capture.local_id,
+        register: self.register(capture.local_id),
+        is_cell: capture.mode == CaptureMode::ByReference,
+      })
+      .collect();
+    for &parameter in &function.parameters {
+      self.register(parameter);
+    }
+
+    let result = self.allocate(1);
+    self.block(body, result, function.span);
+    self.emit(Instruction::Return { src: result }, function.span);
+
+    let state = std::mem::replace(&mut self.state, outer);
+    BytecodeFunction {
+      id: Some(function_id),
+      name: function.name.clone(),
+      parameter_count: function.parameters.len(),
+      register_count: state.register_count as usize,
+      captures,
+      code: state.code,
+      spans: state.spans,
+    }
+  }
+
+  // Compiles a block, whose value goes to `dst`. The locals it declares are
+  // freed at its end.
+  fn block(&mut self, block: &RastBlock, dst: Register, pos: Span) {
+    let mark = self.state.next_register;
+    for statement in &block.statements {
+      self.statement(statement);
+    }
+
+    match &block.result {
+      Some(result) => self.expression(result, dst),
+      None => {
+        self.emit(Instruction::LoadUnit { dst }, pos);
+      }
+    }
+    self.state.next_register = mark;
+  }
+
+  // Compiles expressions into consecutive registers.
+  fn expressions(&mut self, expressions: &[RastExpressionCtx]) -> Register {
+    let first = self.allocate(expressions.len());
+    for (register, expression) in (first..).zip(expressions) {
+      self.expression(expression, register);
+    }
+    first
+  }
+
+  // The register that holds the value of an expression, which is used right
+  // away. A local is used where it is, unless it's kept in a cell.
+  fn operand(&mut self, expression: &RastExpressionCtx) -> Register {
+    self.operand_before(expression, &[])
+  }
+
+  // Like operand, for a value that is used only after the `later` expressions
+  // are evaluated. A local that they can assign to is copied first.
+  fn operand_before(
+    &mut self,
+    expression: &RastExpressionCtx,
+    later: &[&RastExpressionCtx],
+  ) -> Register {
+    if let RastExpressionCtx(_, RastExpression::Local(local_id)) = expression {
+      let local_id = *local_id;
+      let is_assigned =
+        self.ctx.locals[&local_id].is_mutable && later.iter().any(|x| assigns(x, local_id));
+      if !self.cells.contains(&local_id) && !is_assigned {
+        return self.state.registers[&local_id];
+      }
+    }
+
+    let register = self.allocate(1);
+    self.expression(expression, register);
+    register
+  }
+
+  fn expression(&mut self, RastExpressionCtx(pos, expression): &RastExpressionCtx, dst: Register) {
+    use Instruction::*;
+
+    let pos = *pos;
+    let mark = self.state.next_register;
+    match expression {
+      &RastExpression::IntegerConstant(x, literal_id) => {
+        let value = match self.ctx.literals[&literal_id].type_ {
+          TypeRef::Primitive(type_) => Value::from_integer(type_, x).unwrap(),
+          _ => unreachable!(),
+        };
+        self.constant(dst, value, pos);
+      }
+      RastExpression::FloatConstant(x) => self.constant(dst, Value::F64(x.0), pos),
+      &RastExpression::BooleanConstant(b) => self.constant(dst, Value::Bool(b), pos),
+      RastExpression::StringConstant(s) => self.constant(dst, Value::Str(s.as_str().into()), pos),
+      &RastExpression::Local(local_id) => {
+        let register = self.state.registers[&local_id];
+        if self.cells.contains(&local_id) {
+          self.emit(
+            LoadCell {
+              dst,
+              cell: register,
+            },
+            pos,
+          );
+        } else if register != dst {
+          self.emit(Move { dst, src: register }, pos);
+        }
+      }
+      RastExpression::UnaryOp(op, x) => {
+        let x = self.operand(x);
+        self.emit(Unary { op: *op, dst, x }, pos);
+      }
+      // The right hand side of && and || is only evaluated when needed.
+      RastExpression::BinaryOp(BinaryOperator::And, args) => {
+        self.expression(&args.0, dst);
+        let short_circuit = self.emit(
+          JumpIfFalse {
+            condition: dst,
+            target: 0,
+          },
+          pos,
+        );
+        self.expression(&args.1, dst);
+        self.patch(short_circuit, self.here());
+      }
+      RastExpression::BinaryOp(BinaryOperator::Or, args) => {
+        self.expression(&args.0, dst);
+        let evaluate_rhs = self.emit(
+          JumpIfFalse {
+            condition: dst,
+            target: 0,
+          },
+          pos,
+        );
+        let end = self.emit(Jump { target: 0 }, pos);
+        self.patch(evaluate_rhs, self.here());
+        self.expression(&args.1, dst);
+        self.patch(end, self.here());
+      }
+      RastExpression::BinaryOp(op, args) => {
+        let lhs = self.operand_before(&args.0, &[&args.1]);
+        let rhs = self.operand(&args.1);
+        self.emit(
+          Binary {
+            op: *op,
+            dst,
+            lhs,
+            rhs,
+          },
+          pos,
+        );
+      }
+      RastExpression::Block(block) => self.block(block, dst, pos),
+      RastExpression::If {
+        condition,
+        then_branch,
+        else_branch,
+      } => {
+        let condition = self.operand(condition);
+        let to_else = self.emit(
+          JumpIfFalse {
+            condition,
+            target: 0,
+          },
+          pos,
+        );
+        self.expression(then_branch, dst);
+        let end = self.emit(Jump { target: 0 }, pos);
+        self.patch(to_else, self.here());
+        match else_branch {
+          Some(else_branch) => self.expression(else_branch, dst),
+          None => {
+            self.emit(LoadUnit { dst }, pos);
+          }
+        }
+        self.patch(end, self.here());
+      }
+      RastExpression::Loop { loop_id, body } => {
+        let start = self.here();
+        self.state.loops.push(LoopTarget {
+          id: *loop_id,
+          start,
+          dst: Some(dst),
+          breaks: Vec::new(),
+        });
+        let result = self.allocate(1);
+        self.block(body, result, pos);
+        self.emit(
+          Jump {
+            target: start as u32,
+          },
+          pos,
+        );
+
+        let target = self.state.loops.pop().unwrap();
+        let end = self.here();
+        for jump in target.breaks {
+          self.patch(jump, end);
+        }
+      }
+      &RastExpression::Function(function_id, _) => {
+        let function = self.function_indices[&function_id];
+        self.emit(Function { dst, function }, pos);
+      }
+      RastExpression::Closure(function) => {
+        let index = self.reserve(function.id);
+        let compiled = self.function(function.id, &function.body);
+
+        // The closure is created where it's written, so its captures come
+        // from the registers of this function.
+        let captures = compiled
+          .captures
+          .iter()
+          .map(|capture| CaptureSource {
+            register: self.state.registers[&capture.local_id],
+            ..*capture
+          })
+          .collect();
+        self.functions[index as usize] = Some(BytecodeFunction {
+          captures,
+          ..compiled
+        });
+        self.emit(
+          Closure {
+            dst,
+            function: index,
+          },
+          pos,
+        );
+      }
+      RastExpression::Call {
+        function_id,
+        arguments,
+        ..
+      } => {
+        let arguments = self.expressions(arguments);
+        let function = self.function_indices[function_id];
+        self.emit(
+          Call {
+            dst,
+            function,
+            arguments,
+          },
+          pos,
+        );
+      }
+      RastExpression::CallIndirect { callee, arguments } => {
+        let later: Vec<_> = arguments.iter().collect();
+        let callee = self.operand_before(callee, &later);
+        let count = arguments.len() as u32;
+        let arguments = self.expressions(arguments);
+        self.emit(
+          CallIndirect {
+            dst,
+            callee,
+            arguments,
+            count,
+          },
+          pos,
+        );
+      }
+      RastExpression::Builtin {
+        builtin: crate::rast::Builtin::Len,
+        arguments,
+      } => {
+        let x = self.operand(&arguments[0]);
+        self.emit(Len { dst, x }, pos);
+      }
+      RastExpression::Array(elements, _) => {
+        let count = elements.len() as u32;
+        let elements = self.expressions(elements);
+        self.emit(
+          Array {
+            dst,
+            elements,
+            count,
+          },
+          pos,
+        );
+      }
+      RastExpression::Index { array, index } => {
+        let array = self.operand_before(array, &[index]);
+        let index = self.operand(index);
+        self.emit(Index { dst, array, index }, pos);
+      }
+      &RastExpression::Struct {
+        type_id,
+        ref fields,
+        ..
+      } => {
+        let first = self.allocate(fields.len());
+        for (register, (_, value)) in (first..).zip(fields) {
+          self.expression(value, register);
+        }
+        let layout = fields.iter().map(|(index, _)| *index).collect();
+        self.structs.push((type_id, layout));
+        let layout = self.structs.len() as u32 - 1;
+        self.emit(
+          Struct {
+            dst,
+            layout,
+            fields: first,
+          },
+          pos,
+        );
+      }
+      RastExpression::Field { value, field } => {
+        let x = self.operand(value);
+        let field = self.field(field);
+        self.emit(Field { dst, x, field }, pos);
+      }
+      &RastExpression::Variant {
+        type_id,
+        variant,
+        ref arguments,
+        ..
+      } => {
+        let fields = self.expressions(arguments);
+        self.variants.push((type_id, variant, arguments.len()));
+        let variant = self.variants.len() as u32 - 1;
+        self.emit(
+          Variant {
+            dst,
+            variant,
+            fields,
+          },
+          pos,
+        );
+      }
+      RastExpression::Match { scrutinee, arms } => {
+        let scrutinee = self.operand(scrutinee);
+
+        // The type checker made sure that some arm matches, so the last one
+        // doesn't need to fail anywhere.
+        let mut ends = Vec::new();
+        for arm in arms {
+          let mut failures = Vec::new();
+          self.pattern(scrutinee, &arm.pattern, &mut failures);
+          self.expression(&arm.body, dst);
+          ends.push(self.emit(Jump { target: 0 }, pos));
+          for jump in failures {
+            self.patch(jump, self.here());
+          }
+        }
+
+        for jump in ends {
+          self.patch(jump, self.here());
+        }
+      }
+      RastExpression::Cast { value, type_ } => {
+        let x = self.operand(value);
+        match type_ {
+          TypeRef::Primitive(type_) => self.emit(
+            Cast {
+              type_: *type_,
+              dst,
+              x,
+            },
+            pos,
+          ),
+          _ => unreachable!(),
+        };
+      }
+    }
+    self.state.next_register = mark;
+  }
+
+  // Compiles a test of whether the value in a register matches a pattern,
+  // which jumps to one of `failures` if it doesn't, and sets the bindings
+  // otherwise.
+  fn pattern(
+    &mut self,
+    x: Register,
+    RastPatternCtx(pos, pattern): &RastPatternCtx,
+    failures: &mut Vec<usize>,
+  ) {
+    use Instruction::*;
+
+    let pos = *pos;
+    match pattern {
+      RastPattern::Wildcard => {}
+      &RastPattern::Binding(local_id) => {
+        let register = self.register(local_id);
+        if self.cells.contains(&local_id) {
+          self.emit(
+            DeclareCell {
+              cell: register,
+              src: x,
+            },
+            pos,
+          );
+        } else if self.is_copied(local_id) {
+          self.emit(
+            Copy {
+              dst: register,
+              src: x,
+            },
+            pos,
+          );
+        } else {
+          self.emit(
+            Move {
+              dst: register,
+              src: x,
+            },
+            pos,
+          );
+        }
+      }
+      &RastPattern::Integer(value, _) => {
+        self.integers.push(value);
+        let integer = self.integers.len() as u32 - 1;
+        let matches = self.allocate(1);
+        self.emit(
+          IsInteger {
+            dst: matches,
+            x,
+            integer,
+          },
+          pos,
+        );
+        failures.push(self.emit(
+          JumpIfFalse {
+            condition: matches,
+            target: 0,
+          },
+          pos,
+        ));
+      }
+      &RastPattern::Boolean(b) => {
+        let mut condition = x;
+        if !b {
+          condition = self.allocate(1);
+          let op = UnaryOperator::Not;
+          self.emit(
+            Unary {
+              op,
+              dst: condition,
+              x,
+            },
+            pos,
+          );
+        }
+        failures.push(self.emit(
+          JumpIfFalse {
+            condition,
+            target: 0,
+          },
+          pos,
+        ));
+      }
+      RastPattern::Variant {
+        variant, fields, ..
+      } => {
+        let matches = self.allocate(1);
+        self.emit(
+          IsVariant {
+            dst: matches,
+            x,
+            variant: *variant as u32,
+          },
+          pos,
+        );
+        failures.push(self.emit(
+          JumpIfFalse {
+            condition: matches,
+            target: 0,
+          },
+          pos,
+        ));
+
+        for (index, field) in fields.iter().enumerate() {
+          if field.1 == RastPattern::Wildcard {
+            continue;
+          }
+          let value = self.allocate(1);
+          self.emit(
+            VariantField {
+              dst: value,
+              x,
+              index: index as u32,
+            },
+            field.0,
+          );
+          self.pattern(value, field, failures);
+        }
+      }
+    }
+  }
+
+  fn loop_target(&mut self, loop_id: LoopId) -> &mut LoopTarget {
+    self
+      .state
+      .loops
+      .iter_mut()
+      .rev()
+      .find(|target| target.id == loop_id)
+      .unwrap()
+  }
+
+  fn statement(&mut self, RastStatementCtx(pos, statement): &RastStatementCtx) {
+    use Instruction::*;
+
+    let pos = *pos;
+    // The register of a local outlives the statement that declares it.
+    if let RastStatement::DeclareLocal { local_id, .. } = statement {
+      self.register(*local_id);
+    }
+    let mark = self.state.next_register;
+
+    match statement {
+      &RastStatement::DeclareLocal {
+        local_id,
+        ref value,
+      } => {
+        let register = self.state.registers[&local_id];
+        if self.cells.contains(&local_id) {
+          let src = self.operand(value);
+          self.emit(
+            DeclareCell {
+              cell: register,
+              src,
+            },
+            pos,
+          );
+        } else {
+          self.expression(value, register);
+          if self.is_copied(local_id) {
+            self.emit(
+              Copy {
+                dst: register,
+                src: register,
+              },
+              pos,
+            );
+          }
+        }
+      }
+      // The value is computed elsewhere first, since it may use the local.
+      &RastStatement::AssignLocal {
+        local_id,
+        ref value,
+      } => {
+        let register = self.state.registers[&local_id];
+        let src = self.operand(value);
+        if self.cells.contains(&local_id) {
+          self.emit(
+            StoreCell {
+              cell: register,
+              src,
+            },
+            pos,
+          );
+        } else if self.is_copied(local_id) {
+          self.emit(Copy { dst: register, src }, pos);
+        } else {
+          self.emit(Move { dst: register, src }, pos);
+        }
+      }
+      RastStatement::AssignIndex {
+        array,
+        index,
+        value,
+      } => {
+        let array = self.operand_before(array, &[index, value]);
+        let index = self.operand_before(index, &[value]);
+        self.emit(CheckIndex { array, index }, pos);
+        let src = self.operand(value);
+        self.emit(SetIndex { array, index, src }, pos);
+      }
+      RastStatement::AssignField {
+        target,
+        field,
+        value,
+      } => {
+        let target = self.operand_before(target, &[value]);
+        let src = self.operand(value);
+        let field = self.field(field);
+        self.emit(SetField { target, field, src }, pos);
+      }
+      RastStatement::Expression { value } => {
+        let dst = self.allocate(1);
+        self.expression(value, dst);
+      }
+      RastStatement::While {
+        loop_id,
+        condition,
+        body,
+      } => {
+        let start = self.here();
+        let condition = self.operand(condition);
+        let exit = self.emit(
+          JumpIfFalse {
+            condition,
+            target: 0,
+          },
+          pos,
+        );
+        self.state.loops.push(LoopTarget {
+          id: *loop_id,
+          start,
+          dst: None,
+          breaks: vec![exit],
+        });
+        let result = self.allocate(1);
+        self.block(body, result, pos);
+        self.emit(
+          Jump {
+            target: start as u32,
+          },
+          pos,
+        );
+
+        let target = self.state.loops.pop().unwrap();
+        let end = self.here();
+        for jump in target.breaks {
+          self.patch(jump, end);
+        }
+      }
+      RastStatement::Break { loop_id, value } => {
+        let dst = match self.loop_target(*loop_id).dst {
+          Some(dst) => dst,
+          None => self.allocate(1),
+        };
+        match value {
+          Some(value) => self.expression(value, dst),
+          None => {
+            self.emit(LoadUnit { dst }, pos);
+          }
+        }
+        let jump = self.emit(Jump { target: 0 }, pos);
+        self.loop_target(*loop_id).breaks.push(jump);
+      }
+      RastStatement::Continue { loop_id } => {
+        let start = self.loop_target(*loop_id).start;
+        self.emit(
+          Jump {
+            target: start as u32,
+          },
+          pos,
+        );
+      }
+      RastStatement::Return { value, .. } => {
+        let src = match value {
+          Some(value) => self.operand(value),
+          None => {
+            let dst = self.allocate(1);
+            self.emit(LoadUnit { dst }, pos);
+            dst
+          }
+        };
+        self.emit(Return { src }, pos);
+      }
+    }
+    self.state.next_register = mark;
+  }
+}
+
+// Every struct that has a field, for the fields that are used.
+fn field_indices(
+  ctx: &SemanticContext,
+  structs: &[(UserTypeId, Vec<usize>)],
+  name: &str,
+) -> HashMap<UserTypeId, usize> {
+  structs
+    .iter()
+    .filter_map(|&(type_id, _)| match &ctx.resolve_type(type_id).type_def {
+      UserTypeDef::Struct(def) => def.field_index(name).map(|index| (type_id, index)),
+      _ => None,
+    })
+    .collect()
+}
+
+pub fn compile(ctx: &SemanticContext, program: &RastProgram) -> BytecodeProgram {
+  with_stack(PASS_STACK_SIZE, || {
+    let cells = ctx
+      .functions
+      .values()
+      .flat_map(|function| &function.captures)
+      .filter(|capture| capture.mode == CaptureMode::ByReference)
+      .map(|capture| capture.local_id)
+      .collect();
+
+    let mut compiler = Compiler {
+      ctx,
+      functions: Vec::new(),
+      function_indices: HashMap::new(),
+      constants: Vec::new(),
+      integers: Vec::new(),
+      structs: Vec::new(),
+      field_names: Vec::new(),
+      variants: Vec::new(),
+      cells,
+      state: FunctionState::default(),
+    };
+
+    // Functions can be called before they're compiled, so their indices come
+    // first.
+    for function in &program.functions {
+      compiler.reserve(function.id);
+    }
+    for function in &program.functions {
+      let compiled = compiler.function(function.id, &function.body);
+      let index = compiler.function_indices[&function.id];
+      compiler.functions[index as usize] = Some(compiled);
+    }
+
+    let result = compiler.allocate(1);
+    compiler.block(&program.body, result, Span::default());
+    compiler.emit(Instruction::Return { src: result }, Span::default());
+    let state = std::mem::take(&mut compiler.state);
+    compiler.functions.push(Some(BytecodeFunction {
+      id: None,
+      name: "{program}".to_string(),
+      parameter_count: 0,
+      register_count: state.register_count as usize,
+      captures: Vec::new(),
+      code: state.code,
+      spans: state.spans,
+    }));
+
+    let fields = compiler
+      .field_names
+      .iter()
+      .map(|name| {
+        let indices = field_indices(ctx, &compiler.structs, name);
+        (name.clone(), indices)
+      })
+      .collect();
+    BytecodeProgram {
+      functions: compiler.functions.into_iter().map(Option::unwrap).collect(),
+      function_indices: compiler.function_indices,
+      constants: compiler.constants,
+      integers: compiler.integers,
+      structs: compiler.structs,
+      fields,
+      variants: compiler.variants,
+    }
+  })
+}
+
+// Whether evaluating an expression can assign to a local. Closures can't
+// assign to the locals of the function they are in, only to the cells of the
+// ones they capture.
+fn assigns(RastExpressionCtx(_, expression): &RastExpressionCtx, local_id: LocalId) -> bool {
+  use RastExpression::*;
+
+  let any = |expressions: &[RastExpressionCtx]| expressions.iter().any(|x| assigns(x, local_id));
+  match expression {
+    IntegerConstant(..) | FloatConstant(_) | BooleanConstant(_) | StringConstant(_) => false,
+    Local(_) | Function(..) | Closure(_) => false,
+    UnaryOp(_, x) => assigns(x, local_id),
+    BinaryOp(_, args) => assigns(&args.0, local_id) || assigns(&args.1, local_id),
+    Block(block) | Loop { body: block, .. } => block_assigns(block, local_id),
+    If {
+      condition,
+      then_branch,
+      else_branch,
+    } => {
+      assigns(condition, local_id)
+        || assigns(then_branch, local_id)
+        || else_branch.iter().any(|x| assigns(x, local_id))
+    }
+    Call { arguments, .. } | Builtin { arguments, .. } | Variant { arguments, .. } => {
+      any(arguments)
+    }
+    CallIndirect { callee, arguments } => assigns(callee, local_id) || any(arguments),
+    Array(elements, _) => any(elements),
+    Index { array, index } => assigns(array, local_id) || assigns(index, local_id),
+    Struct { fields, .. } => fields.iter().any(|(_, x)| assigns(x, local_id)),
+    Field { value, .. } | Cast { value, .. } => assigns(value, local_id),
+    Match { scrutinee, arms } => {
+      assigns(scrutinee, local_id) || arms.iter().any(|arm| assigns(&arm.body, local_id))
+    }
+  }
+}
+
+fn block_assigns(block: &RastBlock, local_id: LocalId) -> bool {
+  let statement_assigns = |RastStatementCtx(_, statement): &RastStatementCtx| match statement {
+    RastStatement::AssignLocal {
+      local_id: target,
+      value,
+    } => *target == local_id || assigns(value, local_id),
+    RastStatement::DeclareLocal { value, .. } | RastStatement::Expression { value } => {
+      assigns(value, local_id)
+    }
+    RastStatement::AssignIndex {
+      array,
+      index,
+      value,
+    } => assigns(array, local_id) || assigns(index, local_id) || assigns(value, local_id),
+    RastStatement::AssignField { target, value, .. } => {
+      assigns(target, local_id) || assigns(value, local_id)
+    }
+    RastStatement::While {
+      condition, body, ..
+    } => assigns(condition, local_id) || block_assigns(body, local_id),
+    RastStatement::Break { value, .. } | RastStatement::Return { value, .. } => {
+      value.iter().any(|x| assigns(x, local_id))
+    }
+    RastStatement::Continue { .. } => false,
+  };
+  block.statements.iter().any(statement_assigns)
+    || block.result.iter().any(|x| assigns(x, local_id))
+}
+
+// A listing of a compiled program, with its constants, and the instructions of
+// each function next to where they come from in the source.
+pub struct Disassembly<'a> {
+  program: &'a BytecodeProgram,
+  source_map: &'a SourceMap,
+}
+
+impl<'a> Disassembly<'a> {
+  pub fn new(program: &'a BytecodeProgram, source_map: &'a SourceMap) -> Disassembly<'a> {
+    Disassembly {
+      program,
+      source_map,
+    }
+  }
+
+  fn function_name(&self, index: u32) -> String {
+    format!(
+      "f{} <{}>",
+      index, self.program.functions[index as usize].name
+    )
+  }
+
+  fn instruction(&self, instruction: Instruction) -> String {
+    use Instruction::*;
+
+    let program = self.program;
+    let range = |first: Register, count: usize| format!("r{}..r{}", first, first as usize + count);
+    match instruction {
+      LoadConstant { dst, constant } => format!("load_constant r{}, c{}", dst, constant),
+      LoadUnit { dst } => format!("load_unit r{}", dst),
+      Move { dst, src } => format!("move r{}, r{}", dst, src),
+      Copy { dst, src } => format!("copy r{}, r{}", dst, src),
+      LoadCell { dst, cell } => format!("load_cell r{}, r{}", dst, cell),
+      StoreCell { cell, src } => format!("store_cell r{}, r{}", cell, src),
+      DeclareCell { cell, src } => format!("declare_cell r{}, r{}", cell, src),
+      Jump { target } => format!("jump {}", target),
+      JumpIfFalse { condition, target } => format!("jump_if_false r{}, {}", condition, target),
+      Unary { op, dst, x } => format!("unary r{}, {}r{}", dst, op, x),
+      Binary { op, dst, lhs, rhs } => format!("binary r{}, r{} {} r{}", dst, lhs, op, rhs),
+      Cast { type_, dst, x } => format!("cast r{}, r{} as {}", dst, x, type_),
+      Function { dst, function } => format!("function r{}, {}", dst, self.function_name(function)),
+      Closure { dst, function } => format!("closure r{}, {}", dst, self.function_name(function)),
+      Call {
+        dst,
+        function,
+        arguments,
+      } => {
+        let count = program.functions[function as usize].parameter_count;
+        format!(
+          "call r{}, {}, {}",
+          dst,
+          self.function_name(function),
+          range(arguments, count)
+        )
+      }
+      CallIndirect {
+        dst,
+        callee,
+        arguments,
+        count,
+      } => format!(
+        "call_indirect r{}, r{}, {}",
+        dst,
+        callee,
+        range(arguments, count as usize)
+      ),
+      Return { src } => format!("return r{}", src),
+      Len { dst, x } => format!("len r{}, r{}", dst, x),
+      Array {
+        dst,
+        elements,
+        count,
+      } => format!("array r{}, {}", dst, range(elements, count as usize)),
+      Index { dst, array, index } => format!("index r{}, r{}[r{}]", dst, array, index),
+      CheckIndex { array, index } => format!("check_index r{}[r{}]", array, index),
+      SetIndex { array, index, src } => format!("set_index r{}[r{}], r{}", array, index, src),
+      Struct {
+        dst,
+        layout,
+        fields,
+      } => {
+        let (type_id, indices) = &program.structs[layout as usize];
+        let indices: Vec<String> = indices.iter().map(|x| x.to_string()).collect();
+        format!(
+          "struct r{}, {} {{{}}}, {}",
+          dst,
+          TypeRef::UserType(*type_id),
+          indices.join(", "),
+          range(fields, indices.len())
+        )
+      }
+      Field { dst, x, field } => {
+        let name = &program.fields[field as usize].0;
+        format!("field r{}, r{}.{}", dst, x, name)
+      }
+      SetField { target, field, src } => {
+        let name = &program.fields[field as usize].0;
+        format!("set_field r{}.{}, r{}", target, name, src)
+      }
+      Variant {
+        dst,
+        variant,
+        fields,
+      } => {
+        let (type_id, variant, count) = program.variants[variant as usize];
+        format!(
+          "variant r{}, {}::{}, {}",
+          dst,
+          TypeRef::UserType(type_id),
+          variant,
+          range(fields, count)
+        )
+      }
+      IsInteger { dst, x, integer } => {
+        let integer = program.integers[integer as usize];
+        format!("is_integer r{}, r{}, {}", dst, x, integer)
+      }
+      IsVariant { dst, x, variant } => format!("is_variant r{}, r{}, {}", dst, x, variant),
+      VariantField { dst, x, index } => format!("variant_field r{}, r{}, {}", dst, x, index),
+    }
+  }
+}
+
+fn plural(count: usize, noun: &str) -> String {
+  match count {
+    1 => format!("1 {}", noun),
+    _ => format!("{} {}s", count, noun),
+  }
+}
+
+impl<'a> fmt::Display for Disassembly<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "constants:")?;
+    for (index, constant) in self.program.constants.iter().enumerate() {
+      writeln!(f, "  c{} = {:?}", index, constant)?;
+    }
+
+    for (index, function) in self.program.functions.iter().enumerate() {
+      writeln!(
+        f,
+        "\n{}: {}, {}",
+        self.function_name(index as u32),
+        plural(function.parameter_count, "parameter"),
+        plural(function.register_count, "register")
+      )?;
+      // The captures are copied from the registers of the function that
+      // creates the closure.
+      for (index, capture) in function.captures.iter().enumerate() {
+        let mode = if capture.is_cell {
+          "reference"
+        } else {
+          "value"
+        };
+        writeln!(
+          f,
+          "  capture r{} <- r{} by {}",
+          index, capture.register, mode
+        )?;
+      }
+
+      // Instructions are followed by the line and column they come from.
+      for (ip, (&instruction, span)) in function.code.iter().zip(&function.spans).enumerate() {
+        let location = match self.source_map.lookup(span.start) {
+          Some(location) => format!("{}:{}", location.line, location.column),
+          None => String::new(),
+        };
+        let instruction = self.instruction(instruction);
+        writeln!(f, "  {:>4}  {:<40} {}", ip, instruction, location)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod bytecode_tests {
+  use super::*;
+  use crate::parser::Parser;
+  use crate::semantic::transform_program;
+  use crate::token_stream::TokenStream;
+  use crate::type_checker::visit_program;
+
+  fn compile_src(src: &str) -> BytecodeProgram {
+    let mut token_stream = TokenStream::new(src);
+    let mut parser = Parser::new(&mut token_stream);
+    let (program, errors) = parser.parse_program();
+    assert_eq!(errors, vec![]);
+    let (mut ctx, program) = transform_program(program).unwrap();
+    visit_program(&mut ctx, &program).unwrap();
+    compile(&ctx, &program)
+  }
+
+  #[test]
+  fn locals_are_registers() {
+    use Instruction::*;
+    let program = compile_src("let x = 1; let mut y = x; y = y + x; y");
+    let main = &program.functions[program.main()];
+    assert_eq!(4, main.register_count);
+    // Both locals are read where they are.
+    assert_eq!(
+      vec![
+        LoadConstant {
+          dst: 1,
+          constant: 0
+        },
+        Move { dst: 2, src: 1 },
+        Binary {
+          op: BinaryOperator::Add,
+          dst: 3,
+          lhs: 2,
+          rhs: 1
+        },
+        Move { dst: 2, src: 3 },
+        Move { dst: 0, src: 2 },
+        Return { src: 0 },
+      ],
+      main.code
+    );
+  }
+
+  #[test]
+  fn locals_that_change_are_copied() {
+    use Instruction::*;
+    // y is copied before the block that assigns to it.
+    let program = compile_src("let mut y = 1; y + { y = 2; y }");
+    let main = &program.functions[program.main()];
+    assert_eq!(main.code[1], Move { dst: 2, src: 1 });
+    assert_eq!(
+      main.code[5],
+      Binary {
+        op: BinaryOperator::Add,
+        dst: 0,
+        lhs: 2,
+        rhs: 3
+      }
+    );
+  }
+
+  #[test]
+  fn constants_are_shared() {
+    // Equal values of different types are different constants.
+    let program = compile_src("let a = [1, 2, 1]; let b = 1u8 + 1u8; let c = 0.5 * 0.5; 1 + a[2]");
+    assert_eq!(
+      vec![Value::I32(1), Value::I32(2), Value::U8(1), Value::F64(0.5)],
+      program.constants
+    );
+  }
+
+  #[test]
+  fn captures_by_reference_are_cells() {
+    use Instruction::*;
+    let program = compile_src("let mut c = 0; let n = 2; let inc = || { c = c + n; }; inc(); c");
+    let closure = &program.functions[0];
+    assert_eq!(
+      vec![
+        CaptureSource {
+          local_id: LocalId(0),
+          register: 1,
+          is_cell: true
+        },
+        CaptureSource {
+          local_id: LocalId(1),
+          register: 2,
+          is_cell: false
+        },
+      ],
+      closure.captures
+    );
+    assert_eq!(
+      closure.code[..2],
+      [
+        LoadCell { dst: 4, cell: 0 },
+        Binary {
+          op: BinaryOperator::Add,
+          dst: 3,
+          lhs: 4,
+          rhs: 1
+        }
+      ]
+    );
+
+    let main = &program.functions[program.main()];
+    assert_eq!(main.code[1], DeclareCell { cell: 1, src: 2 });
+  }
+
+  #[test]
+  fn disassemble() {
+    let src = "fn twice(x: i32) -> i32 {\n  x * 2\n}\nlet mut y = twice(2);\ny + 2";
+    let program = compile_src(src);
+    let mut source_map = SourceMap::new();
+    source_map.add_file("test", src);
+    assert_eq!(
+      Disassembly::new(&program, &source_map).to_string(),
+      "\
+constants:
+  c0 = I32(2)
+
+f0 <twice>: 1 parameter, 3 registers
+     0  load_constant r2, c0                     2:7
+     1  binary r1, r0 * r2                       2:3
+     2  return r1                                1:4
+
+f1 <{program}>: 0 parameters, 3 registers
+     0  load_constant r2, c0                     4:19
+     1  call r1, f0 <twice>, r2..r3              4:13
+     2  load_constant r2, c0                     5:5
+     3  binary r0, r1 + r2                       5:1
+     4  return r0                                1:1
+"
+    );
+  }
+}
@@ -6,6 +6,7 @@ pub struct CharStream<'a> {
 }
 
 impl<'a> CharStream<'a> {
+  #[allow(clippy::should_implement_trait)]
   pub fn from_str(data: &'a str) -> CharStream<'a> {
     CharStream {
       remaining: data,
@@ -14,22 +15,26 @@ impl<'a> CharStream<'a> {
   }
 
   pub fn peek(&self) -> Option<char> {
-    if self.remaining.len() == 0 {
+    if self.remaining.is_empty() {
       return None;
     }
 
     self.remaining.chars().nth(0)
   }
 
+  pub fn peek_nth(&self, n: usize) -> Option<char> {
+    self.remaining.chars().nth(n)
+  }
+
   pub fn advance(&mut self) {
-    if self.remaining.len() > 0 {
+    if !self.remaining.is_empty() {
       let offset = self.remaining.chars().nth(0).unwrap().len_utf8();
       self.remaining = &self.remaining[offset..];
     }
   }
 
   pub fn take(&mut self) -> Option<char> {
-    if self.remaining.len() == 0 {
+    if self.remaining.is_empty() {
       return None;
     }
 
@@ -99,7 +104,7 @@ impl<'a> CharStream<'a> {
   }
 
   pub fn byte_offset(&self) -> usize {
-    if self.remaining.len() == 0 {
+    if self.remaining.is_empty() {
       return self.full.len();
     }
 
@@ -108,6 +113,11 @@ impl<'a> CharStream<'a> {
     current.checked_sub(first).unwrap()
   }
 
+  // The part of the data between two byte offsets.
+  pub fn slice(&self, start: usize, end: usize) -> &'a str {
+    &self.full[start..end]
+  }
+
   pub fn remaining(&self) -> usize {
     self.remaining.len()
   }
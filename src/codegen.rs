@@ -0,0 +1,4 @@
+// Translations of type checked programs into other languages, so that they
+// can be run without the interpreter.
+
+pub mod c;
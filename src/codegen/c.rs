@@ -0,0 +1,1624 @@
+// Translates a type checked program into a standalone C99 program, which
+// prints the value of the program in the form of its Debug output, or the
+// report of the runtime error that stopped it. Locals become C variables of
+// the C type of their type. Every operation is a statement of its own that
+// stores its result in a temporary, so that the operands are evaluated in the
+// same order as by the interpreter. Arrays and structs are pointers to C
+// structs, which are shared and copied like the interpreter's values, and
+// generic functions get a C function for each list of type arguments they're
+// called with. Enums, closures and function values can't be translated yet.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+
+use crate::ast_common::{BinaryOperator, Float, Operator, UnaryOperator};
+use crate::diagnostics::{Renderer, ToDiagnostic};
+use crate::interpreter::{RuntimeError, RuntimeErrorCtx, MAX_CALL_DEPTH};
+use crate::rast::{
+  FunctionId, LocalId, LoopId, PrimitiveType, RastBlock, RastExpression, RastExpressionCtx,
+  RastFunction, RastPattern, RastPatternCtx, RastProgram, RastStatement, RastStatementCtx,
+  StructDef, TypeArgumentsId, TypeRef, TypeVariableId, UserTypeDef, UserTypeId,
+};
+use crate::semantic::SemanticContext;
+use crate::source_map::SourceMap;
+use crate::span::Span;
+use crate::utils::{with_stack, PASS_STACK_SIZE};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CodegenError {
+  // A feature of the language that has no translation to C, such as "enums".
+  Unsupported(&'static str),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CodegenErrorCtx(pub Span, pub CodegenError);
+
+pub type CodegenResult<T> = Result<T, CodegenErrorCtx>;
+
+// The runtime that every program starts with. Integer operations and calls
+// check for the same errors as the interpreter, and report them with the
+// message they are given. Strings made by concatenation, arrays and structs
+// are never freed.
+const PRELUDE: &str = r##"#include <inttypes.h>
+#include <math.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef unsigned char toy_unit;
+
+typedef struct {
+  const char *data;
+  size_t len;
+} toy_str;
+
+static void toy_fail(const char *error) {
+  fflush(stdout);
+  fputs(error, stderr);
+  exit(1);
+}
+
+/* The number of calls in progress, which is limited like in the interpreter. */
+static size_t toy_depth;
+
+static inline void *toy_alloc(size_t size) {
+  void *data = malloc(size);
+  if (data == NULL) {
+    fputs("out of memory\n", stderr);
+    exit(1);
+  }
+  return data;
+}
+
+/* The first line of the report of an index out of bounds is the only one that
+   depends on the index, so the rest is given. */
+static inline size_t toy_index(int32_t index, size_t len, const char *rest) {
+  if (index < 0 || (size_t)index >= len) {
+    fflush(stdout);
+    fprintf(stderr,
+            "error: index out of bounds: the len is %zu but the index is %" PRId32 "\n",
+            len, index);
+    fputs(rest, stderr);
+    exit(1);
+  }
+  return (size_t)index;
+}
+
+/* Shift amounts of any integer type, clamped to -1..=64. */
+static inline int toy_amount_signed(int64_t amount) {
+  return amount < 0 ? -1 : amount > 64 ? 64 : (int)amount;
+}
+
+static inline int toy_amount_unsigned(uint64_t amount) {
+  return amount > 64 ? 64 : (int)amount;
+}
+
+#define TOY_SHIFTS(name, T, BITS) \
+  static inline T toy_shl_##name(T a, int amount, const char *overflow) { \
+    if (amount < 0 || amount >= BITS) toy_fail(overflow); \
+    return (T)((uint64_t)a << amount); \
+  } \
+  static inline T toy_shr_##name(T a, int amount, const char *overflow) { \
+    if (amount < 0 || amount >= BITS) toy_fail(overflow); \
+    return (T)(a >> amount); \
+  }
+
+/* Floats are rounded towards zero and saturate at the bounds, with NaN
+   becoming 0. */
+#define TOY_FROM_F64(name, T, MIN, MAX) \
+  static inline T toy_##name##_from_f64(double x) { \
+    if (x != x) return 0; \
+    if (x <= (double)MIN) return MIN; \
+    if (x >= (double)MAX) return MAX; \
+    return (T)x; \
+  }
+
+#define TOY_SIGNED(name, T, MIN, MAX, BITS) \
+  static inline T toy_add_##name(T a, T b, const char *overflow) { \
+    if ((b > 0 && a > MAX - b) || (b < 0 && a < MIN - b)) toy_fail(overflow); \
+    return (T)(a + b); \
+  } \
+  static inline T toy_sub_##name(T a, T b, const char *overflow) { \
+    if ((b < 0 && a > MAX + b) || (b > 0 && a < MIN + b)) toy_fail(overflow); \
+    return (T)(a - b); \
+  } \
+  static inline T toy_mul_##name(T a, T b, const char *overflow) { \
+    if (a > 0 ? (b > 0 ? a > MAX / b : b < MIN / a) \
+              : (b > 0 ? a < MIN / b : a != 0 && b < MAX / a)) \
+      toy_fail(overflow); \
+    return (T)(a * b); \
+  } \
+  static inline T toy_div_##name(T a, T b, const char *by_zero, const char *overflow) { \
+    if (b == 0) toy_fail(by_zero); \
+    if (a == MIN && b == -1) toy_fail(overflow); \
+    return (T)(a / b); \
+  } \
+  static inline T toy_rem_##name(T a, T b, const char *by_zero, const char *overflow) { \
+    if (b == 0) toy_fail(by_zero); \
+    if (a == MIN && b == -1) toy_fail(overflow); \
+    return (T)(a % b); \
+  } \
+  static inline T toy_neg_##name(T a, const char *overflow) { \
+    if (a == MIN) toy_fail(overflow); \
+    return (T)-a; \
+  } \
+  TOY_SHIFTS(name, T, BITS) \
+  TOY_FROM_F64(name, T, MIN, MAX)
+
+#define TOY_UNSIGNED(name, T, MAX, BITS) \
+  static inline T toy_add_##name(T a, T b, const char *overflow) { \
+    if (a > MAX - b) toy_fail(overflow); \
+    return (T)(a + b); \
+  } \
+  static inline T toy_sub_##name(T a, T b, const char *overflow) { \
+    if (a < b) toy_fail(overflow); \
+    return (T)(a - b); \
+  } \
+  static inline T toy_mul_##name(T a, T b, const char *overflow) { \
+    if (b != 0 && a > MAX / b) toy_fail(overflow); \
+    return (T)(a * b); \
+  } \
+  static inline T toy_div_##name(T a, T b, const char *by_zero, const char *overflow) { \
+    (void)overflow; \
+    if (b == 0) toy_fail(by_zero); \
+    return (T)(a / b); \
+  } \
+  static inline T toy_rem_##name(T a, T b, const char *by_zero, const char *overflow) { \
+    (void)overflow; \
+    if (b == 0) toy_fail(by_zero); \
+    return (T)(a % b); \
+  } \
+  TOY_SHIFTS(name, T, BITS) \
+  TOY_FROM_F64(name, T, 0, MAX)
+
+TOY_SIGNED(i8, int8_t, INT8_MIN, INT8_MAX, 8)
+TOY_SIGNED(i16, int16_t, INT16_MIN, INT16_MAX, 16)
+TOY_SIGNED(i32, int32_t, INT32_MIN, INT32_MAX, 32)
+TOY_SIGNED(i64, int64_t, INT64_MIN, INT64_MAX, 64)
+TOY_UNSIGNED(u8, uint8_t, UINT8_MAX, 8)
+TOY_UNSIGNED(u16, uint16_t, UINT16_MAX, 16)
+TOY_UNSIGNED(u32, uint32_t, UINT32_MAX, 32)
+TOY_UNSIGNED(u64, uint64_t, UINT64_MAX, 64)
+
+static inline toy_str toy_str_concat(toy_str a, toy_str b) {
+  char *data = toy_alloc(a.len + b.len + 1);
+  toy_str result;
+  memcpy(data, a.data, a.len);
+  memcpy(data + a.len, b.data, b.len);
+  result.data = data;
+  result.len = a.len + b.len;
+  return result;
+}
+
+static inline bool toy_str_eq(toy_str a, toy_str b) {
+  return a.len == b.len && memcmp(a.data, b.data, a.len) == 0;
+}
+
+/* Prints the fewest digits that read back as the same number, in decimal
+   notation unless the number is very large or very small. */
+static inline void toy_print_f64(double x) {
+  char buffer[32];
+  char digits[20];
+  int precision, exponent, count = 0, i;
+  const char *p;
+  if (x != x) {
+    fputs("NaN", stdout);
+    return;
+  }
+  if (x == 0) {
+    fputs(signbit(x) ? "-0.0" : "0.0", stdout);
+    return;
+  }
+  if (x < 0) {
+    putchar('-');
+    x = -x;
+  }
+  if (isinf(x)) {
+    fputs("inf", stdout);
+    return;
+  }
+  for (precision = 0; precision < 17; precision++) {
+    snprintf(buffer, sizeof buffer, "%.*e", precision, x);
+    if (strtod(buffer, NULL) == x) break;
+  }
+  for (p = buffer; *p != 'e'; p++) {
+    if (*p != '.') digits[count++] = *p;
+  }
+  exponent = atoi(p + 1);
+  if (x < 1e-4 || x >= 1e16) {
+    putchar(digits[0]);
+    if (count > 1) {
+      putchar('.');
+      fwrite(digits + 1, 1, count - 1, stdout);
+    }
+    printf("e%d", exponent);
+  } else if (exponent < 0) {
+    fputs("0.", stdout);
+    for (i = -1; i > exponent; i--) putchar('0');
+    fwrite(digits, 1, count, stdout);
+  } else {
+    for (i = 0; i <= exponent; i++) putchar(i < count ? digits[i] : '0');
+    putchar('.');
+    if (count > exponent + 1) {
+      fwrite(digits + exponent + 1, 1, count - exponent - 1, stdout);
+    } else {
+      putchar('0');
+    }
+  }
+}
+
+static inline void toy_print_str(toy_str s) {
+  size_t i;
+  putchar('"');
+  for (i = 0; i < s.len; i++) {
+    unsigned char c = (unsigned char)s.data[i];
+    switch (c) {
+    case '\0': fputs("\\0", stdout); break;
+    case '\t': fputs("\\t", stdout); break;
+    case '\r': fputs("\\r", stdout); break;
+    case '\n': fputs("\\n", stdout); break;
+    case '"': fputs("\\\"", stdout); break;
+    case '\\': fputs("\\\\", stdout); break;
+    default:
+      if (c < 0x20 || c == 0x7f) {
+        printf("\\u{%x}", c);
+      } else {
+        putchar(c);
+      }
+    }
+  }
+  putchar('"');
+}
+"##;
+
+fn unsupported(pos: Span, what: &'static str) -> CodegenErrorCtx {
+  CodegenErrorCtx(pos, CodegenError::Unsupported(what))
+}
+
+fn unsupported_expression(pos: Span, expression: &RastExpression) -> CodegenErrorCtx {
+  let what = match expression {
+    RastExpression::Closure(_) => "closures",
+    RastExpression::Variant { .. } => "enums",
+    _ => "function values",
+  };
+  unsupported(pos, what)
+}
+
+fn c_type(type_: PrimitiveType) -> &'static str {
+  match type_ {
+    PrimitiveType::I8 => "int8_t",
+    PrimitiveType::I16 => "int16_t",
+    PrimitiveType::I32 => "int32_t",
+    PrimitiveType::I64 => "int64_t",
+    PrimitiveType::U8 => "uint8_t",
+    PrimitiveType::U16 => "uint16_t",
+    PrimitiveType::U32 => "uint32_t",
+    PrimitiveType::U64 => "uint64_t",
+    PrimitiveType::F64 => "double",
+    PrimitiveType::Bool => "bool",
+    PrimitiveType::Str => "toy_str",
+    PrimitiveType::Unit => "toy_unit",
+  }
+}
+
+// The declaration of a variable of a C type, which is written without a
+// space after a pointer type.
+fn declare(type_: &str, name: &str) -> String {
+  if type_.ends_with('*') {
+    format!("{}{}", type_, name)
+  } else {
+    format!("{} {}", type_, name)
+  }
+}
+
+// A C expression for an integer constant of the given type.
+fn integer(value: i128, type_: PrimitiveType) -> String {
+  match type_ {
+    PrimitiveType::I64 if value == i64::MIN.into() => "INT64_MIN".to_string(),
+    PrimitiveType::I64 => format!("INT64_C({})", value),
+    PrimitiveType::U64 => format!("UINT64_C({})", value),
+    _ => format!("({}){}", c_type(type_), value),
+  }
+}
+
+// Float literals are never negative or NaN, and the Debug output of the others
+// is valid C.
+fn float(x: f64) -> String {
+  if x.is_infinite() {
+    "INFINITY".to_string()
+  } else {
+    format!("{:?}", x)
+  }
+}
+
+// A C string literal. Bytes other than printable ASCII are escaped in octal,
+// since a hex escape would run into the digits after it, and `?` is escaped so
+// that it can't start a trigraph.
+fn c_string(bytes: &[u8]) -> String {
+  let mut literal = String::from("\"");
+  for &byte in bytes {
+    match byte {
+      b'"' | b'\\' | b'?' => {
+        literal.push('\\');
+        literal.push(byte as char);
+      }
+      b'\n' => literal.push_str("\\n"),
+      b' '..=b'~' => literal.push(byte as char),
+      _ => write!(literal, "\\{:03o}", byte).unwrap(),
+    }
+  }
+  literal.push('"');
+  literal
+}
+
+// The statements that print a value of a primitive type, like Value's Debug
+// implementation does.
+fn print_value(type_: PrimitiveType, value: &str) -> String {
+  match type_ {
+    PrimitiveType::F64 => format!(
+      "fputs(\"F64(\", stdout); toy_print_f64({}); putchar(')');",
+      value
+    ),
+    PrimitiveType::Bool => format!("printf(\"Bool(%s)\", {} ? \"true\" : \"false\");", value),
+    PrimitiveType::Str => format!(
+      "fputs(\"Str(\", stdout); toy_print_str({}); putchar(')');",
+      value
+    ),
+    PrimitiveType::Unit => "fputs(\"Unit\", stdout);".to_string(),
+    _ => {
+      let conversion = if type_.is_signed() { "d" } else { "u" };
+      let bits = &type_.to_string()[1..];
+      format!(
+        "printf(\"{:?}(%\" PRI{}{} \")\", {});",
+        type_, conversion, bits, value
+      )
+    }
+  }
+}
+
+// The most arrays and structs that the type arguments of a generic function
+// can have inside each other. Only a function that calls itself with type
+// arguments made from its own needs more, and it would need endlessly many
+// instances.
+const MAX_TYPE_DEPTH: usize = 32;
+
+// A type whose type parameters have all been decided, which is what the C
+// translation of a value depends on.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Type {
+  Primitive(PrimitiveType),
+  Array(Box<Type>),
+  // A struct and the types that its type parameters stand for.
+  Struct(UserTypeId, Vec<Type>),
+}
+
+impl Type {
+  fn depth(&self) -> usize {
+    match self {
+      Type::Primitive(_) => 0,
+      Type::Array(element) => 1 + element.depth(),
+      Type::Struct(_, arguments) => 1 + arguments.iter().map(Type::depth).max().unwrap_or(0),
+    }
+  }
+}
+
+fn struct_def(ctx: &SemanticContext, id: UserTypeId) -> &StructDef {
+  match &ctx.resolve_type(id).type_def {
+    UserTypeDef::Struct(def) => def,
+    _ => unreachable!(),
+  }
+}
+
+fn substitution(
+  type_parameters: &[TypeVariableId],
+  arguments: &[Type],
+) -> HashMap<TypeVariableId, Type> {
+  type_parameters
+    .iter()
+    .copied()
+    .zip(arguments.iter().cloned())
+    .collect()
+}
+
+// The loop around the code being generated. Breaks and continues jump to
+// labels after the loop and at the end of its body, which are only placed if
+// they're used.
+struct LoopTarget {
+  id: LoopId,
+  // The variable that gets the value of the loop, unless it's ().
+  result: Option<String>,
+  broken: bool,
+  continued: bool,
+}
+
+struct Generator<'a> {
+  ctx: &'a SemanticContext,
+  renderer: Renderer<'a>,
+  // The reports of the runtime errors that can happen, as C string literals.
+  errors: Vec<String>,
+  // The C structs of the arrays and structs used so far, by their type, with
+  // their typedefs, their definitions and the functions that allocate, copy
+  // and print their values.
+  types: HashMap<Type, String>,
+  typedefs: Vec<String>,
+  definitions: String,
+  helpers: String,
+  prototypes: Vec<String>,
+  // The instances of generic functions, by their type arguments, and the ones
+  // that are still to be generated.
+  instances: HashMap<(FunctionId, Vec<Type>), String>,
+  pending: VecDeque<(FunctionId, Vec<Type>, String)>,
+  // What the type parameters of the function being generated stand for.
+  type_arguments: HashMap<TypeVariableId, Type>,
+  // The definitions of the functions generated so far.
+  code: String,
+  indent: usize,
+  next_temporary: usize,
+  loops: Vec<LoopTarget>,
+}
+
+impl<'a> Generator<'a> {
+  fn line(&mut self, line: &str) {
+    if !line.is_empty() {
+      for _ in 0..self.indent {
+        self.code.push_str("  ");
+      }
+    }
+    self.code.push_str(line);
+    self.code.push('\n');
+  }
+
+  fn temporary_name(&mut self) -> String {
+    let name = format!("t{}", self.next_temporary);
+    self.next_temporary += 1;
+    name
+  }
+
+  fn temporary(&mut self, type_: &str, value: &str) -> String {
+    let name = self.temporary_name();
+    self.line(&format!("{} = {};", declare(type_, &name), value));
+    name
+  }
+
+  // Declares the variable that gets the value of an if, a match or a loop,
+  // unless the value is ().
+  fn result(&mut self, type_: &Type, pos: Span) -> CodegenResult<Option<String>> {
+    if *type_ == Type::Primitive(PrimitiveType::Unit) {
+      return Ok(None);
+    }
+    let type_ = self.c_type_of(type_, pos)?;
+    let name = self.temporary_name();
+    self.line(&format!("{};", declare(&type_, &name)));
+    Ok(Some(name))
+  }
+
+  // Adds a report to the ones the program can print, and returns the C
+  // expression for it.
+  fn report(&mut self, report: &str) -> String {
+    self.errors.push(c_string(report.as_bytes()));
+    format!("toy_errors[{}]", self.errors.len() - 1)
+  }
+
+  // Renders the report of an error that can happen at runtime, and returns
+  // the C expression for it.
+  fn runtime_error(&mut self, pos: Span, error: RuntimeError) -> String {
+    let report = self
+      .renderer
+      .render(&RuntimeErrorCtx(pos, error).to_diagnostic());
+    self.report(&report)
+  }
+
+  // The report of an index out of bounds without its first line, which
+  // toy_index prints with the index and the length.
+  fn index_error(&mut self, pos: Span) -> String {
+    let error = RuntimeError::IndexOutOfBounds { index: 0, len: 0 };
+    let report = self
+      .renderer
+      .render(&RuntimeErrorCtx(pos, error).to_diagnostic());
+    let start = report.find('\n').map_or(report.len(), |i| i + 1);
+    self.report(&report[start..])
+  }
+
+  fn local_name(&self, local_id: LocalId) -> String {
+    format!("{}_{}", self.ctx.locals[&local_id].name, local_id.0)
+  }
+
+  fn function_name(&self, function_id: FunctionId) -> String {
+    format!(
+      "{}_f{}",
+      self.ctx.functions[&function_id].name, function_id.0
+    )
+  }
+
+  fn field_name(&self, type_id: UserTypeId, index: usize) -> String {
+    let def = struct_def(self.ctx, type_id);
+    format!("{}_{}", def.fields[index].name, index)
+  }
+
+  fn describe(&self, id: UserTypeId) -> &'static str {
+    match &self.ctx.resolve_type(id).type_def {
+      UserTypeDef::Array(_) => "arrays",
+      UserTypeDef::Function { .. } => "function values",
+      UserTypeDef::Struct(_) => "structs",
+      UserTypeDef::Enum(_) => "enums",
+      UserTypeDef::Applied { type_id, .. } => self.describe(*type_id),
+    }
+  }
+
+  // The type that a type of the program has in the function being generated.
+  fn concrete(&self, type_: TypeRef, pos: Span) -> CodegenResult<Type> {
+    self.substitute(type_, &self.type_arguments, pos)
+  }
+
+  // The type that a type has once its type parameters are replaced. The only
+  // type variables that the type checker leaves are type parameters.
+  fn substitute(
+    &self,
+    type_: TypeRef,
+    substitution: &HashMap<TypeVariableId, Type>,
+    pos: Span,
+  ) -> CodegenResult<Type> {
+    let id = match type_ {
+      TypeRef::Primitive(x) => return Ok(Type::Primitive(x)),
+      TypeRef::Variable(id) => return Ok(substitution[&id].clone()),
+      TypeRef::UserType(id) => id,
+      TypeRef::Error => unreachable!("the program has type errors"),
+      // Nothing of this type is ever made, like the elements of `[loop {}]`.
+      TypeRef::Never => return Ok(Type::Primitive(PrimitiveType::Unit)),
+    };
+
+    match &self.ctx.resolve_type(id).type_def {
+      UserTypeDef::Array(element) => {
+        let element = self.substitute(*element, substitution, pos)?;
+        Ok(Type::Array(Box::new(element)))
+      }
+      UserTypeDef::Struct(_) => Ok(Type::Struct(id, Vec::new())),
+      UserTypeDef::Applied { type_id, arguments }
+        if matches!(
+          self.ctx.resolve_type(*type_id).type_def,
+          UserTypeDef::Struct(_)
+        ) =>
+      {
+        let arguments = arguments
+          .iter()
+          .map(|&x| self.substitute(x, substitution, pos))
+          .collect::<CodegenResult<Vec<Type>>>()?;
+        Ok(Type::Struct(*type_id, arguments))
+      }
+      _ => Err(unsupported(pos, self.describe(id))),
+    }
+  }
+
+  // The types that the type parameters of a generic function or struct stand
+  // for at a call or a literal.
+  fn type_arguments(&self, id: TypeArgumentsId, pos: Span) -> CodegenResult<Vec<Type>> {
+    match self.ctx.type_arguments.get(&id) {
+      Some(arguments) => arguments.iter().map(|&x| self.concrete(x, pos)).collect(),
+      None => Ok(Vec::new()),
+    }
+  }
+
+  // The C type of the values of a type. Arrays and structs are pointers to C
+  // structs, which are defined the first time they're used.
+  fn c_type_of(&mut self, type_: &Type, pos: Span) -> CodegenResult<String> {
+    match type_ {
+      Type::Primitive(x) => Ok(c_type(*x).to_string()),
+      _ => Ok(format!("{} *", self.struct_name(type_, pos)?)),
+    }
+  }
+
+  fn struct_name(&mut self, type_: &Type, pos: Span) -> CodegenResult<String> {
+    if let Some(name) = self.types.get(type_) {
+      return Ok(name.clone());
+    }
+
+    // The name is given before the C struct is defined, since the type can
+    // contain itself.
+    let name = match type_ {
+      Type::Struct(id, _) => format!("{}_s{}", struct_def(self.ctx, *id).name, self.types.len()),
+      _ => format!("array_s{}", self.types.len()),
+    };
+    self.types.insert(type_.clone(), name.clone());
+    self.typedefs.push(format!("typedef struct {0} {0};", name));
+    match type_ {
+      Type::Array(element) => self.define_array(&name, element, pos)?,
+      Type::Struct(id, arguments) => self.define_struct(&name, *id, arguments, pos)?,
+      Type::Primitive(_) => unreachable!(),
+    }
+    Ok(name)
+  }
+
+  // Defines the C struct of an array type, which has the elements after their
+  // number.
+  fn define_array(&mut self, name: &str, element: &Type, pos: Span) -> CodegenResult<()> {
+    let element_type = self.c_type_of(element, pos)?;
+    let copy = self.copy(element, "x->data[i]", pos)?;
+    let print = self.print(element, "x->data[i]", pos)?;
+
+    write!(
+      self.definitions,
+      "\nstruct {} {{\n  size_t len;\n  {}[];\n}};\n",
+      name,
+      declare(&element_type, "data")
+    )
+    .unwrap();
+    let new = format!("static inline {0} *{0}_new(size_t len)", name);
+    let copy_header = format!("static inline {0} *{0}_copy({0} *x)", name);
+    let print_header = format!("static inline void {0}_print({0} *x)", name);
+    write!(
+      self.helpers,
+      "{new} {{
+  {name} *x = toy_alloc(sizeof({name}) + len * sizeof({element}));
+  x->len = len;
+  return x;
+}}
+
+{copy_header} {{
+  {name} *y = {name}_new(x->len);
+  size_t i;
+  for (i = 0; i < x->len; i++) y->data[i] = {copy};
+  return y;
+}}
+
+{print_header} {{
+  size_t i;
+  fputs(\"Array(RefCell {{ value: [\", stdout);
+  for (i = 0; i < x->len; i++) {{
+    if (i > 0) fputs(\", \", stdout);
+    {print}
+  }}
+  fputs(\"] }})\", stdout);
+}}
+
+",
+      new = new,
+      copy_header = copy_header,
+      print_header = print_header,
+      name = name,
+      element = element_type,
+      copy = copy,
+      print = print
+    )
+    .unwrap();
+    for header in &[new, copy_header, print_header] {
+      self.prototypes.push(format!("{};", header));
+    }
+    Ok(())
+  }
+
+  // Defines the C struct of a struct type, with a field for each field.
+  fn define_struct(
+    &mut self,
+    name: &str,
+    id: UserTypeId,
+    arguments: &[Type],
+    pos: Span,
+  ) -> CodegenResult<()> {
+    let def = struct_def(self.ctx, id);
+    let substitution = substitution(&def.type_parameters, arguments);
+    let mut fields = String::new();
+    let mut copies = String::new();
+    let mut prints = Vec::new();
+    for (index, field) in def.fields.iter().enumerate() {
+      let type_ = self.substitute(field.type_, &substitution, pos)?;
+      let field_type = self.c_type_of(&type_, pos)?;
+      let field = self.field_name(id, index);
+      let value = format!("x->{}", field);
+      writeln!(fields, "  {};", declare(&field_type, &field)).unwrap();
+      writeln!(
+        copies,
+        "  y->{} = {};",
+        field,
+        self.copy(&type_, &value, pos)?
+      )
+      .unwrap();
+      prints.push(format!("  {}\n", self.print(&type_, &value, pos)?));
+    }
+    // C structs can't be empty.
+    if def.fields.is_empty() {
+      fields.push_str("  char empty;\n");
+    }
+
+    write!(self.definitions, "\nstruct {} {{\n{}}};\n", name, fields).unwrap();
+    let copy_header = format!("static inline {0} *{0}_copy({0} *x)", name);
+    let print_header = format!("static inline void {0}_print({0} *x)", name);
+    let start = format!("Struct({:?}, RefCell {{ value: [", id);
+    write!(
+      self.helpers,
+      "{copy_header} {{
+  {name} *y = toy_alloc(sizeof({name}));
+{copies}  return y;
+}}
+
+{print_header} {{
+  fputs({start}, stdout);
+{prints}  fputs(\"] }})\", stdout);
+}}
+
+",
+      copy_header = copy_header,
+      print_header = print_header,
+      name = name,
+      copies = copies,
+      start = c_string(start.as_bytes()),
+      prints = prints.join("  fputs(\", \", stdout);\n")
+    )
+    .unwrap();
+    for header in &[copy_header, print_header] {
+      self.prototypes.push(format!("{};", header));
+    }
+    Ok(())
+  }
+
+  // A C expression for a copy of a value, which the interpreter makes when
+  // it binds the value to a local or a parameter, or stores it in an array or
+  // a struct.
+  fn copy(&mut self, type_: &Type, value: &str, pos: Span) -> CodegenResult<String> {
+    match type_ {
+      Type::Primitive(_) => Ok(value.to_string()),
+      _ => Ok(format!("{}_copy({})", self.struct_name(type_, pos)?, value)),
+    }
+  }
+
+  // The statements that print a value, like Value's Debug implementation
+  // does.
+  fn print(&mut self, type_: &Type, value: &str, pos: Span) -> CodegenResult<String> {
+    match type_ {
+      Type::Primitive(x) => Ok(print_value(*x, value)),
+      _ => Ok(format!(
+        "{}_print({});",
+        self.struct_name(type_, pos)?,
+        value
+      )),
+    }
+  }
+
+  // The type of an expression, from the types the type checker left in the
+  // context.
+  fn type_of(&self, RastExpressionCtx(pos, expression): &RastExpressionCtx) -> CodegenResult<Type> {
+    use PrimitiveType::*;
+    use RastExpression::*;
+
+    Ok(match expression {
+      IntegerConstant(_, literal_id) => self.concrete(self.ctx.literals[literal_id].type_, *pos)?,
+      FloatConstant(_) => Type::Primitive(F64),
+      BooleanConstant(_) => Type::Primitive(Bool),
+      StringConstant(_) => Type::Primitive(Str),
+      Local(local_id) => self.concrete(self.ctx.locals[local_id].type_, *pos)?,
+      UnaryOp(_, x) => self.type_of(x)?,
+      BinaryOp(op, args) => match op {
+        BinaryOperator::Equals
+        | BinaryOperator::NotEquals
+        | BinaryOperator::Less
+        | BinaryOperator::LessOrEqual
+        | BinaryOperator::Greater
+        | BinaryOperator::GreaterOrEqual
+        | BinaryOperator::And
+        | BinaryOperator::Or => Type::Primitive(Bool),
+        _ => self.type_of(&args.0)?,
+      },
+      Block(block) => self.block_type(block)?,
+      If {
+        then_branch,
+        else_branch: Some(_),
+        ..
+      } => self.type_of(then_branch)?,
+      If { .. } => Type::Primitive(Unit),
+      Loop { loop_id, .. } => match self.ctx.loops[loop_id].break_type {
+        Some(break_type) => self.concrete(break_type, *pos)?,
+        None => Type::Primitive(Unit),
+      },
+      Call {
+        function_id,
+        type_arguments_id,
+        ..
+      } => {
+        let function = &self.ctx.functions[function_id];
+        let arguments = self.type_arguments(*type_arguments_id, *pos)?;
+        let substitution = substitution(&function.type_parameters, &arguments);
+        self.substitute(function.return_type, &substitution, *pos)?
+      }
+      Builtin { .. } => Type::Primitive(I32),
+      Array(_, type_arguments_id) => {
+        let element = self.concrete(self.ctx.type_arguments[type_arguments_id][0], *pos)?;
+        Type::Array(Box::new(element))
+      }
+      Index { array, .. } => match self.type_of(array)? {
+        Type::Array(element) => *element,
+        _ => unreachable!(),
+      },
+      &Struct {
+        type_id,
+        type_arguments_id,
+        ..
+      } => Type::Struct(type_id, self.type_arguments(type_arguments_id, *pos)?),
+      Field { value, field } => self.field(value, field)?.1,
+      Match { arms, .. } => match arms.first() {
+        Some(arm) => self.type_of(&arm.body)?,
+        None => Type::Primitive(Unit),
+      },
+      Cast { type_, .. } => self.concrete(*type_, *pos)?,
+      _ => return Err(unsupported_expression(*pos, expression)),
+    })
+  }
+
+  fn block_type(&self, block: &RastBlock) -> CodegenResult<Type> {
+    match &block.result {
+      Some(result) => self.type_of(result),
+      None => Ok(Type::Primitive(PrimitiveType::Unit)),
+    }
+  }
+
+  // The type of an operand, which is primitive, since no operator applies to
+  // arrays or structs.
+  fn expression_type(&self, expression: &RastExpressionCtx) -> CodegenResult<PrimitiveType> {
+    match self.type_of(expression)? {
+      Type::Primitive(x) => Ok(x),
+      _ => unreachable!(),
+    }
+  }
+
+  // The C name and the type of a field of the struct that a value is.
+  fn field(&self, value: &RastExpressionCtx, field: &str) -> CodegenResult<(String, Type)> {
+    let (type_id, arguments) = match self.type_of(value)? {
+      Type::Struct(type_id, arguments) => (type_id, arguments),
+      _ => unreachable!(),
+    };
+    let def = struct_def(self.ctx, type_id);
+    let index = def.field_index(field).unwrap();
+    let substitution = substitution(&def.type_parameters, &arguments);
+    let type_ = self.substitute(def.fields[index].type_, &substitution, value.0)?;
+    Ok((self.field_name(type_id, index), type_))
+  }
+
+  // Generates the definition of a function, and returns its header. Without
+  // a return type, the function returns the value of its body, whose type is
+  // checked after the body so that the first error in the source is the one
+  // reported.
+  fn body(
+    &mut self,
+    declarator: &str,
+    return_type: Option<Type>,
+    pos: Span,
+    body: &RastBlock,
+  ) -> CodegenResult<String> {
+    self.next_temporary = 0;
+    let start = self.code.len();
+    self.indent += 1;
+    let value = self.block(body)?;
+    let body_type = self.block_type(body)?;
+    let return_type = return_type.unwrap_or_else(|| body_type.clone());
+    // A body that ends in a return statement or in a loop without a break
+    // has the type () whatever the function returns, and never gets here.
+    if body_type == return_type {
+      self.line(&format!("return {};", value));
+    }
+    self.indent -= 1;
+    self.line("}");
+    self.line("");
+
+    let return_type = self.c_type_of(&return_type, pos)?;
+    let header = format!("static {}", declare(&return_type, declarator));
+    self.code.insert_str(start, &format!("{} {{\n", header));
+    Ok(header)
+  }
+
+  // Generates a function with the given C name. A generic function is
+  // generated once for each list of type arguments it's called with.
+  fn function(
+    &mut self,
+    function: &RastFunction,
+    name: &str,
+    arguments: &[Type],
+  ) -> CodegenResult<()> {
+    let ctx = self.ctx;
+    let signature = &ctx.functions[&function.id];
+    self.type_arguments = substitution(&signature.type_parameters, arguments);
+
+    let return_type = self.concrete(signature.return_type, signature.span)?;
+    let mut parameters = Vec::new();
+    for local_id in &signature.parameters {
+      let local = &ctx.locals[local_id];
+      let type_ = self.concrete(local.type_, local.span)?;
+      let type_ = self.c_type_of(&type_, local.span)?;
+      parameters.push(declare(&type_, &self.local_name(*local_id)));
+    }
+    if parameters.is_empty() {
+      parameters.push("void".to_string());
+    }
+
+    let declarator = format!("{}({})", name, parameters.join(", "));
+    let header = self.body(
+      &declarator,
+      Some(return_type),
+      signature.span,
+      &function.body,
+    )?;
+    self.prototypes.push(format!("{};", header));
+    Ok(())
+  }
+
+  // The C function that a call runs. A generic function gets an instance for
+  // each list of type arguments, which is generated after the program.
+  fn callee(
+    &mut self,
+    function_id: FunctionId,
+    type_arguments_id: TypeArgumentsId,
+    pos: Span,
+  ) -> CodegenResult<String> {
+    let name = self.function_name(function_id);
+    if self.ctx.functions[&function_id].type_parameters.is_empty() {
+      return Ok(name);
+    }
+
+    let arguments = self.type_arguments(type_arguments_id, pos)?;
+    if arguments.iter().any(|x| x.depth() > MAX_TYPE_DEPTH) {
+      return Err(unsupported(pos, "polymorphically recursive functions"));
+    }
+    let key = (function_id, arguments);
+    if let Some(instance) = self.instances.get(&key) {
+      return Ok(instance.clone());
+    }
+    let instance = format!("{}_i{}", name, self.instances.len());
+    self
+      .pending
+      .push_back((function_id, key.1.clone(), instance.clone()));
+    self.instances.insert(key, instance.clone());
+    Ok(instance)
+  }
+
+  // Blocks don't need braces of their own, since every local has a name of
+  // its own.
+  fn block(&mut self, block: &RastBlock) -> CodegenResult<String> {
+    for statement in &block.statements {
+      self.statement(statement)?;
+    }
+    match &block.result {
+      Some(result) => self.expression(result),
+      None => Ok("0".to_string()),
+    }
+  }
+
+  // Generates an expression inside of braces, and stores its value in result.
+  fn branch(
+    &mut self,
+    expression: &RastExpressionCtx,
+    result: &Option<String>,
+  ) -> CodegenResult<()> {
+    self.indent += 1;
+    let value = self.expression(expression)?;
+    if let Some(result) = result {
+      self.line(&format!("{} = {};", result, value));
+    }
+    self.indent -= 1;
+    Ok(())
+  }
+
+  fn loop_(
+    &mut self,
+    loop_id: LoopId,
+    result: Option<String>,
+    condition: Option<&RastExpressionCtx>,
+    body: &RastBlock,
+  ) -> CodegenResult<()> {
+    self.line("for (;;) {");
+    self.indent += 1;
+    // A break or a continue in the condition targets the loop around this
+    // one.
+    if let Some(condition) = condition {
+      let condition = self.expression(condition)?;
+      self.line(&format!("if (!{}) break;", condition));
+    }
+
+    self.loops.push(LoopTarget {
+      id: loop_id,
+      result,
+      broken: false,
+      continued: false,
+    });
+    self.block(body)?;
+    let target = self.loops.pop().unwrap();
+    if target.continued {
+      self.line(&format!("continue_{}:;", loop_id.0));
+    }
+    self.indent -= 1;
+    self.line("}");
+    if target.broken {
+      self.line(&format!("break_{}:;", loop_id.0));
+    }
+    Ok(())
+  }
+
+  fn loop_target(&mut self, loop_id: LoopId) -> &mut LoopTarget {
+    self
+      .loops
+      .iter_mut()
+      .rev()
+      .find(|target| target.id == loop_id)
+      .unwrap()
+  }
+
+  // The condition under which a value matches a pattern, or None if it always
+  // does.
+  fn pattern(
+    &self,
+    RastPatternCtx(pos, pattern): &RastPatternCtx,
+    value: &str,
+    type_: &Type,
+  ) -> CodegenResult<Option<String>> {
+    match pattern {
+      RastPattern::Wildcard | RastPattern::Binding(_) => Ok(None),
+      &RastPattern::Integer(x, _) => match type_ {
+        Type::Primitive(type_) => Ok(Some(format!("{} == {}", value, integer(x, *type_)))),
+        _ => unreachable!(),
+      },
+      RastPattern::Boolean(true) => Ok(Some(value.to_string())),
+      RastPattern::Boolean(false) => Ok(Some(format!("!{}", value))),
+      RastPattern::Variant { .. } => Err(unsupported(*pos, "enums")),
+    }
+  }
+
+  // Evaluates `array[index]` down to the array and an index that is within
+  // its bounds.
+  fn place(
+    &mut self,
+    pos: Span,
+    array: &RastExpressionCtx,
+    index: &RastExpressionCtx,
+  ) -> CodegenResult<(String, String)> {
+    let array = self.expression(array)?;
+    let index = self.expression(index)?;
+    let rest = self.index_error(pos);
+    let index = self.temporary(
+      "size_t",
+      &format!("toy_index({}, {}->len, {})", index, array, rest),
+    );
+    Ok((array, index))
+  }
+
+  fn binary_operation(
+    &mut self,
+    pos: Span,
+    op: BinaryOperator,
+    lhs: &RastExpressionCtx,
+    rhs: &RastExpressionCtx,
+  ) -> CodegenResult<String> {
+    use BinaryOperator::*;
+    use PrimitiveType::*;
+
+    let type_ = self.expression_type(lhs)?;
+    let rhs_type = self.expression_type(rhs)?;
+    let lhs = self.expression(lhs)?;
+    let rhs = self.expression(rhs)?;
+    let name = match op {
+      Add => "add",
+      Sub => "sub",
+      Mul => "mul",
+      Div => "div",
+      Rem => "rem",
+      Shl => "shl",
+      _ => "shr",
+    };
+    let overflow = RuntimeError::IntegerOverflow {
+      op: Operator::Binary(op),
+    };
+
+    let (result_type, value) = match op {
+      Add | Sub | Mul if type_.is_integer() => {
+        let overflow = self.runtime_error(pos, overflow);
+        let value = format!("toy_{}_{}({}, {}, {})", name, type_, lhs, rhs, overflow);
+        (type_, value)
+      }
+      Div | Rem if type_.is_integer() => {
+        let by_zero = self.runtime_error(pos, RuntimeError::DivisionByZero { op });
+        let overflow = self.runtime_error(pos, overflow);
+        let value = format!(
+          "toy_{}_{}({}, {}, {}, {})",
+          name, type_, lhs, rhs, by_zero, overflow
+        );
+        (type_, value)
+      }
+      Shl | Shr => {
+        let overflow = self.runtime_error(pos, overflow);
+        let signedness = if rhs_type.is_signed() {
+          "signed"
+        } else {
+          "unsigned"
+        };
+        let value = format!(
+          "toy_{}_{}({}, toy_amount_{}({}), {})",
+          name, type_, lhs, signedness, rhs, overflow
+        );
+        (type_, value)
+      }
+      BitAnd | BitOr | BitXor if type_.is_integer() => {
+        let value = format!("({})({} {} {})", c_type(type_), lhs, op, rhs);
+        (type_, value)
+      }
+      Add if type_ == Str => (Str, format!("toy_str_concat({}, {})", lhs, rhs)),
+      Equals if type_ == Str => (Bool, format!("toy_str_eq({}, {})", lhs, rhs)),
+      NotEquals if type_ == Str => (Bool, format!("!toy_str_eq({}, {})", lhs, rhs)),
+      Rem => (F64, format!("fmod({}, {})", lhs, rhs)),
+      Equals | NotEquals | Less | LessOrEqual | Greater | GreaterOrEqual => {
+        (Bool, format!("{} {} {}", lhs, op, rhs))
+      }
+      // Arithmetic on floats and bitwise operators on bools.
+      _ => (type_, format!("{} {} {}", lhs, op, rhs)),
+    };
+    Ok(self.temporary(c_type(result_type), &value))
+  }
+
+  // Generates the statements that evaluate an expression, and returns a C
+  // expression for its value: a constant, an immutable local or a temporary.
+  fn expression(&mut self, expression_ctx: &RastExpressionCtx) -> CodegenResult<String> {
+    use RastExpression::*;
+
+    let RastExpressionCtx(pos, expression) = expression_ctx;
+    match expression {
+      IntegerConstant(value, literal_id) => match self.ctx.literals[literal_id].type_ {
+        TypeRef::Primitive(type_) => Ok(integer(*value, type_)),
+        _ => unreachable!(),
+      },
+      &FloatConstant(Float(x)) => Ok(float(x)),
+      BooleanConstant(b) => Ok(b.to_string()),
+      StringConstant(s) => Ok(format!(
+        "((toy_str){{{}, {}}})",
+        c_string(s.as_bytes()),
+        s.len()
+      )),
+      &Local(local_id) => {
+        let local = &self.ctx.locals[&local_id];
+        let type_ = self.concrete(local.type_, *pos)?;
+        let name = self.local_name(local_id);
+        // The rest of the expression could assign to a mutable local before
+        // its value is used.
+        if local.is_mutable {
+          let type_ = self.c_type_of(&type_, *pos)?;
+          Ok(self.temporary(&type_, &name))
+        } else {
+          Ok(name)
+        }
+      }
+      UnaryOp(op, x) => {
+        let type_ = self.expression_type(x)?;
+        let x = self.expression(x)?;
+        let value = match (op, type_) {
+          (UnaryOperator::Negate, PrimitiveType::F64) => format!("-{}", x),
+          (UnaryOperator::Negate, _) => {
+            let overflow = self.runtime_error(
+              *pos,
+              RuntimeError::IntegerOverflow {
+                op: Operator::Unary(*op),
+              },
+            );
+            format!("toy_neg_{}({}, {})", type_, x, overflow)
+          }
+          (_, PrimitiveType::Bool) => format!("!{}", x),
+          (_, _) => format!("({})~{}", c_type(type_), x),
+        };
+        Ok(self.temporary(c_type(type_), &value))
+      }
+      BinaryOp(op @ BinaryOperator::And, args) | BinaryOp(op @ BinaryOperator::Or, args) => {
+        let lhs = self.expression(&args.0)?;
+        let result = self.temporary(c_type(PrimitiveType::Bool), &lhs);
+        match op {
+          BinaryOperator::And => self.line(&format!("if ({}) {{", result)),
+          _ => self.line(&format!("if (!{}) {{", result)),
+        }
+        self.branch(&args.1, &Some(result.clone()))?;
+        self.line("}");
+        Ok(result)
+      }
+      BinaryOp(op, args) => self.binary_operation(*pos, *op, &args.0, &args.1),
+      Block(block) => self.block(block),
+      If {
+        condition,
+        then_branch,
+        else_branch,
+      } => {
+        let condition = self.expression(condition)?;
+        let result = self.result(&self.type_of(expression_ctx)?, *pos)?;
+        self.line(&format!("if ({}) {{", condition));
+        self.branch(then_branch, &result)?;
+        if let Some(else_branch) = else_branch {
+          self.line("} else {");
+          self.branch(else_branch, &result)?;
+        }
+        self.line("}");
+        Ok(result.unwrap_or_else(|| "0".to_string()))
+      }
+      Loop { loop_id, body } => {
+        let result = self.result(&self.type_of(expression_ctx)?, *pos)?;
+        self.loop_(*loop_id, result.clone(), None, body)?;
+        Ok(result.unwrap_or_else(|| "0".to_string()))
+      }
+      Call {
+        function_id,
+        type_arguments_id,
+        arguments,
+      } => {
+        let type_ = self.type_of(expression_ctx)?;
+        let callee = self.callee(*function_id, *type_arguments_id, *pos)?;
+        let mut values = Vec::new();
+        for argument in arguments {
+          let type_ = self.type_of(argument)?;
+          values.push((type_, self.expression(argument)?));
+        }
+        // The arguments are copied once they've all been evaluated, like the
+        // interpreter binds them to the parameters.
+        let arguments = values
+          .iter()
+          .map(|(type_, value)| self.copy(type_, value, *pos))
+          .collect::<CodegenResult<Vec<String>>>()?;
+        let call = format!("{}({})", callee, arguments.join(", "));
+        let error = self.runtime_error(*pos, RuntimeError::StackOverflow);
+        self.line(&format!(
+          "if (++toy_depth > {}) toy_fail({});",
+          MAX_CALL_DEPTH, error
+        ));
+        let result = if type_ == Type::Primitive(PrimitiveType::Unit) {
+          self.line(&format!("{};", call));
+          "0".to_string()
+        } else {
+          let type_ = self.c_type_of(&type_, *pos)?;
+          self.temporary(&type_, &call)
+        };
+        self.line("toy_depth--;");
+        Ok(result)
+      }
+      // Like in Rust, the length of a string is in bytes.
+      Builtin { arguments, .. } => {
+        let type_ = self.type_of(&arguments[0])?;
+        let x = self.expression(&arguments[0])?;
+        let len = match type_ {
+          Type::Primitive(_) => format!("(int32_t){}.len", x),
+          _ => format!("(int32_t){}->len", x),
+        };
+        Ok(self.temporary(c_type(PrimitiveType::I32), &len))
+      }
+      Array(elements, _) => {
+        let type_ = self.type_of(expression_ctx)?;
+        let name = self.struct_name(&type_, *pos)?;
+        let elements = elements
+          .iter()
+          .map(|element| self.expression(element))
+          .collect::<CodegenResult<Vec<String>>>()?;
+        let array = self.temporary(
+          &format!("{} *", name),
+          &format!("{}_new({})", name, elements.len()),
+        );
+        for (i, element) in elements.iter().enumerate() {
+          self.line(&format!("{}->data[{}] = {};", array, i, element));
+        }
+        Ok(array)
+      }
+      Index { array, index } => {
+        let type_ = self.type_of(expression_ctx)?;
+        let type_ = self.c_type_of(&type_, *pos)?;
+        let (array, index) = self.place(*pos, array, index)?;
+        Ok(self.temporary(&type_, &format!("{}->data[{}]", array, index)))
+      }
+      &Struct {
+        type_id,
+        ref fields,
+        ..
+      } => {
+        let type_ = self.type_of(expression_ctx)?;
+        let name = self.struct_name(&type_, *pos)?;
+        let mut values = Vec::new();
+        for (index, value) in fields {
+          values.push((self.field_name(type_id, *index), self.expression(value)?));
+        }
+        let x = self.temporary(
+          &format!("{} *", name),
+          &format!("toy_alloc(sizeof({}))", name),
+        );
+        for (field, value) in values {
+          self.line(&format!("{}->{} = {};", x, field, value));
+        }
+        Ok(x)
+      }
+      Field { value, field } => {
+        let (field, type_) = self.field(value, field)?;
+        let type_ = self.c_type_of(&type_, *pos)?;
+        let value = self.expression(value)?;
+        Ok(self.temporary(&type_, &format!("{}->{}", value, field)))
+      }
+      Match { scrutinee, arms } => {
+        let type_ = self.type_of(scrutinee)?;
+        let scrutinee = self.expression(scrutinee)?;
+        let result = self.result(&self.type_of(expression_ctx)?, *pos)?;
+        for (i, arm) in arms.iter().enumerate() {
+          let condition = self.pattern(&arm.pattern, &scrutinee, &type_)?;
+          let other = if i == 0 { "" } else { "} else " };
+          match &condition {
+            Some(condition) => self.line(&format!("{}if ({}) {{", other, condition)),
+            None if i == 0 => self.line("{"),
+            None => self.line("} else {"),
+          }
+          if let RastPattern::Binding(local_id) = arm.pattern.1 {
+            let name = self.local_name(local_id);
+            let c_type = self.c_type_of(&type_, arm.pattern.0)?;
+            let value = self.copy(&type_, &scrutinee, arm.pattern.0)?;
+            self.indent += 1;
+            self.line(&format!("{} = {};", declare(&c_type, &name), value));
+            self.indent -= 1;
+          }
+          self.branch(&arm.body, &result)?;
+          // The arms after one that always matches are never taken.
+          if condition.is_none() {
+            break;
+          }
+        }
+        self.line("}");
+        Ok(result.unwrap_or_else(|| "0".to_string()))
+      }
+      &Cast { ref value, type_ } => {
+        let from = self.expression_type(value)?;
+        let to = match type_ {
+          TypeRef::Primitive(to) => to,
+          _ => unreachable!(),
+        };
+        let value = self.expression(value)?;
+        let value = match (from, to) {
+          (PrimitiveType::F64, PrimitiveType::F64) => value,
+          (PrimitiveType::F64, _) => format!("toy_{}_from_f64({})", to, value),
+          _ => format!("({}){}", c_type(to), value),
+        };
+        Ok(self.temporary(c_type(to), &value))
+      }
+      _ => Err(unsupported_expression(*pos, expression)),
+    }
+  }
+
+  fn statement(
+    &mut self,
+    RastStatementCtx(pos, statement): &RastStatementCtx,
+  ) -> CodegenResult<()> {
+    match statement {
+      RastStatement::DeclareLocal { local_id, value } => {
+        let local = &self.ctx.locals[local_id];
+        let type_ = self.concrete(local.type_, local.span)?;
+        let c_type = self.c_type_of(&type_, local.span)?;
+        let value = self.expression(value)?;
+        let value = self.copy(&type_, &value, *pos)?;
+        let name = self.local_name(*local_id);
+        self.line(&format!("{} = {};", declare(&c_type, &name), value));
+      }
+      RastStatement::AssignLocal { local_id, value } => {
+        let type_ = self.concrete(self.ctx.locals[local_id].type_, *pos)?;
+        let value = self.expression(value)?;
+        let value = self.copy(&type_, &value, *pos)?;
+        let name = self.local_name(*local_id);
+        self.line(&format!("{} = {};", name, value));
+      }
+      RastStatement::AssignIndex {
+        array,
+        index,
+        value,
+      } => {
+        let (array, index) = self.place(*pos, array, index)?;
+        let type_ = self.type_of(value)?;
+        let value = self.expression(value)?;
+        let value = self.copy(&type_, &value, *pos)?;
+        self.line(&format!("{}->data[{}] = {};", array, index, value));
+      }
+      RastStatement::AssignField {
+        target,
+        field,
+        value,
+      } => {
+        let (field, _) = self.field(target, field)?;
+        let target = self.expression(target)?;
+        let type_ = self.type_of(value)?;
+        let value = self.expression(value)?;
+        let value = self.copy(&type_, &value, *pos)?;
+        self.line(&format!("{}->{} = {};", target, field, value));
+      }
+      RastStatement::Expression { value } => {
+        self.expression(value)?;
+      }
+      RastStatement::While {
+        loop_id,
+        condition,
+        body,
+      } => self.loop_(*loop_id, None, Some(condition), body)?,
+      RastStatement::Break { loop_id, value } => {
+        let value = match value {
+          Some(value) => Some(self.expression(value)?),
+          None => None,
+        };
+        let target = self.loop_target(*loop_id);
+        target.broken = true;
+        if let (Some(result), Some(value)) = (target.result.clone(), value) {
+          self.line(&format!("{} = {};", result, value));
+        }
+        self.line(&format!("goto break_{};", loop_id.0));
+      }
+      RastStatement::Continue { loop_id } => {
+        self.loop_target(*loop_id).continued = true;
+        self.line(&format!("goto continue_{};", loop_id.0));
+      }
+      RastStatement::Return { value, .. } => {
+        let value = match value {
+          Some(value) => self.expression(value)?,
+          None => "0".to_string(),
+        };
+        self.line(&format!("return {};", value));
+      }
+    }
+    Ok(())
+  }
+}
+
+// Translates a program into C. The source map is used to render the reports
+// of runtime errors, which the program prints as they are.
+pub fn generate(
+  ctx: &SemanticContext,
+  program: &RastProgram,
+  source_map: &SourceMap,
+) -> CodegenResult<String> {
+  with_stack(PASS_STACK_SIZE, || {
+    let mut generator = Generator {
+      ctx,
+      renderer: Renderer::new(source_map),
+      errors: Vec::new(),
+      types: HashMap::new(),
+      typedefs: Vec::new(),
+      definitions: String::new(),
+      helpers: String::new(),
+      prototypes: Vec::new(),
+      instances: HashMap::new(),
+      pending: VecDeque::new(),
+      type_arguments: HashMap::new(),
+      code: String::new(),
+      indent: 0,
+      next_temporary: 0,
+      loops: Vec::new(),
+    };
+
+    // Generic functions are generated once the calls to them are.
+    for function in &program.functions {
+      if ctx.functions[&function.id].type_parameters.is_empty() {
+        let name = generator.function_name(function.id);
+        generator.function(function, &name, &[])?;
+      }
+    }
+    generator.type_arguments.clear();
+    let pos = program
+      .body
+      .result
+      .as_ref()
+      .map_or(Span::default(), |result| result.0);
+    generator.body("toy_program(void)", None, pos, &program.body)?;
+    let type_ = generator.block_type(&program.body)?;
+    let value_type = generator.c_type_of(&type_, pos)?;
+    let print = generator.print(&type_, "value", pos)?;
+    while let Some((function_id, arguments, name)) = generator.pending.pop_front() {
+      let function = program
+        .functions
+        .iter()
+        .find(|function| function.id == function_id)
+        .unwrap();
+      generator.function(function, &name, &arguments)?;
+    }
+
+    let mut c = String::from(PRELUDE);
+    if !generator.typedefs.is_empty() {
+      c.push('\n');
+      for typedef in &generator.typedefs {
+        writeln!(c, "{}", typedef).unwrap();
+      }
+      c.push_str(&generator.definitions);
+    }
+    if !generator.errors.is_empty() {
+      c.push_str("\nstatic const char *const toy_errors[] = {\n");
+      for error in &generator.errors {
+        writeln!(c, "  {},", error).unwrap();
+      }
+      c.push_str("};\n");
+    }
+    if !generator.prototypes.is_empty() {
+      c.push('\n');
+      for prototype in &generator.prototypes {
+        writeln!(c, "{}", prototype).unwrap();
+      }
+    }
+    c.push('\n');
+    c.push_str(&generator.helpers);
+    c.push_str(&generator.code);
+    c.push_str("int main(void) {\n");
+    if type_ == Type::Primitive(PrimitiveType::Unit) {
+      c.push_str("  toy_program();\n");
+    } else {
+      writeln!(c, "  {} = toy_program();", declare(&value_type, "value")).unwrap();
+    }
+    writeln!(c, "  {}", print).unwrap();
+    c.push_str("  putchar('\\n');\n  return 0;\n}\n");
+    Ok(c)
+  })
+}
+
+#[cfg(test)]
+mod c_tests {
+  use super::*;
+  use crate::parser::Parser;
+  use crate::semantic::transform_program;
+  use crate::token_stream::TokenStream;
+  use crate::type_checker::visit_program;
+
+  fn generate_src(src: &str) -> CodegenResult<String> {
+    let mut token_stream = TokenStream::new(src);
+    let mut parser = Parser::new(&mut token_stream);
+    let (program, errors) = parser.parse_program();
+    assert_eq!(errors, vec![]);
+    let (mut ctx, program) = transform_program(program).unwrap();
+    visit_program(&mut ctx, &program).unwrap();
+    let mut source_map = SourceMap::new();
+    source_map.add_file("test.toy", src);
+    generate(&ctx, &program, &source_map)
+  }
+
+  #[test]
+  fn locals_are_typed_variables() {
+    let c = generate_src("let x = 1u8; let mut y = 2.5; y = y * 2.0; x").unwrap();
+    assert!(c.contains(
+      "static uint8_t toy_program(void) {
+  uint8_t x_0 = (uint8_t)1;
+  double y_1 = 2.5;
+  double t0 = y_1;
+  double t1 = t0 * 2.0;
+  y_1 = t1;
+  return x_0;
+}
+"
+    ));
+  }
+
+  #[test]
+  fn generic_functions_are_instantiated() {
+    let c = generate_src("fn id<T>(x: T) -> T { x } let a = id(1) + id(2); id(a > 2)").unwrap();
+    assert!(c.contains(
+      "static int32_t id_f0_i0(int32_t x_0);
+static bool id_f0_i1(bool x_0);
+"
+    ));
+    assert!(c.contains(
+      "  if (++toy_depth > 10000) toy_fail(toy_errors[0]);
+  int32_t t0 = id_f0_i0((int32_t)1);
+  toy_depth--;
+  if (++toy_depth > 10000) toy_fail(toy_errors[1]);
+  int32_t t1 = id_f0_i0((int32_t)2);
+  toy_depth--;
+"
+    ));
+    assert!(c.contains("  bool t4 = id_f0_i1(t3);\n"));
+  }
+
+  #[test]
+  fn unsupported() {
+    let error = |start, end, what| {
+      Err(CodegenErrorCtx(
+        Span::new(start, end),
+        CodegenError::Unsupported(what),
+      ))
+    };
+    assert_eq!(
+      error(4, 5, "function values"),
+      generate_src("let f = || 1; 1")
+    );
+    assert_eq!(error(0, 4, "closures"), generate_src("|| 1; 1"));
+    assert_eq!(
+      error(20, 21, "enums"),
+      generate_src("enum E { A, B } let e = [E::A]; 1")
+    );
+    assert_eq!(
+      error(54, 67, "polymorphically recursive functions"),
+      generate_src(
+        "fn f<T>(x: T, n: i32) -> i32 { if n == 0 { 0 } else { f([x], n - 1) } } f(1, 3)"
+      )
+    );
+  }
+}
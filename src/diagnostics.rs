@@ -0,0 +1,692 @@
+// Human readable error reports, loosely modeled after rustc's output:
+//
+// error: expected `;`, found end of file
+//  --> <input>:1:10
+//   |
+// 1 | let x = 9
+//   |          ^ expected `;`
+//   |
+
+use std::fmt;
+
+use crate::ast_common::{BinaryOperator, Operator, UnaryOperator};
+use crate::codegen::c::{CodegenError, CodegenErrorCtx};
+use crate::interpreter::{RuntimeError, RuntimeErrorCtx};
+use crate::parser::{ParseError, ParseErrorCtx, MAX_NESTING};
+use crate::semantic::{SemanticError, SemanticErrorCtx};
+use crate::source_map::SourceMap;
+use crate::span::Span;
+use crate::token_stream::{LexerError, LexerErrorCtx};
+use crate::tokens::TokenKind;
+use crate::type_checker::{TypeError, TypeErrorCtx};
+use crate::EvalError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+  Error,
+  Warning,
+}
+
+impl fmt::Display for Level {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Level::Error => write!(f, "error"),
+      Level::Warning => write!(f, "warning"),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+  pub span: Span,
+  pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+  pub level: Level,
+  pub message: String,
+  pub primary: Label,
+  pub secondary: Vec<Label>,
+  pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+  pub fn error(span: Span, message: impl Into<String>) -> Diagnostic {
+    Diagnostic {
+      level: Level::Error,
+      message: message.into(),
+      primary: Label {
+        span,
+        message: None,
+      },
+      secondary: Vec::new(),
+      notes: Vec::new(),
+    }
+  }
+
+  pub fn with_label(mut self, message: impl Into<String>) -> Diagnostic {
+    self.primary.message = Some(message.into());
+    self
+  }
+
+  pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+    self.secondary.push(Label {
+      span,
+      message: Some(message.into()),
+    });
+    self
+  }
+
+  pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+    self.notes.push(note.into());
+    self
+  }
+}
+
+pub trait ToDiagnostic {
+  fn to_diagnostic(&self) -> Diagnostic;
+}
+
+fn describe_expected(expected: &[TokenKind]) -> String {
+  match expected {
+    [] => "something else".to_string(),
+    [single] => single.to_string(),
+    [init @ .., last] => {
+      let init: Vec<String> = init.iter().map(|x| x.to_string()).collect();
+      format!("one of {} or {}", init.join(", "), last)
+    }
+  }
+}
+
+fn lexer_error_message(error: &LexerError) -> String {
+  match error {
+    LexerError::UnknownToken(token) => format!("unknown token `{}`", token),
+    LexerError::InvalidNumber(number) => format!("invalid number literal `{}`", number),
+    LexerError::UnterminatedString => "unterminated string literal".to_string(),
+    LexerError::InvalidEscape(escape) => format!("invalid escape sequence `{}`", escape),
+    LexerError::UnexpectedEof => "unexpected end of file".to_string(),
+  }
+}
+
+impl ToDiagnostic for LexerErrorCtx {
+  fn to_diagnostic(&self) -> Diagnostic {
+    Diagnostic::error(self.0, lexer_error_message(&self.1))
+  }
+}
+
+impl ToDiagnostic for ParseErrorCtx {
+  fn to_diagnostic(&self) -> Diagnostic {
+    let ParseErrorCtx(span, error) = self;
+    match error {
+      ParseError::LexerError(error) => Diagnostic::error(*span, lexer_error_message(error)),
+      ParseError::UnexpectedToken { expected, was } => {
+        let expected = describe_expected(expected);
+        Diagnostic::error(*span, format!("expected {}, found {}", expected, was))
+          .with_label(format!("expected {}", expected))
+      }
+      ParseError::InvalidAssignmentTarget => {
+        Diagnostic::error(*span, "invalid left-hand side of assignment")
+          .with_label("cannot assign to this expression")
+      }
+      ParseError::NestedTooDeeply => Diagnostic::error(
+        *span,
+        format!("syntax is nested more than {} levels deep", MAX_NESTING),
+      ),
+    }
+  }
+}
+
+impl ToDiagnostic for SemanticErrorCtx {
+  fn to_diagnostic(&self) -> Diagnostic {
+    let SemanticErrorCtx(span, error) = self;
+    match error {
+      SemanticError::UnknownType { name } => {
+        Diagnostic::error(*span, format!("cannot find type `{}` in this scope", name))
+          .with_label("not found in this scope")
+      }
+      SemanticError::UnknownLocal { name } => {
+        Diagnostic::error(*span, format!("cannot find value `{}` in this scope", name))
+          .with_label("not found in this scope")
+      }
+      SemanticError::AssignToImmutable { name, declaration } => Diagnostic::error(
+        *span,
+        format!("cannot assign twice to immutable variable `{}`", name),
+      )
+      .with_label("cannot assign twice to immutable variable")
+      .with_secondary(*declaration, format!("first assignment to `{}`", name))
+      .with_note(format!(
+        "consider making this binding mutable: `mut {}`",
+        name
+      )),
+      SemanticError::BreakOutsideLoop => Diagnostic::error(*span, "`break` outside of a loop")
+        .with_label("cannot `break` outside of a loop"),
+      SemanticError::ContinueOutsideLoop => {
+        Diagnostic::error(*span, "`continue` outside of a loop")
+          .with_label("cannot `continue` outside of a loop")
+      }
+      SemanticError::ReturnOutsideFunction => {
+        Diagnostic::error(*span, "`return` outside of a function")
+          .with_label("cannot `return` outside of a function")
+      }
+      SemanticError::UnknownFunction { name } => Diagnostic::error(
+        *span,
+        format!("cannot find function `{}` in this scope", name),
+      )
+      .with_label("not found in this scope"),
+      SemanticError::DuplicateFunction { name, previous } => Diagnostic::error(
+        *span,
+        format!("the name `{}` is defined multiple times", name),
+      )
+      .with_label(format!("`{}` redefined here", name))
+      .with_secondary(*previous, format!("previous definition of `{}` here", name)),
+      SemanticError::DuplicateType { name, previous } => Diagnostic::error(
+        *span,
+        format!("the name `{}` is defined multiple times", name),
+      )
+      .with_label(format!("`{}` redefined here", name))
+      .with_secondary(*previous, format!("previous definition of `{}` here", name)),
+      SemanticError::DuplicateField { name, previous } => {
+        Diagnostic::error(*span, format!("field `{}` is already declared", name))
+          .with_label("field already declared")
+          .with_secondary(*previous, format!("`{}` first declared here", name))
+      }
+      SemanticError::UnknownField { struct_name, field } => Diagnostic::error(
+        *span,
+        format!("struct `{}` has no field named `{}`", struct_name, field),
+      )
+      .with_label("unknown field"),
+      SemanticError::DuplicateVariant { name, previous } => {
+        Diagnostic::error(*span, format!("variant `{}` is already declared", name))
+          .with_label("variant already declared")
+          .with_secondary(*previous, format!("`{}` first declared here", name))
+      }
+      SemanticError::UnknownVariant { enum_name, variant } => Diagnostic::error(
+        *span,
+        format!(
+          "no variant named `{}` found for enum `{}`",
+          variant, enum_name
+        ),
+      )
+      .with_label("variant not found"),
+      SemanticError::DuplicateBinding { name, previous } => Diagnostic::error(
+        *span,
+        format!(
+          "identifier `{}` is bound more than once in the same pattern",
+          name
+        ),
+      )
+      .with_label("used in a pattern more than once")
+      .with_secondary(*previous, format!("first binding of `{}`", name)),
+      SemanticError::DuplicateTypeParameter { name, previous } => Diagnostic::error(
+        *span,
+        format!(
+          "the name `{}` is already used for a generic parameter",
+          name
+        ),
+      )
+      .with_label("already used")
+      .with_secondary(*previous, format!("first use of `{}`", name)),
+      SemanticError::WrongTypeArgumentCount {
+        name,
+        expected,
+        actual,
+      } => Diagnostic::error(
+        *span,
+        format!(
+          "type `{}` takes {} type argument{} but {} {} supplied",
+          name,
+          expected,
+          if *expected == 1 { "" } else { "s" },
+          actual,
+          if *actual == 1 { "was" } else { "were" }
+        ),
+      )
+      .with_label(format!(
+        "expected {} type argument{}",
+        expected,
+        if *expected == 1 { "" } else { "s" }
+      )),
+      SemanticError::MissingFields {
+        struct_name,
+        fields,
+      } => {
+        let fields: Vec<String> = fields.iter().map(|x| format!("`{}`", x)).collect();
+        Diagnostic::error(
+          *span,
+          format!(
+            "missing field{} {} in initializer of `{}`",
+            if fields.len() == 1 { "" } else { "s" },
+            fields.join(", "),
+            struct_name
+          ),
+        )
+        .with_label("missing fields")
+      }
+    }
+  }
+}
+
+impl ToDiagnostic for TypeErrorCtx {
+  fn to_diagnostic(&self) -> Diagnostic {
+    let TypeErrorCtx(span, error) = self;
+    match error {
+      TypeError::NotAssignable { target, x } => Diagnostic::error(*span, "mismatched types")
+        .with_label(format!("expected `{}`, found `{}`", target, x)),
+      TypeError::InvalidUnaryOpArg { op, x } => Diagnostic::error(
+        *span,
+        format!("cannot apply unary operator `{}` to type `{}`", op, x),
+      ),
+      TypeError::InvalidBinaryOpArgs { op, lhs, rhs } => Diagnostic::error(
+        *span,
+        format!(
+          "cannot apply binary operator `{}` to types `{}` and `{}`",
+          op, lhs, rhs
+        ),
+      ),
+      TypeError::UntypedLocal { .. } => Diagnostic::error(*span, "type annotations needed")
+        .with_label("cannot infer the type of this local"),
+      TypeError::InvalidCondition { x } => Diagnostic::error(*span, "mismatched types")
+        .with_label(format!("expected `bool`, found `{}`", x)),
+      TypeError::MismatchedBranches {
+        then_type,
+        else_type,
+      } => Diagnostic::error(*span, "`if` and `else` have incompatible types")
+        .with_label(format!("expected `{}`, found `{}`", then_type, else_type)),
+      TypeError::BreakWithValueFromWhile => {
+        Diagnostic::error(*span, "`break` with value from a `while` loop")
+          .with_label("can only break with a value inside `loop`")
+      }
+      TypeError::MismatchedBreak { expected, x } => Diagnostic::error(*span, "mismatched types")
+        .with_label(format!("expected `{}`, found `{}`", expected, x))
+        .with_note("every `break` of a loop must have a value of the same type"),
+      TypeError::WrongArgumentCount { expected, actual } => Diagnostic::error(
+        *span,
+        format!(
+          "this function takes {} argument{} but {} {} supplied",
+          expected,
+          if *expected == 1 { "" } else { "s" },
+          actual,
+          if *actual == 1 { "was" } else { "were" }
+        ),
+      ),
+      TypeError::MismatchedReturn { expected, x } => Diagnostic::error(*span, "mismatched types")
+        .with_label(format!("expected `{}`, found `{}`", expected, x))
+        .with_note("the value must match the return type of the function"),
+      TypeError::NotCallable { x } => Diagnostic::error(*span, "expected function")
+        .with_label(format!("expected function, found `{}`", x)),
+      TypeError::CannotInfer => Diagnostic::error(*span, "type annotations needed")
+        .with_label("type must be known at this point"),
+      TypeError::EmptyArray => Diagnostic::error(*span, "type annotations needed")
+        .with_label("cannot infer the element type of an empty array"),
+      TypeError::ExpectedArray { x } => Diagnostic::error(*span, "mismatched types")
+        .with_label(format!("expected array, found `{}`", x)),
+      TypeError::InvalidIndex { x } => Diagnostic::error(*span, "mismatched types")
+        .with_label(format!("expected `i32` index, found `{}`", x)),
+      TypeError::NoSuchField { x, field } => {
+        Diagnostic::error(*span, format!("no field `{}` on type `{}`", field, x))
+          .with_label("unknown field")
+      }
+      TypeError::MismatchedPattern { expected, x } => Diagnostic::error(*span, "mismatched types")
+        .with_label(format!("expected `{}`, found `{}`", expected, x))
+        .with_note("the pattern must match the type of the scrutinee"),
+      TypeError::WrongFieldCount { expected, actual } => Diagnostic::error(
+        *span,
+        format!(
+          "this pattern has {} field{}, but the variant has {}",
+          actual,
+          if *actual == 1 { "" } else { "s" },
+          expected
+        ),
+      ),
+      TypeError::MismatchedArms { expected, x } => {
+        Diagnostic::error(*span, "`match` arms have incompatible types")
+          .with_label(format!("expected `{}`, found `{}`", expected, x))
+      }
+      TypeError::NonExhaustive { missing } => Diagnostic::error(
+        *span,
+        format!("non-exhaustive patterns: `{}` not covered", missing),
+      )
+      .with_label(format!("pattern `{}` not covered", missing)),
+      TypeError::UnreachablePattern => {
+        Diagnostic::error(*span, "unreachable pattern").with_label("unreachable pattern")
+      }
+      TypeError::LiteralOutOfRange { value, x } => {
+        let diagnostic = Diagnostic::error(*span, format!("literal out of range for `{}`", x));
+        match x.integer_range() {
+          Some((min, max)) => diagnostic.with_label(format!(
+            "the literal `{}` does not fit into the type `{}` whose range is `{}..={}`",
+            value, x, min, max
+          )),
+          None => diagnostic,
+        }
+      }
+      TypeError::InvalidCast { x, target } => {
+        Diagnostic::error(*span, format!("casting `{}` as `{}` is invalid", x, target))
+      }
+      TypeError::MissingElse { then_type } => {
+        Diagnostic::error(*span, "`if` may be missing an `else` clause")
+          .with_label(format!("expected `()`, found `{}`", then_type))
+      }
+    }
+  }
+}
+
+impl ToDiagnostic for RuntimeErrorCtx {
+  fn to_diagnostic(&self) -> Diagnostic {
+    let RuntimeErrorCtx(span, error) = self;
+    match error {
+      RuntimeError::IndexOutOfBounds { index, len } => Diagnostic::error(
+        *span,
+        format!(
+          "index out of bounds: the len is {} but the index is {}",
+          len, index
+        ),
+      ),
+      RuntimeError::IntegerOverflow { op } => {
+        let operation = match op {
+          Operator::Binary(BinaryOperator::Add) => "add",
+          Operator::Binary(BinaryOperator::Sub) => "subtract",
+          Operator::Binary(BinaryOperator::Mul) => "multiply",
+          Operator::Binary(BinaryOperator::Div) => "divide",
+          Operator::Binary(BinaryOperator::Rem) => "calculate the remainder",
+          Operator::Binary(BinaryOperator::Shl) => "shift left",
+          Operator::Binary(BinaryOperator::Shr) => "shift right",
+          Operator::Unary(UnaryOperator::Negate) => "negate",
+          _ => unreachable!(),
+        };
+        Diagnostic::error(*span, format!("attempt to {} with overflow", operation))
+      }
+      RuntimeError::DivisionByZero { op } => Diagnostic::error(
+        *span,
+        match op {
+          BinaryOperator::Rem => "attempt to calculate the remainder with a divisor of zero",
+          _ => "attempt to divide by zero",
+        },
+      ),
+      RuntimeError::StackOverflow => {
+        Diagnostic::error(*span, "stack overflow: calls are nested too deeply")
+      }
+    }
+  }
+}
+
+impl ToDiagnostic for CodegenErrorCtx {
+  fn to_diagnostic(&self) -> Diagnostic {
+    let CodegenErrorCtx(span, error) = self;
+    match error {
+      CodegenError::Unsupported(what) => Diagnostic::error(
+        *span,
+        format!("{} are not supported by the C backend", what),
+      ),
+    }
+  }
+}
+
+struct Style {
+  color: bool,
+}
+
+impl Style {
+  fn paint(&self, code: &str, text: &str) -> String {
+    if self.color {
+      format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+      text.to_string()
+    }
+  }
+
+  fn level(&self, level: Level, text: &str) -> String {
+    match level {
+      Level::Error => self.paint("1;31", text),
+      Level::Warning => self.paint("1;33", text),
+    }
+  }
+
+  fn gutter(&self, text: &str) -> String {
+    self.paint("1;34", text)
+  }
+
+  fn bold(&self, text: &str) -> String {
+    self.paint("1", text)
+  }
+}
+
+pub struct Renderer<'a> {
+  source_map: &'a SourceMap,
+  style: Style,
+}
+
+impl<'a> Renderer<'a> {
+  pub fn new(source_map: &'a SourceMap) -> Renderer<'a> {
+    Renderer {
+      source_map,
+      style: Style { color: false },
+    }
+  }
+
+  pub fn with_color(mut self, color: bool) -> Renderer<'a> {
+    self.style.color = color;
+    self
+  }
+
+  pub fn render(&self, diagnostic: &Diagnostic) -> String {
+    let style = &self.style;
+    let mut out = format!(
+      "{}{}\n",
+      style.level(diagnostic.level, &diagnostic.level.to_string()),
+      style.bold(&format!(": {}", diagnostic.message))
+    );
+
+    // Labels are drawn line by line, in source order. Each label gets its own
+    // marker row so that overlapping labels stay readable.
+    let mut labels: Vec<(&Label, bool)> = std::iter::once((&diagnostic.primary, true))
+      .chain(diagnostic.secondary.iter().map(|label| (label, false)))
+      .filter_map(|(label, is_primary)| {
+        self.source_map.lookup(label.span.start)?;
+        Some((label, is_primary))
+      })
+      .collect();
+    labels.sort_by_key(|(label, _)| label.span.start);
+
+    let max_line = labels
+      .iter()
+      .filter_map(|(label, _)| self.source_map.lookup(label.span.start))
+      .map(|location| location.line)
+      .max()
+      .unwrap_or(0);
+    let gutter_width = max_line.to_string().len();
+    let empty_gutter = style.gutter(&format!("{} |", " ".repeat(gutter_width)));
+
+    if let Some(location) = self.source_map.lookup(diagnostic.primary.span.start) {
+      out += &format!(
+        "{}{} {}:{}:{}\n",
+        " ".repeat(gutter_width),
+        style.gutter("-->"),
+        location.file,
+        location.line,
+        location.column
+      );
+      out += &format!("{}\n", empty_gutter);
+    }
+
+    let mut previous_line = None;
+    for (label, is_primary) in labels {
+      let file = self.source_map.file_of(label.span.start).unwrap();
+      let start = self.source_map.lookup(label.span.start).unwrap();
+      let line_text = file.line_text(start.line).unwrap_or("");
+
+      if previous_line != Some((file.start, start.line)) {
+        if let Some((_, line)) = previous_line {
+          if start.line > line + 1 {
+            out += &format!("{}\n", style.gutter("..."));
+          }
+        }
+        out += &format!(
+          "{} {}\n",
+          style.gutter(&format!("{:>width$} |", start.line, width = gutter_width)),
+          line_text
+        );
+        previous_line = Some((file.start, start.line));
+      }
+
+      // Spans that continue past the end of the line are underlined up to the
+      // end of the line, and empty spans still get a single marker.
+      let line_length = line_text.chars().count();
+      let width = match self.source_map.lookup(label.span.end) {
+        Some(end) if end.line == start.line => end.column - start.column,
+        _ => (line_length + 1).saturating_sub(start.column),
+      }
+      .max(1);
+
+      let (marker, paint): (&str, fn(&Style, &str) -> String) = if is_primary {
+        ("^", |style, text| style.level(Level::Error, text))
+      } else {
+        ("-", Style::gutter)
+      };
+      let mut underline = marker.repeat(width);
+      if let Some(message) = &label.message {
+        underline += " ";
+        underline += message;
+      }
+
+      out += &format!(
+        "{} {}{}\n",
+        empty_gutter,
+        " ".repeat(start.column - 1),
+        paint(style, &underline)
+      );
+    }
+
+    if !diagnostic.notes.is_empty() {
+      out += &format!("{}\n", empty_gutter);
+      for note in &diagnostic.notes {
+        out += &format!(
+          "{} {} {}\n",
+          " ".repeat(gutter_width),
+          style.gutter("="),
+          style.bold(&format!("note: {}", note))
+        );
+      }
+    }
+
+    out
+  }
+}
+
+// An EvalError paired with the sources it refers to, ready to be printed.
+pub struct Report<'a> {
+  renderer: Renderer<'a>,
+  error: &'a EvalError,
+}
+
+impl<'a> Report<'a> {
+  pub fn new(source_map: &'a SourceMap, error: &'a EvalError) -> Report<'a> {
+    Report {
+      renderer: Renderer::new(source_map),
+      error,
+    }
+  }
+
+  pub fn with_color(mut self, color: bool) -> Report<'a> {
+    self.renderer = self.renderer.with_color(color);
+    self
+  }
+}
+
+impl<'a> fmt::Display for Report<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let rendered: Vec<String> = self
+      .error
+      .to_diagnostics()
+      .iter()
+      .map(|x| self.renderer.render(x))
+      .collect();
+    write!(f, "{}", rendered.join("\n"))
+  }
+}
+
+impl<'a> fmt::Debug for Report<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fmt::Display::fmt(self, f)
+  }
+}
+
+impl<'a> std::error::Error for Report<'a> {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    None
+  }
+}
+
+#[cfg(test)]
+mod diagnostics_tests {
+  use super::*;
+
+  fn render(src: &str, diagnostic: &Diagnostic) -> String {
+    let mut source_map = SourceMap::new();
+    source_map.add_file("test.toy", src);
+    Renderer::new(&source_map).render(diagnostic)
+  }
+
+  #[test]
+  fn render_single_label() {
+    let diagnostic = Diagnostic::error(Span::new(8, 9), "cannot find value `y` in this scope")
+      .with_label("not found in this scope");
+
+    assert_eq!(
+      "error: cannot find value `y` in this scope
+ --> test.toy:1:9
+  |
+1 | let x = y;
+  |         ^ not found in this scope
+",
+      render("let x = y;", &diagnostic)
+    );
+  }
+
+  #[test]
+  fn render_secondary_label_and_note() {
+    let diagnostic = Diagnostic::error(Span::new(11, 16), "mismatched types")
+      .with_secondary(Span::new(4, 5), "declared here")
+      .with_note("this is a note");
+
+    assert_eq!(
+      "error: mismatched types
+ --> test.toy:2:1
+  |
+1 | let x = 1;
+  |     - declared here
+2 | x = 2;
+  | ^^^^^
+  |
+  = note: this is a note
+",
+      render("let x = 1;\nx = 2;", &diagnostic)
+    );
+  }
+
+  #[test]
+  fn render_eof() {
+    let diagnostic = Diagnostic::error(Span::point(9), "expected `;`, found end of file");
+    assert_eq!(
+      "error: expected `;`, found end of file
+ --> test.toy:1:10
+  |
+1 | let x = 9
+  |          ^
+",
+      render("let x = 9", &diagnostic)
+    );
+  }
+
+  #[test]
+  fn render_with_color() {
+    let mut source_map = SourceMap::new();
+    source_map.add_file("test.toy", "x");
+    let diagnostic = Diagnostic::error(Span::new(0, 1), "oops");
+    let rendered = Renderer::new(&source_map)
+      .with_color(true)
+      .render(&diagnostic);
+    assert!(rendered.starts_with("\x1b[1;31merror\x1b[0m"));
+  }
+}
@@ -1,78 +1,756 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
 
-use crate::ast_common::{BinaryOperator, UnaryOperator};
-use crate::rast::{LocalId, RastExpression, RastProgram, RastStatement};
+use crate::ast_common::{BinaryOperator, Operator, UnaryOperator};
+use crate::rast::{
+  CaptureMode, FunctionId, LocalId, PrimitiveType, RastBlock, RastExpression, RastExpressionCtx,
+  RastFunction, RastPattern, RastPatternCtx, RastProgram, RastStatement, RastStatementCtx, TypeRef,
+  UserTypeDef, UserTypeId,
+};
 use crate::semantic::SemanticContext;
+use crate::span::Span;
+use crate::utils::with_stack;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+// Not Eq, since f64 isn't.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
+  I8(i8),
+  I16(i16),
   I32(i32),
+  I64(i64),
+  U8(u8),
+  U16(u16),
+  U32(u32),
+  U64(u64),
+  F64(f64),
   Bool(bool),
+  Str(Rc<str>),
+  Unit,
+  Function(FunctionId),
+  Closure(Rc<Closure>),
+  // Arrays and structs are shared while they are passed around, and copied
+  // when they are bound to a local or stored somewhere, so that changing one
+  // never changes another.
+  Array(Rc<RefCell<Vec<Value>>>),
+  // The fields are in declaration order.
+  Struct(UserTypeId, Rc<RefCell<Vec<Value>>>),
+  // A variant of an enum, by index, and its fields. They can't be assigned to.
+  Enum(UserTypeId, usize, Rc<Vec<Value>>),
 }
 
-pub struct Interpreter {
+impl Value {
+  // An integer of the given type, if the value is within its range.
+  pub fn from_integer(type_: PrimitiveType, x: i128) -> Option<Value> {
+    use std::convert::TryFrom;
+    match type_ {
+      PrimitiveType::I8 => i8::try_from(x).ok().map(Value::I8),
+      PrimitiveType::I16 => i16::try_from(x).ok().map(Value::I16),
+      PrimitiveType::I32 => i32::try_from(x).ok().map(Value::I32),
+      PrimitiveType::I64 => i64::try_from(x).ok().map(Value::I64),
+      PrimitiveType::U8 => u8::try_from(x).ok().map(Value::U8),
+      PrimitiveType::U16 => u16::try_from(x).ok().map(Value::U16),
+      PrimitiveType::U32 => u32::try_from(x).ok().map(Value::U32),
+      PrimitiveType::U64 => u64::try_from(x).ok().map(Value::U64),
+      PrimitiveType::F64 | PrimitiveType::Bool | PrimitiveType::Str | PrimitiveType::Unit => None,
+    }
+  }
+
+  // An integer of the given type, with the bits of x that don't fit into it
+  // discarded, like two's complement arithmetic would.
+  pub fn wrap_integer(type_: PrimitiveType, x: i128) -> Value {
+    let (min, max) = type_.integer_range().unwrap();
+    let wrapped = x.wrapping_sub(min).rem_euclid(max - min + 1) + min;
+    Value::from_integer(type_, wrapped).unwrap()
+  }
+
+  // A copy that shares no arrays or structs with this value, for binding it
+  // to a local or storing it in an array or a struct. Variants are copied too,
+  // since their fields can be either.
+  pub fn deep_copy(self) -> Value {
+    match self {
+      Value::Array(elements) => {
+        let elements = elements
+          .borrow()
+          .iter()
+          .cloned()
+          .map(Value::deep_copy)
+          .collect();
+        Value::Array(Rc::new(RefCell::new(elements)))
+      }
+      Value::Struct(type_id, fields) => {
+        let fields = fields
+          .borrow()
+          .iter()
+          .cloned()
+          .map(Value::deep_copy)
+          .collect();
+        Value::Struct(type_id, Rc::new(RefCell::new(fields)))
+      }
+      Value::Enum(type_id, variant, fields) => {
+        let fields = fields.iter().cloned().map(Value::deep_copy).collect();
+        Value::Enum(type_id, variant, Rc::new(fields))
+      }
+      value => value,
+    }
+  }
+
+  // The type and the value of an integer. Every integer type fits in an i128.
+  pub fn as_integer(&self) -> Option<(PrimitiveType, i128)> {
+    match *self {
+      Value::I8(x) => Some((PrimitiveType::I8, x.into())),
+      Value::I16(x) => Some((PrimitiveType::I16, x.into())),
+      Value::I32(x) => Some((PrimitiveType::I32, x.into())),
+      Value::I64(x) => Some((PrimitiveType::I64, x.into())),
+      Value::U8(x) => Some((PrimitiveType::U8, x.into())),
+      Value::U16(x) => Some((PrimitiveType::U16, x.into())),
+      Value::U32(x) => Some((PrimitiveType::U32, x.into())),
+      Value::U64(x) => Some((PrimitiveType::U64, x.into())),
+      _ => None,
+    }
+  }
+}
+
+// A variable. Captured mutable locals share their cell with the closure.
+pub(crate) type Cell = Rc<RefCell<Value>>;
+
+// The captures are in the order of the captures of the function.
+pub struct Closure {
+  pub(crate) function_id: FunctionId,
+  pub(crate) captures: Vec<(LocalId, Cell)>,
+}
+
+impl fmt::Debug for Closure {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let captures: Vec<LocalId> = self.captures.iter().map(|(id, _)| *id).collect();
+    f.debug_struct("Closure")
+      .field("function", &self.function_id)
+      .field("captures", &captures)
+      .finish()
+  }
+}
+
+// Closures are only equal to themselves, like references.
+impl PartialEq for Closure {
+  fn eq(&self, other: &Closure) -> bool {
+    std::ptr::eq(self, other)
+  }
+}
+
+impl Eq for Closure {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+  IndexOutOfBounds { index: i32, len: usize },
+  // The result of an arithmetic operation doesn't fit in its type.
+  IntegerOverflow { op: Operator },
+  // The divisor of `/` or `%` is zero.
+  DivisionByZero { op: BinaryOperator },
+  // More than MAX_CALL_DEPTH calls are in progress, or they have used up the
+  // stack that the program runs on.
+  StackOverflow,
+}
+
+// The deepest that calls can nest, so that runaway recursion is reported
+// instead of running until the host runs out of memory.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+// Evaluation recurses on the stack of the host for every nested expression, so
+// programs run on a thread of their own with a stack of this size, whatever
+// the stack of the caller is. Calls report a stack overflow when less than
+// STACK_RESERVE of it is left, which is enough for any single function body
+// that the parser accepts.
+const STACK_SIZE: usize = 512 << 20;
+const STACK_RESERVE: usize = 16 << 20;
+
+// How far down the stack the caller is, as the address of one of its locals.
+#[inline(never)]
+fn stack_position() -> usize {
+  let marker = 0u8;
+  std::hint::black_box(&marker) as *const u8 as usize
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeErrorCtx(pub Span, pub RuntimeError);
+
+pub type RuntimeResult<T> = Result<T, RuntimeErrorCtx>;
+
+// Unwinds evaluation up to the innermost loop, or with Return, up to the
+// innermost call. There are no labeled loops, so the innermost loop is always
+// the target. Errors unwind the whole program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlFlow {
+  Break(Value),
+  Continue,
+  Return(Value),
+  Error(RuntimeErrorCtx),
+}
+
+pub type Flow<T> = Result<T, ControlFlow>;
+
+// The locals of a single function call, and how many calls deep it is. The
+// program itself runs in the first frame, at depth 0.
+#[derive(Debug, Default)]
+pub struct Frame {
+  pub locals: HashMap<LocalId, Cell>,
+  pub depth: usize,
+}
+
+// Checks whether a value matches a pattern, and collects the values of its
+// bindings.
+fn match_pattern(
+  RastPatternCtx(_, pattern): &RastPatternCtx,
+  value: &Value,
+  bindings: &mut Vec<(LocalId, Value)>,
+) -> bool {
+  match (pattern, value) {
+    (RastPattern::Wildcard, _) => true,
+    (RastPattern::Binding(local_id), _) => {
+      bindings.push((*local_id, value.clone()));
+      true
+    }
+    (RastPattern::Integer(i, _), _) => value.as_integer().map(|(_, x)| x) == Some(*i),
+    (RastPattern::Boolean(b), Value::Bool(x)) => b == x,
+    (
+      RastPattern::Variant {
+        variant, fields, ..
+      },
+      Value::Enum(_, x, values),
+    ) => {
+      variant == x
+        && fields
+          .iter()
+          .zip(values.iter())
+          .all(|(field, value)| match_pattern(field, value, bindings))
+    }
+    _ => unreachable!(),
+  }
+}
+
+// Casts never fail. Integers are truncated like two's complement, and floats
+// are rounded towards zero and saturate at the bounds of the integer type, with
+// NaN becoming 0.
+pub(crate) fn cast(value: Value, to: PrimitiveType) -> Value {
+  match (value, to) {
+    (Value::F64(x), PrimitiveType::F64) => Value::F64(x),
+    (Value::F64(x), _) => {
+      let (min, max) = to.integer_range().unwrap();
+      Value::from_integer(to, (x as i128).max(min).min(max)).unwrap()
+    }
+    (Value::Bool(b), _) => Value::from_integer(to, b.into()).unwrap(),
+    (value, PrimitiveType::F64) => Value::F64(value.as_integer().unwrap().1 as f64),
+    (value, _) => Value::wrap_integer(to, value.as_integer().unwrap().1),
+  }
+}
+
+fn overflow(op: Operator) -> RuntimeError {
+  RuntimeError::IntegerOverflow { op }
+}
+
+// Applies a unary operator to a value of a type that it accepts.
+pub(crate) fn unary_operation(op: UnaryOperator, x: Value) -> Result<Value, RuntimeError> {
+  use UnaryOperator::*;
+
+  Ok(match (op, x) {
+    (Negate, Value::F64(x)) => Value::F64(-x),
+    (Negate, x) => {
+      let (type_, x) = x.as_integer().unwrap();
+      Value::from_integer(type_, -x).ok_or_else(|| overflow(Operator::Unary(Negate)))?
+    }
+    (Not, Value::Bool(b)) | (BitNot, Value::Bool(b)) => Value::Bool(!b),
+    (Not, x) | (BitNot, x) => {
+      let (type_, x) = x.as_integer().unwrap();
+      Value::wrap_integer(type_, !x)
+    }
+  })
+}
+
+// Applies a binary operator other than && and ||, which only evaluate their
+// right hand side when needed, to values of types that it accepts.
+pub(crate) fn binary_operation(
+  op: BinaryOperator,
+  lhs: Value,
+  rhs: Value,
+) -> Result<Value, RuntimeError> {
+  use BinaryOperator::*;
+  use Value::*;
+
+  Ok(match (lhs.as_integer(), op, rhs.as_integer()) {
+    // Both sides have the same type. The result is computed as an i128, and
+    // it's an error if it doesn't fit in that type.
+    (Some((type_, a)), Add, Some((_, b)))
+    | (Some((type_, a)), Sub, Some((_, b)))
+    | (Some((type_, a)), Mul, Some((_, b))) => {
+      let result = match op {
+        Add => a.checked_add(b),
+        Sub => a.checked_sub(b),
+        _ => a.checked_mul(b),
+      };
+      result
+        .and_then(|x| Value::from_integer(type_, x))
+        .ok_or_else(|| overflow(Operator::Binary(op)))?
+    }
+    (Some((_, _)), Div, Some((_, 0))) | (Some((_, _)), Rem, Some((_, 0))) => {
+      return Err(RuntimeError::DivisionByZero { op });
+    }
+    (Some((type_, a)), Div, Some((_, b))) | (Some((type_, a)), Rem, Some((_, b))) => {
+      // As in Rust, the remainder overflows whenever the quotient does, as
+      // for `-128i8 % -1`.
+      let quotient =
+        Value::from_integer(type_, a / b).ok_or_else(|| overflow(Operator::Binary(op)))?;
+      match op {
+        Div => quotient,
+        _ => Value::from_integer(type_, a % b).unwrap(),
+      }
+    }
+    (Some((type_, a)), BitAnd, Some((_, b))) => Value::from_integer(type_, a & b).unwrap(),
+    (Some((type_, a)), BitOr, Some((_, b))) => Value::from_integer(type_, a | b).unwrap(),
+    (Some((type_, a)), BitXor, Some((_, b))) => Value::from_integer(type_, a ^ b).unwrap(),
+    // Bits shifted out are discarded, but shifting by the width of the type or
+    // more is an overflow.
+    (Some((type_, a)), Shl, Some((_, b))) | (Some((type_, a)), Shr, Some((_, b))) => {
+      let (min, max) = type_.integer_range().unwrap();
+      let bits = (max - min + 1).trailing_zeros();
+      if b < 0 || b >= bits.into() {
+        return Err(overflow(Operator::Binary(op)));
+      }
+      match op {
+        Shl => Value::wrap_integer(type_, a << b),
+        _ => Value::from_integer(type_, a >> b).unwrap(),
+      }
+    }
+    (Some((_, a)), Less, Some((_, b))) => Bool(a < b),
+    (Some((_, a)), LessOrEqual, Some((_, b))) => Bool(a <= b),
+    (Some((_, a)), Greater, Some((_, b))) => Bool(a > b),
+    (Some((_, a)), GreaterOrEqual, Some((_, b))) => Bool(a >= b),
+    _ => match (lhs, op, rhs) {
+      (F64(a), Add, F64(b)) => F64(a + b),
+      (F64(a), Sub, F64(b)) => F64(a - b),
+      (F64(a), Mul, F64(b)) => F64(a * b),
+      (F64(a), Div, F64(b)) => F64(a / b),
+      (F64(a), Rem, F64(b)) => F64(a % b),
+      (F64(a), Less, F64(b)) => Bool(a < b),
+      (F64(a), LessOrEqual, F64(b)) => Bool(a <= b),
+      (F64(a), Greater, F64(b)) => Bool(a > b),
+      (F64(a), GreaterOrEqual, F64(b)) => Bool(a >= b),
+      (Str(a), Add, Str(b)) => Str([&*a, &*b].concat().into()),
+      (Bool(a), BitAnd, Bool(b)) => Bool(a & b),
+      (Bool(a), BitOr, Bool(b)) => Bool(a | b),
+      (Bool(a), BitXor, Bool(b)) => Bool(a ^ b),
+      (a, Equals, b) => Bool(a == b),
+      (a, NotEquals, b) => Bool(a != b),
+      _ => unreachable!(),
+    },
+  })
+}
+
+pub struct Interpreter<'a> {
   semantic_ctx: SemanticContext,
-  pub locals: HashMap<LocalId, Value>,
+  functions: HashMap<FunctionId, &'a RastFunction>,
+  // The closures that have been created so far.
+  closures: HashMap<FunctionId, Rc<RastFunction>>,
+  frames: Vec<Frame>,
+  // The stack position at which the program started running.
+  stack_base: usize,
 }
 
-impl Interpreter {
-  pub fn new(semantic_ctx: SemanticContext) -> Interpreter {
+impl<'a> Interpreter<'a> {
+  pub fn new(semantic_ctx: SemanticContext) -> Interpreter<'a> {
     Interpreter {
       semantic_ctx,
-      locals: HashMap::new(),
+      functions: HashMap::new(),
+      closures: HashMap::new(),
+      frames: vec![Frame::default()],
+      stack_base: 0,
+    }
+  }
+
+  // A snapshot of the locals of the innermost call, or of the program outside
+  // of any call.
+  pub fn locals(&self) -> HashMap<LocalId, Value> {
+    self
+      .frames
+      .last()
+      .unwrap()
+      .locals
+      .iter()
+      .map(|(id, cell)| (*id, cell.borrow().clone()))
+      .collect()
+  }
+
+  fn cell(&self, local_id: LocalId) -> &Cell {
+    &self.frames.last().unwrap().locals[&local_id]
+  }
+
+  fn locals_mut(&mut self) -> &mut HashMap<LocalId, Cell> {
+    &mut self.frames.last_mut().unwrap().locals
+  }
+
+  // Calls a function or a closure, whose frame already holds the captures.
+  fn call(
+    &mut self,
+    pos: Span,
+    function: &RastFunction,
+    mut frame: Frame,
+    arguments: Vec<Value>,
+  ) -> Flow<Value> {
+    frame.depth = self.frames.last().unwrap().depth + 1;
+    let stack_used = self.stack_base.abs_diff(stack_position());
+    if frame.depth > MAX_CALL_DEPTH || stack_used > STACK_SIZE - STACK_RESERVE {
+      return Err(ControlFlow::Error(RuntimeErrorCtx(
+        pos,
+        RuntimeError::StackOverflow,
+      )));
+    }
+
+    let parameters = &self.semantic_ctx.functions[&function.id].parameters;
+    frame.locals.extend(
+      parameters.iter().cloned().zip(
+        arguments
+          .into_iter()
+          .map(|x| Rc::new(RefCell::new(x.deep_copy()))),
+      ),
+    );
+
+    self.frames.push(frame);
+    let result = self.evaluate_block(&function.body);
+    self.frames.pop();
+
+    match result {
+      Ok(value) | Err(ControlFlow::Return(value)) => Ok(value),
+      Err(ControlFlow::Error(error)) => Err(ControlFlow::Error(error)),
+      Err(flow) => unreachable!("{:?} escaped a function", flow),
     }
   }
 
-  fn evaluate(&mut self, expression: &RastExpression) -> Value {
+  // Evaluates `array[index]` down to the array and an index that is within its
+  // bounds.
+  fn evaluate_place(
+    &mut self,
+    pos: Span,
+    array: &RastExpressionCtx,
+    index: &RastExpressionCtx,
+  ) -> Flow<(Rc<RefCell<Vec<Value>>>, usize)> {
+    let array = match self.evaluate(array)? {
+      Value::Array(array) => array,
+      _ => unreachable!(),
+    };
+    let index = match self.evaluate(index)? {
+      Value::I32(index) => index,
+      _ => unreachable!(),
+    };
+
+    let len = array.borrow().len();
+    if index < 0 || index as usize >= len {
+      return Err(ControlFlow::Error(RuntimeErrorCtx(
+        pos,
+        RuntimeError::IndexOutOfBounds { index, len },
+      )));
+    }
+
+    Ok((array, index as usize))
+  }
+
+  // Evaluates a struct down to its fields and the index of one of them.
+  fn evaluate_field(
+    &mut self,
+    value: &RastExpressionCtx,
+    field: &str,
+  ) -> Flow<(Rc<RefCell<Vec<Value>>>, usize)> {
+    match self.evaluate(value)? {
+      Value::Struct(type_id, fields) => {
+        let index = match &self.semantic_ctx.resolve_type(type_id).type_def {
+          UserTypeDef::Struct(def) => def.field_index(field).unwrap(),
+          _ => unreachable!(),
+        };
+        Ok((fields, index))
+      }
+      _ => unreachable!(),
+    }
+  }
+
+  fn evaluate(&mut self, expression: &RastExpressionCtx) -> Flow<Value> {
     use BinaryOperator::*;
     use RastExpression::*;
-    use UnaryOperator::*;
     use Value::*;
 
-    match expression {
-      IntegerConstant(i) => I32(*i as i32),
-      Local(local_id) => *self.locals.get(local_id).unwrap(),
-      UnaryOp(Negate, expr) => {
-        if let I32(i) = self.evaluate(&expr.1) {
-          I32(-i)
-        } else {
-          panic!()
-        }
+    let RastExpressionCtx(pos, expression) = expression;
+
+    Ok(match expression {
+      &IntegerConstant(x, literal_id) => match self.semantic_ctx.literals[&literal_id].type_ {
+        // The type checker made sure that the literal is in range.
+        TypeRef::Primitive(type_) => Value::from_integer(type_, x).unwrap(),
+        _ => unreachable!(),
+      },
+      FloatConstant(x) => F64(x.0),
+      BooleanConstant(b) => Bool(*b),
+      StringConstant(s) => Str(s.as_str().into()),
+      Local(local_id) => self.cell(*local_id).borrow().clone(),
+      UnaryOp(op, x) => {
+        let x = self.evaluate(x)?;
+        unary_operation(*op, x).map_err(|error| ControlFlow::Error(RuntimeErrorCtx(*pos, error)))?
       }
+      // The right hand side of && and || is only evaluated when needed.
+      BinaryOp(And, args) => match self.evaluate(&args.0)? {
+        Bool(false) => Bool(false),
+        _ => self.evaluate(&args.1)?,
+      },
+      BinaryOp(Or, args) => match self.evaluate(&args.0)? {
+        Bool(true) => Bool(true),
+        _ => self.evaluate(&args.1)?,
+      },
       BinaryOp(op, args) => {
-        let lhs = self.evaluate(&(args.0).1);
-        let rhs = self.evaluate(&(args.1).1);
+        let lhs = self.evaluate(&args.0)?;
+        let rhs = self.evaluate(&args.1)?;
+        binary_operation(*op, lhs, rhs)
+          .map_err(|error| ControlFlow::Error(RuntimeErrorCtx(*pos, error)))?
+      }
+      Block(block) => self.evaluate_block(block)?,
+      If {
+        condition,
+        then_branch,
+        else_branch,
+      } => match (self.evaluate(condition)?, else_branch) {
+        (Bool(true), _) => self.evaluate(then_branch)?,
+        (_, Some(else_branch)) => self.evaluate(else_branch)?,
+        (_, None) => Unit,
+      },
+      RastExpression::Function(function_id, _) => Value::Function(*function_id),
+      RastExpression::Closure(function) => {
+        let captures = self.semantic_ctx.functions[&function.id]
+          .captures
+          .iter()
+          .map(|capture| {
+            let cell = self.cell(capture.local_id);
+            let cell = match capture.mode {
+              CaptureMode::ByValue => Rc::new(RefCell::new(cell.borrow().clone())),
+              CaptureMode::ByReference => cell.clone(),
+            };
+            (capture.local_id, cell)
+          })
+          .collect();
 
-        match (lhs, op, rhs) {
-          (I32(a), Add, I32(b)) => I32(a + b),
-          (I32(a), Sub, I32(b)) => I32(a - b),
-          (I32(a), Mul, I32(b)) => I32(a * b),
+        // Calls to the closure find its body here, since only its id is in
+        // the value.
+        self
+          .closures
+          .entry(function.id)
+          .or_insert_with(|| function.clone());
+        Value::Closure(Rc::new(self::Closure {
+          function_id: function.id,
+          captures,
+        }))
+      }
+      Call {
+        function_id,
+        arguments,
+        ..
+      } => {
+        let arguments = self.evaluate_arguments(arguments)?;
+        let function = self.functions[function_id];
+        self.call(*pos, function, Frame::default(), arguments)?
+      }
+      CallIndirect { callee, arguments } => {
+        let callee = self.evaluate(callee)?;
+        let arguments = self.evaluate_arguments(arguments)?;
+        match callee {
+          Value::Function(function_id) => {
+            let function = self.functions[&function_id];
+            self.call(*pos, function, Frame::default(), arguments)?
+          }
+          Value::Closure(closure) => {
+            let frame = Frame {
+              locals: closure.captures.iter().cloned().collect(),
+              depth: 0,
+            };
+            let function = self.closures[&closure.function_id].clone();
+            self.call(*pos, &function, frame, arguments)?
+          }
           _ => unreachable!(),
         }
       }
-      _ => unreachable!(),
+      Builtin {
+        builtin: crate::rast::Builtin::Len,
+        arguments,
+      } => match self.evaluate(&arguments[0])? {
+        Value::Array(array) => I32(array.borrow().len() as i32),
+        // Like in Rust, the length of a string is in bytes.
+        Str(s) => I32(s.len() as i32),
+        _ => unreachable!(),
+      },
+      RastExpression::Array(elements, _) => {
+        let elements = self.evaluate_arguments(elements)?;
+        Value::Array(Rc::new(RefCell::new(elements)))
+      }
+      &RastExpression::Struct {
+        type_id,
+        ref fields,
+        ..
+      } => {
+        let mut values = vec![Unit; fields.len()];
+        for (index, value) in fields {
+          values[*index] = self.evaluate(value)?;
+        }
+        Value::Struct(type_id, Rc::new(RefCell::new(values)))
+      }
+      &Variant {
+        type_id,
+        variant,
+        ref arguments,
+        ..
+      } => {
+        let fields = self.evaluate_arguments(arguments)?;
+        Enum(type_id, variant, Rc::new(fields))
+      }
+      Match { scrutinee, arms } => {
+        let value = self.evaluate(scrutinee)?;
+
+        // The type checker made sure that some arm matches.
+        let mut bindings = Vec::new();
+        let arm = arms
+          .iter()
+          .find(|arm| {
+            bindings.clear();
+            match_pattern(&arm.pattern, &value, &mut bindings)
+          })
+          .unwrap();
+
+        for (local_id, value) in bindings {
+          self
+            .locals_mut()
+            .insert(local_id, Rc::new(RefCell::new(value.deep_copy())));
+        }
+        self.evaluate(&arm.body)?
+      }
+      Field { value, field } => {
+        let (fields, index) = self.evaluate_field(value, field)?;
+        let value = fields.borrow()[index].clone();
+        value
+      }
+      Index { array, index } => {
+        let (array, index) = self.evaluate_place(*pos, array, index)?;
+        let element = array.borrow()[index].clone();
+        element
+      }
+      Loop { body, .. } => loop {
+        match self.evaluate_block(body) {
+          Ok(_) | Err(ControlFlow::Continue) => {}
+          Err(ControlFlow::Break(value)) => break value,
+          Err(flow) => return Err(flow),
+        }
+      },
+      Cast { value, type_ } => match type_ {
+        TypeRef::Primitive(type_) => cast(self.evaluate(value)?, *type_),
+        _ => unreachable!(),
+      },
+    })
+  }
+
+  fn evaluate_arguments(&mut self, arguments: &[RastExpressionCtx]) -> Flow<Vec<Value>> {
+    arguments
+      .iter()
+      .map(|argument| self.evaluate(argument))
+      .collect()
+  }
+
+  fn evaluate_block(&mut self, block: &RastBlock) -> Flow<Value> {
+    for statement in &block.statements {
+      self.execute(statement)?;
+    }
+
+    match &block.result {
+      Some(result) => self.evaluate(result),
+      None => Ok(Value::Unit),
     }
   }
 
-  pub fn execute(&mut self, statement: &RastStatement) -> Option<Value> {
+  fn execute(&mut self, statement: &RastStatementCtx) -> Flow<()> {
+    let RastStatementCtx(pos, statement) = statement;
+
     match statement {
+      RastStatement::DeclareLocal { local_id, value } => {
+        let rhs = self.evaluate(value)?.deep_copy();
+        self
+          .locals_mut()
+          .insert(*local_id, Rc::new(RefCell::new(rhs)));
+      }
       RastStatement::AssignLocal { local_id, value } => {
-        let rhs = self.evaluate(&value.1);
-        self.locals.insert(*local_id, rhs);
-        None
+        let rhs = self.evaluate(value)?.deep_copy();
+        *self.cell(*local_id).borrow_mut() = rhs;
+      }
+      RastStatement::AssignIndex {
+        array,
+        index,
+        value,
+      } => {
+        let (array, index) = self.evaluate_place(*pos, array, index)?;
+        let rhs = self.evaluate(value)?.deep_copy();
+        array.borrow_mut()[index] = rhs;
       }
-      RastStatement::Block { inner, .. } => {
-        for statement in inner {
-          self.execute(&statement.1);
+      RastStatement::AssignField {
+        target,
+        field,
+        value,
+      } => {
+        let (fields, index) = self.evaluate_field(target, field)?;
+        let rhs = self.evaluate(value)?.deep_copy();
+        fields.borrow_mut()[index] = rhs;
+      }
+      RastStatement::Expression { value } => {
+        self.evaluate(value)?;
+      }
+      RastStatement::While {
+        condition, body, ..
+      } => {
+        while let Value::Bool(true) = self.evaluate(condition)? {
+          match self.evaluate_block(body) {
+            Ok(_) | Err(ControlFlow::Continue) => {}
+            Err(ControlFlow::Break(_)) => break,
+            Err(flow) => return Err(flow),
+          }
         }
-        None
+      }
+      RastStatement::Break { value, .. } => {
+        let value = match value {
+          Some(value) => self.evaluate(value)?,
+          None => Value::Unit,
+        };
+        return Err(ControlFlow::Break(value));
+      }
+      RastStatement::Continue { .. } => return Err(ControlFlow::Continue),
+      RastStatement::Return { value, .. } => {
+        let value = match value {
+          Some(value) => self.evaluate(value)?,
+          None => Value::Unit,
+        };
+        return Err(ControlFlow::Return(value));
       }
     }
+
+    Ok(())
   }
 
-  pub fn execute_program(&mut self, program: &RastProgram) {
-    for statement in &program.0 {
-      self.execute(&statement.1);
+  // Returns the value of the program, which is the value of its trailing
+  // expression.
+  pub fn execute_program(&mut self, program: &'a RastProgram) -> RuntimeResult<Value> {
+    self.functions = program
+      .functions
+      .iter()
+      .map(|function| (function.id, function))
+      .collect();
+
+    let result = with_stack(STACK_SIZE, || {
+      self.stack_base = stack_position();
+      self.evaluate_block(&program.body)
+    });
+
+    // break, continue and return outside of a loop or a function are rejected
+    // by semantic analysis.
+    match result {
+      Ok(value) => Ok(value),
+      Err(ControlFlow::Error(error)) => Err(error),
+      Err(flow) => unreachable!("{:?} escaped the program", flow),
     }
   }
 }
@@ -1,61 +1,138 @@
 pub mod ast;
 pub mod ast_common;
+pub mod bytecode;
 pub mod char_stream;
+pub mod codegen;
+pub mod diagnostics;
 pub mod interpreter;
-pub mod rast;
 pub mod parse_utils;
 pub mod parser;
+pub mod rast;
 pub mod semantic;
+pub mod source_map;
+pub mod span;
 pub mod token_stream;
 pub mod tokens;
 pub mod type_checker;
 pub mod utils;
+pub mod vm;
+
+use std::fmt;
 
+use crate::codegen::c::CodegenErrorCtx;
+use crate::diagnostics::{Diagnostic, ToDiagnostic};
+use crate::interpreter::RuntimeErrorCtx;
 use crate::parser::ParseErrorCtx;
 use crate::semantic::SemanticErrorCtx;
 use crate::type_checker::TypeErrorCtx;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum EvalError {
-  ParseError(ParseErrorCtx),
+  ParseError(Vec<ParseErrorCtx>),
   SemanticError(SemanticErrorCtx),
-  TypeError(TypeErrorCtx),
+  TypeError(Vec<TypeErrorCtx>),
+  RuntimeError(RuntimeErrorCtx),
+  CodegenError(CodegenErrorCtx),
+}
+
+impl From<Vec<ParseErrorCtx>> for EvalError {
+  fn from(x: Vec<ParseErrorCtx>) -> EvalError {
+    EvalError::ParseError(x)
+  }
+}
+
+impl From<SemanticErrorCtx> for EvalError {
+  fn from(x: SemanticErrorCtx) -> EvalError {
+    EvalError::SemanticError(x)
+  }
+}
+
+impl From<Vec<TypeErrorCtx>> for EvalError {
+  fn from(x: Vec<TypeErrorCtx>) -> EvalError {
+    EvalError::TypeError(x)
+  }
+}
+
+impl From<RuntimeErrorCtx> for EvalError {
+  fn from(x: RuntimeErrorCtx) -> EvalError {
+    EvalError::RuntimeError(x)
+  }
 }
 
-impl Into<EvalError> for ParseErrorCtx {
-  fn into(self) -> EvalError {
-    EvalError::ParseError(self)
+impl From<CodegenErrorCtx> for EvalError {
+  fn from(x: CodegenErrorCtx) -> EvalError {
+    EvalError::CodegenError(x)
   }
 }
 
-impl Into<EvalError> for SemanticErrorCtx {
-  fn into(self) -> EvalError {
-    EvalError::SemanticError(self)
+impl EvalError {
+  pub fn to_diagnostics(&self) -> Vec<Diagnostic> {
+    match self {
+      EvalError::ParseError(errors) => errors.iter().map(|x| x.to_diagnostic()).collect(),
+      EvalError::SemanticError(error) => vec![error.to_diagnostic()],
+      EvalError::TypeError(errors) => errors.iter().map(|x| x.to_diagnostic()).collect(),
+      EvalError::RuntimeError(error) => vec![error.to_diagnostic()],
+      EvalError::CodegenError(error) => vec![error.to_diagnostic()],
+    }
   }
 }
 
-impl Into<EvalError> for TypeErrorCtx {
-  fn into(self) -> EvalError {
-    EvalError::TypeError(self)
+// Only prints the primary messages, one per line. Use diagnostics::Report to
+// include the offending source code.
+impl fmt::Display for EvalError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let messages: Vec<String> = self
+      .to_diagnostics()
+      .into_iter()
+      .map(|x| x.message)
+      .collect();
+    write!(f, "{}", messages.join("\n"))
   }
 }
 
+impl std::error::Error for EvalError {}
+
+// How a program is run once it has been type checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+  // Walks the resolved AST.
+  Interpreter,
+  // Compiles the program to bytecode and runs it on a register machine.
+  Vm,
+}
+
+// Evaluates a program and returns its value, or None if the value is ().
 pub fn eval(src: &str) -> Result<Option<interpreter::Value>, EvalError> {
-  use crate::interpreter::Interpreter;
+  eval_with(src, Backend::Interpreter)
+}
+
+// Like eval, with the given backend. Both give the same results.
+pub fn eval_with(src: &str, backend: Backend) -> Result<Option<interpreter::Value>, EvalError> {
+  use crate::interpreter::{Interpreter, Value};
   use crate::parser::Parser;
   use crate::semantic::transform_program;
   use crate::token_stream::TokenStream;
   use crate::type_checker::visit_program;
-  use crate::utils::ResultExt;
 
   let mut token_stream = TokenStream::new(src);
   let mut parser = Parser::new(&mut token_stream);
-  let program = parser.parse_program().err_into()?;
+  let (program, errors) = parser.parse_program();
+  if !errors.is_empty() {
+    return Err(errors.into());
+  }
 
-  let (mut ctx, mut program) = transform_program(program).err_into()?;
-  visit_program(&mut ctx, &mut program).err_into()?;
+  let (mut ctx, program) = transform_program(program)?;
+  visit_program(&mut ctx, &program)?;
 
-  let mut interpreter = Interpreter::new(ctx);
-  interpreter.execute_program(&program);
-  Ok(None)
+  let value = match backend {
+    Backend::Interpreter => Interpreter::new(ctx).execute_program(&program)?,
+    Backend::Vm => {
+      let program = bytecode::compile(&ctx, &program);
+      vm::Vm::new(&program).run()?
+    }
+  };
+  match value {
+    Value::Unit => Ok(None),
+    value => Ok(Some(value)),
+  }
 }
@@ -1,54 +1,122 @@
-mod ast;
-mod ast_common;
-mod char_stream;
-mod interpreter;
-mod parse_utils;
-mod parser;
-mod rast;
-mod semantic;
-mod token_stream;
-mod tokens;
-mod type_checker;
-mod utils;
-
-use std::io::stdin;
-
-use crate::interpreter::Interpreter;
-use crate::parser::Parser;
-use crate::semantic::transform_program;
-use crate::token_stream::TokenStream;
-use crate::type_checker::visit_program;
+use std::io::{stderr, stdin, IsTerminal};
+
+use toylang::bytecode::{compile, Disassembly};
+use toylang::codegen::c::generate;
+use toylang::diagnostics::Report;
+use toylang::interpreter::Interpreter;
+use toylang::parser::Parser;
+use toylang::semantic::transform_program;
+use toylang::source_map::SourceMap;
+use toylang::token_stream::TokenStream;
+use toylang::type_checker::visit_program;
+use toylang::vm::Vm;
+use toylang::{Backend, EvalError};
+
+// What to print instead of running the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Emit {
+  Bytecode,
+  C,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Options {
+  backend: Backend,
+  emit: Option<Emit>,
+}
+
+fn run(src: &str, source_map: &SourceMap, options: Options) -> Result<(), EvalError> {
+  let verbose = options.emit.is_none();
+  let mut token_stream = TokenStream::new(src);
+  let mut parser = Parser::new(&mut token_stream);
+  let (program, errors) = parser.parse_program();
+  if verbose {
+    println!("Parsed: {:#?}", program);
+  }
+  if !errors.is_empty() {
+    return Err(errors.into());
+  }
+
+  let (mut ctx, program) = transform_program(program)?;
+  if verbose {
+    println!("RAST: {:#?}", program);
+  }
+
+  visit_program(&mut ctx, &program)?;
+  if verbose {
+    println!("Type checked OK! Locals: {:#?}", ctx.locals);
+  }
+
+  match options.emit {
+    Some(Emit::Bytecode) => {
+      let program = compile(&ctx, &program);
+      print!("{}", Disassembly::new(&program, source_map));
+      return Ok(());
+    }
+    Some(Emit::C) => {
+      print!("{}", generate(&ctx, &program, source_map)?);
+      return Ok(());
+    }
+    None => {}
+  }
+
+  match options.backend {
+    Backend::Interpreter => {
+      let mut interpreter = Interpreter::new(ctx);
+      let result = interpreter.execute_program(&program);
+      println!("Locals: {:?}", interpreter.locals());
+      println!("Result: {:?}", result?);
+    }
+    Backend::Vm => {
+      let program = compile(&ctx, &program);
+      println!("Result: {:?}", Vm::new(&program).run()?);
+    }
+  }
+
+  Ok(())
+}
 
 fn main() {
+  let mut options = Options {
+    backend: Backend::Interpreter,
+    emit: None,
+  };
+  for arg in std::env::args().skip(1) {
+    match arg.as_str() {
+      "--backend=interpreter" => options.backend = Backend::Interpreter,
+      "--backend=vm" => options.backend = Backend::Vm,
+      "--emit=bytecode" => options.emit = Some(Emit::Bytecode),
+      "--emit=c" => options.emit = Some(Emit::C),
+      _ => {
+        eprintln!("usage: toylangi [--backend=interpreter|vm] [--emit=bytecode|c]");
+        eprintln!("--emit=c translates programs without enums, closures or function values");
+        std::process::exit(2);
+      }
+    }
+  }
+
   let input = stdin();
+  let color = stderr().is_terminal();
   let mut buffer = String::new();
+  let mut failed = false;
   loop {
     buffer.clear();
-    input.read_line(&mut buffer).unwrap();
-    let mut token_stream = TokenStream::new(&buffer);
-    let mut parser = Parser::new(&mut token_stream);
-    let program = parser.parse_program();
-    println!("Parsed: {:#?}", program);
+    if input.read_line(&mut buffer).unwrap() == 0 {
+      break;
+    }
 
-    let program = if let Ok(program) = program {
-      program
-    } else {
-      continue;
-    };
-
-    let transformed = transform_program(program);
-    println!("RAST: {:#?}", transformed);
-
-    if let Ok((mut ctx, mut program)) = transformed {
-      match visit_program(&mut ctx, &mut program) {
-        Ok(_) => {
-          println!("Type checked OK! Locals: {:#?}", ctx.locals);
-          let mut interpreter = Interpreter::new(ctx);
-          interpreter.execute_program(&program);
-          println!("Locals: {:?}", interpreter.locals);
-        }
-        Err(err) => println!("Err: {:?}", err),
-      }
+    let src = buffer.trim_end_matches(&['\r', '\n'][..]);
+    let mut source_map = SourceMap::new();
+    source_map.add_file("<stdin>", src);
+    if let Err(err) = run(src, &source_map, options) {
+      eprint!("{}", Report::new(&source_map, &err).with_color(color));
+      failed = true;
     }
   }
+
+  // Whatever reads the emitted code must be able to tell that a program
+  // didn't translate. Running programs interactively goes on after errors.
+  if failed && options.emit.is_some() {
+    std::process::exit(1);
+  }
 }
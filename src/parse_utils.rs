@@ -1,17 +1,73 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
 pub fn is_whitespace(ch: char) -> bool {
   ch == ' ' || ch == '\r' || ch == '\n'
 }
 
 pub fn is_valid_identifier_first(ch: char) -> bool {
-  match ch {
-    'A'..='Z' | 'a'..='z' | '_' => true,
-    _ => false,
-  }
+  matches!(ch, 'A'..='Z' | 'a'..='z' | '_')
 }
 
 pub fn is_valid_in_identifier(ch: char) -> bool {
-  match ch {
-    '0'..='9' | 'A'..='Z' | 'a'..='z' | '_' => true,
-    _ => false,
+  matches!(ch, '0'..='9' | 'A'..='Z' | 'a'..='z' | '_')
+}
+
+// The names of the integer types, which integer literals can be suffixed with.
+pub fn is_integer_suffix(suffix: &str) -> bool {
+  matches!(
+    suffix,
+    "i8" | "i16" | "i32" | "i64" | "u8" | "u16" | "u32" | "u64"
+  )
+}
+
+// Replaces the escape sequences in the contents of a string literal, or
+// returns the byte range of the first one that is invalid.
+pub fn unescape(raw: &str) -> Result<String, (usize, usize)> {
+  let mut result = String::with_capacity(raw.len());
+  let mut chars = raw.char_indices().peekable();
+  while let Some((start, ch)) = chars.next() {
+    if ch != '\\' {
+      result.push(ch);
+      continue;
+    }
+
+    let escaped = match chars.next() {
+      Some((_, 'n')) => Some('\n'),
+      Some((_, 't')) => Some('\t'),
+      Some((_, '"')) => Some('"'),
+      Some((_, '\\')) => Some('\\'),
+      Some((_, 'u')) => unescape_unicode(&mut chars),
+      _ => None,
+    };
+
+    match escaped {
+      Some(escaped) => result.push(escaped),
+      None => {
+        let end = chars.peek().map_or(raw.len(), |&(end, _)| end);
+        return Err((start, end));
+      }
+    }
+  }
+
+  Ok(result)
+}
+
+// Reads the `{1F600}` part of `\u{1F600}`, which is one to six hex digits.
+// Everything up to the closing brace is part of the escape, even if invalid.
+fn unescape_unicode(chars: &mut Peekable<CharIndices>) -> Option<char> {
+  chars.next_if(|&(_, ch)| ch == '{')?;
+
+  let mut digits = String::new();
+  loop {
+    match chars.next()? {
+      (_, '}') => break,
+      (_, ch) => digits.push(ch),
+    }
+  }
+
+  if digits.is_empty() || digits.len() > 6 || !digits.chars().all(|ch| ch.is_ascii_hexdigit()) {
+    return None;
   }
+  std::char::from_u32(u32::from_str_radix(&digits, 16).unwrap())
 }
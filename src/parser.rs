@@ -1,7 +1,15 @@
-use crate::ast::{Expression, ExpressionCtx, IdentifierCtx, Program, Statement, StatementCtx};
+use crate::ast::{
+  Block, EnumDeclaration, EnumDeclarationCtx, Expression, ExpressionCtx, FieldDeclaration,
+  FunctionDeclaration, FunctionDeclarationCtx, IdentifierCtx, MatchArm, Parameter, Pattern,
+  PatternCtx, Program, Statement, StatementCtx, StructDeclaration, StructDeclarationCtx, Type,
+  TypeCtx, VariantDeclaration,
+};
 use crate::ast_common::{BinaryOperator, Operator, UnaryOperator};
+use crate::parse_utils;
+use crate::span::Span;
 use crate::token_stream::{LexerError, LexerErrorCtx, TokenStream};
 use crate::tokens::{Token, TokenKind};
+use crate::utils::{with_stack, PASS_STACK_SIZE};
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum ParseError {
@@ -10,10 +18,18 @@ pub enum ParseError {
     expected: Vec<TokenKind>,
     was: TokenKind,
   },
+  InvalidAssignmentTarget,
+  // More than MAX_NESTING expressions, blocks, types or patterns are nested
+  // inside each other.
+  NestedTooDeeply,
 }
 
+// How deep the syntax tree can get. The passes over it recurse on the stack of
+// the host for every level, so this keeps them within PASS_STACK_SIZE.
+pub const MAX_NESTING: usize = 256;
+
 #[derive(Debug, Eq, PartialEq)]
-pub struct ParseErrorCtx(pub usize, pub ParseError);
+pub struct ParseErrorCtx(pub Span, pub ParseError);
 
 impl From<LexerErrorCtx> for ParseErrorCtx {
   fn from(x: LexerErrorCtx) -> ParseErrorCtx {
@@ -23,19 +39,76 @@ impl From<LexerErrorCtx> for ParseErrorCtx {
 
 pub type ParseResult<T> = Result<T, ParseErrorCtx>;
 
+fn expression_start() -> Vec<TokenKind> {
+  vec![
+    TokenKind::Minus,
+    TokenKind::Bang,
+    TokenKind::Tilde,
+    TokenKind::Integer,
+    TokenKind::Float,
+    TokenKind::Str,
+    TokenKind::True,
+    TokenKind::False,
+    TokenKind::Identifier,
+    TokenKind::LParen,
+    TokenKind::LBrace,
+    TokenKind::LBracket,
+    TokenKind::If,
+    TokenKind::Loop,
+    TokenKind::Match,
+    TokenKind::Pipe,
+    TokenKind::DoublePipe,
+  ]
+}
+
+fn statement_start() -> Vec<TokenKind> {
+  let mut expected = vec![
+    TokenKind::Let,
+    TokenKind::While,
+    TokenKind::Break,
+    TokenKind::Continue,
+    TokenKind::Return,
+  ];
+  expected.extend(expression_start());
+  expected
+}
+
+// A statement, the trailing expression of a block or, at the top level, a
+// function or a type.
+enum BlockItem {
+  Statement(StatementCtx),
+  Result(ExpressionCtx),
+  Function(FunctionDeclarationCtx),
+  Struct(StructDeclarationCtx),
+  Enum(EnumDeclarationCtx),
+}
+
 pub struct Parser<'a> {
   lexer: &'a mut TokenStream<'a>,
+  errors: Vec<ParseErrorCtx>,
+  functions: Vec<FunctionDeclarationCtx>,
+  structs: Vec<StructDeclarationCtx>,
+  enums: Vec<EnumDeclarationCtx>,
+  block_depth: usize,
+  // How many levels deep the syntax tree is at the current token.
+  nesting: usize,
+  // Like in Rust, `if x {` and `while x {` start a block rather than a struct
+  // literal. Parentheses and brackets allow struct literals again.
+  allow_struct_literals: bool,
 }
 
 impl<'a> TokenStream<'a> {
-  pub fn take_of(&mut self, kind: TokenKind) -> ParseResult<(usize, Token)> {
-    let token = self.take_pos()?;
-    let token_kind = token.1.to_kind();
+  // Takes the next token if it's of the expected kind. Otherwise the token is
+  // left in the stream, so that error recovery can decide what to do with it.
+  pub fn take_of(&mut self, kind: TokenKind) -> ParseResult<(Span, Token<'a>)> {
+    let &(pos, token) = self.peek_pos()?;
+    let token_kind = token.to_kind();
     if token_kind == kind {
-      Ok(token)
+      self.take_pos()?;
+      Ok((pos, token))
     } else {
       Err(ParseErrorCtx(
-        token.0,
+        pos,
         ParseError::UnexpectedToken {
           expected: vec![kind],
           was: token_kind,
@@ -44,7 +117,7 @@ impl<'a> TokenStream<'a> {
     }
   }
 
-  pub fn take_identifier(&mut self) -> ParseResult<(usize, IdentifierCtx)> {
+  pub fn take_identifier(&mut self) -> ParseResult<(Span, IdentifierCtx)> {
     self
       .take_of(TokenKind::Identifier)
       .map(|token| match token.1 {
@@ -55,9 +128,9 @@ impl<'a> TokenStream<'a> {
       })
   }
 
-  pub fn take_integer(&mut self) -> ParseResult<(usize, i128)> {
+  pub fn take_integer(&mut self) -> ParseResult<(Span, i128, Option<&'a str>)> {
     self.take_of(TokenKind::Integer).map(|token| match token.1 {
-      Token::Integer(value) => (token.0, value),
+      Token::Integer(value, suffix) => (token.0, value, suffix),
       _ => unsafe {
         std::hint::unreachable_unchecked();
       },
@@ -67,45 +140,480 @@ impl<'a> TokenStream<'a> {
 
 impl<'a> Parser<'a> {
   pub fn new(lexer: &'a mut TokenStream<'a>) -> Parser<'a> {
-    Parser { lexer }
+    Parser {
+      lexer,
+      errors: Vec::new(),
+      functions: Vec::new(),
+      structs: Vec::new(),
+      enums: Vec::new(),
+      block_depth: 0,
+      nesting: 0,
+      allow_struct_literals: true,
+    }
+  }
+
+  // Parses something one level deeper in the syntax tree than the current
+  // token.
+  fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+    let nesting = self.nesting;
+    let result = self.nest().and_then(|()| parse(self));
+    self.nesting = nesting;
+    result
+  }
+
+  // Goes one level deeper until the innermost call to nested returns. Chains
+  // of operators nest the left hand side deeper with every operator.
+  fn nest(&mut self) -> ParseResult<()> {
+    let &(pos, _) = self.lexer.peek_pos()?;
+    if self.nesting == MAX_NESTING {
+      return Err(ParseErrorCtx(pos, ParseError::NestedTooDeeply));
+    }
+    self.nesting += 1;
+    Ok(())
+  }
+
+  fn with_struct_literals<T>(&mut self, allow: bool, parse: impl FnOnce(&mut Self) -> T) -> T {
+    let previous = std::mem::replace(&mut self.allow_struct_literals, allow);
+    let result = parse(self);
+    self.allow_struct_literals = previous;
+    result
   }
 
   fn parse_expression_token(&mut self) -> ParseResult<ExpressionCtx> {
-    let (pos, first) = self.lexer.take_pos()?;
+    self.nested(|parser| {
+      let mut expression = parser.parse_primary_expression()?;
+      loop {
+        expression = match parser.lexer.peek()? {
+          Token::LParen => parser.nest().and_then(|()| parser.parse_call(expression))?,
+          Token::LBracket => parser
+            .nest()
+            .and_then(|()| parser.parse_index(expression))?,
+          Token::Dot => parser
+            .nest()
+            .and_then(|()| parser.parse_field(expression))?,
+          _ => return Ok(expression),
+        };
+      }
+    })
+  }
+
+  fn parse_primary_expression(&mut self) -> ParseResult<ExpressionCtx> {
+    let &(pos, first) = self.lexer.peek_pos()?;
+
+    match first {
+      Token::Minus
+      | Token::Bang
+      | Token::Tilde
+      | Token::Integer(..)
+      | Token::Float(_)
+      | Token::Str(_)
+      | Token::True
+      | Token::False
+      | Token::Identifier(_)
+      | Token::LParen => {
+        self.lexer.take()?;
+      }
+      Token::LBrace => return self.parse_block(),
+      Token::If => return self.parse_if(),
+      Token::Loop => return self.parse_loop(),
+      Token::Pipe | Token::DoublePipe => return self.parse_closure(),
+      Token::LBracket => return self.parse_array(),
+      Token::Match => return self.parse_match(),
+      _ => {}
+    }
 
     match first {
-      Token::Minus => Ok(ExpressionCtx(
+      Token::Minus | Token::Bang | Token::Tilde => {
+        let op = match first {
+          Token::Minus => UnaryOperator::Negate,
+          Token::Bang => UnaryOperator::Not,
+          _ => UnaryOperator::BitNot,
+        };
+        let operand = self.parse_expression_token()?;
+        Ok(ExpressionCtx(
+          pos.to(operand.0),
+          Expression::UnaryOp(op, Box::new(operand)),
+        ))
+      }
+      Token::Integer(i, suffix) => Ok(ExpressionCtx(
         pos,
-        Expression::UnaryOp(
-          UnaryOperator::Negate,
-          Box::new(self.parse_expression_token()?),
-        ),
+        Expression::IntegerConstant(i, suffix.map(str::to_string)),
       )),
-      Token::Integer(i) => Ok(ExpressionCtx(pos, Expression::IntegerConstant(i))),
-      Token::Identifier(x) => Ok(ExpressionCtx(pos, Expression::Local(x.to_string()))),
+      Token::Float(x) => Ok(ExpressionCtx(pos, Expression::FloatConstant(x))),
+      Token::Str(raw) => {
+        let value = parse_utils::unescape(raw).expect("escapes are checked by the lexer");
+        Ok(ExpressionCtx(pos, Expression::StringConstant(value)))
+      }
+      Token::True => Ok(ExpressionCtx(pos, Expression::BooleanConstant(true))),
+      Token::False => Ok(ExpressionCtx(pos, Expression::BooleanConstant(false))),
+      Token::Identifier(x) => match self.lexer.peek()? {
+        Token::LBrace if self.allow_struct_literals => {
+          self.parse_struct_literal(IdentifierCtx(pos, x.to_string()))
+        }
+        Token::DoubleColon => {
+          self.lexer.take()?;
+          let (end, variant) = self.lexer.take_identifier()?;
+          Ok(ExpressionCtx(
+            pos.to(end),
+            Expression::Path {
+              type_name: IdentifierCtx(pos, x.to_string()),
+              variant,
+            },
+          ))
+        }
+        _ => Ok(ExpressionCtx(pos, Expression::Local(x.to_string()))),
+      },
       Token::LParen => {
-        let inner = self.parse_expression()?;
-        self.lexer.take_of(TokenKind::RParen)?;
-        Ok(inner)
+        let inner = self.with_struct_literals(true, Parser::parse_expression)?;
+        let (end, _) = self.lexer.take_of(TokenKind::RParen)?;
+        Ok(ExpressionCtx(pos.to(end), inner.1))
+      }
+      _ => Err(ParseErrorCtx(
+        pos,
+        ParseError::UnexpectedToken {
+          expected: expression_start(),
+          was: first.to_kind(),
+        },
+      )),
+    }
+  }
+
+  fn parse_closure(&mut self) -> ParseResult<ExpressionCtx> {
+    // A closure without parameters starts with `||`, which is a single token.
+    let (start, parameters) = match self.lexer.take_pos()? {
+      (start, Token::DoublePipe) => (start, Vec::new()),
+      (start, _) => {
+        let (parameters, _) =
+          self.parse_comma_separated(TokenKind::Pipe, Parser::parse_parameter)?;
+        (start, parameters)
+      }
+    };
+
+    let return_type = match self.lexer.peek()? {
+      Token::Arrow => {
+        self.lexer.take()?;
+        Some(self.parse_type()?)
+      }
+      _ => None,
+    };
+
+    let body = self.parse_expression()?;
+
+    Ok(ExpressionCtx(
+      start.to(body.0),
+      Expression::Closure {
+        parameters,
+        return_type,
+        body: Box::new(body),
+      },
+    ))
+  }
+
+  fn parse_call(&mut self, callee: ExpressionCtx) -> ParseResult<ExpressionCtx> {
+    self.lexer.take_of(TokenKind::LParen)?;
+    let (arguments, end) =
+      self.parse_comma_separated(TokenKind::RParen, Parser::parse_expression)?;
+
+    Ok(ExpressionCtx(
+      callee.0.to(end),
+      Expression::Call {
+        callee: Box::new(callee),
+        arguments,
+      },
+    ))
+  }
+
+  fn parse_array(&mut self) -> ParseResult<ExpressionCtx> {
+    let (start, _) = self.lexer.take_of(TokenKind::LBracket)?;
+    let (elements, end) =
+      self.parse_comma_separated(TokenKind::RBracket, Parser::parse_expression)?;
+    Ok(ExpressionCtx(start.to(end), Expression::Array(elements)))
+  }
+
+  fn parse_index(&mut self, array: ExpressionCtx) -> ParseResult<ExpressionCtx> {
+    self.lexer.take_of(TokenKind::LBracket)?;
+    let index = self.with_struct_literals(true, Parser::parse_expression)?;
+    let (end, _) = self.lexer.take_of(TokenKind::RBracket)?;
+
+    Ok(ExpressionCtx(
+      array.0.to(end),
+      Expression::Index {
+        array: Box::new(array),
+        index: Box::new(index),
+      },
+    ))
+  }
+
+  fn parse_field(&mut self, value: ExpressionCtx) -> ParseResult<ExpressionCtx> {
+    self.lexer.take_of(TokenKind::Dot)?;
+    let (end, field) = self.lexer.take_identifier()?;
+
+    Ok(ExpressionCtx(
+      value.0.to(end),
+      Expression::Field {
+        value: Box::new(value),
+        field,
+      },
+    ))
+  }
+
+  fn parse_struct_literal(&mut self, name: IdentifierCtx) -> ParseResult<ExpressionCtx> {
+    self.lexer.take_of(TokenKind::LBrace)?;
+    let (fields, end) = self.parse_comma_separated(TokenKind::RBrace, |parser| {
+      let field = parser.lexer.take_identifier()?.1;
+      parser.lexer.take_of(TokenKind::Colon)?;
+      Ok((field, parser.parse_expression()?))
+    })?;
+
+    Ok(ExpressionCtx(
+      name.0.to(end),
+      Expression::StructLiteral { name, fields },
+    ))
+  }
+
+  fn parse_match(&mut self) -> ParseResult<ExpressionCtx> {
+    let (start, _) = self.lexer.take_of(TokenKind::Match)?;
+    let scrutinee = self.with_struct_literals(false, Parser::parse_expression)?;
+    self.lexer.take_of(TokenKind::LBrace)?;
+
+    let mut arms = Vec::new();
+    let end = loop {
+      if let &(end, Token::RBrace) = self.lexer.peek_pos()? {
+        self.lexer.take()?;
+        break end;
+      }
+
+      let pattern = self.parse_pattern()?;
+      self.lexer.take_of(TokenKind::FatArrow)?;
+      let body = self.with_struct_literals(true, Parser::parse_expression)?;
+      let is_block_like = matches!(
+        body.1,
+        Expression::Block(_)
+          | Expression::If { .. }
+          | Expression::Loop { .. }
+          | Expression::Match { .. }
+      );
+      arms.push(MatchArm { pattern, body });
+
+      // Arms are separated by commas, which are optional after a block.
+      match *self.lexer.peek_pos()? {
+        (_, Token::Comma) => {
+          self.lexer.take()?;
+        }
+        (_, Token::RBrace) => {}
+        _ if is_block_like => {}
+        (pos, token) => {
+          return Err(ParseErrorCtx(
+            pos,
+            ParseError::UnexpectedToken {
+              expected: vec![TokenKind::Comma, TokenKind::RBrace],
+              was: token.to_kind(),
+            },
+          ))
+        }
       }
+    };
+
+    Ok(ExpressionCtx(
+      start.to(end),
+      Expression::Match {
+        scrutinee: Box::new(scrutinee),
+        arms,
+      },
+    ))
+  }
+
+  fn parse_pattern(&mut self) -> ParseResult<PatternCtx> {
+    let (pos, token) = self.lexer.take_pos()?;
+    match token {
+      Token::Identifier("_") => Ok(PatternCtx(pos, Pattern::Wildcard)),
+      Token::Identifier(name) => match self.lexer.peek()? {
+        Token::DoubleColon => {
+          self.lexer.take()?;
+          let (mut end, variant) = self.lexer.take_identifier()?;
+          let mut fields = Vec::new();
+          if let Token::LParen = self.lexer.peek()? {
+            self.lexer.take()?;
+            let (patterns, close) = self.parse_comma_separated(TokenKind::RParen, |parser| {
+              parser.nested(Parser::parse_pattern)
+            })?;
+            fields = patterns;
+            end = close;
+          }
+
+          Ok(PatternCtx(
+            pos.to(end),
+            Pattern::Variant {
+              type_name: IdentifierCtx(pos, name.to_string()),
+              variant,
+              fields,
+            },
+          ))
+        }
+        _ => Ok(PatternCtx(pos, Pattern::Binding(name.to_string()))),
+      },
+      Token::Integer(i, suffix) => Ok(PatternCtx(
+        pos,
+        Pattern::Integer(i, suffix.map(str::to_string)),
+      )),
+      Token::Minus => {
+        let (end, i, suffix) = self.lexer.take_integer()?;
+        Ok(PatternCtx(
+          pos.to(end),
+          Pattern::Integer(-i, suffix.map(str::to_string)),
+        ))
+      }
+      Token::True => Ok(PatternCtx(pos, Pattern::Boolean(true))),
+      Token::False => Ok(PatternCtx(pos, Pattern::Boolean(false))),
       _ => Err(ParseErrorCtx(
         pos,
         ParseError::UnexpectedToken {
           expected: vec![
-            TokenKind::Minus,
-            TokenKind::Integer,
             TokenKind::Identifier,
-            TokenKind::LParen,
+            TokenKind::Integer,
+            TokenKind::Minus,
+            TokenKind::True,
+            TokenKind::False,
           ],
-          was: first.to_kind(),
+          was: token.to_kind(),
         },
       )),
     }
   }
 
+  fn parse_type(&mut self) -> ParseResult<TypeCtx> {
+    self.parse_type_with(true)
+  }
+
+  // The target of a cast is followed by an expression operator, so a `<`
+  // there is a comparison rather than the start of type arguments.
+  fn parse_cast_type(&mut self) -> ParseResult<TypeCtx> {
+    self.parse_type_with(false)
+  }
+
+  fn parse_type_with(&mut self, type_arguments: bool) -> ParseResult<TypeCtx> {
+    let &(pos, token) = self.lexer.peek_pos()?;
+    match token {
+      Token::LBracket => {
+        self.lexer.take()?;
+        let element = self.nested(Parser::parse_type)?;
+        let (end, _) = self.lexer.take_of(TokenKind::RBracket)?;
+        Ok(TypeCtx(pos.to(end), Type::Array(Box::new(element))))
+      }
+      Token::Fn => {
+        self.lexer.take()?;
+        self.lexer.take_of(TokenKind::LParen)?;
+        let (parameters, mut end) = self.parse_comma_separated(TokenKind::RParen, |parser| {
+          parser.nested(Parser::parse_type)
+        })?;
+        let return_type = match self.lexer.peek()? {
+          Token::Arrow => {
+            self.lexer.take()?;
+            let return_type = self.nested(|parser| parser.parse_type_with(type_arguments))?;
+            end = return_type.0;
+            Some(Box::new(return_type))
+          }
+          _ => None,
+        };
+        Ok(TypeCtx(
+          pos.to(end),
+          Type::Function {
+            parameters,
+            return_type,
+          },
+        ))
+      }
+      Token::Identifier(name) => {
+        self.lexer.take()?;
+        match self.lexer.peek()? {
+          Token::LessThan if type_arguments => {
+            self.lexer.take()?;
+            let (arguments, end) = self
+              .parse_comma_separated(TokenKind::GreaterThan, |parser| {
+                parser.nested(Parser::parse_type)
+              })?;
+            Ok(TypeCtx(
+              pos.to(end),
+              Type::Applied(name.to_string(), arguments),
+            ))
+          }
+          _ => Ok(TypeCtx(pos, Type::Named(name.to_string()))),
+        }
+      }
+      _ => Err(ParseErrorCtx(
+        pos,
+        ParseError::UnexpectedToken {
+          expected: vec![TokenKind::Identifier, TokenKind::LBracket, TokenKind::Fn],
+          was: token.to_kind(),
+        },
+      )),
+    }
+  }
+
+  // Parses comma separated items up to and including the closing token. A
+  // trailing comma is allowed. Returns the span of the closing token as well.
+  fn parse_comma_separated<T>(
+    &mut self,
+    close: TokenKind,
+    parse_item: impl FnMut(&mut Self) -> ParseResult<T>,
+  ) -> ParseResult<(Vec<T>, Span)> {
+    self.with_struct_literals(true, |parser| {
+      parser.parse_comma_separated_items(close, parse_item)
+    })
+  }
+
+  fn parse_comma_separated_items<T>(
+    &mut self,
+    close: TokenKind,
+    mut parse_item: impl FnMut(&mut Self) -> ParseResult<T>,
+  ) -> ParseResult<(Vec<T>, Span)> {
+    let mut items = Vec::new();
+
+    loop {
+      self.lexer.peek()?;
+      if close == TokenKind::GreaterThan {
+        if let Some(pos) = self.lexer.split_double_greater_than() {
+          return Ok((items, pos));
+        }
+      }
+
+      let &(pos, token) = self.lexer.peek_pos()?;
+      if token.to_kind() == close {
+        self.lexer.take()?;
+        return Ok((items, pos));
+      }
+
+      items.push(parse_item(self)?);
+
+      let &(pos, token) = self.lexer.peek_pos()?;
+      match token {
+        Token::Comma => {
+          self.lexer.take()?;
+        }
+        _ if token.to_kind() == close => {}
+        Token::DoubleGreaterThan if close == TokenKind::GreaterThan => {}
+        _ => {
+          return Err(ParseErrorCtx(
+            pos,
+            ParseError::UnexpectedToken {
+              expected: vec![TokenKind::Comma, close],
+              was: token.to_kind(),
+            },
+          ))
+        }
+      }
+    }
+  }
+
   fn parse_expression(&mut self) -> ParseResult<ExpressionCtx> {
-    let lhs = self.parse_expression_token()?;
-    self.parse_expression_opp(lhs, 0)
+    // The operators nest the left hand side only until the end of the
+    // expression.
+    let nesting = self.nesting;
+    let result = self
+      .parse_expression_token()
+      .and_then(|lhs| self.parse_expression_opp(lhs, 0));
+    self.nesting = nesting;
+    result
   }
 
   // This is based on the algorithm presented on Wikipedia: Operator-precedence parser
@@ -120,43 +628,105 @@ impl<'a> Parser<'a> {
         Token::Plus => Some(Operator::Binary(BinaryOperator::Add)),
         Token::Minus => Some(Operator::Binary(BinaryOperator::Sub)),
         Token::Asterisk => Some(Operator::Binary(BinaryOperator::Mul)),
+        Token::Slash => Some(Operator::Binary(BinaryOperator::Div)),
+        Token::Percent => Some(Operator::Binary(BinaryOperator::Rem)),
+        Token::Ampersand => Some(Operator::Binary(BinaryOperator::BitAnd)),
+        Token::Pipe => Some(Operator::Binary(BinaryOperator::BitOr)),
+        Token::Caret => Some(Operator::Binary(BinaryOperator::BitXor)),
+        Token::DoubleLessThan => Some(Operator::Binary(BinaryOperator::Shl)),
+        Token::DoubleGreaterThan => Some(Operator::Binary(BinaryOperator::Shr)),
+        Token::DoubleEquals => Some(Operator::Binary(BinaryOperator::Equals)),
+        Token::NotEquals => Some(Operator::Binary(BinaryOperator::NotEquals)),
+        Token::LessThan => Some(Operator::Binary(BinaryOperator::Less)),
+        Token::LessThanEquals => Some(Operator::Binary(BinaryOperator::LessOrEqual)),
+        Token::GreaterThan => Some(Operator::Binary(BinaryOperator::Greater)),
+        Token::GreaterThanEquals => Some(Operator::Binary(BinaryOperator::GreaterOrEqual)),
+        Token::DoubleAmpersand => Some(Operator::Binary(BinaryOperator::And)),
+        Token::DoublePipe => Some(Operator::Binary(BinaryOperator::Or)),
+        Token::As => Some(Operator::Cast),
         _ => None,
       }
     }
 
     loop {
-      let &(pos, token) = self.lexer.peek_pos()?;
+      let &(_, token) = self.lexer.peek_pos()?;
       let op = as_op(token);
 
       match op {
+        Some(Operator::Cast) if Operator::Cast.get_precedence() >= min_precedence => {
+          self.nest()?;
+          self.lexer.take()?;
+          let type_ = self.parse_cast_type()?;
+          lhs = ExpressionCtx(
+            lhs.0.to(type_.0),
+            Expression::Cast {
+              value: Box::new(lhs),
+              type_,
+            },
+          );
+        }
         Some(Operator::Binary(op)) if op.get_precedence() >= min_precedence => {
+          self.nest()?;
           self.lexer.take()?;
           let mut rhs = self.parse_expression_token()?;
           loop {
             let &(_, token) = self.lexer.peek_pos()?;
             let next_op = as_op(token);
 
+            // All binary operators are left associative, so only operators
+            // binding tighter than op are folded into the right hand side.
             match next_op {
-              Some(next_op) if next_op.get_precedence() >= op.get_precedence() => {
-                rhs = self.parse_expression_opp(rhs, next_op.get_precedence())?;
+              Some(next_op) if next_op.get_precedence() > op.get_precedence() => {
+                rhs = self.parse_expression_opp(rhs, op.get_precedence() + 1)?;
               }
               _ => break,
             }
           }
-          lhs = ExpressionCtx(pos, Expression::BinaryOp(op, Box::new((lhs, rhs))));
+          lhs = ExpressionCtx(
+            lhs.0.to(rhs.0),
+            Expression::BinaryOp(op, Box::new((lhs, rhs))),
+          );
         }
         _ => return Ok(lhs),
       }
     }
   }
 
-  fn parse_assignment(&mut self) -> ParseResult<StatementCtx> {
-    let (pos, local) = self.lexer.take_identifier()?;
+  fn parse_assignment(&mut self, target: ExpressionCtx) -> ParseResult<StatementCtx> {
+    let start = target.0;
+    if !matches!(
+      target.1,
+      Expression::Local(_) | Expression::Index { .. } | Expression::Field { .. }
+    ) {
+      return Err(ParseErrorCtx(start, ParseError::InvalidAssignmentTarget));
+    }
+
     self.lexer.take_of(TokenKind::Equals)?;
     let value = self.parse_expression()?;
-    self.lexer.take_of(TokenKind::Semicolon)?;
+    let (end, _) = self.lexer.take_of(TokenKind::Semicolon)?;
+
+    let statement = match target.1 {
+      Expression::Local(name) => Statement::AssignLocal {
+        local: IdentifierCtx(start, name),
+        value,
+      },
+      Expression::Index { array, index } => Statement::AssignIndex {
+        array: *array,
+        index: *index,
+        value,
+      },
+      Expression::Field {
+        value: target,
+        field,
+      } => Statement::AssignField {
+        target: *target,
+        field,
+        value,
+      },
+      _ => unreachable!(),
+    };
 
-    Ok(StatementCtx(pos, Statement::AssignLocal { local, value }))
+    Ok(StatementCtx(start.to(end), statement))
   }
 
   fn parse_declaration(&mut self) -> ParseResult<StatementCtx> {
@@ -174,17 +744,17 @@ impl<'a> Parser<'a> {
     let initial_type = match self.lexer.peek()? {
       Token::Colon => {
         self.lexer.take()?;
-        Some(self.lexer.take_identifier()?.1)
+        Some(self.parse_type()?)
       }
       _ => None,
     };
 
     self.lexer.take_of(TokenKind::Equals)?;
     let initial_value = self.parse_expression()?;
-    self.lexer.take_of(TokenKind::Semicolon)?;
+    let (end, _) = self.lexer.take_of(TokenKind::Semicolon)?;
 
     Ok(StatementCtx(
-      pos,
+      pos.to(end),
       Statement::DeclareVariable {
         name,
         initial_type,
@@ -194,73 +764,459 @@ impl<'a> Parser<'a> {
     ))
   }
 
-  pub fn parse_block(&mut self) -> ParseResult<StatementCtx> {
-    let (pos, _) = self.lexer.take_of(TokenKind::LBrace)?;
+  fn parse_block(&mut self) -> ParseResult<ExpressionCtx> {
+    let (span, block) = self.parse_braced_block()?;
+    Ok(ExpressionCtx(span, Expression::Block(block)))
+  }
 
-    let mut inner = Vec::new();
+  fn parse_braced_block(&mut self) -> ParseResult<(Span, Block)> {
+    self.nested(|parser| {
+      let (start, _) = parser.lexer.take_of(TokenKind::LBrace)?;
 
-    loop {
-      let next = self.lexer.peek()?;
-      if *next == Token::RBrace {
+      parser.block_depth += 1;
+      let block = parser.with_struct_literals(true, Parser::parse_block_contents);
+      parser.block_depth -= 1;
+
+      // The block ends either at `}` or at the end of file. In the latter case
+      // the error is reported, but the block is kept.
+      let end = match parser.lexer.take_of(TokenKind::RBrace) {
+        Ok((end, _)) => end,
+        Err(err) => {
+          let end = err.0;
+          parser.errors.push(err);
+          end
+        }
+      };
+
+      Ok((start.to(end), block))
+    })
+  }
+
+  fn parse_if(&mut self) -> ParseResult<ExpressionCtx> {
+    let (start, _) = self.lexer.take_of(TokenKind::If)?;
+    let condition = self.with_struct_literals(false, Parser::parse_expression)?;
+    let then_branch = self.parse_block()?;
+
+    let else_branch = match self.lexer.peek()? {
+      Token::Else => {
         self.lexer.take()?;
-        break;
+        match self.lexer.peek()? {
+          Token::If => Some(Box::new(self.nested(Parser::parse_if)?)),
+          _ => Some(Box::new(self.parse_block()?)),
+        }
       }
+      _ => None,
+    };
 
-      inner.push(self.parse_statement()?);
+    let end = else_branch.as_ref().map_or(then_branch.0, |x| x.0);
+
+    Ok(ExpressionCtx(
+      start.to(end),
+      Expression::If {
+        condition: Box::new(condition),
+        then_branch: Box::new(then_branch),
+        else_branch,
+      },
+    ))
+  }
+
+  fn parse_loop(&mut self) -> ParseResult<ExpressionCtx> {
+    let (start, _) = self.lexer.take_of(TokenKind::Loop)?;
+    let (end, body) = self.parse_braced_block()?;
+    Ok(ExpressionCtx(start.to(end), Expression::Loop { body }))
+  }
+
+  fn parse_while(&mut self) -> ParseResult<StatementCtx> {
+    let (start, _) = self.lexer.take_of(TokenKind::While)?;
+    let condition = self.with_struct_literals(false, Parser::parse_expression)?;
+    let (mut end, body) = self.parse_braced_block()?;
+
+    // Like other block-like statements, a while loop needs no semicolon.
+    if let Token::Semicolon = self.lexer.peek()? {
+      end = self.lexer.take_pos()?.0;
     }
 
-    Ok(StatementCtx(pos, Statement::Block { inner }))
+    Ok(StatementCtx(
+      start.to(end),
+      Statement::While { condition, body },
+    ))
   }
 
-  pub fn parse_statement(&mut self) -> ParseResult<StatementCtx> {
-    let first = self.lexer.peek()?;
+  fn parse_break(&mut self) -> ParseResult<StatementCtx> {
+    let (start, _) = self.lexer.take_of(TokenKind::Break)?;
 
-    match first {
-      Token::Let => self.parse_declaration(),
-      Token::LBrace => self.parse_block(),
-      Token::Identifier(_) => self.parse_assignment(),
-      _ => unimplemented!("Unimplemented statement."),
+    let value = match self.lexer.peek()? {
+      Token::Semicolon | Token::RBrace => None,
+      _ => Some(self.parse_expression()?),
+    };
+
+    let end = self.take_jump_end(value.as_ref().map_or(start, |value| value.0))?;
+    Ok(StatementCtx(start.to(end), Statement::Break { value }))
+  }
+
+  fn parse_return(&mut self) -> ParseResult<StatementCtx> {
+    let (start, _) = self.lexer.take_of(TokenKind::Return)?;
+
+    let value = match self.lexer.peek()? {
+      Token::Semicolon | Token::RBrace => None,
+      _ => Some(self.parse_expression()?),
+    };
+
+    let end = self.take_jump_end(value.as_ref().map_or(start, |value| value.0))?;
+    Ok(StatementCtx(start.to(end), Statement::Return { value }))
+  }
+
+  fn parse_continue(&mut self) -> ParseResult<StatementCtx> {
+    let (start, _) = self.lexer.take_of(TokenKind::Continue)?;
+    let end = self.take_jump_end(start)?;
+    Ok(StatementCtx(start.to(end), Statement::Continue))
+  }
+
+  // break, continue and return may leave out the semicolon at the end of a
+  // block.
+  fn take_jump_end(&mut self, end: Span) -> ParseResult<Span> {
+    match self.lexer.peek()? {
+      Token::RBrace => Ok(end),
+      _ => Ok(self.lexer.take_of(TokenKind::Semicolon)?.0),
+    }
+  }
+
+  // Parses the `<T, U>` of a generic declaration, if there is one.
+  fn parse_type_parameters(&mut self) -> ParseResult<Vec<IdentifierCtx>> {
+    match self.lexer.peek()? {
+      Token::LessThan => {
+        self.lexer.take()?;
+        let (names, _) = self.parse_comma_separated(TokenKind::GreaterThan, |parser| {
+          Ok(parser.lexer.take_identifier()?.1)
+        })?;
+        Ok(names)
+      }
+      _ => Ok(Vec::new()),
     }
   }
 
-  pub fn parse_program(&mut self) -> ParseResult<Program> {
+  fn parse_function(&mut self) -> ParseResult<FunctionDeclarationCtx> {
+    let (start, _) = self.lexer.take_of(TokenKind::Fn)?;
+    let name = self.lexer.take_identifier()?.1;
+    let type_parameters = self.parse_type_parameters()?;
+
+    self.lexer.take_of(TokenKind::LParen)?;
+    let (parameters, _) = self.parse_comma_separated(TokenKind::RParen, Parser::parse_parameter)?;
+
+    let return_type = match self.lexer.peek()? {
+      Token::Arrow => {
+        self.lexer.take()?;
+        Some(self.parse_type()?)
+      }
+      _ => None,
+    };
+
+    let (end, body) = self.parse_braced_block()?;
+
+    Ok(FunctionDeclarationCtx(
+      start.to(end),
+      FunctionDeclaration {
+        name,
+        type_parameters,
+        parameters,
+        return_type,
+        body,
+      },
+    ))
+  }
+
+  fn parse_struct(&mut self) -> ParseResult<StructDeclarationCtx> {
+    let (start, _) = self.lexer.take_of(TokenKind::Struct)?;
+    let name = self.lexer.take_identifier()?.1;
+    let type_parameters = self.parse_type_parameters()?;
+
+    self.lexer.take_of(TokenKind::LBrace)?;
+    let (fields, end) = self.parse_comma_separated(TokenKind::RBrace, |parser| {
+      let name = parser.lexer.take_identifier()?.1;
+      parser.lexer.take_of(TokenKind::Colon)?;
+      let type_ = parser.parse_type()?;
+      Ok(FieldDeclaration { name, type_ })
+    })?;
+
+    Ok(StructDeclarationCtx(
+      start.to(end),
+      StructDeclaration {
+        name,
+        type_parameters,
+        fields,
+      },
+    ))
+  }
+
+  fn parse_enum(&mut self) -> ParseResult<EnumDeclarationCtx> {
+    let (start, _) = self.lexer.take_of(TokenKind::Enum)?;
+    let name = self.lexer.take_identifier()?.1;
+    let type_parameters = self.parse_type_parameters()?;
+
+    self.lexer.take_of(TokenKind::LBrace)?;
+    let (variants, end) = self.parse_comma_separated(TokenKind::RBrace, |parser| {
+      let name = parser.lexer.take_identifier()?.1;
+      let fields = match parser.lexer.peek()? {
+        Token::LParen => {
+          parser.lexer.take()?;
+          parser
+            .parse_comma_separated(TokenKind::RParen, Parser::parse_type)?
+            .0
+        }
+        _ => Vec::new(),
+      };
+      Ok(VariantDeclaration { name, fields })
+    })?;
+
+    Ok(EnumDeclarationCtx(
+      start.to(end),
+      EnumDeclaration {
+        name,
+        type_parameters,
+        variants,
+      },
+    ))
+  }
+
+  fn parse_parameter(&mut self) -> ParseResult<Parameter> {
+    let name = self.lexer.take_identifier()?.1;
+    let type_ = match self.lexer.peek()? {
+      Token::Colon => {
+        self.lexer.take()?;
+        Some(self.parse_type()?)
+      }
+      _ => None,
+    };
+    Ok(Parameter { name, type_ })
+  }
+
+  // Parses statements up to the end of the current block, without consuming
+  // the closing `}`.
+  fn parse_block_contents(&mut self) -> Block {
     let mut statements = Vec::new();
+    let mut result: Option<Box<ExpressionCtx>> = None;
+
+    loop {
+      match self.lexer.peek() {
+        Ok(Token::EOF) => break,
+        Ok(Token::RBrace) if self.block_depth > 0 => break,
+        _ => {}
+      }
+
+      // Only happens after a stray `}` at the top level, which is an error.
+      if let Some(value) = result.take() {
+        statements.push(StatementCtx(
+          value.0,
+          Statement::Expression { value: *value },
+        ));
+      }
+
+      match self.parse_block_item_or_recover() {
+        BlockItem::Statement(statement) => statements.push(statement),
+        BlockItem::Result(value) => result = Some(Box::new(value)),
+        BlockItem::Function(function) => self.functions.push(function),
+        BlockItem::Struct(declaration) => self.structs.push(declaration),
+        BlockItem::Enum(declaration) => self.enums.push(declaration),
+      }
+    }
+
+    Block { statements, result }
+  }
+
+  fn parse_block_item(&mut self) -> ParseResult<BlockItem> {
+    let &(pos, first) = self.lexer.peek_pos()?;
+
+    match first {
+      Token::Let => Ok(BlockItem::Statement(self.parse_declaration()?)),
+      // Functions and structs can only be declared at the top level.
+      Token::Fn if self.block_depth == 0 => Ok(BlockItem::Function(self.parse_function()?)),
+      Token::Struct if self.block_depth == 0 => Ok(BlockItem::Struct(self.parse_struct()?)),
+      Token::Enum if self.block_depth == 0 => Ok(BlockItem::Enum(self.parse_enum()?)),
+      Token::While => Ok(BlockItem::Statement(self.parse_while()?)),
+      Token::Break => Ok(BlockItem::Statement(self.parse_break()?)),
+      Token::Continue => Ok(BlockItem::Statement(self.parse_continue()?)),
+      Token::Return => Ok(BlockItem::Statement(self.parse_return()?)),
+      // A block in statement position ends at its closing brace, even if an
+      // operator follows.
+      Token::LBrace => {
+        let value = self.parse_block()?;
+        self.finish_expression_statement(value, true)
+      }
+      Token::If => {
+        let value = self.parse_if()?;
+        self.finish_expression_statement(value, true)
+      }
+      Token::Loop => {
+        let value = self.parse_loop()?;
+        self.finish_expression_statement(value, true)
+      }
+      Token::Match => {
+        let value = self.parse_match()?;
+        self.finish_expression_statement(value, true)
+      }
+      _ if expression_start().contains(&first.to_kind()) => {
+        let value = self.parse_expression()?;
+        if let Token::Equals = self.lexer.peek()? {
+          return Ok(BlockItem::Statement(self.parse_assignment(value)?));
+        }
+        self.finish_expression_statement(value, false)
+      }
+      _ => Err(ParseErrorCtx(
+        pos,
+        ParseError::UnexpectedToken {
+          expected: statement_start(),
+          was: first.to_kind(),
+        },
+      )),
+    }
+  }
+
+  fn finish_expression_statement(
+    &mut self,
+    value: ExpressionCtx,
+    is_block_like: bool,
+  ) -> ParseResult<BlockItem> {
+    let &(pos, next) = self.lexer.peek_pos()?;
+
+    match next {
+      Token::Semicolon => {
+        self.lexer.take()?;
+        Ok(BlockItem::Statement(StatementCtx(
+          value.0.to(pos),
+          Statement::Expression { value },
+        )))
+      }
+      Token::RBrace | Token::EOF => Ok(BlockItem::Result(value)),
+      _ if is_block_like => Ok(BlockItem::Statement(StatementCtx(
+        value.0,
+        Statement::Expression { value },
+      ))),
+      _ => Err(ParseErrorCtx(
+        pos,
+        ParseError::UnexpectedToken {
+          expected: vec![TokenKind::Semicolon],
+          was: next.to_kind(),
+        },
+      )),
+    }
+  }
+
+  // A trailing expression is returned as an expression statement. Functions
+  // and structs aren't statements, so they're only accepted by parse_program.
+  pub fn parse_statement(&mut self) -> ParseResult<StatementCtx> {
+    with_stack(PASS_STACK_SIZE, || {
+      let (pos, was) = match self.parse_block_item()? {
+        BlockItem::Statement(statement) => return Ok(statement),
+        BlockItem::Result(value) => {
+          return Ok(StatementCtx(value.0, Statement::Expression { value }))
+        }
+        BlockItem::Function(FunctionDeclarationCtx(pos, _)) => (pos, TokenKind::Fn),
+        BlockItem::Struct(StructDeclarationCtx(pos, _)) => (pos, TokenKind::Struct),
+        BlockItem::Enum(EnumDeclarationCtx(pos, _)) => (pos, TokenKind::Enum),
+      };
+
+      Err(ParseErrorCtx(
+        pos,
+        ParseError::UnexpectedToken {
+          expected: statement_start(),
+          was,
+        },
+      ))
+    })
+  }
+
+  // Skips tokens until the start of the next statement: past the next `;`, or
+  // up to the `}` closing the current block. Returns the span of the last
+  // skipped token, if any.
+  fn synchronize(&mut self) -> Option<Span> {
+    let mut depth = 0;
+    let mut skipped = None;
 
     loop {
-      let next = self.lexer.peek()?;
-      if *next == Token::EOF {
-        break;
+      let span = match self.lexer.peek_pos() {
+        Ok(&(_, Token::EOF)) => return skipped,
+        Ok(&(_, Token::RBrace)) if depth == 0 && self.block_depth > 0 => return skipped,
+        Ok(&(span, Token::Semicolon)) if depth == 0 => {
+          self.lexer.take_pos().ok();
+          return Some(span);
+        }
+        Ok(&(span, Token::LBrace)) => {
+          depth += 1;
+          span
+        }
+        Ok(&(span, Token::RBrace)) => {
+          depth = usize::saturating_sub(depth, 1);
+          span
+        }
+        Ok(&(span, _)) => span,
+        Err(LexerErrorCtx(span, _)) => span,
+      };
+
+      self.lexer.take_pos().ok();
+      skipped = Some(span);
+    }
+  }
+
+  // Parses a statement. On a syntax error the error is recorded, the parser
+  // skips to the next statement and an error node is returned in its place.
+  fn parse_block_item_or_recover(&mut self) -> BlockItem {
+    let start = match self.lexer.peek_pos() {
+      Ok(&(span, _)) => span,
+      Err(LexerErrorCtx(span, _)) => span,
+    };
+
+    match self.parse_block_item() {
+      Ok(item) => item,
+      Err(err) => {
+        let end = err.0;
+        self.errors.push(err);
+        let end = self.synchronize().unwrap_or(end);
+        BlockItem::Statement(StatementCtx(start.to(end), Statement::Error))
       }
-      statements.push(self.parse_statement()?);
     }
+  }
 
-    Ok(Program(statements))
+  // Parses the whole program, recovering from syntax errors. The program is
+  // returned even if errors were found, in which case it contains
+  // Statement::Error nodes.
+  pub fn parse_program(&mut self) -> (Program, Vec<ParseErrorCtx>) {
+    with_stack(PASS_STACK_SIZE, || {
+      let body = self.parse_block_contents();
+      let program = Program {
+        structs: std::mem::take(&mut self.structs),
+        enums: std::mem::take(&mut self.enums),
+        functions: std::mem::take(&mut self.functions),
+        body,
+      };
+      (program, std::mem::take(&mut self.errors))
+    })
   }
 }
 
 #[cfg(test)]
 mod parser_tests {
-  use super::{Parser, TokenStream};
+  use super::{ParseError, ParseErrorCtx, Parser, TokenKind, TokenStream};
   use crate::ast::Expression::*;
   use crate::ast::Statement::*;
-  use crate::ast::{ExpressionCtx, IdentifierCtx, StatementCtx};
+  use crate::ast::{self, ExpressionCtx, IdentifierCtx, StatementCtx, Type, TypeCtx};
+  use crate::ast_common::BinaryOperator;
+  use crate::span::Span;
 
   #[test]
   fn parse_declaration() {
-    let mut parser = Parser {
-      lexer: &mut TokenStream::new("let x = 10;"),
-    };
+    let mut lexer = TokenStream::new("let x = 10;");
+    let mut parser = Parser::new(&mut lexer);
 
     let statement = parser.parse_statement();
 
     match statement {
       Ok(StatementCtx(
-        0,
+        Span { start: 0, end: 11 },
         DeclareVariable {
           ref name,
           is_mutable: false,
           initial_type: None,
-          initial_value: ExpressionCtx(8, IntegerConstant(10)),
+          initial_value: ExpressionCtx(Span { start: 8, end: 10 }, IntegerConstant(10, None)),
         },
       )) if name.1 == "x" => {}
       _ => panic!("Unexpected AST: {:#?}", statement),
@@ -269,20 +1225,19 @@ mod parser_tests {
 
   #[test]
   fn parse_declaration_with_type_annotation() {
-    let mut parser = Parser {
-      lexer: &mut TokenStream::new("let x : i32 = 10;"),
-    };
+    let mut lexer = TokenStream::new("let x : i32 = 10;");
+    let mut parser = Parser::new(&mut lexer);
 
     let statement = parser.parse_statement();
 
     match statement {
       Ok(StatementCtx(
-        0,
+        Span { start: 0, end: 17 },
         DeclareVariable {
-          name: IdentifierCtx(4, ref name),
+          name: IdentifierCtx(Span { start: 4, end: 5 }, ref name),
           is_mutable: false,
-          initial_type: Some(IdentifierCtx(8, ref type_name)),
-          initial_value: ExpressionCtx(14, IntegerConstant(10)),
+          initial_type: Some(TypeCtx(Span { start: 8, end: 11 }, Type::Named(ref type_name))),
+          initial_value: ExpressionCtx(Span { start: 14, end: 16 }, IntegerConstant(10, None)),
         },
       )) if name == "x" && type_name == "i32" => {}
       _ => panic!("Unexpected AST: {:#?}", statement),
@@ -291,20 +1246,19 @@ mod parser_tests {
 
   #[test]
   fn parse_mut_declaration() {
-    let mut parser = Parser {
-      lexer: &mut TokenStream::new("let mut mutable_x = 0;"),
-    };
+    let mut lexer = TokenStream::new("let mut mutable_x = 0;");
+    let mut parser = Parser::new(&mut lexer);
 
     let statement = parser.parse_statement();
 
     match statement {
       Ok(StatementCtx(
-        0,
+        Span { start: 0, end: 22 },
         DeclareVariable {
-          name: IdentifierCtx(8, ref name),
+          name: IdentifierCtx(Span { start: 8, end: 17 }, ref name),
           is_mutable: true,
           initial_type: None,
-          initial_value: ExpressionCtx(20, IntegerConstant(0)),
+          initial_value: ExpressionCtx(Span { start: 20, end: 21 }, IntegerConstant(0, None)),
         },
       )) if name == "mutable_x" => {}
       _ => panic!("Unexpected AST: {:#?}", statement),
@@ -313,28 +1267,603 @@ mod parser_tests {
 
   #[test]
   fn parse_block() {
-    let mut parser = Parser {
-      lexer: &mut TokenStream::new("{ let x = 0; }"),
-    };
+    let mut lexer = TokenStream::new("{ let x = 0; }");
+    let mut parser = Parser::new(&mut lexer);
 
     let statement = parser.parse_statement();
 
     assert_eq!(
       Ok(StatementCtx(
-        0,
-        Block {
-          inner: vec![StatementCtx(
-            2,
-            DeclareVariable {
-              name: IdentifierCtx(6, "x".to_string()),
-              initial_type: None,
-              is_mutable: false,
-              initial_value: ExpressionCtx(10, IntegerConstant(0))
-            }
-          )]
+        Span::new(0, 14),
+        Expression {
+          value: ExpressionCtx(
+            Span::new(0, 14),
+            Block(ast::Block {
+              statements: vec![StatementCtx(
+                Span::new(2, 12),
+                DeclareVariable {
+                  name: IdentifierCtx(Span::new(6, 7), "x".to_string()),
+                  initial_type: None,
+                  is_mutable: false,
+                  initial_value: ExpressionCtx(Span::new(10, 11), IntegerConstant(0, None))
+                }
+              )],
+              result: None
+            })
+          )
         }
       )),
       statement
     );
   }
+
+  #[test]
+  fn parse_block_result() {
+    let mut lexer = TokenStream::new("let y = { x; x + 1 }; y");
+    let mut parser = Parser::new(&mut lexer);
+
+    let (program, errors) = parser.parse_program();
+    assert_eq!(Vec::<ParseErrorCtx>::new(), errors);
+
+    let block = program.body;
+    assert_eq!(1, block.statements.len());
+    assert_eq!(
+      Some(Box::new(ExpressionCtx(
+        Span::new(22, 23),
+        Local("y".to_string())
+      ))),
+      block.result
+    );
+
+    match &block.statements[0].1 {
+      DeclareVariable {
+        initial_value: ExpressionCtx(_, Block(inner)),
+        ..
+      } => {
+        assert_eq!(1, inner.statements.len());
+        assert_eq!(Span::new(13, 18), inner.result.as_ref().unwrap().0);
+      }
+      statement => panic!("Unexpected AST: {:#?}", statement),
+    }
+  }
+
+  #[test]
+  fn parse_block_statement_ends_at_brace() {
+    let mut lexer = TokenStream::new("{ 1 } -2");
+    let mut parser = Parser::new(&mut lexer);
+
+    let (program, errors) = parser.parse_program();
+    assert_eq!(Vec::<ParseErrorCtx>::new(), errors);
+    assert_eq!(1, program.body.statements.len());
+    assert_eq!(Span::new(6, 8), program.body.result.unwrap().0);
+  }
+
+  #[test]
+  fn parse_missing_semicolon_between_expressions() {
+    let mut lexer = TokenStream::new("x y");
+    let mut parser = Parser::new(&mut lexer);
+
+    let (_, errors) = parser.parse_program();
+    assert_eq!(
+      vec![ParseErrorCtx(
+        Span::new(2, 3),
+        ParseError::UnexpectedToken {
+          expected: vec![TokenKind::Semicolon],
+          was: TokenKind::Identifier,
+        }
+      )],
+      errors
+    );
+  }
+
+  #[test]
+  fn parse_invalid_assignment_target() {
+    let mut lexer = TokenStream::new("x + 1 = 2;");
+    let mut parser = Parser::new(&mut lexer);
+
+    assert_eq!(
+      Err(ParseErrorCtx(
+        Span::new(0, 5),
+        ParseError::InvalidAssignmentTarget
+      )),
+      parser.parse_statement()
+    );
+  }
+
+  #[test]
+  fn parse_expression_spans() {
+    let mut lexer = TokenStream::new("(a + 1) * -b");
+    let mut parser = Parser::new(&mut lexer);
+
+    let expression = parser.parse_expression().unwrap();
+    assert_eq!(Span::new(0, 12), expression.0);
+
+    match expression.1 {
+      BinaryOp(BinaryOperator::Mul, ref args) => {
+        assert_eq!(Span::new(0, 7), (args.0).0);
+        assert_eq!(Span::new(10, 12), (args.1).0);
+      }
+      _ => panic!("Unexpected AST: {:#?}", expression),
+    }
+  }
+
+  #[test]
+  fn recover_partial_program() {
+    let mut lexer = TokenStream::new("let x = ; let y = 2; { let z = ; } let w");
+    let mut parser = Parser::new(&mut lexer);
+
+    let (program, errors) = parser.parse_program();
+    assert_eq!(3, errors.len());
+
+    let statements: Vec<_> = program
+      .body
+      .statements
+      .into_iter()
+      .map(|x| (x.0, x.1))
+      .collect();
+    match &statements[..] {
+      [(first, Error), (_, DeclareVariable { .. }), (_, Expression { value }), (last, Error)] => {
+        assert_eq!(Span::new(0, 9), *first);
+        assert_eq!(Span::new(35, 40), *last);
+        match &value.1 {
+          Block(inner) => {
+            assert_eq!(Span::new(23, 32), inner.statements[0].0);
+            assert_eq!(Error, inner.statements[0].1);
+          }
+          _ => panic!("Unexpected AST: {:#?}", value),
+        }
+      }
+      _ => panic!("Unexpected AST: {:#?}", statements),
+    }
+  }
+
+  #[test]
+  fn recover_unclosed_block() {
+    let mut lexer = TokenStream::new("{ let x = 1;");
+    let mut parser = Parser::new(&mut lexer);
+
+    let (program, errors) = parser.parse_program();
+    assert_eq!(1, errors.len());
+    match program.body.result.map(|x| x.1) {
+      Some(Block(inner)) => assert_eq!(1, inner.statements.len()),
+      _ => panic!("Unexpected AST: {:#?}", errors),
+    }
+  }
+
+  #[test]
+  fn parse_unexpected_statement() {
+    let mut lexer = TokenStream::new(");");
+    let mut parser = Parser::new(&mut lexer);
+
+    assert_eq!(
+      Err(ParseErrorCtx(
+        Span::new(0, 1),
+        ParseError::UnexpectedToken {
+          expected: super::statement_start(),
+          was: TokenKind::RParen,
+        }
+      )),
+      parser.parse_statement()
+    );
+  }
+
+  fn parse_source_expression(src: &str) -> ExpressionCtx {
+    let mut lexer = TokenStream::new(src);
+    let mut parser = Parser::new(&mut lexer);
+    parser.parse_expression().unwrap()
+  }
+
+  // Renders an expression with explicit parentheses.
+  fn parenthesize(expression: &ExpressionCtx) -> String {
+    match &expression.1 {
+      IntegerConstant(i, _) => i.to_string(),
+      BooleanConstant(b) => b.to_string(),
+      Local(name) => name.clone(),
+      UnaryOp(op, x) => format!("({}{})", op, parenthesize(x)),
+      BinaryOp(op, args) => format!(
+        "({} {} {})",
+        parenthesize(&args.0),
+        op,
+        parenthesize(&args.1)
+      ),
+      Cast {
+        value,
+        type_: TypeCtx(_, ast::Type::Named(name)),
+      } => format!("({} as {})", parenthesize(value), name),
+      _ => panic!("Unexpected AST: {:#?}", expression),
+    }
+  }
+
+  #[test]
+  fn parse_left_associative() {
+    assert_eq!(
+      "((10 - 2) - 3)",
+      parenthesize(&parse_source_expression("10 - 2 - 3"))
+    );
+    assert_eq!(
+      "(((a * b) * c) + d)",
+      parenthesize(&parse_source_expression("a * b * c + d"))
+    );
+  }
+
+  #[test]
+  fn parse_precedence() {
+    assert_eq!(
+      "((a || ((b + (1 * 2)) < c)) || ((!d) && (e == f)))",
+      parenthesize(&parse_source_expression(
+        "a || b + 1 * 2 < c || !d && e == f"
+      ))
+    );
+  }
+
+  #[test]
+  fn parse_bitwise_precedence() {
+    assert_eq!(
+      "(((a | (b ^ (c & (d << 1)))) == (e % 2)) && ((~f) >> (g / h)))",
+      parenthesize(&parse_source_expression(
+        "a | b ^ c & d << 1 == e % 2 && ~f >> g / h"
+      ))
+    );
+  }
+
+  #[test]
+  fn parse_cast_precedence() {
+    assert_eq!(
+      "((a + ((-b) as f64)) < ((c as i32) as u8))",
+      parenthesize(&parse_source_expression("a + -b as f64 < c as i32 as u8"))
+    );
+  }
+
+  #[test]
+  fn parse_else_if_chain() {
+    let expression = parse_source_expression("if a { 1 } else if b { 2 } else { 3 }");
+    assert_eq!(Span::new(0, 37), expression.0);
+
+    match expression.1 {
+      If {
+        else_branch: Some(else_branch),
+        ..
+      } => match else_branch.1 {
+        If {
+          else_branch: Some(else_branch),
+          ..
+        } => assert_eq!(Span::new(32, 37), else_branch.0),
+        _ => panic!("Unexpected AST: {:#?}", else_branch),
+      },
+      _ => panic!("Unexpected AST: {:#?}", expression),
+    }
+  }
+
+  #[test]
+  fn parse_if_statement_ends_at_brace() {
+    let mut lexer = TokenStream::new("if a { 1; } -x");
+    let mut parser = Parser::new(&mut lexer);
+    let (program, errors) = parser.parse_program();
+    assert_eq!(Vec::<ParseErrorCtx>::new(), errors);
+    assert_eq!(1, program.body.statements.len());
+    assert!(program.body.result.is_some());
+  }
+
+  #[test]
+  fn parse_function() {
+    let mut lexer = TokenStream::new("fn add(a: i32, b: i32,) -> i32 { a + b } add(1, 2)");
+    let mut parser = Parser::new(&mut lexer);
+    let (program, errors) = parser.parse_program();
+    assert_eq!(Vec::<ParseErrorCtx>::new(), errors);
+
+    let ast::FunctionDeclarationCtx(pos, function) = &program.functions[0];
+    assert_eq!(Span::new(0, 40), *pos);
+    assert_eq!(
+      IdentifierCtx(Span::new(3, 6), "add".to_string()),
+      function.name
+    );
+    assert_eq!(
+      vec!["a", "b"],
+      function
+        .parameters
+        .iter()
+        .map(|parameter| parameter.name.1.as_str())
+        .collect::<Vec<_>>()
+    );
+    assert_eq!(
+      Some(TypeCtx(Span::new(27, 30), Type::Named("i32".to_string()))),
+      function.return_type
+    );
+
+    match program.body.result.map(|x| *x) {
+      Some(ExpressionCtx(pos, Call { callee, arguments })) => {
+        assert_eq!(Span::new(41, 50), pos);
+        assert_eq!(Local("add".to_string()), callee.1);
+        assert_eq!(2, arguments.len());
+      }
+      result => panic!("Unexpected AST: {:#?}", result),
+    }
+  }
+
+  #[test]
+  fn parse_nested_function() {
+    let mut lexer = TokenStream::new("{ fn f() { } }");
+    let mut parser = Parser::new(&mut lexer);
+    let (_, errors) = parser.parse_program();
+    assert_eq!(Span::new(2, 4), errors[0].0);
+  }
+
+  #[test]
+  fn parse_call_binds_tighter_than_unary() {
+    match parse_source_expression("-f(1)(2)").1 {
+      UnaryOp(_, operand) => match operand.1 {
+        Call { callee, .. } => assert_eq!(Span::new(1, 5), callee.0),
+        _ => panic!("Unexpected AST: {:#?}", operand),
+      },
+      expression => panic!("Unexpected AST: {:#?}", expression),
+    }
+  }
+
+  #[test]
+  fn parse_closure() {
+    match parse_source_expression("|a: i32, b: i32| -> i32 { a + b }") {
+      ExpressionCtx(
+        pos,
+        Closure {
+          parameters,
+          return_type,
+          body,
+        },
+      ) => {
+        assert_eq!(Span::new(0, 33), pos);
+        assert_eq!(2, parameters.len());
+        assert_eq!(
+          Some(TypeCtx(Span::new(20, 23), Type::Named("i32".to_string()))),
+          return_type
+        );
+        assert_eq!(Span::new(24, 33), body.0);
+      }
+      expression => panic!("Unexpected AST: {:#?}", expression),
+    }
+
+    match parse_source_expression("|| 1 + 2").1 {
+      Closure {
+        parameters, body, ..
+      } => {
+        assert!(parameters.is_empty());
+        assert_eq!(Span::new(3, 8), body.0);
+      }
+      expression => panic!("Unexpected AST: {:#?}", expression),
+    }
+  }
+
+  #[test]
+  fn parse_array_type() {
+    let mut lexer = TokenStream::new("let a: [[bool]] = [];");
+    let mut parser = Parser::new(&mut lexer);
+
+    match parser.parse_statement() {
+      Ok(StatementCtx(
+        _,
+        DeclareVariable {
+          initial_type: Some(TypeCtx(pos, Type::Array(element))),
+          ..
+        },
+      )) => {
+        assert_eq!(Span::new(7, 15), pos);
+        match element.1 {
+          Type::Array(inner) => assert_eq!(Type::Named("bool".to_string()), inner.1),
+          element => panic!("Unexpected type: {:#?}", element),
+        }
+      }
+      statement => panic!("Unexpected AST: {:#?}", statement),
+    }
+  }
+
+  #[test]
+  fn parse_function_type() {
+    let mut lexer = TokenStream::new("let f: fn(i32, [bool]) -> fn() = g;");
+    let mut parser = Parser::new(&mut lexer);
+
+    let named =
+      |start, end, name: &str| TypeCtx(Span::new(start, end), Type::Named(name.to_string()));
+    match parser.parse_statement() {
+      Ok(StatementCtx(
+        _,
+        DeclareVariable {
+          initial_type: Some(type_),
+          ..
+        },
+      )) => assert_eq!(
+        TypeCtx(
+          Span::new(7, 30),
+          Type::Function {
+            parameters: vec![
+              named(10, 13, "i32"),
+              TypeCtx(
+                Span::new(15, 21),
+                Type::Array(Box::new(named(16, 20, "bool")))
+              ),
+            ],
+            return_type: Some(Box::new(TypeCtx(
+              Span::new(26, 30),
+              Type::Function {
+                parameters: vec![],
+                return_type: None
+              }
+            ))),
+          }
+        ),
+        type_
+      ),
+      statement => panic!("Unexpected AST: {:#?}", statement),
+    }
+  }
+
+  #[test]
+  fn parse_generics() {
+    let mut lexer = TokenStream::new(
+      "struct Pair<A, B> { a: A, b: B } fn f<T>(p: Pair<T, Option<Option<T>>>) { }",
+    );
+    let mut parser = Parser::new(&mut lexer);
+    let (program, errors) = parser.parse_program();
+    assert_eq!(Vec::<ParseErrorCtx>::new(), errors);
+
+    let names = |parameters: &[IdentifierCtx]| {
+      parameters
+        .iter()
+        .map(|name| name.1.clone())
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(vec!["A", "B"], names(&program.structs[0].1.type_parameters));
+    let function = &program.functions[0].1;
+    assert_eq!(vec!["T"], names(&function.type_parameters));
+
+    // The closing `>>>` ends three lists of type arguments.
+    let named =
+      |start, name: &str| TypeCtx(Span::new(start, start + 1), Type::Named(name.to_string()));
+    let inner = TypeCtx(
+      Span::new(59, 68),
+      Type::Applied("Option".to_string(), vec![named(66, "T")]),
+    );
+    assert_eq!(
+      Some(TypeCtx(
+        Span::new(44, 70),
+        Type::Applied(
+          "Pair".to_string(),
+          vec![
+            named(49, "T"),
+            TypeCtx(
+              Span::new(52, 69),
+              Type::Applied("Option".to_string(), vec![inner])
+            ),
+          ]
+        )
+      )),
+      function.parameters[0].type_
+    );
+  }
+
+  #[test]
+  fn parse_index_assignment() {
+    let mut lexer = TokenStream::new("a[0][i + 1] = [1, 2,];");
+    let mut parser = Parser::new(&mut lexer);
+
+    match parser.parse_statement() {
+      Ok(StatementCtx(
+        pos,
+        AssignIndex {
+          array: ExpressionCtx(array_pos, Index { .. }),
+          index: ExpressionCtx(index_pos, BinaryOp(BinaryOperator::Add, _)),
+          value: ExpressionCtx(_, Array(elements)),
+        },
+      )) => {
+        assert_eq!(Span::new(0, 22), pos);
+        assert_eq!(Span::new(0, 4), array_pos);
+        assert_eq!(Span::new(5, 10), index_pos);
+        assert_eq!(2, elements.len());
+      }
+      statement => panic!("Unexpected AST: {:#?}", statement),
+    }
+  }
+
+  #[test]
+  fn parse_struct() {
+    let mut lexer =
+      TokenStream::new("struct Point { x: i32, y: [i32], } let p = Point { y: [], x: 1 }; p.y");
+    let mut parser = Parser::new(&mut lexer);
+    let (program, errors) = parser.parse_program();
+    assert_eq!(Vec::<ParseErrorCtx>::new(), errors);
+
+    let ast::StructDeclarationCtx(pos, declaration) = &program.structs[0];
+    assert_eq!(Span::new(0, 34), *pos);
+    assert_eq!("Point", declaration.name.1);
+    assert_eq!(
+      vec!["x", "y"],
+      declaration
+        .fields
+        .iter()
+        .map(|field| field.name.1.as_str())
+        .collect::<Vec<_>>()
+    );
+
+    match &program.body.statements[0].1 {
+      DeclareVariable {
+        initial_value: ExpressionCtx(pos, StructLiteral { name, fields }),
+        ..
+      } => {
+        assert_eq!(Span::new(43, 64), *pos);
+        assert_eq!("Point", name.1);
+        assert_eq!("y", (fields[0].0).1);
+      }
+      statement => panic!("Unexpected AST: {:#?}", statement),
+    }
+
+    match program.body.result.map(|x| *x) {
+      Some(ExpressionCtx(pos, Field { field, .. })) => {
+        assert_eq!(Span::new(66, 69), pos);
+        assert_eq!("y", field.1);
+      }
+      result => panic!("Unexpected AST: {:#?}", result),
+    }
+  }
+
+  #[test]
+  fn parse_no_struct_literal_in_condition() {
+    match parse_source_expression("if x { y } else { (P { y: 1 }) }").1 {
+      If {
+        condition,
+        else_branch: Some(else_branch),
+        ..
+      } => {
+        assert_eq!(Local("x".to_string()), condition.1);
+        match else_branch.1 {
+          Block(ast::Block {
+            result: Some(result),
+            ..
+          }) => match result.1 {
+            StructLiteral { .. } => {}
+            result => panic!("Unexpected AST: {:#?}", result),
+          },
+          else_branch => panic!("Unexpected AST: {:#?}", else_branch),
+        }
+      }
+      expression => panic!("Unexpected AST: {:#?}", expression),
+    }
+  }
+
+  #[test]
+  fn parse_match() {
+    match parse_source_expression(
+      "match s { Shape::Rect(w, _) => w, -1 => 1, Shape::Empty => { 0 } _ => 2 }",
+    )
+    .1
+    {
+      Match { scrutinee, arms } => {
+        assert_eq!(Local("s".to_string()), scrutinee.1);
+        assert_eq!(4, arms.len());
+
+        let ast::PatternCtx(pos, pattern) = &arms[0].pattern;
+        assert_eq!(Span::new(10, 27), *pos);
+        match pattern {
+          ast::Pattern::Variant {
+            type_name,
+            variant,
+            fields,
+          } => {
+            assert_eq!("Shape", type_name.1);
+            assert_eq!("Rect", variant.1);
+            assert_eq!(
+              vec![
+                ast::Pattern::Binding("w".to_string()),
+                ast::Pattern::Wildcard
+              ],
+              fields.iter().map(|x| x.1.clone()).collect::<Vec<_>>()
+            );
+          }
+          pattern => panic!("Unexpected pattern: {:#?}", pattern),
+        }
+
+        assert_eq!(ast::Pattern::Integer(-1, None), arms[1].pattern.1);
+        // No comma is needed after a block.
+        assert_eq!(ast::Pattern::Wildcard, arms[3].pattern.1);
+      }
+      expression => panic!("Unexpected AST: {:#?}", expression),
+    }
+  }
 }
@@ -3,14 +3,18 @@
 // RAST (pronounced like rust, of course) stands for Resolved AST
 // It represents a higher level AST after name and/or type resolution.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
 
-use crate::ast_common::{BinaryOperator, UnaryOperator};
+use crate::ast_common::{BinaryOperator, Float, Operator, UnaryOperator};
+use crate::span::Span;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Default)]
 pub struct ScopeId(pub(crate) usize);
 
 impl ScopeId {
+  #[allow(clippy::should_implement_trait)]
   pub fn next(&mut self) -> ScopeId {
     let current = self.0;
     self.0 += 1;
@@ -22,6 +26,7 @@ impl ScopeId {
 pub struct LocalId(pub(crate) usize);
 
 impl LocalId {
+  #[allow(clippy::should_implement_trait)]
   pub fn next(&mut self) -> LocalId {
     let current = self.0;
     self.0 += 1;
@@ -29,16 +34,88 @@ impl LocalId {
   }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Default)]
+pub struct LoopId(pub(crate) usize);
+
+impl LoopId {
+  #[allow(clippy::should_implement_trait)]
+  pub fn next(&mut self) -> LoopId {
+    let current = self.0;
+    self.0 += 1;
+    LoopId(current)
+  }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Default)]
+pub struct LiteralId(pub(crate) usize);
+
+impl LiteralId {
+  #[allow(clippy::should_implement_trait)]
+  pub fn next(&mut self) -> LiteralId {
+    let current = self.0;
+    self.0 += 1;
+    LiteralId(current)
+  }
+}
+
+// A use of a generic function, struct or enum, or an array literal, whose type
+// arguments or element type the type checker decides.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Default)]
+pub struct TypeArgumentsId(pub(crate) usize);
+
+impl TypeArgumentsId {
+  #[allow(clippy::should_implement_trait)]
+  pub fn next(&mut self) -> TypeArgumentsId {
+    let current = self.0;
+    self.0 += 1;
+    TypeArgumentsId(current)
+  }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Default)]
+pub struct TypeVariableId(pub(crate) usize);
+
+impl TypeVariableId {
+  #[allow(clippy::should_implement_trait)]
+  pub fn next(&mut self) -> TypeVariableId {
+    let current = self.0;
+    self.0 += 1;
+    TypeVariableId(current)
+  }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Default)]
+pub struct FunctionId(pub(crate) usize);
+
+impl FunctionId {
+  #[allow(clippy::should_implement_trait)]
+  pub fn next(&mut self) -> FunctionId {
+    let current = self.0;
+    self.0 += 1;
+    FunctionId(current)
+  }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 pub enum TypeRef {
   Primitive(PrimitiveType),
   UserType(UserTypeId),
+  // A type that is not known yet, and is found by the type checker.
+  Variable(TypeVariableId),
+  // The type of an expression that failed to type check. Like Never, it can be
+  // used as any type, so that one mistake isn't reported again wherever its
+  // value ends up.
+  Error,
+  // The type of an expression that never finishes, like a block that ends in
+  // a return. It has no values, so it can be used as any type.
+  Never,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Default)]
 pub struct UserTypeId(pub(crate) usize);
 
 impl UserTypeId {
+  #[allow(clippy::should_implement_trait)]
   pub fn next(&mut self) -> UserTypeId {
     let current = self.0;
     self.0 += 1;
@@ -46,15 +123,169 @@ impl UserTypeId {
   }
 }
 
+impl fmt::Display for TypeRef {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TypeRef::Primitive(primitive) => write!(f, "{}", primitive),
+      TypeRef::UserType(UserTypeId(id)) => write!(f, "type#{}", id),
+      TypeRef::Variable(_) => write!(f, "_"),
+      TypeRef::Error => write!(f, "{{error}}"),
+      TypeRef::Never => write!(f, "!"),
+    }
+  }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 pub enum PrimitiveType {
+  I8,
+  I16,
   I32,
+  I64,
+  U8,
+  U16,
+  U32,
+  U64,
+  F64,
   Bool,
+  Str,
+  Unit,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl PrimitiveType {
+  // The smallest and the largest value of an integer type.
+  pub fn integer_range(self) -> Option<(i128, i128)> {
+    use PrimitiveType::*;
+    match self {
+      I8 => Some((i8::MIN.into(), i8::MAX.into())),
+      I16 => Some((i16::MIN.into(), i16::MAX.into())),
+      I32 => Some((i32::MIN.into(), i32::MAX.into())),
+      I64 => Some((i64::MIN.into(), i64::MAX.into())),
+      U8 => Some((0, u8::MAX.into())),
+      U16 => Some((0, u16::MAX.into())),
+      U32 => Some((0, u32::MAX.into())),
+      U64 => Some((0, u64::MAX.into())),
+      F64 | Bool | Str | Unit => None,
+    }
+  }
+
+  pub fn is_integer(self) -> bool {
+    self.integer_range().is_some()
+  }
+
+  pub fn is_signed(self) -> bool {
+    matches!(self.integer_range(), Some((min, _)) if min < 0)
+  }
+
+  // The types that support arithmetic and can be cast into each other.
+  pub fn is_numeric(self) -> bool {
+    self.is_integer() || self == PrimitiveType::F64
+  }
+}
+
+impl fmt::Display for PrimitiveType {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PrimitiveType::I8 => write!(f, "i8"),
+      PrimitiveType::I16 => write!(f, "i16"),
+      PrimitiveType::I32 => write!(f, "i32"),
+      PrimitiveType::I64 => write!(f, "i64"),
+      PrimitiveType::U8 => write!(f, "u8"),
+      PrimitiveType::U16 => write!(f, "u16"),
+      PrimitiveType::U32 => write!(f, "u32"),
+      PrimitiveType::U64 => write!(f, "u64"),
+      PrimitiveType::F64 => write!(f, "f64"),
+      PrimitiveType::Bool => write!(f, "bool"),
+      PrimitiveType::Str => write!(f, "str"),
+      PrimitiveType::Unit => write!(f, "()"),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructField {
+  pub name: String,
+  pub type_: TypeRef,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructDef {
+  pub name: String,
+  pub type_parameters: Vec<TypeVariableId>,
+  // In declaration order, which is also the order of the values at runtime.
+  pub fields: Vec<StructField>,
+  // Span of the name in the declaration.
+  pub span: Span,
+}
+
+impl StructDef {
+  pub fn field_index(&self, name: &str) -> Option<usize> {
+    self.fields.iter().position(|field| field.name == name)
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnumVariant {
+  pub name: String,
+  pub fields: Vec<TypeRef>,
+  // Span of the name in the declaration.
+  pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnumDef {
+  pub name: String,
+  pub type_parameters: Vec<TypeVariableId>,
+  pub variants: Vec<EnumVariant>,
+  // Span of the name in the declaration.
+  pub span: Span,
+}
+
+impl EnumDef {
+  pub fn variant_index(&self, name: &str) -> Option<usize> {
+    self
+      .variants
+      .iter()
+      .position(|variant| variant.name == name)
+  }
+}
+
+// Structural types, such as function types, are interned: two of them are
+// equal exactly when their ids are. Structs are nominal, so every declaration
+// is a type of its own. The field types of a generic struct or enum refer to
+// its type parameters, and each use of it with type arguments is an interned
+// Applied type.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum UserTypeDef {
   Array(TypeRef),
+  Function {
+    parameters: Vec<TypeRef>,
+    return_type: TypeRef,
+  },
+  Struct(StructDef),
+  Enum(EnumDef),
+  Applied {
+    type_id: UserTypeId,
+    arguments: Vec<TypeRef>,
+  },
+}
+
+impl UserTypeDef {
+  // Span of the name of a declared type. Other types aren't declared.
+  pub fn declaration_span(&self) -> Option<Span> {
+    match self {
+      UserTypeDef::Struct(def) => Some(def.span),
+      UserTypeDef::Enum(def) => Some(def.span),
+      _ => None,
+    }
+  }
+
+  pub fn type_parameters(&self) -> &[TypeVariableId] {
+    match self {
+      UserTypeDef::Struct(def) => &def.type_parameters,
+      UserTypeDef::Enum(def) => &def.type_parameters,
+      _ => &[],
+    }
+  }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -63,20 +294,138 @@ pub struct UserType {
   pub type_def: UserTypeDef,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TypeVariableKind {
+  General,
+  // The type of an integer literal without a suffix. It can only be an integer
+  // type, and is i32 if nothing decides otherwise.
+  Integer,
+  // A type parameter of a generic declaration, or a variable that the
+  // signature of a function was generalized over. It only unifies with
+  // itself, and every use of the declaration replaces it with a fresh
+  // variable.
+  Generic,
+}
+
+// What a type variable stands for, to explain why it's needed when it can't
+// be inferred.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TypeVariableOrigin {
+  Local(LocalId),
+  EmptyArray,
+  Expression,
+  TypeParameter,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TypeVariable {
+  pub id: TypeVariableId,
+  pub kind: TypeVariableKind,
+  pub origin: TypeVariableOrigin,
+  // The type the variable has been unified with, if any.
+  pub value: Option<TypeRef>,
+  // Whether the variable was unified with Never. If nothing else decides it,
+  // it becomes ().
+  pub diverging: bool,
+  // The name of a type parameter, to show in errors.
+  pub name: Option<String>,
+  pub span: Span,
+}
+
+// An operator whose operands had types that weren't known yet when it was
+// type checked, so it is checked again once they are.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DeferredOperation {
+  pub op: Operator,
+  pub operands: Vec<TypeRef>,
+  pub span: Span,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Local {
   pub id: LocalId,
   pub scope_id: ScopeId,
-  pub initial_type: Option<TypeRef>,
+  // A type variable until the type checker has inferred it, unless the local
+  // has an annotation.
+  pub type_: TypeRef,
   pub name: String,
+  pub is_mutable: bool,
+  // Span of the name in the declaration.
+  pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LoopKind {
+  While,
+  Loop,
 }
 
+// An integer literal, whose type is decided by its suffix, or by the type
+// checker from where it's used.
 #[derive(Debug, PartialEq, Eq)]
+pub struct Literal {
+  pub id: LiteralId,
+  pub value: i128,
+  pub type_: TypeRef,
+  pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Loop {
+  pub id: LoopId,
+  pub kind: LoopKind,
+  // The type of the values passed to break, once one has been type checked.
+  pub break_type: Option<TypeRef>,
+}
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CaptureMode {
+  // Immutable locals are copied into the closure when it's created.
+  ByValue,
+  // Mutable locals are shared between the closure and its surroundings, so
+  // that assignments on either side are visible to the other.
+  ByReference,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Capture {
+  pub local_id: LocalId,
+  pub mode: CaptureMode,
+}
+
+// The signature of a function or a closure. The body of a function is in the
+// RastProgram, and the body of a closure is in the closure expression.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Function {
+  pub id: FunctionId,
+  pub name: String,
+  // The scope of the parameters, which is the parent of the body's scope.
+  pub scope_id: ScopeId,
+  pub parameters: Vec<LocalId>,
+  // A type variable for closures without an annotated return type.
+  pub return_type: TypeRef,
+  // The variables in the signature that the function is generic over, which
+  // are only known once it has been type checked.
+  pub type_parameters: Vec<TypeVariableId>,
+  // Locals of enclosing scopes used by a closure. Always empty for functions.
+  pub captures: Vec<Capture>,
+  // Span of the name in the declaration, or of the whole closure.
+  pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub struct Scope {
   pub id: ScopeId,
   pub parent: Option<ScopeId>,
   pub locals: HashSet<LocalId>,
+  // Maps each name to the latest local declared with it in this scope, so a
+  // redeclaration shadows the earlier local from that point on.
+  pub names: HashMap<String, LocalId>,
+  // Set on the scope of a loop body, so that break and continue can find the
+  // innermost loop around them.
+  pub loop_id: Option<LoopId>,
+  // Set on the parameter scope of a function, for return.
+  pub function_id: Option<FunctionId>,
 }
 
 impl Scope {
@@ -85,35 +434,192 @@ impl Scope {
       id,
       parent,
       locals: HashSet::new(),
+      names: HashMap::new(),
+      loop_id: None,
+      function_id: None,
+    }
+  }
+}
+
+// Functions provided by the language itself. They can be shadowed by locals and
+// by functions of the same name.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Builtin {
+  // The length of an array.
+  Len,
+}
+
+impl Builtin {
+  pub fn from_name(name: &str) -> Option<Builtin> {
+    match name {
+      "len" => Some(Builtin::Len),
+      _ => None,
     }
   }
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum RastExpression {
-  IntegerConstant(i128),
+  IntegerConstant(i128, LiteralId),
+  FloatConstant(Float),
+  BooleanConstant(bool),
+  StringConstant(String),
   Local(LocalId),
   UnaryOp(UnaryOperator, Box<RastExpressionCtx>),
   BinaryOp(BinaryOperator, Box<(RastExpressionCtx, RastExpressionCtx)>),
+  Block(RastBlock),
+  If {
+    condition: Box<RastExpressionCtx>,
+    then_branch: Box<RastExpressionCtx>,
+    else_branch: Option<Box<RastExpressionCtx>>,
+  },
+  Loop {
+    loop_id: LoopId,
+    body: RastBlock,
+  },
+  // A function used as a value.
+  Function(FunctionId, TypeArgumentsId),
+  Closure(Rc<RastFunction>),
+  Call {
+    function_id: FunctionId,
+    type_arguments_id: TypeArgumentsId,
+    arguments: Vec<RastExpressionCtx>,
+  },
+  // A call to a function value, such as a closure.
+  CallIndirect {
+    callee: Box<RastExpressionCtx>,
+    arguments: Vec<RastExpressionCtx>,
+  },
+  Builtin {
+    builtin: Builtin,
+    arguments: Vec<RastExpressionCtx>,
+  },
+  Array(Vec<RastExpressionCtx>, TypeArgumentsId),
+  Index {
+    array: Box<RastExpressionCtx>,
+    index: Box<RastExpressionCtx>,
+  },
+  // The values are paired with the index of their field, and kept in the
+  // order they're written in.
+  Struct {
+    type_id: UserTypeId,
+    type_arguments_id: TypeArgumentsId,
+    fields: Vec<(usize, RastExpressionCtx)>,
+  },
+  // Fields are looked up by name, since the type of the value is only known
+  // after type checking.
+  Field {
+    value: Box<RastExpressionCtx>,
+    field: String,
+  },
+  Variant {
+    type_id: UserTypeId,
+    type_arguments_id: TypeArgumentsId,
+    variant: usize,
+    arguments: Vec<RastExpressionCtx>,
+  },
+  // The first arm whose pattern matches is taken.
+  Match {
+    scrutinee: Box<RastExpressionCtx>,
+    arms: Vec<RastMatchArm>,
+  },
+  Cast {
+    value: Box<RastExpressionCtx>,
+    type_: TypeRef,
+  },
 }
 
 #[derive(Debug, PartialEq, Eq)]
-pub struct RastExpressionCtx(pub usize, pub RastExpression);
+pub struct RastExpressionCtx(pub Span, pub RastExpression);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RastPattern {
+  Wildcard,
+  Binding(LocalId),
+  // The value and the type of the suffix, if any.
+  Integer(i128, Option<TypeRef>),
+  Boolean(bool),
+  Variant {
+    type_id: UserTypeId,
+    type_arguments_id: TypeArgumentsId,
+    variant: usize,
+    fields: Vec<RastPatternCtx>,
+  },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RastPatternCtx(pub Span, pub RastPattern);
+
+// Each arm has a scope of its own, for the locals bound by its pattern.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RastMatchArm {
+  pub scope_id: ScopeId,
+  pub pattern: RastPatternCtx,
+  pub body: RastExpressionCtx,
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum RastStatement {
-  Block {
-    scope_id: ScopeId,
-    inner: Vec<RastStatementCtx>,
+  // Every execution of a declaration creates a new variable, even if it
+  // happens in a loop.
+  DeclareLocal {
+    local_id: LocalId,
+    value: RastExpressionCtx,
   },
   AssignLocal {
     local_id: LocalId,
     value: RastExpressionCtx,
   },
+  AssignIndex {
+    array: RastExpressionCtx,
+    index: RastExpressionCtx,
+    value: RastExpressionCtx,
+  },
+  AssignField {
+    target: RastExpressionCtx,
+    field: String,
+    value: RastExpressionCtx,
+  },
+  Expression {
+    value: RastExpressionCtx,
+  },
+  While {
+    loop_id: LoopId,
+    condition: RastExpressionCtx,
+    body: RastBlock,
+  },
+  Break {
+    loop_id: LoopId,
+    value: Option<RastExpressionCtx>,
+  },
+  Continue {
+    loop_id: LoopId,
+  },
+  Return {
+    function_id: FunctionId,
+    value: Option<RastExpressionCtx>,
+  },
 }
 
 #[derive(Debug, PartialEq, Eq)]
-pub struct RastStatementCtx(pub usize, pub RastStatement);
+pub struct RastStatementCtx(pub Span, pub RastStatement);
+
+// Every block introduces a new scope. A block without a result has the value ().
+#[derive(Debug, PartialEq, Eq)]
+pub struct RastBlock {
+  pub scope_id: ScopeId,
+  pub statements: Vec<RastStatementCtx>,
+  pub result: Option<Box<RastExpressionCtx>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RastFunction {
+  pub id: FunctionId,
+  pub body: RastBlock,
+}
 
 #[derive(Debug)]
-pub struct RastProgram(pub Vec<RastStatementCtx>);
+pub struct RastProgram {
+  pub functions: Vec<RastFunction>,
+  pub body: RastBlock,
+}
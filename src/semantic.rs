@@ -1,70 +1,257 @@
 use crate::ast::*;
+use crate::ast_common::UnaryOperator;
 use crate::rast::*;
+use crate::span::Span;
+use crate::type_checker::TypeErrorCtx;
+use crate::utils::{with_stack, PASS_STACK_SIZE};
 use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct SemanticContext {
   user_types: HashMap<UserTypeId, UserType>,
+  type_names: HashMap<String, UserTypeId>,
   scopes: HashMap<ScopeId, Scope>,
   pub locals: HashMap<LocalId, Local>,
+  pub loops: HashMap<LoopId, Loop>,
+  pub literals: HashMap<LiteralId, Literal>,
+  pub functions: HashMap<FunctionId, Function>,
+  pub type_variables: HashMap<TypeVariableId, TypeVariable>,
+  pub deferred_operations: Vec<DeferredOperation>,
+  // The types that the type parameters of generic functions and types are
+  // given at each use of them, and the element types of array literals.
+  pub type_arguments: HashMap<TypeArgumentsId, Vec<TypeRef>>,
+  // The errors found by the type checker so far.
+  pub type_errors: Vec<TypeErrorCtx>,
+  function_names: HashMap<String, FunctionId>,
+  // The type parameters of the generic declaration being transformed.
+  type_parameter_names: HashMap<String, TypeRef>,
   next_scope_id: ScopeId,
   next_user_type_id: UserTypeId,
   next_local_id: LocalId,
+  next_loop_id: LoopId,
+  next_literal_id: LiteralId,
+  next_type_arguments_id: TypeArgumentsId,
+  next_function_id: FunctionId,
+  next_type_variable_id: TypeVariableId,
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum SemanticError {
-  UnknownType { name: String },
-  UnknownLocal { name: String },
+  UnknownType {
+    name: String,
+  },
+  UnknownLocal {
+    name: String,
+  },
+  AssignToImmutable {
+    name: String,
+    declaration: Span,
+  },
+  BreakOutsideLoop,
+  ContinueOutsideLoop,
+  ReturnOutsideFunction,
+  UnknownFunction {
+    name: String,
+  },
+  DuplicateFunction {
+    name: String,
+    previous: Span,
+  },
+  DuplicateType {
+    name: String,
+    previous: Span,
+  },
+  DuplicateField {
+    name: String,
+    previous: Span,
+  },
+  UnknownField {
+    struct_name: String,
+    field: String,
+  },
+  MissingFields {
+    struct_name: String,
+    fields: Vec<String>,
+  },
+  DuplicateVariant {
+    name: String,
+    previous: Span,
+  },
+  UnknownVariant {
+    enum_name: String,
+    variant: String,
+  },
+  DuplicateBinding {
+    name: String,
+    previous: Span,
+  },
+  DuplicateTypeParameter {
+    name: String,
+    previous: Span,
+  },
+  // A generic struct or enum used with the wrong number of type arguments, or
+  // any other type used with some.
+  WrongTypeArgumentCount {
+    name: String,
+    expected: usize,
+    actual: usize,
+  },
 }
 
 #[derive(Debug, PartialEq, Eq)]
-pub struct SemanticErrorCtx(pub usize, pub SemanticError);
+pub struct SemanticErrorCtx(pub Span, pub SemanticError);
 
 pub type SemanticResult<T> = Result<T, SemanticErrorCtx>;
 
+impl Default for SemanticContext {
+  fn default() -> Self {
+    SemanticContext::new()
+  }
+}
+
 impl SemanticContext {
   pub fn new() -> SemanticContext {
     SemanticContext {
       user_types: HashMap::new(),
+      type_names: HashMap::new(),
       scopes: HashMap::new(),
       locals: HashMap::new(),
+      loops: HashMap::new(),
+      literals: HashMap::new(),
+      functions: HashMap::new(),
+      type_variables: HashMap::new(),
+      deferred_operations: Vec::new(),
+      type_arguments: HashMap::new(),
+      type_errors: Vec::new(),
+      function_names: HashMap::new(),
+      type_parameter_names: HashMap::new(),
       next_scope_id: ScopeId::default(),
       next_user_type_id: UserTypeId::default(),
       next_local_id: LocalId::default(),
+      next_loop_id: LoopId::default(),
+      next_literal_id: LiteralId::default(),
+      next_type_arguments_id: TypeArgumentsId::default(),
+      next_function_id: FunctionId::default(),
+      next_type_variable_id: TypeVariableId::default(),
     }
   }
 
   pub fn declare_local(
     &mut self,
     scope_id: ScopeId,
-    name: String,
-    initial_type: Option<TypeRef>,
+    IdentifierCtx(span, name): &IdentifierCtx,
+    type_: Option<TypeRef>,
+    is_mutable: bool,
   ) -> LocalId {
     let id = self.next_local_id.next();
+    let type_ = type_.unwrap_or_else(|| {
+      self.declare_type_variable(
+        TypeVariableKind::General,
+        TypeVariableOrigin::Local(id),
+        *span,
+      )
+    });
 
     let scope = self.scopes.get_mut(&scope_id).unwrap();
     scope.locals.insert(id);
+    scope.names.insert(name.clone(), id);
 
     self.locals.insert(
       id,
       Local {
         id,
         scope_id,
-        name,
-        initial_type,
+        name: name.clone(),
+        type_,
+        is_mutable,
+        span: *span,
       },
     );
 
     id
   }
 
-  pub fn declare_type(&mut self, type_: UserType) -> TypeRef {
+  pub fn declare_type(&mut self, type_def: UserTypeDef) -> TypeRef {
     let id = self.next_user_type_id.next();
-    self.user_types.insert(id, type_);
+    self.user_types.insert(id, UserType { id, type_def });
     TypeRef::UserType(id)
   }
 
+  // Returns the existing type with the same definition, if there is one.
+  pub fn intern_type(&mut self, type_def: UserTypeDef) -> TypeRef {
+    let existing = self
+      .user_types
+      .values()
+      .find(|user_type| user_type.type_def == type_def);
+
+    match existing {
+      Some(user_type) => TypeRef::UserType(user_type.id),
+      None => self.declare_type(type_def),
+    }
+  }
+
+  pub fn resolve_type(&self, id: UserTypeId) -> &UserType {
+    self.user_types.get(&id).unwrap()
+  }
+
+  pub fn resolve_type_mut(&mut self, id: UserTypeId) -> &mut UserType {
+    self.user_types.get_mut(&id).unwrap()
+  }
+
+  // Makes a type usable by its name, in annotations and struct literals.
+  pub fn declare_type_name(&mut self, name: &str, id: UserTypeId) {
+    self.type_names.insert(name.to_string(), id);
+  }
+
+  pub fn resolve_named_user_type(&self, name: &str) -> Option<UserTypeId> {
+    self.type_names.get(name).cloned()
+  }
+
+  pub fn resolve_named_struct(&self, name: &str) -> Option<(UserTypeId, &StructDef)> {
+    let id = self.resolve_named_user_type(name)?;
+    match &self.resolve_type(id).type_def {
+      UserTypeDef::Struct(def) => Some((id, def)),
+      _ => None,
+    }
+  }
+
+  pub fn resolve_named_enum(&self, name: &str) -> Option<(UserTypeId, &EnumDef)> {
+    let id = self.resolve_named_user_type(name)?;
+    match &self.resolve_type(id).type_def {
+      UserTypeDef::Enum(def) => Some((id, def)),
+      _ => None,
+    }
+  }
+
+  // Resolves `Enum::Variant` to the enum and the index of the variant.
+  pub fn resolve_variant(
+    &self,
+    IdentifierCtx(type_pos, type_name): &IdentifierCtx,
+    IdentifierCtx(variant_pos, variant): &IdentifierCtx,
+  ) -> SemanticResult<(UserTypeId, usize)> {
+    let (type_id, def) = self.resolve_named_enum(type_name).ok_or_else(|| {
+      SemanticErrorCtx(
+        *type_pos,
+        SemanticError::UnknownType {
+          name: type_name.clone(),
+        },
+      )
+    })?;
+
+    let index = def.variant_index(variant).ok_or_else(|| {
+      SemanticErrorCtx(
+        *variant_pos,
+        SemanticError::UnknownVariant {
+          enum_name: type_name.clone(),
+          variant: variant.clone(),
+        },
+      )
+    })?;
+
+    Ok((type_id, index))
+  }
+
   pub fn declare_scope(&mut self, parent: Option<ScopeId>) -> ScopeId {
     let id = self.next_scope_id.next();
     let scope = Scope::new(id, parent);
@@ -72,20 +259,276 @@ impl SemanticContext {
     id
   }
 
-  pub fn resolve_named_type(
-    &self,
-    IdentifierCtx(pos, identifier): &IdentifierCtx,
-  ) -> SemanticResult<TypeRef> {
-    match identifier.as_str() {
+  pub fn declare_loop(&mut self, kind: LoopKind) -> LoopId {
+    let id = self.next_loop_id.next();
+    self.loops.insert(
+      id,
+      Loop {
+        id,
+        kind,
+        break_type: None,
+      },
+    );
+    id
+  }
+
+  // The type checker records the type arguments under this id once it has
+  // decided them.
+  pub fn declare_type_arguments(&mut self) -> TypeArgumentsId {
+    self.next_type_arguments_id.next()
+  }
+
+  pub fn declare_literal(&mut self, span: Span, value: i128, type_: Option<TypeRef>) -> LiteralId {
+    let id = self.next_literal_id.next();
+    let type_ = type_.unwrap_or_else(|| {
+      self.declare_type_variable(
+        TypeVariableKind::Integer,
+        TypeVariableOrigin::Expression,
+        span,
+      )
+    });
+    self.literals.insert(
+      id,
+      Literal {
+        id,
+        value,
+        type_,
+        span,
+      },
+    );
+    id
+  }
+
+  pub fn declare_type_variable(
+    &mut self,
+    kind: TypeVariableKind,
+    origin: TypeVariableOrigin,
+    span: Span,
+  ) -> TypeRef {
+    let id = self.next_type_variable_id.next();
+    self.type_variables.insert(
+      id,
+      TypeVariable {
+        id,
+        kind,
+        origin,
+        value: None,
+        diverging: false,
+        name: None,
+        span,
+      },
+    );
+    TypeRef::Variable(id)
+  }
+
+  pub fn declare_function(
+    &mut self,
+    IdentifierCtx(span, name): &IdentifierCtx,
+    scope_id: ScopeId,
+    parameters: Vec<LocalId>,
+    return_type: Option<TypeRef>,
+  ) -> FunctionId {
+    let id = self.next_function_id.next();
+    let return_type = return_type.unwrap_or_else(|| {
+      self.declare_type_variable(
+        TypeVariableKind::General,
+        TypeVariableOrigin::Expression,
+        *span,
+      )
+    });
+    self.resolve_scope_mut(scope_id).function_id = Some(id);
+    self.functions.insert(
+      id,
+      Function {
+        id,
+        name: name.clone(),
+        scope_id,
+        parameters,
+        return_type,
+        type_parameters: Vec::new(),
+        captures: Vec::new(),
+        span: *span,
+      },
+    );
+    id
+  }
+
+  // Makes a function callable by its name. Closures have no name.
+  pub fn declare_function_name(&mut self, name: &str, id: FunctionId) {
+    self.function_names.insert(name.to_string(), id);
+  }
+
+  pub fn resolve_named_function(&self, name: &str) -> Option<FunctionId> {
+    self.function_names.get(name).cloned()
+  }
+
+  // Finds the function whose body contains scope_id.
+  pub fn resolve_enclosing_function(&self, mut scope_id: ScopeId) -> Option<FunctionId> {
+    loop {
+      let scope = self.resolve_scope(scope_id);
+
+      if let Some(function_id) = scope.function_id {
+        return Some(function_id);
+      }
+
+      scope_id = scope.parent?;
+    }
+  }
+
+  pub fn resolve_named_type(&self, pos: Span, name: &str) -> SemanticResult<TypeRef> {
+    match name {
+      "i8" => Ok(TypeRef::Primitive(PrimitiveType::I8)),
+      "i16" => Ok(TypeRef::Primitive(PrimitiveType::I16)),
       "i32" => Ok(TypeRef::Primitive(PrimitiveType::I32)),
+      "i64" => Ok(TypeRef::Primitive(PrimitiveType::I64)),
+      "u8" => Ok(TypeRef::Primitive(PrimitiveType::U8)),
+      "u16" => Ok(TypeRef::Primitive(PrimitiveType::U16)),
+      "u32" => Ok(TypeRef::Primitive(PrimitiveType::U32)),
+      "u64" => Ok(TypeRef::Primitive(PrimitiveType::U64)),
+      "f64" => Ok(TypeRef::Primitive(PrimitiveType::F64)),
       "bool" => Ok(TypeRef::Primitive(PrimitiveType::Bool)),
-      _ => Err(SemanticErrorCtx(
+      "str" => Ok(TypeRef::Primitive(PrimitiveType::Str)),
+      _ if self.type_parameter_names.contains_key(name) => Ok(self.type_parameter_names[name]),
+      _ => match self.type_names.get(name) {
+        Some(&id) => {
+          let expected = self.resolve_type(id).type_def.type_parameters().len();
+          if expected != 0 {
+            return Err(SemanticErrorCtx(
+              pos,
+              SemanticError::WrongTypeArgumentCount {
+                name: name.to_string(),
+                expected,
+                actual: 0,
+              },
+            ));
+          }
+          Ok(TypeRef::UserType(id))
+        }
+        None => Err(SemanticErrorCtx(
+          pos,
+          SemanticError::UnknownType {
+            name: name.to_string(),
+          },
+        )),
+      },
+    }
+  }
+
+  pub fn resolve_type_annotation(
+    &mut self,
+    TypeCtx(pos, type_): &TypeCtx,
+  ) -> SemanticResult<TypeRef> {
+    match type_ {
+      Type::Named(name) => self.resolve_named_type(*pos, name),
+      Type::Array(element) => {
+        let element = self.resolve_type_annotation(element)?;
+        Ok(self.intern_type(UserTypeDef::Array(element)))
+      }
+      Type::Applied(name, arguments) => {
+        // Only a generic struct or enum takes type arguments.
+        let type_id = match self.resolve_named_type(*pos, name) {
+          Err(SemanticErrorCtx(_, SemanticError::WrongTypeArgumentCount { .. })) => {
+            self.type_names[name]
+          }
+          Ok(_) => {
+            return Err(SemanticErrorCtx(
+              *pos,
+              SemanticError::WrongTypeArgumentCount {
+                name: name.clone(),
+                expected: 0,
+                actual: arguments.len(),
+              },
+            ))
+          }
+          Err(error) => return Err(error),
+        };
+
+        let expected = self.resolve_type(type_id).type_def.type_parameters().len();
+        if arguments.len() != expected {
+          return Err(SemanticErrorCtx(
+            *pos,
+            SemanticError::WrongTypeArgumentCount {
+              name: name.clone(),
+              expected,
+              actual: arguments.len(),
+            },
+          ));
+        }
+
+        let arguments = arguments
+          .iter()
+          .map(|argument| self.resolve_type_annotation(argument))
+          .collect::<SemanticResult<_>>()?;
+        Ok(self.intern_type(UserTypeDef::Applied { type_id, arguments }))
+      }
+      Type::Function {
+        parameters,
+        return_type,
+      } => {
+        let parameters = parameters
+          .iter()
+          .map(|parameter| self.resolve_type_annotation(parameter))
+          .collect::<SemanticResult<_>>()?;
+        let return_type = match return_type {
+          Some(x) => self.resolve_type_annotation(x)?,
+          None => TypeRef::Primitive(PrimitiveType::Unit),
+        };
+        Ok(self.intern_type(UserTypeDef::Function {
+          parameters,
+          return_type,
+        }))
+      }
+    }
+  }
+
+  // Declares the type parameters of a generic declaration, as variables that
+  // only unify with themselves.
+  fn declare_type_parameters(
+    &mut self,
+    names: &[IdentifierCtx],
+  ) -> SemanticResult<Vec<TypeVariableId>> {
+    let mut ids = Vec::new();
+    for (i, IdentifierCtx(pos, name)) in names.iter().enumerate() {
+      if let Some(IdentifierCtx(previous, _)) = names[..i].iter().find(|other| other.1 == *name) {
+        return Err(SemanticErrorCtx(
+          *pos,
+          SemanticError::DuplicateTypeParameter {
+            name: name.clone(),
+            previous: *previous,
+          },
+        ));
+      }
+
+      match self.declare_type_variable(
+        TypeVariableKind::Generic,
+        TypeVariableOrigin::TypeParameter,
         *pos,
-        SemanticError::UnknownType {
-          name: identifier.clone(),
-        },
-      )),
+      ) {
+        TypeRef::Variable(id) => {
+          self.type_variables.get_mut(&id).unwrap().name = Some(name.clone());
+          ids.push(id)
+        }
+        _ => unreachable!(),
+      }
     }
+    Ok(ids)
+  }
+
+  // Runs `f` with the type parameters of a generic declaration in scope.
+  fn with_type_parameters<T>(
+    &mut self,
+    names: &[IdentifierCtx],
+    ids: &[TypeVariableId],
+    f: impl FnOnce(&mut SemanticContext) -> T,
+  ) -> T {
+    self.type_parameter_names = names
+      .iter()
+      .zip(ids)
+      .map(|(IdentifierCtx(_, name), &id)| (name.clone(), TypeRef::Variable(id)))
+      .collect();
+    let result = f(self);
+    self.type_parameter_names.clear();
+    result
   }
 
   pub fn resolve_scope(&self, scope_id: ScopeId) -> &Scope {
@@ -113,13 +556,69 @@ impl SemanticContext {
     }
   }
 
-  pub fn resolve_named_local(&self, scope_id: ScopeId, name: &str) -> Option<LocalId> {
-    self
-      .locals
-      .values()
-      .find(|x| x.name == name)
-      .map(|x| x.id)
-      .filter(|id| self.is_local_within_scope(scope_id, *id))
+  // Finds the innermost local with the given name, walking outwards from
+  // scope_id through the parent scopes.
+  pub fn resolve_named_local(&self, mut scope_id: ScopeId, name: &str) -> Option<LocalId> {
+    loop {
+      let scope = self.resolve_scope(scope_id);
+
+      if let Some(&local_id) = scope.names.get(name) {
+        return Some(local_id);
+      }
+
+      scope_id = scope.parent?;
+    }
+  }
+
+  // Finds the innermost loop whose body contains scope_id. A closure body is
+  // never within a loop, even if the closure itself is.
+  pub fn resolve_enclosing_loop(&self, mut scope_id: ScopeId) -> Option<LoopId> {
+    loop {
+      let scope = self.resolve_scope(scope_id);
+
+      if let Some(loop_id) = scope.loop_id {
+        return Some(loop_id);
+      }
+
+      if scope.function_id.is_some() {
+        return None;
+      }
+
+      scope_id = scope.parent?;
+    }
+  }
+
+  // Like resolve_named_local, but when the local is declared outside of the
+  // closures around scope_id, it's also recorded as a capture of each of them.
+  pub fn reference_named_local(&mut self, scope_id: ScopeId, name: &str) -> Option<LocalId> {
+    let local_id = self.resolve_named_local(scope_id, name)?;
+    let local = &self.locals[&local_id];
+    let declared_in = local.scope_id;
+    let capture = Capture {
+      local_id,
+      mode: if local.is_mutable {
+        CaptureMode::ByReference
+      } else {
+        CaptureMode::ByValue
+      },
+    };
+
+    let mut scope_id = scope_id;
+    while scope_id != declared_in {
+      let scope = self.resolve_scope(scope_id);
+      let parent = scope.parent.unwrap();
+
+      if let Some(function_id) = scope.function_id {
+        let captures = &mut self.functions.get_mut(&function_id).unwrap().captures;
+        if !captures.contains(&capture) {
+          captures.push(capture);
+        }
+      }
+
+      scope_id = parent;
+    }
+
+    Some(local_id)
   }
 
   pub fn resolve_local(&self, scope_id: ScopeId, local_id: LocalId) -> Option<&Local> {
@@ -145,19 +644,39 @@ pub fn transform_expression(
 ) -> SemanticResult<RastExpressionCtx> {
   let ExpressionCtx(pos, expression) = expression;
   match expression {
-    Expression::IntegerConstant(x) => {
-      Ok(RastExpressionCtx(*pos, RastExpression::IntegerConstant(*x)))
+    Expression::IntegerConstant(x, suffix) => transform_integer(ctx, *pos, *x, suffix),
+
+    Expression::FloatConstant(x) => Ok(RastExpressionCtx(*pos, RastExpression::FloatConstant(*x))),
+    Expression::BooleanConstant(x) => {
+      Ok(RastExpressionCtx(*pos, RastExpression::BooleanConstant(*x)))
     }
-    Expression::Local(local) => match ctx.resolve_named_local(scope_id, local) {
+    Expression::StringConstant(x) => Ok(RastExpressionCtx(
+      *pos,
+      RastExpression::StringConstant(x.clone()),
+    )),
+    // Locals shadow functions of the same name.
+    Expression::Local(local) => match ctx.reference_named_local(scope_id, local) {
       Some(local_id) => Ok(RastExpressionCtx(*pos, RastExpression::Local(local_id))),
-      None => Err(SemanticErrorCtx(
-        *pos,
-        SemanticError::UnknownLocal {
-          name: local.to_string(),
-        },
-      )),
+      None => match ctx.resolve_named_function(local) {
+        Some(function_id) => Ok(RastExpressionCtx(
+          *pos,
+          RastExpression::Function(function_id, ctx.declare_type_arguments()),
+        )),
+        None => Err(SemanticErrorCtx(
+          *pos,
+          SemanticError::UnknownLocal {
+            name: local.to_string(),
+          },
+        )),
+      },
     },
     Expression::UnaryOp(op, arg) => {
+      // Negative literals are folded, so that the type checker can check their
+      // range, and `-128i8` doesn't overflow.
+      if let (UnaryOperator::Negate, Expression::IntegerConstant(x, suffix)) = (op, &arg.1) {
+        return transform_integer(ctx, *pos, -x, suffix);
+      }
+
       let value = transform_expression(ctx, scope_id, arg)?;
       Ok(RastExpressionCtx(
         *pos,
@@ -172,102 +691,843 @@ pub fn transform_expression(
         RastExpression::BinaryOp(*op, Box::new((lhs, rhs))),
       ))
     }
-  }
-}
-
-pub fn transform_statement(
-  ctx: &mut SemanticContext,
-  scope_id: ScopeId,
-  statement: &StatementCtx,
-) -> SemanticResult<RastStatementCtx> {
-  let StatementCtx(pos, statement) = statement;
-  match statement {
-    Statement::Block { inner } => {
-      let scope_id = ctx.declare_scope(Some(scope_id));
-      let inner: Result<_, _> = inner
-        .iter()
-        .map(|statement| transform_statement(ctx, scope_id, statement))
-        .collect();
-      let inner = inner?;
-
-      Ok(RastStatementCtx(
+    Expression::Block(block) => Ok(RastExpressionCtx(
+      *pos,
+      RastExpression::Block(transform_block(ctx, Some(scope_id), block)?),
+    )),
+    Expression::If {
+      condition,
+      then_branch,
+      else_branch,
+    } => {
+      let condition = transform_expression(ctx, scope_id, condition)?;
+      let then_branch = transform_expression(ctx, scope_id, then_branch)?;
+      let else_branch = match else_branch {
+        Some(else_branch) => Some(Box::new(transform_expression(ctx, scope_id, else_branch)?)),
+        None => None,
+      };
+      Ok(RastExpressionCtx(
         *pos,
-        RastStatement::Block { scope_id, inner },
+        RastExpression::If {
+          condition: Box::new(condition),
+          then_branch: Box::new(then_branch),
+          else_branch,
+        },
       ))
     }
-    Statement::AssignLocal { local, value } => {
-      let IdentifierCtx(pos, identifier) = local;
-      match ctx.resolve_named_local(scope_id, identifier) {
-        Some(local_id) => Ok(RastStatementCtx(
-          *pos,
-          RastStatement::AssignLocal {
-            local_id,
-            value: transform_expression(ctx, scope_id, value)?,
-          },
-        )),
-        None => Err(SemanticErrorCtx(
-          *pos,
-          SemanticError::UnknownLocal {
-            name: identifier.to_string(),
-          },
-        )),
+    Expression::Call { callee, arguments } => {
+      enum Callee {
+        Function(FunctionId),
+        Builtin(Builtin),
+        Variant(UserTypeId, usize),
+        Value,
       }
-    }
-    Statement::DeclareVariable {
-      name,
-      initial_type,
-      initial_value,
-      ..
-    } => {
-      let initial_type = match initial_type {
-        Some(x) => Some(ctx.resolve_named_type(x)?),
-        None => None,
+
+      // A function called by its name is called directly. Anything else is
+      // called as a value, which the type checker makes sure is a function.
+      let callee_kind = match &callee.1 {
+        Expression::Local(name) if ctx.resolve_named_local(scope_id, name).is_none() => {
+          match (ctx.resolve_named_function(name), Builtin::from_name(name)) {
+            (Some(function_id), _) => Callee::Function(function_id),
+            (None, Some(builtin)) => Callee::Builtin(builtin),
+            (None, None) => {
+              return Err(SemanticErrorCtx(
+                callee.0,
+                SemanticError::UnknownFunction { name: name.clone() },
+              ))
+            }
+          }
+        }
+        Expression::Path { type_name, variant } => {
+          let (type_id, variant) = ctx.resolve_variant(type_name, variant)?;
+          Callee::Variant(type_id, variant)
+        }
+        _ => Callee::Value,
       };
-      let local_id = ctx.declare_local(scope_id, name.1.clone(), initial_type);
-      let value = transform_expression(ctx, scope_id, initial_value)?;
 
-      Ok(RastStatementCtx(
+      let arguments: Result<_, _> = arguments
+        .iter()
+        .map(|argument| transform_expression(ctx, scope_id, argument))
+        .collect();
+
+      let expression = match callee_kind {
+        Callee::Function(function_id) => RastExpression::Call {
+          function_id,
+          type_arguments_id: ctx.declare_type_arguments(),
+          arguments: arguments?,
+        },
+        Callee::Builtin(builtin) => RastExpression::Builtin {
+          builtin,
+          arguments: arguments?,
+        },
+        Callee::Variant(type_id, variant) => RastExpression::Variant {
+          type_id,
+          type_arguments_id: ctx.declare_type_arguments(),
+          variant,
+          arguments: arguments?,
+        },
+        Callee::Value => RastExpression::CallIndirect {
+          callee: Box::new(transform_expression(ctx, scope_id, callee)?),
+          arguments: arguments?,
+        },
+      };
+
+      Ok(RastExpressionCtx(*pos, expression))
+    }
+    Expression::Array(elements) => {
+      let elements: Result<_, _> = elements
+        .iter()
+        .map(|element| transform_expression(ctx, scope_id, element))
+        .collect();
+      Ok(RastExpressionCtx(
         *pos,
-        RastStatement::AssignLocal { local_id, value },
+        RastExpression::Array(elements?, ctx.declare_type_arguments()),
       ))
     }
-  }
-}
+    Expression::StructLiteral { name, fields } => {
+      let IdentifierCtx(name_pos, name) = name;
+      let (type_id, def) = ctx.resolve_named_struct(name).ok_or_else(|| {
+        SemanticErrorCtx(*name_pos, SemanticError::UnknownType { name: name.clone() })
+      })?;
 
-pub fn transform_program(
-  Program(statements): Program,
-) -> SemanticResult<(SemanticContext, RastProgram)> {
-  let mut ctx = SemanticContext::new();
-  let root_scope = ctx.declare_scope(None);
+      let mut indices: Vec<(usize, Span)> = Vec::new();
+      for IdentifierCtx(field_pos, field) in fields.iter().map(|(field, _)| field) {
+        let index = def.field_index(field).ok_or_else(|| {
+          SemanticErrorCtx(
+            *field_pos,
+            SemanticError::UnknownField {
+              struct_name: name.clone(),
+              field: field.clone(),
+            },
+          )
+        })?;
 
-  let mut transformed_statements = Vec::new();
-  for statement in statements {
-    transformed_statements.push(transform_statement(&mut ctx, root_scope, &statement)?);
-  }
+        if let Some(&(_, previous)) = indices.iter().find(|(other, _)| *other == index) {
+          return Err(SemanticErrorCtx(
+            *field_pos,
+            SemanticError::DuplicateField {
+              name: field.clone(),
+              previous,
+            },
+          ));
+        }
+        indices.push((index, *field_pos));
+      }
 
-  Ok((ctx, RastProgram(transformed_statements)))
-}
+      let missing: Vec<String> = def
+        .fields
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !indices.iter().any(|(other, _)| other == index))
+        .map(|(_, field)| field.name.clone())
+        .collect();
+      if !missing.is_empty() {
+        return Err(SemanticErrorCtx(
+          *pos,
+          SemanticError::MissingFields {
+            struct_name: name.clone(),
+            fields: missing,
+          },
+        ));
+      }
 
-#[cfg(test)]
-mod rast_transformer_tests {
-  use super::*;
+      let mut values = Vec::new();
+      for ((index, _), (_, value)) in indices.into_iter().zip(fields) {
+        values.push((index, transform_expression(ctx, scope_id, value)?));
+      }
 
-  #[test]
-  fn transform_assignment() {
-    let mut ctx = SemanticContext::new();
+      Ok(RastExpressionCtx(
+        *pos,
+        RastExpression::Struct {
+          type_id,
+          type_arguments_id: ctx.declare_type_arguments(),
+          fields: values,
+        },
+      ))
+    }
+    Expression::Path { type_name, variant } => {
+      let (type_id, variant) = ctx.resolve_variant(type_name, variant)?;
+      Ok(RastExpressionCtx(
+        *pos,
+        RastExpression::Variant {
+          type_id,
+          type_arguments_id: ctx.declare_type_arguments(),
+          variant,
+          arguments: Vec::new(),
+        },
+      ))
+    }
+    Expression::Match { scrutinee, arms } => {
+      let scrutinee = transform_expression(ctx, scope_id, scrutinee)?;
 
-    let ast = StatementCtx(
-      0,
-      Statement::DeclareVariable {
-        name: IdentifierCtx(0, "x".to_string()),
-        is_mutable: false,
-        initial_type: Some(IdentifierCtx(0, "i32".to_string())),
-        initial_value: ExpressionCtx(0, Expression::IntegerConstant(32)),
-      },
-    );
+      let mut rast_arms = Vec::new();
+      for MatchArm { pattern, body } in arms {
+        let arm_scope = ctx.declare_scope(Some(scope_id));
+        let pattern = transform_pattern(ctx, arm_scope, pattern, &mut Vec::new())?;
+        let body = transform_expression(ctx, arm_scope, body)?;
+        rast_arms.push(RastMatchArm {
+          scope_id: arm_scope,
+          pattern,
+          body,
+        });
+      }
 
-    let scope_id = ctx.declare_scope(None);
-    let transformed = transform_statement(&mut ctx, scope_id, &ast);
+      Ok(RastExpressionCtx(
+        *pos,
+        RastExpression::Match {
+          scrutinee: Box::new(scrutinee),
+          arms: rast_arms,
+        },
+      ))
+    }
+    Expression::Field { value, field } => {
+      let value = transform_expression(ctx, scope_id, value)?;
+      Ok(RastExpressionCtx(
+        *pos,
+        RastExpression::Field {
+          value: Box::new(value),
+          field: field.1.clone(),
+        },
+      ))
+    }
+    Expression::Index { array, index } => {
+      let array = transform_expression(ctx, scope_id, array)?;
+      let index = transform_expression(ctx, scope_id, index)?;
+      Ok(RastExpressionCtx(
+        *pos,
+        RastExpression::Index {
+          array: Box::new(array),
+          index: Box::new(index),
+        },
+      ))
+    }
+    Expression::Cast { value, type_ } => {
+      let value = transform_expression(ctx, scope_id, value)?;
+      let type_ = ctx.resolve_type_annotation(type_)?;
+      Ok(RastExpressionCtx(
+        *pos,
+        RastExpression::Cast {
+          value: Box::new(value),
+          type_,
+        },
+      ))
+    }
+    Expression::Closure {
+      parameters,
+      return_type,
+      body,
+    } => {
+      let return_type = match return_type {
+        Some(x) => Some(ctx.resolve_type_annotation(x)?),
+        None => None,
+      };
+
+      // Unlike the parameters of a function, the parameters of a closure are
+      // in a scope nested in the surrounding one, so that its locals can be
+      // captured.
+      let parameter_scope = ctx.declare_scope(Some(scope_id));
+      let parameters = declare_parameters(ctx, parameter_scope, parameters)?;
+      let name = IdentifierCtx(*pos, "{closure}".to_string());
+      let id = ctx.declare_function(&name, parameter_scope, parameters, return_type);
+
+      // A block is the body of the closure, like the body of a function, and
+      // any other expression is the result of it.
+      let body = match &body.1 {
+        Expression::Block(block) => transform_block(ctx, Some(parameter_scope), block)?,
+        _ => {
+          let body_scope = ctx.declare_scope(Some(parameter_scope));
+          let result = transform_expression(ctx, body_scope, body)?;
+          RastBlock {
+            scope_id: body_scope,
+            statements: Vec::new(),
+            result: Some(Box::new(result)),
+          }
+        }
+      };
+
+      Ok(RastExpressionCtx(
+        *pos,
+        RastExpression::Closure(Rc::new(RastFunction { id, body })),
+      ))
+    }
+    Expression::Loop { body } => {
+      let loop_id = ctx.declare_loop(LoopKind::Loop);
+      let body = transform_loop_body(ctx, scope_id, loop_id, body)?;
+      Ok(RastExpressionCtx(
+        *pos,
+        RastExpression::Loop { loop_id, body },
+      ))
+    }
+  }
+}
+
+fn transform_integer(
+  ctx: &mut SemanticContext,
+  pos: Span,
+  value: i128,
+  suffix: &Option<String>,
+) -> SemanticResult<RastExpressionCtx> {
+  let type_ = match suffix {
+    Some(suffix) => Some(ctx.resolve_named_type(pos, suffix)?),
+    None => None,
+  };
+  let literal_id = ctx.declare_literal(pos, value, type_);
+  Ok(RastExpressionCtx(
+    pos,
+    RastExpression::IntegerConstant(value, literal_id),
+  ))
+}
+
+// Declares the locals bound by a pattern in the scope of its arm. `bindings`
+// holds the names bound so far, since each can only be bound once.
+fn transform_pattern(
+  ctx: &mut SemanticContext,
+  scope_id: ScopeId,
+  PatternCtx(pos, pattern): &PatternCtx,
+  bindings: &mut Vec<IdentifierCtx>,
+) -> SemanticResult<RastPatternCtx> {
+  let pattern = match pattern {
+    Pattern::Wildcard => RastPattern::Wildcard,
+    Pattern::Binding(name) => {
+      if let Some(IdentifierCtx(previous, _)) = bindings.iter().find(|x| x.1 == *name) {
+        return Err(SemanticErrorCtx(
+          *pos,
+          SemanticError::DuplicateBinding {
+            name: name.clone(),
+            previous: *previous,
+          },
+        ));
+      }
+
+      let name = IdentifierCtx(*pos, name.clone());
+      let local_id = ctx.declare_local(scope_id, &name, None, false);
+      bindings.push(name);
+      RastPattern::Binding(local_id)
+    }
+    Pattern::Integer(i, suffix) => {
+      let type_ = match suffix {
+        Some(suffix) => Some(ctx.resolve_named_type(*pos, suffix)?),
+        None => None,
+      };
+      RastPattern::Integer(*i, type_)
+    }
+    Pattern::Boolean(b) => RastPattern::Boolean(*b),
+    Pattern::Variant {
+      type_name,
+      variant,
+      fields,
+    } => {
+      let (type_id, variant) = ctx.resolve_variant(type_name, variant)?;
+      let fields: Result<_, _> = fields
+        .iter()
+        .map(|field| transform_pattern(ctx, scope_id, field, bindings))
+        .collect();
+      RastPattern::Variant {
+        type_id,
+        type_arguments_id: ctx.declare_type_arguments(),
+        variant,
+        fields: fields?,
+      }
+    }
+  };
+
+  Ok(RastPatternCtx(*pos, pattern))
+}
+
+pub fn transform_block(
+  ctx: &mut SemanticContext,
+  parent: Option<ScopeId>,
+  block: &Block,
+) -> SemanticResult<RastBlock> {
+  let scope_id = ctx.declare_scope(parent);
+  transform_block_in_scope(ctx, scope_id, block)
+}
+
+fn transform_loop_body(
+  ctx: &mut SemanticContext,
+  parent: ScopeId,
+  loop_id: LoopId,
+  block: &Block,
+) -> SemanticResult<RastBlock> {
+  let scope_id = ctx.declare_scope(Some(parent));
+  ctx.resolve_scope_mut(scope_id).loop_id = Some(loop_id);
+  transform_block_in_scope(ctx, scope_id, block)
+}
+
+fn transform_block_in_scope(
+  ctx: &mut SemanticContext,
+  scope_id: ScopeId,
+  block: &Block,
+) -> SemanticResult<RastBlock> {
+  // The statements are checked first, since an error in one of them can
+  // cause another in the result, such as a local that was never declared.
+  let statements = block
+    .statements
+    .iter()
+    .map(|statement| transform_statement(ctx, scope_id, statement))
+    .collect::<Result<_, _>>()?;
+
+  let result = match &block.result {
+    Some(result) => Some(Box::new(transform_expression(ctx, scope_id, result)?)),
+    None => None,
+  };
+
+  Ok(RastBlock {
+    scope_id,
+    statements,
+    result,
+  })
+}
+
+pub fn transform_statement(
+  ctx: &mut SemanticContext,
+  scope_id: ScopeId,
+  statement: &StatementCtx,
+) -> SemanticResult<RastStatementCtx> {
+  let StatementCtx(pos, statement) = statement;
+  match statement {
+    Statement::Expression { value } => Ok(RastStatementCtx(
+      *pos,
+      RastStatement::Expression {
+        value: transform_expression(ctx, scope_id, value)?,
+      },
+    )),
+    Statement::AssignLocal { local, value } => {
+      let IdentifierCtx(local_pos, identifier) = local;
+      match ctx.reference_named_local(scope_id, identifier) {
+        Some(local_id) => {
+          let local = &ctx.locals[&local_id];
+          if !local.is_mutable {
+            return Err(SemanticErrorCtx(
+              *pos,
+              SemanticError::AssignToImmutable {
+                name: identifier.clone(),
+                declaration: local.span,
+              },
+            ));
+          }
+
+          Ok(RastStatementCtx(
+            *pos,
+            RastStatement::AssignLocal {
+              local_id,
+              value: transform_expression(ctx, scope_id, value)?,
+            },
+          ))
+        }
+        None => Err(SemanticErrorCtx(
+          *local_pos,
+          SemanticError::UnknownLocal {
+            name: identifier.to_string(),
+          },
+        )),
+      }
+    }
+    Statement::AssignIndex {
+      array,
+      index,
+      value,
+    } => {
+      let array = transform_expression(ctx, scope_id, array)?;
+      check_mutable_place(ctx, *pos, &array)?;
+
+      Ok(RastStatementCtx(
+        *pos,
+        RastStatement::AssignIndex {
+          array,
+          index: transform_expression(ctx, scope_id, index)?,
+          value: transform_expression(ctx, scope_id, value)?,
+        },
+      ))
+    }
+    Statement::AssignField {
+      target,
+      field,
+      value,
+    } => {
+      let target = transform_expression(ctx, scope_id, target)?;
+      check_mutable_place(ctx, *pos, &target)?;
+
+      Ok(RastStatementCtx(
+        *pos,
+        RastStatement::AssignField {
+          target,
+          field: field.1.clone(),
+          value: transform_expression(ctx, scope_id, value)?,
+        },
+      ))
+    }
+    Statement::While { condition, body } => {
+      let condition = transform_expression(ctx, scope_id, condition)?;
+      let loop_id = ctx.declare_loop(LoopKind::While);
+      let body = transform_loop_body(ctx, scope_id, loop_id, body)?;
+      Ok(RastStatementCtx(
+        *pos,
+        RastStatement::While {
+          loop_id,
+          condition,
+          body,
+        },
+      ))
+    }
+    Statement::Break { value } => {
+      let loop_id = ctx
+        .resolve_enclosing_loop(scope_id)
+        .ok_or(SemanticErrorCtx(*pos, SemanticError::BreakOutsideLoop))?;
+      let value = match value {
+        Some(value) => Some(transform_expression(ctx, scope_id, value)?),
+        None => None,
+      };
+      Ok(RastStatementCtx(
+        *pos,
+        RastStatement::Break { loop_id, value },
+      ))
+    }
+    Statement::Continue => {
+      let loop_id = ctx
+        .resolve_enclosing_loop(scope_id)
+        .ok_or(SemanticErrorCtx(*pos, SemanticError::ContinueOutsideLoop))?;
+      Ok(RastStatementCtx(*pos, RastStatement::Continue { loop_id }))
+    }
+    Statement::Return { value } => {
+      let function_id = ctx
+        .resolve_enclosing_function(scope_id)
+        .ok_or(SemanticErrorCtx(*pos, SemanticError::ReturnOutsideFunction))?;
+      let value = match value {
+        Some(value) => Some(transform_expression(ctx, scope_id, value)?),
+        None => None,
+      };
+      Ok(RastStatementCtx(
+        *pos,
+        RastStatement::Return { function_id, value },
+      ))
+    }
+    // Syntax errors have already been reported by the parser. They're treated
+    // as empty blocks so that the rest of a partially parsed program can still
+    // be resolved.
+    Statement::Error => {
+      let block = RastBlock {
+        scope_id: ctx.declare_scope(Some(scope_id)),
+        statements: Vec::new(),
+        result: None,
+      };
+
+      Ok(RastStatementCtx(
+        *pos,
+        RastStatement::Expression {
+          value: RastExpressionCtx(*pos, RastExpression::Block(block)),
+        },
+      ))
+    }
+    Statement::DeclareVariable {
+      name,
+      is_mutable,
+      initial_type,
+      initial_value,
+    } => {
+      let initial_type = match initial_type {
+        Some(x) => Some(ctx.resolve_type_annotation(x)?),
+        None => None,
+      };
+      // The local isn't in scope within its own initializer.
+      let value = transform_expression(ctx, scope_id, initial_value)?;
+      let local_id = ctx.declare_local(scope_id, name, initial_type, *is_mutable);
+
+      Ok(RastStatementCtx(
+        *pos,
+        RastStatement::DeclareLocal { local_id, value },
+      ))
+    }
+  }
+}
+
+// Arrays and structs stored in a local can only be changed if the local is
+// mutable, however deeply nested the changed element is.
+fn check_mutable_place(
+  ctx: &SemanticContext,
+  pos: Span,
+  target: &RastExpressionCtx,
+) -> SemanticResult<()> {
+  let mut root = target;
+  loop {
+    root = match &root.1 {
+      RastExpression::Index { array, .. } => array,
+      RastExpression::Field { value, .. } => value,
+      _ => break,
+    };
+  }
+
+  match root.1 {
+    RastExpression::Local(local_id) if !ctx.locals[&local_id].is_mutable => {
+      let local = &ctx.locals[&local_id];
+      Err(SemanticErrorCtx(
+        pos,
+        SemanticError::AssignToImmutable {
+          name: local.name.clone(),
+          declaration: local.span,
+        },
+      ))
+    }
+    _ => Ok(()),
+  }
+}
+
+// Declares the name of a struct or an enum, whose definition doesn't have its
+// fields yet. They're resolved once all the names are known, so that types
+// can refer to each other.
+fn declare_named_type(
+  ctx: &mut SemanticContext,
+  IdentifierCtx(pos, name): &IdentifierCtx,
+  type_def: UserTypeDef,
+) -> SemanticResult<UserTypeId> {
+  if let Some(previous) = ctx.resolve_named_user_type(name) {
+    return Err(SemanticErrorCtx(
+      *pos,
+      SemanticError::DuplicateType {
+        name: name.clone(),
+        previous: ctx
+          .resolve_type(previous)
+          .type_def
+          .declaration_span()
+          .unwrap(),
+      },
+    ));
+  }
+
+  let id = match ctx.declare_type(type_def) {
+    TypeRef::UserType(id) => id,
+    _ => unreachable!(),
+  };
+  ctx.declare_type_name(name, id);
+  Ok(id)
+}
+
+fn declare_struct(
+  ctx: &mut SemanticContext,
+  StructDeclarationCtx(_, declaration): &StructDeclarationCtx,
+) -> SemanticResult<UserTypeId> {
+  let IdentifierCtx(pos, name) = &declaration.name;
+  let type_parameters = ctx.declare_type_parameters(&declaration.type_parameters)?;
+  let type_def = UserTypeDef::Struct(StructDef {
+    name: name.clone(),
+    type_parameters,
+    fields: Vec::new(),
+    span: *pos,
+  });
+  declare_named_type(ctx, &declaration.name, type_def)
+}
+
+fn declare_enum(
+  ctx: &mut SemanticContext,
+  EnumDeclarationCtx(_, declaration): &EnumDeclarationCtx,
+) -> SemanticResult<UserTypeId> {
+  let IdentifierCtx(pos, name) = &declaration.name;
+  let type_parameters = ctx.declare_type_parameters(&declaration.type_parameters)?;
+  let type_def = UserTypeDef::Enum(EnumDef {
+    name: name.clone(),
+    type_parameters,
+    variants: Vec::new(),
+    span: *pos,
+  });
+  declare_named_type(ctx, &declaration.name, type_def)
+}
+
+fn declare_enum_variants(
+  ctx: &mut SemanticContext,
+  id: UserTypeId,
+  EnumDeclarationCtx(_, declaration): &EnumDeclarationCtx,
+) -> SemanticResult<()> {
+  let mut variants: Vec<EnumVariant> = Vec::new();
+  for variant in &declaration.variants {
+    let IdentifierCtx(pos, name) = &variant.name;
+    if let Some(previous) = variants.iter().find(|other| other.name == *name) {
+      return Err(SemanticErrorCtx(
+        *pos,
+        SemanticError::DuplicateVariant {
+          name: name.clone(),
+          previous: previous.span,
+        },
+      ));
+    }
+
+    let type_parameters = ctx.resolve_type(id).type_def.type_parameters().to_vec();
+    let fields: Result<_, _> =
+      ctx.with_type_parameters(&declaration.type_parameters, &type_parameters, |ctx| {
+        variant
+          .fields
+          .iter()
+          .map(|field| ctx.resolve_type_annotation(field))
+          .collect()
+      });
+    variants.push(EnumVariant {
+      name: name.clone(),
+      fields: fields?,
+      span: *pos,
+    });
+  }
+
+  if let UserTypeDef::Enum(def) = &mut ctx.resolve_type_mut(id).type_def {
+    def.variants = variants;
+  }
+  Ok(())
+}
+
+fn declare_struct_fields(
+  ctx: &mut SemanticContext,
+  id: UserTypeId,
+  StructDeclarationCtx(_, declaration): &StructDeclarationCtx,
+) -> SemanticResult<()> {
+  let mut fields: Vec<StructField> = Vec::new();
+  let mut spans: Vec<Span> = Vec::new();
+  for field in &declaration.fields {
+    let IdentifierCtx(pos, name) = &field.name;
+    if let Some(previous) = fields.iter().position(|other| other.name == *name) {
+      return Err(SemanticErrorCtx(
+        *pos,
+        SemanticError::DuplicateField {
+          name: name.clone(),
+          previous: spans[previous],
+        },
+      ));
+    }
+
+    let type_parameters = ctx.resolve_type(id).type_def.type_parameters().to_vec();
+    let type_ =
+      ctx.with_type_parameters(&declaration.type_parameters, &type_parameters, |ctx| {
+        ctx.resolve_type_annotation(&field.type_)
+      })?;
+    fields.push(StructField {
+      name: name.clone(),
+      type_,
+    });
+    spans.push(*pos);
+  }
+
+  if let UserTypeDef::Struct(def) = &mut ctx.resolve_type_mut(id).type_def {
+    def.fields = fields;
+  }
+  Ok(())
+}
+
+// Declares the signature of a function. The parameters are locals in a scope
+// of their own, which has no parent: a function can't see the locals of the
+// program, and so never captures anything.
+fn declare_function(
+  ctx: &mut SemanticContext,
+  FunctionDeclarationCtx(_, function): &FunctionDeclarationCtx,
+) -> SemanticResult<FunctionId> {
+  let IdentifierCtx(pos, name) = &function.name;
+  if let Some(previous) = ctx.resolve_named_function(name) {
+    return Err(SemanticErrorCtx(
+      *pos,
+      SemanticError::DuplicateFunction {
+        name: name.clone(),
+        previous: ctx.functions[&previous].span,
+      },
+    ));
+  }
+
+  let type_parameters = ctx.declare_type_parameters(&function.type_parameters)?;
+  let scope_id = ctx.declare_scope(None);
+  let (return_type, parameters) =
+    ctx.with_type_parameters(&function.type_parameters, &type_parameters, |ctx| {
+      let return_type = match &function.return_type {
+        Some(x) => ctx.resolve_type_annotation(x)?,
+        None => TypeRef::Primitive(PrimitiveType::Unit),
+      };
+      let parameters = declare_parameters(ctx, scope_id, &function.parameters)?;
+      Ok((return_type, parameters))
+    })?;
+
+  let id = ctx.declare_function(&function.name, scope_id, parameters, Some(return_type));
+  ctx.functions.get_mut(&id).unwrap().type_parameters = type_parameters;
+  ctx.declare_function_name(name, id);
+  Ok(id)
+}
+
+fn declare_parameters(
+  ctx: &mut SemanticContext,
+  scope_id: ScopeId,
+  parameters: &[Parameter],
+) -> SemanticResult<Vec<LocalId>> {
+  let mut locals = Vec::new();
+  for parameter in parameters {
+    let type_ = match &parameter.type_ {
+      Some(x) => Some(ctx.resolve_type_annotation(x)?),
+      None => None,
+    };
+    locals.push(ctx.declare_local(scope_id, &parameter.name, type_, false));
+  }
+  Ok(locals)
+}
+
+pub fn transform_program(program: Program) -> SemanticResult<(SemanticContext, RastProgram)> {
+  with_stack(PASS_STACK_SIZE, || {
+    let mut ctx = SemanticContext::new();
+
+    let mut struct_ids = Vec::new();
+    for declaration in &program.structs {
+      struct_ids.push(declare_struct(&mut ctx, declaration)?);
+    }
+    let mut enum_ids = Vec::new();
+    for declaration in &program.enums {
+      enum_ids.push(declare_enum(&mut ctx, declaration)?);
+    }
+    for (id, declaration) in struct_ids.into_iter().zip(&program.structs) {
+      declare_struct_fields(&mut ctx, id, declaration)?;
+    }
+    for (id, declaration) in enum_ids.into_iter().zip(&program.enums) {
+      declare_enum_variants(&mut ctx, id, declaration)?;
+    }
+
+    // All signatures are declared first, so that functions can call each other
+    // regardless of the order they're declared in.
+    let mut function_ids = Vec::new();
+    for function in &program.functions {
+      function_ids.push(declare_function(&mut ctx, function)?);
+    }
+
+    let mut functions = Vec::new();
+    for (id, FunctionDeclarationCtx(_, function)) in
+      function_ids.into_iter().zip(&program.functions)
+    {
+      let &Function {
+        scope_id,
+        ref type_parameters,
+        ..
+      } = &ctx.functions[&id];
+      let type_parameters = type_parameters.clone();
+      let body = ctx.with_type_parameters(&function.type_parameters, &type_parameters, |ctx| {
+        transform_block(ctx, Some(scope_id), &function.body)
+      })?;
+      functions.push(RastFunction { id, body });
+    }
+
+    let body = transform_block(&mut ctx, None, &program.body)?;
+    Ok((ctx, RastProgram { functions, body }))
+  })
+}
+
+#[cfg(test)]
+mod rast_transformer_tests {
+  use super::*;
+
+  #[test]
+  fn transform_assignment() {
+    let mut ctx = SemanticContext::new();
+
+    let ast = StatementCtx(
+      Span::new(0, 17),
+      Statement::DeclareVariable {
+        name: IdentifierCtx(Span::new(4, 5), "x".to_string()),
+        is_mutable: false,
+        initial_type: Some(TypeCtx(Span::new(8, 11), Type::Named("i32".to_string()))),
+        initial_value: ExpressionCtx(Span::new(14, 16), Expression::IntegerConstant(32, None)),
+      },
+    );
+
+    let scope_id = ctx.declare_scope(None);
+    let transformed = transform_statement(&mut ctx, scope_id, &ast);
 
     let scope = ctx.resolve_scope(scope_id);
     assert_eq!(ScopeId(0), scope.id);
@@ -282,12 +1542,409 @@ mod rast_transformer_tests {
     assert_eq!(
       transformed,
       Ok(RastStatementCtx(
-        0,
-        RastStatement::AssignLocal {
+        Span::new(0, 17),
+        RastStatement::DeclareLocal {
           local_id: LocalId(0),
-          value: RastExpressionCtx(0, RastExpression::IntegerConstant(32))
+          value: RastExpressionCtx(
+            Span::new(14, 16),
+            RastExpression::IntegerConstant(32, LiteralId(0))
+          )
         }
       ))
     );
   }
+
+  fn transform_source(src: &str) -> SemanticResult<(SemanticContext, RastProgram)> {
+    use crate::parser::Parser;
+    use crate::token_stream::TokenStream;
+
+    let mut lexer = TokenStream::new(src);
+    let (program, errors) = Parser::new(&mut lexer).parse_program();
+    assert!(errors.is_empty(), "{:?}", errors);
+    transform_program(program)
+  }
+
+  #[test]
+  fn assign_to_mutable() {
+    let (ctx, _) = transform_source("let mut x = 1; x = 2;").unwrap();
+    assert!(ctx.locals[&LocalId(0)].is_mutable);
+  }
+
+  #[test]
+  fn assign_to_immutable() {
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(13, 19),
+        SemanticError::AssignToImmutable {
+          name: "x".to_string(),
+          declaration: Span::new(4, 5),
+        }
+      ),
+      transform_source("let x = 1; { x = 2; }").unwrap_err()
+    );
+  }
+
+  fn find_assignment(block: &RastBlock) -> Option<LocalId> {
+    block
+      .statements
+      .iter()
+      .find_map(|statement| match &statement.1 {
+        RastStatement::AssignLocal { local_id, .. } => Some(*local_id),
+        _ => None,
+      })
+  }
+
+  #[test]
+  fn resolve_shadowed_in_nested_block() {
+    let (_, program) = transform_source("let mut x = 1; { let mut x = 2; x = 3; } x").unwrap();
+
+    match &program.body.statements[1].1 {
+      RastStatement::Expression {
+        value: RastExpressionCtx(_, RastExpression::Block(inner)),
+      } => {
+        assert_eq!(Some(LocalId(1)), find_assignment(inner));
+        match &inner.statements[1].1 {
+          RastStatement::AssignLocal { local_id, .. } => assert_eq!(LocalId(1), *local_id),
+          statement => panic!("Unexpected RAST: {:#?}", statement),
+        }
+      }
+      statement => panic!("Unexpected RAST: {:#?}", statement),
+    }
+  }
+
+  #[test]
+  fn resolve_outer_after_inner_block() {
+    let (ctx, program) = transform_source("{ let x = 1; } let mut x = 2; x = 3;").unwrap();
+
+    let x = find_assignment(&program.body).unwrap();
+    assert_eq!(ScopeId(0), ctx.locals[&x].scope_id);
+    assert_eq!(Some(LocalId(1)), ctx.resolve_named_local(ScopeId(0), "x"));
+  }
+
+  #[test]
+  fn resolve_shadowed_in_same_scope() {
+    let (ctx, program) = transform_source("let x = 1; let x = x + 1; x").unwrap();
+
+    assert_eq!(Some(LocalId(1)), ctx.resolve_named_local(ScopeId(0), "x"));
+    match &program.body.statements[1].1 {
+      RastStatement::DeclareLocal {
+        local_id,
+        value: RastExpressionCtx(_, RastExpression::BinaryOp(_, args)),
+      } => {
+        assert_eq!(LocalId(1), *local_id);
+        assert_eq!(RastExpression::Local(LocalId(0)), (args.0).1);
+      }
+      statement => panic!("Unexpected RAST: {:#?}", statement),
+    }
+    assert_eq!(
+      Some(Box::new(RastExpressionCtx(
+        Span::new(26, 27),
+        RastExpression::Local(LocalId(1))
+      ))),
+      program.body.result
+    );
+  }
+
+  #[test]
+  fn inner_local_not_visible_outside() {
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(15, 16),
+        SemanticError::UnknownLocal {
+          name: "y".to_string()
+        }
+      ),
+      transform_source("{ let y = 1; } y").unwrap_err()
+    );
+  }
+
+  #[test]
+  fn resolve_innermost_loop() {
+    let (ctx, program) = transform_source("loop { while true { break; } break; }").unwrap();
+
+    match &program.body.result.as_ref().unwrap().1 {
+      RastExpression::Loop { loop_id, body } => {
+        assert_eq!(LoopKind::Loop, ctx.loops[loop_id].kind);
+        match (&body.statements[0].1, &body.statements[1].1) {
+          (
+            RastStatement::While {
+              loop_id: inner,
+              body,
+              ..
+            },
+            RastStatement::Break { loop_id: outer, .. },
+          ) => {
+            assert_eq!(loop_id, outer);
+            assert_ne!(loop_id, inner);
+            assert_eq!(
+              RastStatement::Break {
+                loop_id: *inner,
+                value: None
+              },
+              body.statements[0].1
+            );
+          }
+          statements => panic!("Unexpected RAST: {:#?}", statements),
+        }
+      }
+      expression => panic!("Unexpected RAST: {:#?}", expression),
+    }
+  }
+
+  #[test]
+  fn break_outside_loop() {
+    assert_eq!(
+      SemanticErrorCtx(Span::new(20, 26), SemanticError::BreakOutsideLoop),
+      transform_source("while true { } { 1; break; }").unwrap_err()
+    );
+    assert_eq!(
+      SemanticErrorCtx(Span::new(0, 9), SemanticError::ContinueOutsideLoop),
+      transform_source("continue;").unwrap_err()
+    );
+  }
+
+  #[test]
+  fn resolve_functions_in_any_order() {
+    let (ctx, program) = transform_source("fn a() -> i32 { b() } fn b() -> i32 { 1 } a()").unwrap();
+
+    let b = ctx.resolve_named_function("b").unwrap();
+    match &program.functions[0].body.result.as_ref().unwrap().1 {
+      RastExpression::Call { function_id, .. } => assert_eq!(b, *function_id),
+      expression => panic!("Unexpected RAST: {:#?}", expression),
+    }
+  }
+
+  #[test]
+  fn function_cannot_see_program_locals() {
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(27, 28),
+        SemanticError::UnknownLocal {
+          name: "x".to_string()
+        }
+      ),
+      transform_source("let x = 1; fn f() -> i32 { x }").unwrap_err()
+    );
+  }
+
+  #[test]
+  fn duplicate_function() {
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(14, 15),
+        SemanticError::DuplicateFunction {
+          name: "f".to_string(),
+          previous: Span::new(3, 4),
+        }
+      ),
+      transform_source("fn f() { } fn f() { }").unwrap_err()
+    );
+  }
+
+  #[test]
+  fn unknown_function() {
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(0, 1),
+        SemanticError::UnknownFunction {
+          name: "g".to_string()
+        }
+      ),
+      transform_source("g()").unwrap_err()
+    );
+  }
+
+  #[test]
+  fn return_outside_function() {
+    assert_eq!(
+      SemanticErrorCtx(Span::new(0, 9), SemanticError::ReturnOutsideFunction),
+      transform_source("return 1;").unwrap_err()
+    );
+  }
+
+  fn captures_of(ctx: &SemanticContext, function_id: FunctionId) -> Vec<(&str, CaptureMode)> {
+    let mut captures: Vec<(&str, CaptureMode)> = ctx.functions[&function_id]
+      .captures
+      .iter()
+      .map(|capture| (ctx.locals[&capture.local_id].name.as_str(), capture.mode))
+      .collect();
+    captures.sort_by_key(|capture| capture.0);
+    captures
+  }
+
+  #[test]
+  fn closure_captures() {
+    let (ctx, _) =
+      transform_source("let a = 1; let mut b = 2; let f = |x: i32| { let c = x; || a + b + c };")
+        .unwrap();
+
+    // The outer closure also captures what the inner one needs from outside
+    // of it, but not its own locals.
+    assert_eq!(
+      vec![("a", CaptureMode::ByValue), ("b", CaptureMode::ByReference)],
+      captures_of(&ctx, FunctionId(0))
+    );
+    assert_eq!(
+      vec![
+        ("a", CaptureMode::ByValue),
+        ("b", CaptureMode::ByReference),
+        ("c", CaptureMode::ByValue)
+      ],
+      captures_of(&ctx, FunctionId(1))
+    );
+  }
+
+  #[test]
+  fn break_inside_closure() {
+    assert_eq!(
+      SemanticErrorCtx(Span::new(20, 26), SemanticError::BreakOutsideLoop),
+      transform_source("loop { let f = || { break; }; }").unwrap_err()
+    );
+  }
+
+  #[test]
+  fn assign_to_element_of_immutable_array() {
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(15, 27),
+        SemanticError::AssignToImmutable {
+          name: "a".to_string(),
+          declaration: Span::new(4, 5),
+        }
+      ),
+      transform_source("let a = [[1]]; a[0][0] = 2;").unwrap_err()
+    );
+  }
+
+  #[test]
+  fn struct_literal_fields() {
+    let declaration = "struct P { x: i32, y: i32 } ";
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(44, 45),
+        SemanticError::UnknownField {
+          struct_name: "P".to_string(),
+          field: "z".to_string()
+        }
+      ),
+      transform_source(&format!("{}P {{ x: 1, y: 2, z: 3 }}", declaration)).unwrap_err()
+    );
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(38, 39),
+        SemanticError::DuplicateField {
+          name: "x".to_string(),
+          previous: Span::new(32, 33)
+        }
+      ),
+      transform_source(&format!("{}P {{ x: 1, x: 2 }}", declaration)).unwrap_err()
+    );
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(28, 38),
+        SemanticError::MissingFields {
+          struct_name: "P".to_string(),
+          fields: vec!["x".to_string()]
+        }
+      ),
+      transform_source(&format!("{}P {{ y: 2 }}", declaration)).unwrap_err()
+    );
+  }
+
+  #[test]
+  fn duplicate_struct() {
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(20, 21),
+        SemanticError::DuplicateType {
+          name: "P".to_string(),
+          previous: Span::new(7, 8),
+        }
+      ),
+      transform_source("struct P { } struct P { }").unwrap_err()
+    );
+  }
+
+  #[test]
+  fn structs_refer_to_each_other() {
+    assert!(transform_source("struct A { b: [B] } struct B { a: A }").is_ok());
+  }
+
+  #[test]
+  fn unknown_variant() {
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(16, 17),
+        SemanticError::UnknownVariant {
+          enum_name: "E".to_string(),
+          variant: "B".to_string()
+        }
+      ),
+      transform_source("enum E { A } E::B").unwrap_err()
+    );
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(31, 32),
+        SemanticError::UnknownType {
+          name: "P".to_string()
+        }
+      ),
+      transform_source("struct P { } match 1 { _ => 1, P::A => 2 }").unwrap_err()
+    );
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(12, 13),
+        SemanticError::DuplicateVariant {
+          name: "A".to_string(),
+          previous: Span::new(9, 10)
+        }
+      ),
+      transform_source("enum E { A, A }").unwrap_err()
+    );
+  }
+
+  #[test]
+  fn match_bindings() {
+    let (ctx, _) =
+      transform_source("enum E { A(i32, i32) } match E::A(1, 2) { E::A(x, _) => x, y => 0 }")
+        .unwrap();
+    let arm_scopes: Vec<ScopeId> = ctx.locals.values().map(|local| local.scope_id).collect();
+    assert_eq!(2, arm_scopes.len());
+    assert_ne!(arm_scopes[0], arm_scopes[1]);
+
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(20, 21),
+        SemanticError::UnknownLocal {
+          name: "x".to_string()
+        }
+      ),
+      transform_source("match 1 { x => x }; x").unwrap_err()
+    );
+    assert_eq!(
+      SemanticErrorCtx(
+        Span::new(50, 51),
+        SemanticError::DuplicateBinding {
+          name: "x".to_string(),
+          previous: Span::new(47, 48)
+        }
+      ),
+      transform_source("enum E { A(i32, i32) } match E::A(1, 2) { E::A(x, x) => x }").unwrap_err()
+    );
+  }
+
+  #[test]
+  fn negative_literals_are_folded() {
+    let (ctx, program) = transform_source("-128i8").unwrap();
+    match program.body.result.as_deref() {
+      Some(RastExpressionCtx(pos, RastExpression::IntegerConstant(-128, literal_id))) => {
+        assert_eq!(Span::new(0, 6), *pos);
+        assert_eq!(
+          TypeRef::Primitive(PrimitiveType::I8),
+          ctx.literals[literal_id].type_
+        );
+      }
+      result => panic!("Unexpected RAST: {:#?}", result),
+    }
+  }
 }
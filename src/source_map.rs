@@ -0,0 +1,163 @@
+use crate::span::Span;
+
+// Files are laid out back to back in a single global offset space, so a Span
+// alone is enough to find the file it belongs to.
+pub struct SourceFile {
+  pub name: String,
+  pub src: String,
+  pub start: usize,
+  line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+  fn new(name: String, src: String, start: usize) -> SourceFile {
+    let line_starts = std::iter::once(0)
+      .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+      .collect();
+
+    SourceFile {
+      name,
+      src,
+      start,
+      line_starts,
+    }
+  }
+
+  pub fn end(&self) -> usize {
+    self.start + self.src.len()
+  }
+
+  pub fn contains(&self, offset: usize) -> bool {
+    offset >= self.start && offset <= self.end()
+  }
+
+  pub fn line_count(&self) -> usize {
+    self.line_starts.len()
+  }
+
+  // Zero-based line index of a file-relative offset.
+  fn line_index(&self, local_offset: usize) -> usize {
+    match self.line_starts.binary_search(&local_offset) {
+      Ok(line) => line,
+      Err(next_line) => next_line - 1,
+    }
+  }
+
+  // Text of a one-based line, without the line terminator.
+  pub fn line_text(&self, line: usize) -> Option<&str> {
+    let start = *self.line_starts.get(line.checked_sub(1)?)?;
+    let end = self
+      .line_starts
+      .get(line)
+      .map(|&next| next - 1)
+      .unwrap_or_else(|| self.src.len());
+    Some(self.src[start..end].trim_end_matches('\r'))
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location<'a> {
+  pub file: &'a str,
+  // Both line and column are one-based. Columns are counted in chars.
+  pub line: usize,
+  pub column: usize,
+}
+
+#[derive(Default)]
+pub struct SourceMap {
+  files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+  pub fn new() -> SourceMap {
+    SourceMap { files: Vec::new() }
+  }
+
+  // Registers a file and returns the offset its spans start at.
+  pub fn add_file(&mut self, name: &str, src: &str) -> usize {
+    // Leave a gap of one byte between files so that an EOF span of one file
+    // can't be confused with the first byte of the next one.
+    let start = self.files.last().map(|x| x.end() + 1).unwrap_or(0);
+    self
+      .files
+      .push(SourceFile::new(name.to_string(), src.to_string(), start));
+    start
+  }
+
+  pub fn file_of(&self, offset: usize) -> Option<&SourceFile> {
+    self.files.iter().find(|file| file.contains(offset))
+  }
+
+  pub fn lookup(&self, offset: usize) -> Option<Location<'_>> {
+    let file = self.file_of(offset)?;
+    let local_offset = offset - file.start;
+    let line = file.line_index(local_offset);
+    let line_start = file.line_starts[line];
+    let column = file.src.get(line_start..local_offset)?.chars().count() + 1;
+
+    Some(Location {
+      file: &file.name,
+      line: line + 1,
+      column,
+    })
+  }
+
+  // Returns the start and end locations of a span.
+  pub fn lookup_span(&self, span: Span) -> Option<(Location<'_>, Location<'_>)> {
+    Some((self.lookup(span.start)?, self.lookup(span.end)?))
+  }
+
+  pub fn snippet(&self, span: Span) -> Option<&str> {
+    let file = self.file_of(span.start)?;
+    file.src.get(span.start - file.start..span.end - file.start)
+  }
+}
+
+#[cfg(test)]
+mod source_map_tests {
+  use super::{Location, SourceMap};
+  use crate::span::Span;
+
+  #[test]
+  fn lookup_lines_and_columns() {
+    let mut map = SourceMap::new();
+    map.add_file("main.toy", "let x = 1;\nlet y = x;\n");
+
+    assert_eq!(
+      Some(Location {
+        file: "main.toy",
+        line: 1,
+        column: 1
+      }),
+      map.lookup(0)
+    );
+    assert_eq!(
+      Some(Location {
+        file: "main.toy",
+        line: 2,
+        column: 9
+      }),
+      map.lookup(19)
+    );
+    assert_eq!(Some("let y = x;"), map.file_of(0).unwrap().line_text(2));
+  }
+
+  #[test]
+  fn lookup_unicode_column() {
+    let mut map = SourceMap::new();
+    map.add_file("a", "乇乂 x");
+    assert_eq!(4, map.lookup(7).unwrap().column);
+  }
+
+  #[test]
+  fn lookup_multiple_files() {
+    let mut map = SourceMap::new();
+    map.add_file("a", "abc");
+    let start = map.add_file("b", "def\nghi");
+
+    let location = map.lookup(start + 5).unwrap();
+    assert_eq!("b", location.file);
+    assert_eq!((2, 2), (location.line, location.column));
+    assert_eq!(Some("def"), map.snippet(Span::new(start, start + 3)));
+  }
+}
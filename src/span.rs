@@ -0,0 +1,55 @@
+use std::fmt;
+
+// A half-open byte range [start, end) into the source text.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+}
+
+impl Span {
+  pub fn new(start: usize, end: usize) -> Span {
+    debug_assert!(start <= end);
+    Span { start, end }
+  }
+
+  pub fn point(offset: usize) -> Span {
+    Span::new(offset, offset)
+  }
+
+  // Smallest span covering both self and other.
+  pub fn to(self, other: Span) -> Span {
+    Span::new(self.start.min(other.start), self.end.max(other.end))
+  }
+
+  pub fn len(&self) -> usize {
+    self.end - self.start
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.start == self.end
+  }
+}
+
+impl fmt::Debug for Span {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}..{}", self.start, self.end)
+  }
+}
+
+#[cfg(test)]
+mod span_tests {
+  use super::Span;
+
+  #[test]
+  fn merge_spans() {
+    assert_eq!(Span::new(2, 9), Span::new(2, 4).to(Span::new(6, 9)));
+    assert_eq!(Span::new(2, 9), Span::new(6, 9).to(Span::new(2, 4)));
+  }
+
+  #[test]
+  fn point_is_empty() {
+    assert!(Span::point(3).is_empty());
+    assert_eq!(0, Span::point(3).len());
+  }
+}
@@ -1,5 +1,7 @@
+use crate::ast_common::Float;
 use crate::char_stream::CharStream;
 use crate::parse_utils;
+use crate::span::Span;
 use crate::tokens::Token;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -7,23 +9,33 @@ pub enum LexerError {
   UnknownToken(String),
   InvalidNumber(String),
   UnterminatedString,
+  InvalidEscape(String),
   UnexpectedEof,
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct LexerErrorCtx(pub usize, pub LexerError);
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LexerErrorCtx(pub Span, pub LexerError);
 
 pub type LexerResult<T> = Result<T, LexerErrorCtx>;
 
 pub struct TokenStream<'a> {
   stream: CharStream<'a>,
-  lookahead: Option<(usize, Token<'a>)>,
+  base_offset: usize,
+  // Errors are kept in the lookahead too, so that peeking is idempotent.
+  lookahead: Option<LexerResult<(Span, Token<'a>)>>,
 }
 
 impl<'a> TokenStream<'a> {
   pub fn new(src: &'a str) -> TokenStream<'a> {
+    TokenStream::with_base_offset(src, 0)
+  }
+
+  // Creates a token stream whose spans start at base_offset, which is used
+  // when the source is one of several files in a SourceMap.
+  pub fn with_base_offset(src: &'a str, base_offset: usize) -> TokenStream<'a> {
     TokenStream {
       stream: CharStream::from_str(src),
+      base_offset,
       lookahead: None,
     }
   }
@@ -40,20 +52,124 @@ impl<'a> TokenStream<'a> {
     match keyword_or_identifier {
       "let" => Ok(Token::Let),
       "mut" => Ok(Token::Mut),
+      "if" => Ok(Token::If),
+      "else" => Ok(Token::Else),
+      "while" => Ok(Token::While),
+      "loop" => Ok(Token::Loop),
+      "break" => Ok(Token::Break),
+      "continue" => Ok(Token::Continue),
+      "fn" => Ok(Token::Fn),
+      "return" => Ok(Token::Return),
+      "struct" => Ok(Token::Struct),
+      "enum" => Ok(Token::Enum),
+      "match" => Ok(Token::Match),
+      "as" => Ok(Token::As),
+      "true" => Ok(Token::True),
+      "false" => Ok(Token::False),
       otherwise => Ok(Token::Identifier(otherwise)),
     }
   }
 
+  // A number is a float if it has a fractional part, an exponent or the
+  // suffix `f64`. The dot must be followed by a digit, so `1.` is not a float.
   fn read_number(&mut self) -> LexerResult<Token<'a>> {
     let offset = self.byte_offset();
-    let chars = self.stream.take_while(|c| c.is_digit(10));
-    let parsed = chars
-      .parse()
-      .map_err(|_| LexerErrorCtx(offset, LexerError::InvalidNumber(chars.to_string())))?;
-    Ok(Token::Integer(parsed))
+    let start = self.stream.byte_offset();
+    self.stream.skip_while(|c| c.is_ascii_digit());
+
+    let mut is_float = false;
+    if self.stream.peek() == Some('.') && self.digit_at(1) {
+      self.stream.advance();
+      self.stream.skip_while(|c| c.is_ascii_digit());
+      is_float = true;
+    }
+    if let Some('e') | Some('E') = self.stream.peek() {
+      let sign = matches!(self.stream.peek_nth(1), Some('+') | Some('-'));
+      if self.digit_at(if sign { 2 } else { 1 }) {
+        self.stream.advance();
+        if sign {
+          self.stream.advance();
+        }
+        self.stream.skip_while(|c| c.is_ascii_digit());
+        is_float = true;
+      }
+    }
+
+    let chars = self.stream.slice(start, self.stream.byte_offset());
+    let suffix = self.stream.take_while(parse_utils::is_valid_in_identifier);
+    let invalid = || {
+      LexerErrorCtx(
+        Span::new(offset, self.byte_offset()),
+        LexerError::InvalidNumber(format!("{}{}", chars, suffix)),
+      )
+    };
+
+    if is_float || suffix == "f64" {
+      // Floats that are too large for an f64 are rejected rather than rounded
+      // to infinity.
+      return match (suffix, chars.parse::<f64>()) {
+        ("", Ok(x)) | ("f64", Ok(x)) if x.is_finite() => Ok(Token::Float(Float(x))),
+        _ => Err(invalid()),
+      };
+    }
+
+    let parsed = chars.parse().map_err(|_| invalid())?;
+    match suffix {
+      "" => Ok(Token::Integer(parsed, None)),
+      suffix if parse_utils::is_integer_suffix(suffix) => Ok(Token::Integer(parsed, Some(suffix))),
+      _ => Err(invalid()),
+    }
+  }
+
+  fn digit_at(&self, n: usize) -> bool {
+    matches!(self.stream.peek_nth(n), Some(c) if c.is_ascii_digit())
+  }
+
+  // Reads a string literal up to the closing quote. The escape sequences in it
+  // are only checked here, and replaced by the parser.
+  fn read_string(&mut self) -> LexerResult<Token<'a>> {
+    let offset = self.byte_offset();
+    self.stream.advance();
+
+    let start = self.stream.byte_offset();
+    let end = loop {
+      let end = self.stream.byte_offset();
+      match self.stream.take() {
+        Some('"') => break end,
+        // An escaped quote doesn't end the literal.
+        Some('\\') => self.stream.advance(),
+        Some(_) => {}
+        None => {
+          return Err(LexerErrorCtx(
+            Span::new(offset, self.byte_offset()),
+            LexerError::UnterminatedString,
+          ))
+        }
+      }
+    };
+
+    let contents = self.stream.slice(start, end);
+    if let Err((from, to)) = parse_utils::unescape(contents) {
+      return Err(LexerErrorCtx(
+        Span::new(offset + 1 + from, offset + 1 + to),
+        LexerError::InvalidEscape(contents[from..to].to_string()),
+      ));
+    }
+    Ok(Token::Str(contents))
+  }
+
+  // Picks `two_char` and consumes `next` if it's the next character, or
+  // picks `one_char` otherwise.
+  fn followed_by(&mut self, next: char, two_char: Token<'a>, one_char: Token<'a>) -> Token<'a> {
+    if self.stream.peek() == Some(next) {
+      self.stream.advance();
+      two_char
+    } else {
+      one_char
+    }
   }
 
-  fn read_token(&mut self) -> LexerResult<(usize, Token<'a>)> {
+  fn read_token(&mut self) -> LexerResult<(Span, Token<'a>)> {
     use Token::*;
 
     self.stream.skip_whitespace();
@@ -61,13 +177,13 @@ impl<'a> TokenStream<'a> {
     let offset = self.byte_offset();
 
     if self.stream.remaining() == 0 {
-      return Ok((offset, Token::EOF));
+      return Ok((Span::point(offset), Token::EOF));
     }
 
-    let fst = self
-      .stream
-      .peek()
-      .ok_or_else(|| LexerErrorCtx(offset, LexerError::UnexpectedEof))?;
+    let fst = self.stream.peek().ok_or(LexerErrorCtx(
+      Span::point(offset),
+      LexerError::UnexpectedEof,
+    ))?;
 
     (match fst {
       '(' => {
@@ -86,9 +202,55 @@ impl<'a> TokenStream<'a> {
         self.stream.advance();
         Ok(RBrace)
       }
+      '[' => {
+        self.stream.advance();
+        Ok(LBracket)
+      }
+      ']' => {
+        self.stream.advance();
+        Ok(RBracket)
+      }
       '=' => {
         self.stream.advance();
-        Ok(Equals)
+        match self.stream.peek() {
+          Some('>') => {
+            self.stream.advance();
+            Ok(FatArrow)
+          }
+          _ => Ok(self.followed_by('=', DoubleEquals, Equals)),
+        }
+      }
+      '!' => {
+        self.stream.advance();
+        Ok(self.followed_by('=', NotEquals, Bang))
+      }
+      '<' => {
+        self.stream.advance();
+        match self.stream.peek() {
+          Some('<') => {
+            self.stream.advance();
+            Ok(DoubleLessThan)
+          }
+          _ => Ok(self.followed_by('=', LessThanEquals, LessThan)),
+        }
+      }
+      '>' => {
+        self.stream.advance();
+        match self.stream.peek() {
+          Some('>') => {
+            self.stream.advance();
+            Ok(DoubleGreaterThan)
+          }
+          _ => Ok(self.followed_by('=', GreaterThanEquals, GreaterThan)),
+        }
+      }
+      '&' => {
+        self.stream.advance();
+        Ok(self.followed_by('&', DoubleAmpersand, Ampersand))
+      }
+      '|' => {
+        self.stream.advance();
+        Ok(self.followed_by('|', DoublePipe, Pipe))
       }
       ';' => {
         self.stream.advance();
@@ -96,7 +258,15 @@ impl<'a> TokenStream<'a> {
       }
       ':' => {
         self.stream.advance();
-        Ok(Colon)
+        Ok(self.followed_by(':', DoubleColon, Colon))
+      }
+      ',' => {
+        self.stream.advance();
+        Ok(Comma)
+      }
+      '.' => {
+        self.stream.advance();
+        Ok(Dot)
       }
       '+' => {
         self.stream.advance();
@@ -104,57 +274,94 @@ impl<'a> TokenStream<'a> {
       }
       '-' => {
         self.stream.advance();
-        Ok(Minus)
+        Ok(self.followed_by('>', Arrow, Minus))
       }
       '*' => {
         self.stream.advance();
         Ok(Asterisk)
       }
+      '/' => {
+        self.stream.advance();
+        Ok(Slash)
+      }
+      '%' => {
+        self.stream.advance();
+        Ok(Percent)
+      }
+      '^' => {
+        self.stream.advance();
+        Ok(Caret)
+      }
+      '~' => {
+        self.stream.advance();
+        Ok(Tilde)
+      }
       '0'..='9' => self.read_number(),
-      'A'..='z' => self.read_keyword_or_identifier(),
-      _ => Err(LexerErrorCtx(
-        offset,
-        LexerError::UnknownToken(fst.to_string()),
-      )),
+      '"' => self.read_string(),
+      'A'..='Z' | 'a'..='z' | '_' => self.read_keyword_or_identifier(),
+      _ => {
+        // Skip the offending character so that the parser can recover.
+        self.stream.advance();
+        Err(LexerErrorCtx(
+          Span::new(offset, self.byte_offset()),
+          LexerError::UnknownToken(fst.to_string()),
+        ))
+      }
     })
-    .map(|x| (offset, x))
+    .map(|x| (Span::new(offset, self.byte_offset()), x))
   }
 
-  pub fn peek_pos(&mut self) -> LexerResult<&(usize, Token)> {
+  pub fn peek_pos(&mut self) -> LexerResult<&(Span, Token<'a>)> {
     if self.lookahead.is_none() {
-      self.lookahead = Some(self.read_token()?);
+      self.lookahead = Some(self.read_token());
     }
 
-    Ok(&self.lookahead.as_ref().unwrap_or_else(|| unsafe {
-      std::hint::unreachable_unchecked();
-    }))
+    match &self.lookahead {
+      Some(Ok(token)) => Ok(token),
+      Some(Err(err)) => Err(err.clone()),
+      None => unsafe { std::hint::unreachable_unchecked() },
+    }
   }
 
-  pub fn peek(&mut self) -> LexerResult<&Token> {
+  pub fn peek(&mut self) -> LexerResult<&Token<'a>> {
     self.peek_pos().map(|x| &x.1)
   }
 
-  pub fn take_pos(&mut self) -> LexerResult<(usize, Token)> {
-    if let Some(token) = self.lookahead {
-      self.lookahead = None;
-      Ok(token)
-    } else {
-      self.read_token()
+  pub fn take_pos(&mut self) -> LexerResult<(Span, Token<'a>)> {
+    match self.lookahead.take() {
+      Some(token) => token,
+      None => self.read_token(),
     }
   }
 
-  pub fn take(&mut self) -> LexerResult<Token> {
+  pub fn take(&mut self) -> LexerResult<Token<'a>> {
     self.take_pos().map(|x| x.1)
   }
 
+  // Splits a peeked `>>` into two `>`, as in `A<B<C>>` where it closes two
+  // lists of type arguments. Takes the first and returns its span, leaving
+  // the second one peeked.
+  pub fn split_double_greater_than(&mut self) -> Option<Span> {
+    match &mut self.lookahead {
+      Some(Ok((span, token @ Token::DoubleGreaterThan))) => {
+        let first = Span::new(span.start, span.start + 1);
+        *span = Span::new(span.start + 1, span.end);
+        *token = Token::GreaterThan;
+        Some(first)
+      }
+      _ => None,
+    }
+  }
+
   pub fn byte_offset(&self) -> usize {
-    self.stream.byte_offset()
+    self.base_offset + self.stream.byte_offset()
   }
 }
 
 #[cfg(test)]
 mod token_stream_tests {
-  use super::{Token, TokenStream};
+  use super::{Float, LexerError, LexerErrorCtx, Token, TokenStream};
+  use crate::span::Span;
 
   #[test]
   fn read_seq() {
@@ -162,7 +369,306 @@ mod token_stream_tests {
     assert_eq!(Ok(Token::Let), stream.take());
     assert_eq!(Ok(Token::Identifier("x")), stream.take());
     assert_eq!(Ok(Token::Equals), stream.take());
-    assert_eq!(Ok(Token::Integer(10)), stream.take());
+    assert_eq!(Ok(Token::Integer(10, None)), stream.take());
+    assert_eq!(Ok(Token::EOF), stream.take());
+  }
+
+  #[test]
+  fn token_spans() {
+    let mut stream = TokenStream::new("let  value= 10");
+    assert_eq!(Ok((Span::new(0, 3), Token::Let)), stream.take_pos());
+    assert_eq!(
+      Ok((Span::new(5, 10), Token::Identifier("value"))),
+      stream.take_pos()
+    );
+    assert_eq!(Ok((Span::new(10, 11), Token::Equals)), stream.take_pos());
+    assert_eq!(
+      Ok((Span::new(12, 14), Token::Integer(10, None))),
+      stream.take_pos()
+    );
+    assert_eq!(Ok((Span::point(14), Token::EOF)), stream.take_pos());
+  }
+
+  #[test]
+  fn token_spans_with_base_offset() {
+    let mut stream = TokenStream::with_base_offset("x", 100);
+    assert_eq!(
+      Ok((Span::new(100, 101), Token::Identifier("x"))),
+      stream.take_pos()
+    );
+  }
+
+  #[test]
+  fn unknown_token_span() {
+    let mut stream = TokenStream::new("  ä");
+    assert_eq!(
+      Err(LexerErrorCtx(
+        Span::new(2, 4),
+        LexerError::UnknownToken("ä".to_string())
+      )),
+      stream.take_pos()
+    );
+  }
+
+  #[test]
+  fn skip_unknown_token() {
+    let mut stream = TokenStream::new("x @ y");
+    assert_eq!(Ok(Token::Identifier("x")), stream.take());
+    assert!(stream.peek().is_err());
+    assert!(stream.peek().is_err());
+    assert!(stream.take().is_err());
+    assert_eq!(Ok(Token::Identifier("y")), stream.take());
+  }
+
+  #[test]
+  fn read_operators() {
+    let mut stream = TokenStream::new("= == ! != < <= > >= && || true false");
+    for token in &[
+      Token::Equals,
+      Token::DoubleEquals,
+      Token::Bang,
+      Token::NotEquals,
+      Token::LessThan,
+      Token::LessThanEquals,
+      Token::GreaterThan,
+      Token::GreaterThanEquals,
+      Token::DoubleAmpersand,
+      Token::DoublePipe,
+      Token::True,
+      Token::False,
+      Token::EOF,
+    ] {
+      assert_eq!(Ok(*token), stream.take());
+    }
+  }
+
+  #[test]
+  fn read_operators_without_whitespace() {
+    let mut stream = TokenStream::new("a<=!b|c|||");
+    assert_eq!(Ok(Token::Identifier("a")), stream.take());
+    assert_eq!(Ok(Token::LessThanEquals), stream.take());
+    assert_eq!(Ok(Token::Bang), stream.take());
+    assert_eq!(Ok(Token::Identifier("b")), stream.take());
+    assert_eq!(Ok(Token::Pipe), stream.take());
+    assert_eq!(Ok(Token::Identifier("c")), stream.take());
+    assert_eq!(Ok(Token::DoublePipe), stream.take());
+    assert_eq!(Ok(Token::Pipe), stream.take());
+  }
+
+  #[test]
+  fn read_arithmetic_and_bitwise_operators() {
+    let mut stream = TokenStream::new("+-*/%&^~<<>>&&<<=");
+    for token in &[
+      Token::Plus,
+      Token::Minus,
+      Token::Asterisk,
+      Token::Slash,
+      Token::Percent,
+      Token::Ampersand,
+      Token::Caret,
+      Token::Tilde,
+      Token::DoubleLessThan,
+      Token::DoubleGreaterThan,
+      Token::DoubleAmpersand,
+      Token::DoubleLessThan,
+      Token::Equals,
+      Token::EOF,
+    ] {
+      assert_eq!(Ok(*token), stream.take());
+    }
+  }
+
+  #[test]
+  fn read_array_type() {
+    let mut stream = TokenStream::new("a:[[i32]]");
+    for token in &[
+      Token::Identifier("a"),
+      Token::Colon,
+      Token::LBracket,
+      Token::LBracket,
+      Token::Identifier("i32"),
+      Token::RBracket,
+      Token::RBracket,
+      Token::EOF,
+    ] {
+      assert_eq!(Ok(*token), stream.take());
+    }
+  }
+
+  #[test]
+  fn read_field_access() {
+    let mut stream = TokenStream::new("p.x.y=a[0].z");
+    for token in &[
+      Token::Identifier("p"),
+      Token::Dot,
+      Token::Identifier("x"),
+      Token::Dot,
+      Token::Identifier("y"),
+      Token::Equals,
+      Token::Identifier("a"),
+      Token::LBracket,
+      Token::Integer(0, None),
+      Token::RBracket,
+      Token::Dot,
+      Token::Identifier("z"),
+      Token::EOF,
+    ] {
+      assert_eq!(Ok(*token), stream.take());
+    }
+  }
+
+  #[test]
+  fn read_match_arm() {
+    let mut stream = TokenStream::new("Shape::Circle(r)=>r,_=>0==0");
+    for token in &[
+      Token::Identifier("Shape"),
+      Token::DoubleColon,
+      Token::Identifier("Circle"),
+      Token::LParen,
+      Token::Identifier("r"),
+      Token::RParen,
+      Token::FatArrow,
+      Token::Identifier("r"),
+      Token::Comma,
+      Token::Identifier("_"),
+      Token::FatArrow,
+      Token::Integer(0, None),
+      Token::DoubleEquals,
+      Token::Integer(0, None),
+      Token::EOF,
+    ] {
+      assert_eq!(Ok(*token), stream.take());
+    }
+  }
+
+  #[test]
+  fn read_integer_suffix() {
+    let mut stream = TokenStream::new("1u8+255i64 7x");
+    assert_eq!(Ok(Token::Integer(1, Some("u8"))), stream.take());
+    assert_eq!(Ok(Token::Plus), stream.take());
+    assert_eq!(
+      Ok((Span::new(4, 10), Token::Integer(255, Some("i64")))),
+      stream.take_pos()
+    );
+    assert_eq!(
+      Err(LexerErrorCtx(
+        Span::new(11, 13),
+        LexerError::InvalidNumber("7x".to_string())
+      )),
+      stream.take_pos()
+    );
+  }
+
+  #[test]
+  fn read_float() {
+    let mut stream = TokenStream::new("1.5 2e3 1E-3 7f64 0.25e+1 a.0 1.x");
+    for token in &[
+      Token::Float(Float(1.5)),
+      Token::Float(Float(2000.0)),
+      Token::Float(Float(0.001)),
+      Token::Float(Float(7.0)),
+      Token::Float(Float(2.5)),
+      Token::Identifier("a"),
+      Token::Dot,
+      Token::Integer(0, None),
+      Token::Integer(1, None),
+      Token::Dot,
+      Token::Identifier("x"),
+      Token::EOF,
+    ] {
+      assert_eq!(Ok(*token), stream.take());
+    }
+  }
+
+  #[test]
+  fn float_errors() {
+    let mut stream = TokenStream::new("1.5u8 1e999 2e");
+    assert_eq!(
+      Err(LexerErrorCtx(
+        Span::new(0, 5),
+        LexerError::InvalidNumber("1.5u8".to_string())
+      )),
+      stream.take_pos()
+    );
+    assert_eq!(
+      Err(LexerErrorCtx(
+        Span::new(6, 11),
+        LexerError::InvalidNumber("1e999".to_string())
+      )),
+      stream.take_pos()
+    );
+    assert_eq!(
+      Err(LexerErrorCtx(
+        Span::new(12, 14),
+        LexerError::InvalidNumber("2e".to_string())
+      )),
+      stream.take_pos()
+    );
+  }
+
+  #[test]
+  fn read_string() {
+    let mut stream = TokenStream::new(r#"x+"a \"b\" \\"+"""#);
+    assert_eq!(Ok(Token::Identifier("x")), stream.take());
+    assert_eq!(Ok(Token::Plus), stream.take());
+    assert_eq!(
+      Ok((Span::new(2, 14), Token::Str(r#"a \"b\" \\"#))),
+      stream.take_pos()
+    );
+    assert_eq!(Ok(Token::Plus), stream.take());
+    assert_eq!(Ok(Token::Str("")), stream.take());
     assert_eq!(Ok(Token::EOF), stream.take());
   }
+
+  #[test]
+  fn string_errors() {
+    let mut stream = TokenStream::new(r#""\u{1F600}\x" 1 "\"#);
+    assert_eq!(
+      Err(LexerErrorCtx(
+        Span::new(10, 12),
+        LexerError::InvalidEscape("\\x".to_string())
+      )),
+      stream.take_pos()
+    );
+    // The rest of the literal is skipped.
+    assert_eq!(Ok(Token::Integer(1, None)), stream.take());
+    assert_eq!(
+      Err(LexerErrorCtx(
+        Span::new(16, 18),
+        LexerError::UnterminatedString
+      )),
+      stream.take_pos()
+    );
+  }
+
+  #[test]
+  fn read_function_signature() {
+    let mut stream = TokenStream::new("fn f(a: i32, b: i32) -> i32 { return a--b; }");
+    for token in &[
+      Token::Fn,
+      Token::Identifier("f"),
+      Token::LParen,
+      Token::Identifier("a"),
+      Token::Colon,
+      Token::Identifier("i32"),
+      Token::Comma,
+      Token::Identifier("b"),
+      Token::Colon,
+      Token::Identifier("i32"),
+      Token::RParen,
+      Token::Arrow,
+      Token::Identifier("i32"),
+      Token::LBrace,
+      Token::Return,
+      Token::Identifier("a"),
+      Token::Minus,
+      Token::Minus,
+      Token::Identifier("b"),
+      Token::Semicolon,
+      Token::RBrace,
+      Token::EOF,
+    ] {
+      assert_eq!(Ok(*token), stream.take());
+    }
+  }
 }
@@ -1,19 +1,65 @@
+use std::fmt;
+
+use crate::ast_common::Float;
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Token<'a> {
   Let,
   Mut,
+  If,
+  Else,
+  While,
+  Loop,
+  Break,
+  Continue,
+  Fn,
+  Return,
+  Struct,
+  Enum,
+  Match,
+  As,
   Equals,
   LParen,
   RParen,
   LBrace,
   RBrace,
+  LBracket,
+  RBracket,
   Colon,
+  DoubleColon,
   Semicolon,
+  Comma,
+  Dot,
+  Arrow,
+  FatArrow,
   Identifier(&'a str),
-  Integer(i128),
+  // The value and the type suffix, as in `10u8`.
+  Integer(i128, Option<&'a str>),
+  Float(Float),
+  // The contents of a string literal, with escape sequences not yet replaced.
+  Str(&'a str),
   Plus,
   Minus,
   Asterisk,
+  Slash,
+  Percent,
+  Ampersand,
+  Caret,
+  Tilde,
+  DoubleLessThan,
+  DoubleGreaterThan,
+  True,
+  False,
+  DoubleEquals,
+  NotEquals,
+  LessThan,
+  LessThanEquals,
+  GreaterThan,
+  GreaterThanEquals,
+  DoubleAmpersand,
+  DoublePipe,
+  Pipe,
+  Bang,
   EOF,
 }
 
@@ -21,18 +67,58 @@ pub enum Token<'a> {
 pub enum TokenKind {
   Let,
   Mut,
+  If,
+  Else,
+  While,
+  Loop,
+  Break,
+  Continue,
+  Fn,
+  Return,
+  Struct,
+  Enum,
+  Match,
+  As,
   Equals,
   LParen,
   RParen,
   LBrace,
   RBrace,
+  LBracket,
+  RBracket,
   Colon,
+  DoubleColon,
   Semicolon,
+  Comma,
+  Dot,
+  Arrow,
+  FatArrow,
   Identifier,
   Integer,
+  Float,
+  Str,
   Plus,
   Minus,
   Asterisk,
+  Slash,
+  Percent,
+  Ampersand,
+  Caret,
+  Tilde,
+  DoubleLessThan,
+  DoubleGreaterThan,
+  True,
+  False,
+  DoubleEquals,
+  NotEquals,
+  LessThan,
+  LessThanEquals,
+  GreaterThan,
+  GreaterThanEquals,
+  DoubleAmpersand,
+  DoublePipe,
+  Pipe,
+  Bang,
   EOF,
 }
 
@@ -41,19 +127,122 @@ impl<'a> Token<'a> {
     match self {
       Token::Let => TokenKind::Let,
       Token::Mut => TokenKind::Mut,
+      Token::If => TokenKind::If,
+      Token::Else => TokenKind::Else,
+      Token::While => TokenKind::While,
+      Token::Loop => TokenKind::Loop,
+      Token::Break => TokenKind::Break,
+      Token::Continue => TokenKind::Continue,
+      Token::Fn => TokenKind::Fn,
+      Token::Return => TokenKind::Return,
+      Token::Struct => TokenKind::Struct,
+      Token::Enum => TokenKind::Enum,
+      Token::Match => TokenKind::Match,
+      Token::As => TokenKind::As,
       Token::Equals => TokenKind::Equals,
       Token::LParen => TokenKind::LParen,
       Token::RParen => TokenKind::RParen,
       Token::LBrace => TokenKind::LBrace,
       Token::RBrace => TokenKind::RBrace,
+      Token::LBracket => TokenKind::LBracket,
+      Token::RBracket => TokenKind::RBracket,
       Token::Colon => TokenKind::Colon,
+      Token::DoubleColon => TokenKind::DoubleColon,
       Token::Semicolon => TokenKind::Semicolon,
+      Token::Comma => TokenKind::Comma,
+      Token::Dot => TokenKind::Dot,
+      Token::Arrow => TokenKind::Arrow,
+      Token::FatArrow => TokenKind::FatArrow,
       Token::Identifier(_) => TokenKind::Identifier,
-      Token::Integer(_) => TokenKind::Integer,
+      Token::Integer(..) => TokenKind::Integer,
+      Token::Float(_) => TokenKind::Float,
+      Token::Str(_) => TokenKind::Str,
       Token::Plus => TokenKind::Plus,
       Token::Minus => TokenKind::Minus,
       Token::Asterisk => TokenKind::Asterisk,
+      Token::Slash => TokenKind::Slash,
+      Token::Percent => TokenKind::Percent,
+      Token::Ampersand => TokenKind::Ampersand,
+      Token::Caret => TokenKind::Caret,
+      Token::Tilde => TokenKind::Tilde,
+      Token::DoubleLessThan => TokenKind::DoubleLessThan,
+      Token::DoubleGreaterThan => TokenKind::DoubleGreaterThan,
+      Token::True => TokenKind::True,
+      Token::False => TokenKind::False,
+      Token::DoubleEquals => TokenKind::DoubleEquals,
+      Token::NotEquals => TokenKind::NotEquals,
+      Token::LessThan => TokenKind::LessThan,
+      Token::LessThanEquals => TokenKind::LessThanEquals,
+      Token::GreaterThan => TokenKind::GreaterThan,
+      Token::GreaterThanEquals => TokenKind::GreaterThanEquals,
+      Token::DoubleAmpersand => TokenKind::DoubleAmpersand,
+      Token::DoublePipe => TokenKind::DoublePipe,
+      Token::Pipe => TokenKind::Pipe,
+      Token::Bang => TokenKind::Bang,
       Token::EOF => TokenKind::EOF,
     }
   }
 }
+
+impl fmt::Display for TokenKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let description = match self {
+      TokenKind::Let => "`let`",
+      TokenKind::Mut => "`mut`",
+      TokenKind::If => "`if`",
+      TokenKind::Else => "`else`",
+      TokenKind::While => "`while`",
+      TokenKind::Loop => "`loop`",
+      TokenKind::Break => "`break`",
+      TokenKind::Continue => "`continue`",
+      TokenKind::Fn => "`fn`",
+      TokenKind::Return => "`return`",
+      TokenKind::Struct => "`struct`",
+      TokenKind::Enum => "`enum`",
+      TokenKind::Match => "`match`",
+      TokenKind::As => "`as`",
+      TokenKind::Equals => "`=`",
+      TokenKind::LParen => "`(`",
+      TokenKind::RParen => "`)`",
+      TokenKind::LBrace => "`{`",
+      TokenKind::RBrace => "`}`",
+      TokenKind::LBracket => "`[`",
+      TokenKind::RBracket => "`]`",
+      TokenKind::Colon => "`:`",
+      TokenKind::DoubleColon => "`::`",
+      TokenKind::Semicolon => "`;`",
+      TokenKind::Comma => "`,`",
+      TokenKind::Dot => "`.`",
+      TokenKind::Arrow => "`->`",
+      TokenKind::FatArrow => "`=>`",
+      TokenKind::Identifier => "identifier",
+      TokenKind::Integer => "integer literal",
+      TokenKind::Float => "float literal",
+      TokenKind::Str => "string literal",
+      TokenKind::Plus => "`+`",
+      TokenKind::Minus => "`-`",
+      TokenKind::Asterisk => "`*`",
+      TokenKind::Slash => "`/`",
+      TokenKind::Percent => "`%`",
+      TokenKind::Ampersand => "`&`",
+      TokenKind::Caret => "`^`",
+      TokenKind::Tilde => "`~`",
+      TokenKind::DoubleLessThan => "`<<`",
+      TokenKind::DoubleGreaterThan => "`>>`",
+      TokenKind::True => "`true`",
+      TokenKind::False => "`false`",
+      TokenKind::DoubleEquals => "`==`",
+      TokenKind::NotEquals => "`!=`",
+      TokenKind::LessThan => "`<`",
+      TokenKind::LessThanEquals => "`<=`",
+      TokenKind::GreaterThan => "`>`",
+      TokenKind::GreaterThanEquals => "`>=`",
+      TokenKind::DoubleAmpersand => "`&&`",
+      TokenKind::DoublePipe => "`||`",
+      TokenKind::Pipe => "`|`",
+      TokenKind::Bang => "`!`",
+      TokenKind::EOF => "end of file",
+    };
+    write!(f, "{}", description)
+  }
+}
@@ -1,153 +1,1945 @@
 #![allow(dead_code)]
 
-use crate::ast_common::{BinaryOperator, UnaryOperator};
+use std::collections::HashMap;
+
+use crate::ast_common::{BinaryOperator, Operator, UnaryOperator};
 use crate::rast::{
-  LocalId, PrimitiveType, RastExpression, RastExpressionCtx, RastProgram, RastStatement,
-  RastStatementCtx, ScopeId, TypeRef,
+  DeferredOperation, FunctionId, LocalId, Loop, LoopKind, PrimitiveType, RastBlock, RastExpression,
+  RastExpressionCtx, RastFunction, RastMatchArm, RastPattern, RastPatternCtx, RastProgram,
+  RastStatement, RastStatementCtx, ScopeId, TypeArgumentsId, TypeRef, TypeVariableId,
+  TypeVariableKind, TypeVariableOrigin, UserTypeDef, UserTypeId,
 };
 use crate::semantic::SemanticContext;
+use crate::span::Span;
+use crate::utils::{with_stack, PASS_STACK_SIZE};
 
+// Types are named as they are when the error is found, since the context that
+// knows what they are is gone by the time the error is shown.
 #[derive(Debug, PartialEq, Eq)]
 pub enum TypeError {
   NotAssignable {
-    target: TypeRef,
-    x: TypeRef,
+    target: String,
+    x: String,
   },
   InvalidUnaryOpArg {
     op: UnaryOperator,
-    x: TypeRef,
+    x: String,
   },
   InvalidBinaryOpArgs {
     op: BinaryOperator,
-    lhs: TypeRef,
-    rhs: TypeRef,
+    lhs: String,
+    rhs: String,
   },
   UntypedLocal {
     local_id: LocalId,
   },
+  InvalidCondition {
+    x: String,
+  },
+  MismatchedBranches {
+    then_type: String,
+    else_type: String,
+  },
+  // An if without an else has the value (), so its block must as well.
+  MissingElse {
+    then_type: String,
+  },
+  BreakWithValueFromWhile,
+  MismatchedBreak {
+    expected: String,
+    x: String,
+  },
+  WrongArgumentCount {
+    expected: usize,
+    actual: usize,
+  },
+  MismatchedReturn {
+    expected: String,
+    x: String,
+  },
+  NotCallable {
+    x: String,
+  },
+  // The element type of `[]` can't be known.
+  EmptyArray,
+  ExpectedArray {
+    x: String,
+  },
+  InvalidIndex {
+    x: String,
+  },
+  NoSuchField {
+    x: String,
+    field: String,
+  },
+  MismatchedPattern {
+    expected: String,
+    x: String,
+  },
+  WrongFieldCount {
+    expected: usize,
+    actual: usize,
+  },
+  MismatchedArms {
+    expected: String,
+    x: String,
+  },
+  // `missing` is a pattern that none of the arms match.
+  NonExhaustive {
+    missing: String,
+  },
+  UnreachablePattern,
+  LiteralOutOfRange {
+    value: i128,
+    x: PrimitiveType,
+  },
+  // Only numbers, and bools into integers, can be cast.
+  InvalidCast {
+    x: String,
+    target: String,
+  },
+  // The type of an expression must be known where it's used, but nothing
+  // decides it.
+  CannotInfer,
 }
 
 #[derive(Debug, PartialEq, Eq)]
-pub struct TypeErrorCtx(usize, TypeError);
+pub struct TypeErrorCtx(pub Span, pub TypeError);
 
 pub type TypeResult<T> = Result<T, TypeErrorCtx>;
 
-pub fn are_equal(ctx: &mut SemanticContext, a: TypeRef, b: TypeRef) -> bool {
-  use self::TypeRef::*;
+// Follows type variables to their values, until a type that isn't a variable
+// or a variable without a value.
+pub fn shallow_resolve(ctx: &SemanticContext, x: TypeRef) -> TypeRef {
+  let mut x = x;
+  while let TypeRef::Variable(id) = x {
+    match ctx.type_variables[&id].value {
+      Some(value) => x = value,
+      None => break,
+    }
+  }
+  x
+}
+
+// Replaces the type variables in a type, including those in its element or
+// parameter types, with their values. Integer variables without a value are
+// shown as i32, which is what they become if nothing else decides them.
+pub fn resolve_type(ctx: &mut SemanticContext, x: TypeRef) -> TypeRef {
+  map_variables(ctx, x, &mut |ctx, id| match ctx.type_variables[&id].kind {
+    TypeVariableKind::Integer => TypeRef::Primitive(PrimitiveType::I32),
+    _ => TypeRef::Variable(id),
+  })
+}
+
+// The name of a type as it's written in the source, like `[i32]` or
+// `fn(Pair<i32, bool>) -> str`. Type variables are replaced with their values
+// as in resolve_type, and those without a value are shown as `_`, unless they
+// are type parameters.
+pub fn type_name(ctx: &SemanticContext, x: TypeRef) -> String {
+  let names = |types: &[TypeRef]| {
+    types
+      .iter()
+      .map(|&x| type_name(ctx, x))
+      .collect::<Vec<_>>()
+      .join(", ")
+  };
+  match shallow_resolve(ctx, x) {
+    TypeRef::Primitive(primitive) => primitive.to_string(),
+    TypeRef::Variable(id) => match &ctx.type_variables[&id] {
+      variable if variable.kind == TypeVariableKind::Integer => PrimitiveType::I32.to_string(),
+      variable => variable.name.clone().unwrap_or_else(|| "_".to_string()),
+    },
+    TypeRef::UserType(id) => match &ctx.resolve_type(id).type_def {
+      UserTypeDef::Array(element_type) => format!("[{}]", type_name(ctx, *element_type)),
+      UserTypeDef::Function {
+        parameters,
+        return_type,
+      } => match shallow_resolve(ctx, *return_type) {
+        TypeRef::Primitive(PrimitiveType::Unit) => format!("fn({})", names(parameters)),
+        _ => format!(
+          "fn({}) -> {}",
+          names(parameters),
+          type_name(ctx, *return_type)
+        ),
+      },
+      UserTypeDef::Struct(def) => def.name.clone(),
+      UserTypeDef::Enum(def) => def.name.clone(),
+      UserTypeDef::Applied { type_id, arguments } => {
+        format!(
+          "{}<{}>",
+          type_name(ctx, TypeRef::UserType(*type_id)),
+          names(arguments)
+        )
+      }
+    },
+    TypeRef::Error => "{error}".to_string(),
+    TypeRef::Never => "!".to_string(),
+  }
+}
+
+// Arrays, function types and uses of generic types are compared by structure,
+// since they may contain type variables. Structs and enums are nominal.
+fn structural_type_def(ctx: &SemanticContext, id: UserTypeId) -> Option<UserTypeDef> {
+  match &ctx.resolve_type(id).type_def {
+    type_def @ UserTypeDef::Array(_)
+    | type_def @ UserTypeDef::Function { .. }
+    | type_def @ UserTypeDef::Applied { .. } => Some(type_def.clone()),
+    _ => None,
+  }
+}
+
+// Rebuilds a type with each type variable without a value replaced by `f`.
+fn map_variables(
+  ctx: &mut SemanticContext,
+  x: TypeRef,
+  f: &mut dyn FnMut(&SemanticContext, TypeVariableId) -> TypeRef,
+) -> TypeRef {
+  let x = shallow_resolve(ctx, x);
+  match x {
+    TypeRef::Variable(id) => f(ctx, id),
+    TypeRef::UserType(id) => match structural_type_def(ctx, id) {
+      Some(UserTypeDef::Array(element_type)) => {
+        let element_type = map_variables(ctx, element_type, f);
+        ctx.intern_type(UserTypeDef::Array(element_type))
+      }
+      Some(UserTypeDef::Function {
+        parameters,
+        return_type,
+      }) => {
+        let parameters = parameters
+          .into_iter()
+          .map(|parameter| map_variables(ctx, parameter, f))
+          .collect();
+        let return_type = map_variables(ctx, return_type, f);
+        ctx.intern_type(UserTypeDef::Function {
+          parameters,
+          return_type,
+        })
+      }
+      Some(UserTypeDef::Applied { type_id, arguments }) => {
+        let arguments = arguments
+          .into_iter()
+          .map(|argument| map_variables(ctx, argument, f))
+          .collect();
+        ctx.intern_type(UserTypeDef::Applied { type_id, arguments })
+      }
+      _ => x,
+    },
+    TypeRef::Primitive(_) | TypeRef::Error | TypeRef::Never => x,
+  }
+}
+
+// Replaces the type parameters in a type by the types they stand for.
+fn substitute(
+  ctx: &mut SemanticContext,
+  x: TypeRef,
+  substitution: &HashMap<TypeVariableId, TypeRef>,
+) -> TypeRef {
+  map_variables(ctx, x, &mut |_, id| {
+    substitution
+      .get(&id)
+      .copied()
+      .unwrap_or(TypeRef::Variable(id))
+  })
+}
+
+// A generic struct or enum with fresh variables for its type parameters, and
+// the substitution that gives the types of its fields. Other types are their
+// own instance.
+fn instantiate_type(
+  ctx: &mut SemanticContext,
+  type_id: UserTypeId,
+  type_arguments_id: TypeArgumentsId,
+  pos: Span,
+) -> (TypeRef, HashMap<TypeVariableId, TypeRef>) {
+  let type_parameters = ctx
+    .resolve_type(type_id)
+    .type_def
+    .type_parameters()
+    .to_vec();
+  if type_parameters.is_empty() {
+    return (TypeRef::UserType(type_id), HashMap::new());
+  }
+
+  let arguments: Vec<TypeRef> = type_parameters
+    .iter()
+    .map(|_| {
+      ctx.declare_type_variable(
+        TypeVariableKind::General,
+        TypeVariableOrigin::Expression,
+        pos,
+      )
+    })
+    .collect();
+  ctx
+    .type_arguments
+    .insert(type_arguments_id, arguments.clone());
+  let substitution = type_parameters.into_iter().zip(arguments.clone()).collect();
+  let x = ctx.intern_type(UserTypeDef::Applied { type_id, arguments });
+  (x, substitution)
+}
+
+// The struct or enum that a type is, and what its type parameters stand for.
+fn nominal_type(
+  ctx: &SemanticContext,
+  x: TypeRef,
+) -> Option<(UserTypeId, HashMap<TypeVariableId, TypeRef>)> {
+  let id = match shallow_resolve(ctx, x) {
+    TypeRef::UserType(id) => id,
+    _ => return None,
+  };
+
+  match &ctx.resolve_type(id).type_def {
+    UserTypeDef::Struct(_) | UserTypeDef::Enum(_) => Some((id, HashMap::new())),
+    UserTypeDef::Applied { type_id, arguments } => {
+      let type_parameters = ctx.resolve_type(*type_id).type_def.type_parameters();
+      let substitution = type_parameters
+        .iter()
+        .copied()
+        .zip(arguments.iter().copied())
+        .collect();
+      Some((*type_id, substitution))
+    }
+    _ => None,
+  }
+}
+
+fn occurs(ctx: &SemanticContext, id: TypeVariableId, x: TypeRef) -> bool {
+  match shallow_resolve(ctx, x) {
+    TypeRef::Variable(other) => other == id,
+    TypeRef::UserType(user_type) => match structural_type_def(ctx, user_type) {
+      Some(UserTypeDef::Array(element_type)) => occurs(ctx, id, element_type),
+      Some(UserTypeDef::Function {
+        parameters,
+        return_type,
+      }) => parameters.into_iter().any(|x| occurs(ctx, id, x)) || occurs(ctx, id, return_type),
+      Some(UserTypeDef::Applied { arguments, .. }) => {
+        arguments.into_iter().any(|x| occurs(ctx, id, x))
+      }
+      _ => false,
+    },
+    TypeRef::Primitive(_) | TypeRef::Error | TypeRef::Never => false,
+  }
+}
+
+fn bind(ctx: &mut SemanticContext, id: TypeVariableId, x: TypeRef) {
+  ctx.type_variables.get_mut(&id).unwrap().value = Some(x);
+}
+
+// Makes two types the same by giving values to the type variables in them, and
+// returns whether that's possible. There is no subtyping, so a value can be
+// assigned to a place exactly if their types unify.
+pub fn unify(ctx: &mut SemanticContext, a: TypeRef, b: TypeRef) -> bool {
+  use TypeRef::*;
+  use TypeVariableKind::*;
+
+  let a = shallow_resolve(ctx, a);
+  let b = shallow_resolve(ctx, b);
+  if a == b {
+    return true;
+  }
+
+  let kind = |ctx: &SemanticContext, id| ctx.type_variables[&id].kind;
   match (a, b) {
-    (Primitive(a), Primitive(b)) => a == b,
+    (Variable(x), Variable(y)) => match (kind(ctx, x), kind(ctx, y)) {
+      // The more specific variable is kept.
+      (General, _) => {
+        bind(ctx, x, b);
+        true
+      }
+      (_, General) => {
+        bind(ctx, y, a);
+        true
+      }
+      (Generic, _) | (_, Generic) => false,
+      (Integer, Integer) => {
+        bind(ctx, y, a);
+        true
+      }
+    },
+    // The error type stands in for every type. A variable takes it as its
+    // value, so that the error isn't reported again as an unknown type.
+    (Variable(x), Error) | (Error, Variable(x)) => {
+      if kind(ctx, x) != Generic {
+        bind(ctx, x, Error);
+      }
+      true
+    }
+    (Error, _) | (_, Error) => true,
+    // Never stands in for every type too, but a variable isn't given it as its
+    // value, since the expression that decides the variable may come later.
+    (Variable(x), Never) | (Never, Variable(x)) => {
+      ctx.type_variables.get_mut(&x).unwrap().diverging = true;
+      true
+    }
+    (Never, _) | (_, Never) => true,
+    (Variable(x), _) | (_, Variable(x)) => {
+      let other = if a == Variable(x) { b } else { a };
+      let allowed = match kind(ctx, x) {
+        General => !occurs(ctx, x, other),
+        Integer => matches!(other, Primitive(primitive) if primitive.is_integer()),
+        Generic => false,
+      };
+      if allowed {
+        bind(ctx, x, other);
+      }
+      allowed
+    }
+    (UserType(x), UserType(y)) => {
+      match (structural_type_def(ctx, x), structural_type_def(ctx, y)) {
+        (Some(UserTypeDef::Array(a)), Some(UserTypeDef::Array(b))) => unify(ctx, a, b),
+        (
+          Some(UserTypeDef::Function {
+            parameters: a_parameters,
+            return_type: a_return_type,
+          }),
+          Some(UserTypeDef::Function {
+            parameters: b_parameters,
+            return_type: b_return_type,
+          }),
+        ) => {
+          a_parameters.len() == b_parameters.len()
+            && a_parameters
+              .into_iter()
+              .zip(b_parameters)
+              .all(|(a, b)| unify(ctx, a, b))
+            && unify(ctx, a_return_type, b_return_type)
+        }
+        (
+          Some(UserTypeDef::Applied {
+            type_id: a_type_id,
+            arguments: a_arguments,
+          }),
+          Some(UserTypeDef::Applied {
+            type_id: b_type_id,
+            arguments: b_arguments,
+          }),
+        ) => {
+          a_type_id == b_type_id
+            && a_arguments
+              .into_iter()
+              .zip(b_arguments)
+              .all(|(a, b)| unify(ctx, a, b))
+        }
+        _ => false,
+      }
+    }
     _ => false,
   }
 }
 
-pub fn is_assignable(ctx: &mut SemanticContext, a: TypeRef, b: TypeRef) -> bool {
-  use self::TypeRef::*;
-  match (a, b) {
-    (Primitive(a), Primitive(b)) => a == b,
+// Whether a type is a variable that nothing has decided yet. Integer
+// variables are known to be some integer, which is enough wherever this is
+// asked.
+fn is_unknown(ctx: &SemanticContext, x: TypeRef) -> bool {
+  match shallow_resolve(ctx, x) {
+    TypeRef::Variable(id) => ctx.type_variables[&id].kind == TypeVariableKind::General,
     _ => false,
   }
 }
 
+// The type of the result of applying an operator to operands of the given
+// types, if it can be. Every operand must be known by now.
+fn operation_type(op: Operator, operands: &[TypeRef]) -> Option<TypeRef> {
+  use BinaryOperator::*;
+  use PrimitiveType::*;
+  use UnaryOperator::*;
+
+  let operands = operands
+    .iter()
+    .map(|x| match x {
+      TypeRef::Primitive(x) => Some(*x),
+      _ => None,
+    })
+    .collect::<Option<Vec<_>>>()?;
+
+  let result = match (op, operands.as_slice()) {
+    (Operator::Unary(op), &[x]) => match op {
+      Negate if x.is_signed() || x == F64 => x,
+      Not if x == Bool || x.is_integer() => x,
+      BitNot if x.is_integer() => x,
+      _ => return None,
+    },
+    (Operator::Binary(op), &[lhs, rhs]) => match op {
+      Add | Sub | Mul | Div | Rem if lhs == rhs && lhs.is_numeric() => lhs,
+      Add if lhs == Str && rhs == Str => Str,
+      BitAnd | BitOr | BitXor if lhs == rhs && (lhs.is_integer() || lhs == Bool) => lhs,
+      // The amount of a shift can have any integer type.
+      Shl | Shr if lhs.is_integer() && rhs.is_integer() => lhs,
+      Less | LessOrEqual | Greater | GreaterOrEqual if lhs == rhs && lhs.is_numeric() => Bool,
+      Equals | NotEquals if lhs == rhs => Bool,
+      And | Or if lhs == Bool && rhs == Bool => Bool,
+      _ => return None,
+    },
+    (Operator::Cast, &[from, to]) if from.is_numeric() && to.is_numeric() => to,
+    (Operator::Cast, &[Bool, to]) if to.is_integer() => to,
+    _ => return None,
+  };
+
+  Some(TypeRef::Primitive(result))
+}
+
+fn operation_error(ctx: &mut SemanticContext, op: Operator, operands: &[TypeRef]) -> TypeError {
+  let mut operands = operands.iter().map(|&x| type_name(ctx, x));
+  let mut operand = || operands.next().unwrap();
+  match op {
+    Operator::Unary(op) => TypeError::InvalidUnaryOpArg { op, x: operand() },
+    Operator::Binary(op) => TypeError::InvalidBinaryOpArgs {
+      op,
+      lhs: operand(),
+      rhs: operand(),
+    },
+    Operator::Cast => TypeError::InvalidCast {
+      x: operand(),
+      target: operand(),
+    },
+  }
+}
+
+// The type of the result of an operator whose operands aren't known yet, or
+// aren't valid. It follows from the operator alone, or is the type of the
+// first operand.
+fn pending_operation_type(op: Operator, operands: &[TypeRef]) -> TypeRef {
+  use BinaryOperator::*;
+
+  match op {
+    Operator::Binary(Less)
+    | Operator::Binary(LessOrEqual)
+    | Operator::Binary(Greater)
+    | Operator::Binary(GreaterOrEqual)
+    | Operator::Binary(Equals)
+    | Operator::Binary(NotEquals)
+    | Operator::Binary(And)
+    | Operator::Binary(Or) => TypeRef::Primitive(PrimitiveType::Bool),
+    Operator::Cast => operands[1],
+    _ => operands[0],
+  }
+}
+
+// Type checks an operator applied to operands of the given types. Binary
+// operators other than shifts need the same type on both sides, so those are
+// unified first, which gives literals the type of the other side. If an
+// operand still isn't known, the operator is checked again later, since the
+// type of its result doesn't depend on it.
+fn check_operation(
+  ctx: &mut SemanticContext,
+  pos: Span,
+  op: Operator,
+  operands: &[TypeRef],
+) -> TypeResult<TypeRef> {
+  use BinaryOperator::*;
+
+  let bool_type = TypeRef::Primitive(PrimitiveType::Bool);
+  let unified = match op {
+    Operator::Binary(And) | Operator::Binary(Or) => {
+      unify(ctx, operands[0], bool_type) && unify(ctx, operands[1], bool_type)
+    }
+    Operator::Binary(Shl) | Operator::Binary(Shr) | Operator::Unary(_) | Operator::Cast => true,
+    Operator::Binary(_) => unify(ctx, operands[0], operands[1]),
+  };
+  if !unified {
+    return Err(TypeErrorCtx(pos, operation_error(ctx, op, operands)));
+  }
+
+  // Type parameters never become known, and no operator applies to them.
+  let operands: Vec<TypeRef> = operands.iter().map(|&x| shallow_resolve(ctx, x)).collect();
+  let is_pending = |x: &TypeRef| match x {
+    TypeRef::Variable(id) => ctx.type_variables[id].kind != TypeVariableKind::Generic,
+    _ => false,
+  };
+  if operands.contains(&TypeRef::Error) {
+    return Ok(pending_operation_type(op, &operands));
+  }
+  // The operator is never applied.
+  if operands.contains(&TypeRef::Never) {
+    return Ok(TypeRef::Never);
+  }
+  if operands.iter().any(is_pending) {
+    ctx.deferred_operations.push(DeferredOperation {
+      op,
+      operands: operands.clone(),
+      span: pos,
+    });
+    return Ok(pending_operation_type(op, &operands));
+  }
+
+  match operation_type(op, &operands) {
+    Some(x) => Ok(x),
+    None => Err(TypeErrorCtx(pos, operation_error(ctx, op, &operands))),
+  }
+}
+
+fn not_assignable(
+  ctx: &mut SemanticContext,
+  pos: Span,
+  target: TypeRef,
+  x: TypeRef,
+) -> TypeErrorCtx {
+  let target = type_name(ctx, target);
+  let x = type_name(ctx, x);
+  TypeErrorCtx(pos, TypeError::NotAssignable { target, x })
+}
+
+// Records an error, and returns the type of the expression that caused it.
+fn report(ctx: &mut SemanticContext, error: TypeErrorCtx) -> TypeRef {
+  ctx.type_errors.push(error);
+  TypeRef::Error
+}
+
+// Type checks an expression and returns its type. Errors are recorded in the
+// context rather than returned, and the expression has the error type then, so
+// that the rest of the program is still checked.
 pub fn resolve_expression(
   ctx: &mut SemanticContext,
   scope_id: ScopeId,
   expression: &RastExpressionCtx,
+) -> TypeRef {
+  match check_expression(ctx, scope_id, expression) {
+    Ok(x) => x,
+    Err(error) => report(ctx, error),
+  }
+}
+
+fn check_expression(
+  ctx: &mut SemanticContext,
+  scope_id: ScopeId,
+  expression: &RastExpressionCtx,
 ) -> TypeResult<TypeRef> {
-  use BinaryOperator::*;
   use PrimitiveType::*;
   use RastExpression::*;
   use TypeRef::*;
-  use UnaryOperator::*;
 
   let RastExpressionCtx(pos, expression) = expression;
 
   match expression {
-    IntegerConstant(_) => Ok(Primitive(I32)),
-    &Local(local_id) => {
-      let local = ctx.resolve_local(scope_id, local_id).unwrap();
-      local
-        .initial_type
-        .ok_or_else(|| TypeErrorCtx(*pos, TypeError::UntypedLocal { local_id }))
-    }
+    IntegerConstant(_, literal_id) => Ok(ctx.literals[literal_id].type_),
+    FloatConstant(_) => Ok(Primitive(F64)),
+    BooleanConstant(_) => Ok(Primitive(Bool)),
+    StringConstant(_) => Ok(Primitive(Str)),
+    &Local(local_id) => Ok(ctx.resolve_local(scope_id, local_id).unwrap().type_),
     UnaryOp(op, x) => {
-      let x_type = resolve_expression(ctx, scope_id, x)?;
-      match (*op, x_type) {
-        (Negate, Primitive(I32)) => Ok(Primitive(I32)),
-        _ => Err(TypeErrorCtx(
-          *pos,
-          TypeError::InvalidUnaryOpArg { op: *op, x: x_type },
+      let x_type = resolve_expression(ctx, scope_id, x);
+      check_operation(ctx, *pos, Operator::Unary(*op), &[x_type])
+    }
+    BinaryOp(op, args) => {
+      let lhs_type = resolve_expression(ctx, scope_id, &args.0);
+      let rhs_type = resolve_expression(ctx, scope_id, &args.1);
+      check_operation(ctx, *pos, Operator::Binary(*op), &[lhs_type, rhs_type])
+    }
+    Block(block) => Ok(resolve_block(ctx, block)),
+    If {
+      condition,
+      then_branch,
+      else_branch,
+    } => {
+      let condition_type = resolve_expression(ctx, scope_id, condition);
+      if !unify(ctx, condition_type, Primitive(Bool)) {
+        let x = type_name(ctx, condition_type);
+        report(
+          ctx,
+          TypeErrorCtx(condition.0, TypeError::InvalidCondition { x }),
+        );
+      }
+
+      let then_type = resolve_expression(ctx, scope_id, then_branch);
+
+      match else_branch {
+        Some(else_branch) => {
+          let else_type = resolve_expression(ctx, scope_id, else_branch);
+          if unify(ctx, then_type, else_type) {
+            Ok(either(ctx, then_type, else_type))
+          } else {
+            Err(TypeErrorCtx(
+              *pos,
+              TypeError::MismatchedBranches {
+                then_type: type_name(ctx, then_type),
+                else_type: type_name(ctx, else_type),
+              },
+            ))
+          }
+        }
+        None if unify(ctx, then_type, Primitive(Unit)) => Ok(Primitive(Unit)),
+        None => Err(TypeErrorCtx(
+          then_branch.0,
+          TypeError::MissingElse {
+            then_type: type_name(ctx, then_type),
+          },
         )),
       }
     }
-    BinaryOp(op, args) => {
-      let lhs_type = resolve_expression(ctx, scope_id, &args.0)?;
-      let rhs_type = resolve_expression(ctx, scope_id, &args.1)?;
+    &Function(function_id, type_arguments_id) => {
+      let signature = instantiate(ctx, function_id, type_arguments_id, *pos);
+      Ok(function_type(ctx, signature))
+    }
+    Closure(function) => {
+      visit_function(ctx, function);
+      let signature = signature(ctx, function.id);
+      Ok(function_type(ctx, signature))
+    }
+    Call {
+      function_id,
+      type_arguments_id,
+      arguments,
+    } => {
+      let (parameters, return_type) = instantiate(ctx, *function_id, *type_arguments_id, *pos);
+      check_arguments(ctx, scope_id, *pos, &parameters, arguments);
+      Ok(return_type)
+    }
+    Builtin {
+      builtin: crate::rast::Builtin::Len,
+      arguments,
+    } => match arguments.as_slice() {
+      [array] => {
+        let array_type = resolve_expression(ctx, scope_id, array);
+        if is_unknown(ctx, array_type) {
+          return Err(TypeErrorCtx(array.0, TypeError::CannotInfer));
+        }
+        match element_type(ctx, array_type) {
+          Some(_) => Ok(Primitive(I32)),
+          None if shallow_resolve(ctx, array_type) == Primitive(Str) => Ok(Primitive(I32)),
+          None => Err(TypeErrorCtx(
+            array.0,
+            TypeError::ExpectedArray {
+              x: type_name(ctx, array_type),
+            },
+          )),
+        }
+      }
+      _ => Err(TypeErrorCtx(
+        *pos,
+        TypeError::WrongArgumentCount {
+          expected: 1,
+          actual: arguments.len(),
+        },
+      )),
+    },
+    Array(elements, type_arguments_id) => {
+      // The type of the elements of `[]` is left to the way it's used.
+      let element_type = match elements.first() {
+        Some(first) => resolve_expression(ctx, scope_id, first),
+        None => ctx.declare_type_variable(
+          TypeVariableKind::General,
+          TypeVariableOrigin::EmptyArray,
+          *pos,
+        ),
+      };
+      ctx
+        .type_arguments
+        .insert(*type_arguments_id, vec![element_type]);
+      for element in elements.iter().skip(1) {
+        let x = resolve_expression(ctx, scope_id, element);
+        if !unify(ctx, element_type, x) {
+          let error = not_assignable(ctx, element.0, element_type, x);
+          report(ctx, error);
+        }
+      }
 
-      match (lhs_type, *op, rhs_type) {
-        (Primitive(I32), Add, Primitive(I32))
-        | (Primitive(I32), Sub, Primitive(I32))
-        | (Primitive(I32), Mul, Primitive(I32)) => Ok(Primitive(I32)),
-        _ => Err(TypeErrorCtx(
+      Ok(ctx.intern_type(UserTypeDef::Array(element_type)))
+    }
+    Index { array, index } => resolve_index(ctx, scope_id, array, index),
+    &Struct {
+      type_id,
+      type_arguments_id,
+      ref fields,
+    } => {
+      let (x, substitution) = instantiate_type(ctx, type_id, type_arguments_id, *pos);
+      for (index, value) in fields {
+        let field_type = match &ctx.resolve_type(type_id).type_def {
+          UserTypeDef::Struct(def) => def.fields[*index].type_,
+          _ => unreachable!(),
+        };
+        let field_type = substitute(ctx, field_type, &substitution);
+        let value_type = resolve_expression(ctx, scope_id, value);
+
+        if !unify(ctx, field_type, value_type) {
+          let error = not_assignable(ctx, value.0, field_type, value_type);
+          report(ctx, error);
+        }
+      }
+
+      Ok(x)
+    }
+    Field { value, field } => resolve_field(ctx, scope_id, *pos, value, field),
+    &Variant {
+      type_id,
+      type_arguments_id,
+      variant,
+      ref arguments,
+    } => {
+      let (x, substitution) = instantiate_type(ctx, type_id, type_arguments_id, *pos);
+      let fields = match &ctx.resolve_type(type_id).type_def {
+        UserTypeDef::Enum(def) => def.variants[variant].fields.clone(),
+        _ => unreachable!(),
+      };
+      let fields: Vec<TypeRef> = fields
+        .into_iter()
+        .map(|field| substitute(ctx, field, &substitution))
+        .collect();
+      check_arguments(ctx, scope_id, *pos, &fields, arguments);
+      Ok(x)
+    }
+    Match { scrutinee, arms } => resolve_match(ctx, scope_id, scrutinee, arms),
+    CallIndirect { callee, arguments } => {
+      let callee_type = resolve_expression(ctx, scope_id, callee);
+      if let x @ (Error | Never) = shallow_resolve(ctx, callee_type) {
+        for argument in arguments {
+          resolve_expression(ctx, scope_id, argument);
+        }
+        return Ok(x);
+      }
+
+      // A callee whose type isn't known yet must be a function that takes
+      // these arguments.
+      if is_unknown(ctx, callee_type) {
+        let parameters = arguments
+          .iter()
+          .map(|argument| {
+            ctx.declare_type_variable(
+              TypeVariableKind::General,
+              TypeVariableOrigin::Expression,
+              argument.0,
+            )
+          })
+          .collect();
+        let return_type = ctx.declare_type_variable(
+          TypeVariableKind::General,
+          TypeVariableOrigin::Expression,
           *pos,
-          TypeError::InvalidBinaryOpArgs {
-            op: *op,
-            lhs: lhs_type,
-            rhs: rhs_type,
+        );
+        let function_type = ctx.intern_type(UserTypeDef::Function {
+          parameters,
+          return_type,
+        });
+        unify(ctx, callee_type, function_type);
+      }
+
+      let type_def = match shallow_resolve(ctx, callee_type) {
+        UserType(id) => structural_type_def(ctx, id),
+        _ => None,
+      };
+
+      match type_def {
+        Some(UserTypeDef::Function {
+          parameters,
+          return_type,
+        }) => {
+          check_arguments(ctx, scope_id, *pos, &parameters, arguments);
+          Ok(return_type)
+        }
+        _ => Err(TypeErrorCtx(
+          callee.0,
+          TypeError::NotCallable {
+            x: type_name(ctx, callee_type),
           },
         )),
       }
     }
-    _ => unimplemented!(),
+    Loop { loop_id, body } => {
+      resolve_block(ctx, body);
+      // Without any break, the loop never ends.
+      Ok(ctx.loops[loop_id].break_type.unwrap_or(Never))
+    }
+    &Cast { ref value, type_ } => {
+      let x = resolve_expression(ctx, scope_id, value);
+      check_operation(ctx, *pos, Operator::Cast, &[x, type_])
+    }
   }
 }
 
-pub fn visit_statement(
+fn element_type(ctx: &SemanticContext, x: TypeRef) -> Option<TypeRef> {
+  match shallow_resolve(ctx, x) {
+    x @ (TypeRef::Error | TypeRef::Never) => Some(x),
+    TypeRef::UserType(id) => match ctx.resolve_type(id).type_def {
+      UserTypeDef::Array(element_type) => Some(element_type),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+// Type checks `array[index]` and returns the type of the element.
+fn resolve_index(
+  ctx: &mut SemanticContext,
+  scope_id: ScopeId,
+  array: &RastExpressionCtx,
+  index: &RastExpressionCtx,
+) -> TypeResult<TypeRef> {
+  let array_type = resolve_expression(ctx, scope_id, array);
+
+  // Only arrays can be indexed, so a value whose type isn't known yet must be
+  // one.
+  if is_unknown(ctx, array_type) {
+    let element_type = ctx.declare_type_variable(
+      TypeVariableKind::General,
+      TypeVariableOrigin::Expression,
+      array.0,
+    );
+    let x = ctx.intern_type(UserTypeDef::Array(element_type));
+    unify(ctx, array_type, x);
+  }
+
+  let element_type = match element_type(ctx, array_type) {
+    Some(element_type) => element_type,
+    None => {
+      return Err(TypeErrorCtx(
+        array.0,
+        TypeError::ExpectedArray {
+          x: type_name(ctx, array_type),
+        },
+      ))
+    }
+  };
+
+  let index_type = resolve_expression(ctx, scope_id, index);
+  if !unify(ctx, index_type, TypeRef::Primitive(PrimitiveType::I32)) {
+    let x = type_name(ctx, index_type);
+    report(ctx, TypeErrorCtx(index.0, TypeError::InvalidIndex { x }));
+  }
+
+  Ok(element_type)
+}
+
+// Type checks `value.field` and returns the type of the field.
+fn resolve_field(
   ctx: &mut SemanticContext,
   scope_id: ScopeId,
-  statement: &mut RastStatementCtx,
+  pos: Span,
+  value: &RastExpressionCtx,
+  field: &str,
+) -> TypeResult<TypeRef> {
+  let value_type = resolve_expression(ctx, scope_id, value);
+  if is_unknown(ctx, value_type) {
+    return Err(TypeErrorCtx(value.0, TypeError::CannotInfer));
+  }
+  if let x @ (TypeRef::Error | TypeRef::Never) = shallow_resolve(ctx, value_type) {
+    return Ok(x);
+  }
+
+  let field_type = nominal_type(ctx, value_type).and_then(|(id, substitution)| {
+    match &ctx.resolve_type(id).type_def {
+      UserTypeDef::Struct(def) => def
+        .field_index(field)
+        .map(|index| (def.fields[index].type_, substitution)),
+      _ => None,
+    }
+  });
+
+  match field_type {
+    Some((field_type, substitution)) => Ok(substitute(ctx, field_type, &substitution)),
+    None => Err(TypeErrorCtx(
+      pos,
+      TypeError::NoSuchField {
+        x: type_name(ctx, value_type),
+        field: field.to_string(),
+      },
+    )),
+  }
+}
+
+fn resolve_match(
+  ctx: &mut SemanticContext,
+  scope_id: ScopeId,
+  scrutinee: &RastExpressionCtx,
+  arms: &[RastMatchArm],
+) -> TypeResult<TypeRef> {
+  let scrutinee_type = resolve_expression(ctx, scope_id, scrutinee);
+
+  let mut match_type = None;
+  let mut patterns_valid = true;
+  for arm in arms {
+    if let Err(error) = check_pattern(ctx, arm.scope_id, scrutinee_type, &arm.pattern) {
+      report(ctx, error);
+      patterns_valid = false;
+    }
+    let body_type = resolve_expression(ctx, arm.scope_id, &arm.body);
+    match match_type {
+      None => match_type = Some(body_type),
+      Some(expected) if unify(ctx, expected, body_type) => {
+        match_type = Some(either(ctx, expected, body_type));
+      }
+      Some(expected) => {
+        let error = TypeError::MismatchedArms {
+          expected: type_name(ctx, expected),
+          x: type_name(ctx, body_type),
+        };
+        report(ctx, TypeErrorCtx(arm.body.0, error));
+      }
+    }
+  }
+
+  // Patterns of the wrong type, or on a value with an error, would only
+  // confuse the exhaustiveness check.
+  let scrutinee_type = shallow_resolve(ctx, scrutinee_type);
+  if patterns_valid && !matches!(scrutinee_type, TypeRef::Error | TypeRef::Never) {
+    if let Err(error) = check_exhaustive(ctx, scrutinee_type, scrutinee.0, arms) {
+      report(ctx, error);
+    }
+  }
+
+  // A match without arms can only be on an enum without variants, and never
+  // produces a value.
+  Ok(match_type.unwrap_or(TypeRef::Never))
+}
+
+// The type of a value that comes from one of two expressions whose types
+// unify. If one of them never finishes, the value comes from the other.
+fn either(ctx: &SemanticContext, a: TypeRef, b: TypeRef) -> TypeRef {
+  if shallow_resolve(ctx, a) == TypeRef::Never {
+    b
+  } else {
+    a
+  }
+}
+
+// Checks that a pattern can match values of type `expected`, and gives its
+// bindings their types.
+fn check_pattern(
+  ctx: &mut SemanticContext,
+  scope_id: ScopeId,
+  expected: TypeRef,
+  RastPatternCtx(pos, pattern): &RastPatternCtx,
+) -> TypeResult<()> {
+  let mismatch = |ctx: &mut SemanticContext, x| {
+    TypeErrorCtx(
+      *pos,
+      TypeError::MismatchedPattern {
+        expected: type_name(ctx, expected),
+        x: type_name(ctx, x),
+      },
+    )
+  };
+
+  match pattern {
+    RastPattern::Wildcard => Ok(()),
+    RastPattern::Binding(local_id) => {
+      // The type of a binding is a variable of its own, so this can't fail.
+      let local_type = ctx.resolve_local(scope_id, *local_id).unwrap().type_;
+      unify(ctx, local_type, expected);
+      Ok(())
+    }
+    &RastPattern::Integer(value, suffix) => {
+      let x = suffix.unwrap_or_else(|| {
+        ctx.declare_type_variable(
+          TypeVariableKind::Integer,
+          TypeVariableOrigin::Expression,
+          *pos,
+        )
+      });
+      if !unify(ctx, expected, x) {
+        return Err(mismatch(ctx, x));
+      }
+
+      // The range is checked along with the literals, once the type is known.
+      ctx.declare_literal(*pos, value, Some(x));
+      Ok(())
+    }
+    RastPattern::Boolean(_) => {
+      let x = TypeRef::Primitive(PrimitiveType::Bool);
+      if unify(ctx, expected, x) {
+        Ok(())
+      } else {
+        Err(mismatch(ctx, x))
+      }
+    }
+    &RastPattern::Variant {
+      type_id,
+      type_arguments_id,
+      variant,
+      ref fields,
+    } => {
+      let (x, substitution) = instantiate_type(ctx, type_id, type_arguments_id, *pos);
+      if !unify(ctx, expected, x) {
+        return Err(mismatch(ctx, x));
+      }
+
+      let field_types = match &ctx.resolve_type(type_id).type_def {
+        UserTypeDef::Enum(def) => def.variants[variant].fields.clone(),
+        _ => unreachable!(),
+      };
+      let field_types: Vec<TypeRef> = field_types
+        .into_iter()
+        .map(|field_type| substitute(ctx, field_type, &substitution))
+        .collect();
+      if field_types.len() != fields.len() {
+        return Err(TypeErrorCtx(
+          *pos,
+          TypeError::WrongFieldCount {
+            expected: field_types.len(),
+            actual: fields.len(),
+          },
+        ));
+      }
+
+      for (field_type, field) in field_types.into_iter().zip(fields) {
+        check_pattern(ctx, scope_id, field_type, field)?;
+      }
+      Ok(())
+    }
+  }
+}
+
+// Patterns as seen by the exhaustiveness check, where a binding is the same as
+// a wildcard and the rest is a constructor applied to patterns for its fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Constructor {
+  Variant(UserTypeId, usize),
+  Boolean(bool),
+  Integer(i128),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum SimplePattern {
+  Wildcard,
+  Constructor(Constructor, Vec<SimplePattern>),
+}
+
+fn simplify_pattern(RastPatternCtx(_, pattern): &RastPatternCtx) -> SimplePattern {
+  match pattern {
+    RastPattern::Wildcard | RastPattern::Binding(_) => SimplePattern::Wildcard,
+    RastPattern::Integer(i, _) => SimplePattern::Constructor(Constructor::Integer(*i), Vec::new()),
+    RastPattern::Boolean(b) => SimplePattern::Constructor(Constructor::Boolean(*b), Vec::new()),
+    RastPattern::Variant {
+      type_id,
+      variant,
+      fields,
+      ..
+    } => SimplePattern::Constructor(
+      Constructor::Variant(*type_id, *variant),
+      fields.iter().map(simplify_pattern).collect(),
+    ),
+  }
+}
+
+// Every constructor of a type, if there are finitely many.
+fn all_constructors(ctx: &SemanticContext, x: TypeRef) -> Option<Vec<Constructor>> {
+  if shallow_resolve(ctx, x) == TypeRef::Primitive(PrimitiveType::Bool) {
+    return Some(vec![
+      Constructor::Boolean(false),
+      Constructor::Boolean(true),
+    ]);
+  }
+
+  let (id, _) = nominal_type(ctx, x)?;
+  match &ctx.resolve_type(id).type_def {
+    UserTypeDef::Enum(def) => Some(
+      (0..def.variants.len())
+        .map(|variant| Constructor::Variant(id, variant))
+        .collect(),
+    ),
+    _ => None,
+  }
+}
+
+// The types of the fields of a constructor of values of type `x`.
+fn field_types(ctx: &mut SemanticContext, x: TypeRef, constructor: &Constructor) -> Vec<TypeRef> {
+  match constructor {
+    &Constructor::Variant(id, variant) => {
+      let fields = match &ctx.resolve_type(id).type_def {
+        UserTypeDef::Enum(def) => def.variants[variant].fields.clone(),
+        _ => unreachable!(),
+      };
+      // A field with the error type may be matched by any pattern, and is then
+      // only used for the number of fields.
+      let substitution = nominal_type(ctx, x).map_or_else(HashMap::new, |(_, x)| x);
+      fields
+        .into_iter()
+        .map(|field| substitute(ctx, field, &substitution))
+        .collect()
+    }
+    Constructor::Boolean(_) | Constructor::Integer(_) => Vec::new(),
+  }
+}
+
+// The rows that match `constructor`, with their first pattern replaced by the
+// patterns for its fields.
+fn specialize(
+  rows: &[Vec<SimplePattern>],
+  constructor: &Constructor,
+  arity: usize,
+) -> Vec<Vec<SimplePattern>> {
+  rows
+    .iter()
+    .filter_map(|row| {
+      let (head, rest) = row.split_first().unwrap();
+      let mut fields = match head {
+        SimplePattern::Wildcard => vec![SimplePattern::Wildcard; arity],
+        SimplePattern::Constructor(other, fields) if other == constructor => fields.clone(),
+        SimplePattern::Constructor(..) => return None,
+      };
+      fields.extend_from_slice(rest);
+      Some(fields)
+    })
+    .collect()
+}
+
+// Puts the first `arity` patterns of a witness back into `constructor`.
+fn rebuild(
+  constructor: Constructor,
+  arity: usize,
+  mut witness: Vec<SimplePattern>,
+) -> Vec<SimplePattern> {
+  let rest = witness.split_off(arity);
+  let mut rebuilt = vec![SimplePattern::Constructor(constructor, witness)];
+  rebuilt.extend(rest);
+  rebuilt
+}
+
+// Finds values that `row` matches but none of `rows` do, and returns one of
+// them as a witness. This is the usefulness algorithm from "Warnings for
+// pattern matching" by Luc Maranget, which rustc uses as well.
+fn find_useful(
+  ctx: &mut SemanticContext,
+  rows: &[Vec<SimplePattern>],
+  row: &[SimplePattern],
+  types: &[TypeRef],
+) -> Option<Vec<SimplePattern>> {
+  let (head, rest) = match row.split_first() {
+    Some(split) => split,
+    None if rows.is_empty() => return Some(Vec::new()),
+    None => return None,
+  };
+  let (&head_type, rest_types) = types.split_first().unwrap();
+
+  let specialized =
+    |ctx: &mut SemanticContext, constructor: &Constructor, fields: &[SimplePattern]| {
+      let mut types = field_types(ctx, head_type, constructor);
+      let arity = types.len();
+      types.extend_from_slice(rest_types);
+      let mut row = fields.to_vec();
+      row.extend_from_slice(rest);
+
+      let witness = find_useful(ctx, &specialize(rows, constructor, arity), &row, &types)?;
+      Some(rebuild(constructor.clone(), arity, witness))
+    };
+
+  if let SimplePattern::Constructor(constructor, fields) = head {
+    return specialized(ctx, constructor, fields);
+  }
+
+  let mut used: Vec<&Constructor> = Vec::new();
+  for row in rows {
+    if let SimplePattern::Constructor(constructor, _) = &row[0] {
+      used.push(constructor);
+    }
+  }
+
+  let all = all_constructors(ctx, head_type);
+  match all {
+    // Every constructor is matched by some row, so the wildcard is useful only
+    // if it is for one of them.
+    Some(all) if all.iter().all(|x| used.contains(&x)) => all.iter().find_map(|constructor| {
+      let fields = vec![SimplePattern::Wildcard; field_types(ctx, head_type, constructor).len()];
+      specialized(ctx, constructor, &fields)
+    }),
+    // Otherwise the values the rows miss are enough, and only the rows
+    // starting with a wildcard matter for the rest.
+    _ => {
+      let defaults: Vec<Vec<SimplePattern>> = rows
+        .iter()
+        .filter(|row| row[0] == SimplePattern::Wildcard)
+        .map(|row| row[1..].to_vec())
+        .collect();
+      let mut witness = find_useful(ctx, &defaults, rest, rest_types)?;
+
+      let missing = all.and_then(|all| all.into_iter().find(|x| !used.contains(&x)));
+      let head = match missing {
+        Some(constructor) => {
+          let fields =
+            vec![SimplePattern::Wildcard; field_types(ctx, head_type, &constructor).len()];
+          SimplePattern::Constructor(constructor, fields)
+        }
+        None => SimplePattern::Wildcard,
+      };
+      witness.insert(0, head);
+      Some(witness)
+    }
+  }
+}
+
+fn describe_pattern(ctx: &SemanticContext, pattern: &SimplePattern) -> String {
+  let (constructor, fields) = match pattern {
+    SimplePattern::Wildcard => return "_".to_string(),
+    SimplePattern::Constructor(constructor, fields) => (constructor, fields),
+  };
+
+  match constructor {
+    &Constructor::Variant(id, variant) => {
+      let def = match &ctx.resolve_type(id).type_def {
+        UserTypeDef::Enum(def) => def,
+        _ => unreachable!(),
+      };
+      let name = format!("{}::{}", def.name, def.variants[variant].name);
+      if fields.is_empty() {
+        name
+      } else {
+        let fields: Vec<String> = fields.iter().map(|x| describe_pattern(ctx, x)).collect();
+        format!("{}({})", name, fields.join(", "))
+      }
+    }
+    Constructor::Boolean(b) => b.to_string(),
+    Constructor::Integer(i) => i.to_string(),
+  }
+}
+
+// Every arm must match some value that the arms before it don't, and together
+// they must match every value of the scrutinee.
+fn check_exhaustive(
+  ctx: &mut SemanticContext,
+  scrutinee_type: TypeRef,
+  pos: Span,
+  arms: &[RastMatchArm],
 ) -> TypeResult<()> {
+  let types = [scrutinee_type];
+  let mut rows = Vec::new();
+  for arm in arms {
+    let row = vec![simplify_pattern(&arm.pattern)];
+    if find_useful(ctx, &rows, &row, &types).is_none() {
+      return Err(TypeErrorCtx(arm.pattern.0, TypeError::UnreachablePattern));
+    }
+    rows.push(row);
+  }
+
+  match find_useful(ctx, &rows, &[SimplePattern::Wildcard], &types) {
+    Some(witness) => Err(TypeErrorCtx(
+      pos,
+      TypeError::NonExhaustive {
+        missing: describe_pattern(ctx, &witness[0]),
+      },
+    )),
+    None => Ok(()),
+  }
+}
+
+// The parameter and return types of a function, as it's declared.
+fn signature(ctx: &SemanticContext, function_id: FunctionId) -> (Vec<TypeRef>, TypeRef) {
+  let function = &ctx.functions[&function_id];
+  let parameters = function
+    .parameters
+    .iter()
+    .map(|parameter| ctx.locals[parameter].type_)
+    .collect();
+  (parameters, function.return_type)
+}
+
+// The parameter and return types of a function at one of its uses. Each use
+// of a generic function gets fresh variables for its type parameters, so that
+// it can be used with different types.
+fn instantiate(
+  ctx: &mut SemanticContext,
+  function_id: FunctionId,
+  type_arguments_id: TypeArgumentsId,
+  pos: Span,
+) -> (Vec<TypeRef>, TypeRef) {
+  let (mut types, return_type) = signature(ctx, function_id);
+  types.push(return_type);
+
+  let type_parameters = ctx.functions[&function_id].type_parameters.clone();
+  if !type_parameters.is_empty() {
+    let arguments: Vec<TypeRef> = type_parameters
+      .iter()
+      .map(|_| {
+        ctx.declare_type_variable(
+          TypeVariableKind::General,
+          TypeVariableOrigin::Expression,
+          pos,
+        )
+      })
+      .collect();
+    ctx
+      .type_arguments
+      .insert(type_arguments_id, arguments.clone());
+    let substitution = type_parameters.into_iter().zip(arguments).collect();
+    types = types
+      .into_iter()
+      .map(|x| substitute(ctx, x, &substitution))
+      .collect();
+  }
+
+  let return_type = types.pop().unwrap();
+  (types, return_type)
+}
+
+fn function_type(
+  ctx: &mut SemanticContext,
+  (parameters, return_type): (Vec<TypeRef>, TypeRef),
+) -> TypeRef {
+  ctx.intern_type(UserTypeDef::Function {
+    parameters,
+    return_type,
+  })
+}
+
+// Type checks the arguments of a call. The errors are only recorded, since the
+// type of the call is known either way.
+fn check_arguments(
+  ctx: &mut SemanticContext,
+  scope_id: ScopeId,
+  pos: Span,
+  parameters: &[TypeRef],
+  arguments: &[RastExpressionCtx],
+) {
+  if parameters.len() != arguments.len() {
+    let error = TypeError::WrongArgumentCount {
+      expected: parameters.len(),
+      actual: arguments.len(),
+    };
+    report(ctx, TypeErrorCtx(pos, error));
+    for argument in arguments {
+      resolve_expression(ctx, scope_id, argument);
+    }
+    return;
+  }
+
+  for (&parameter_type, argument) in parameters.iter().zip(arguments) {
+    // A closure takes the types of its parameters from the function it's
+    // passed to, so that its body can rely on them.
+    if let RastExpression::Closure(function) = &argument.1 {
+      let signature = signature(ctx, function.id);
+      let closure_type = function_type(ctx, signature);
+      unify(ctx, parameter_type, closure_type);
+    }
+
+    let argument_type = resolve_expression(ctx, scope_id, argument);
+    if !unify(ctx, parameter_type, argument_type) {
+      let error = not_assignable(ctx, argument.0, parameter_type, argument_type);
+      report(ctx, error);
+    }
+  }
+}
+
+// Type checks the statements of a block and returns the type of its value. A
+// block without a result never finishes if one of its statements doesn't.
+pub fn resolve_block(ctx: &mut SemanticContext, block: &RastBlock) -> TypeRef {
+  let mut diverges = false;
+  for statement in &block.statements {
+    match visit_statement(ctx, block.scope_id, statement) {
+      Ok(x) => diverges |= x == TypeRef::Never,
+      Err(error) => {
+        report(ctx, error);
+      }
+    }
+  }
+
+  match &block.result {
+    Some(result) => resolve_expression(ctx, block.scope_id, result),
+    None if diverges => TypeRef::Never,
+    None => TypeRef::Primitive(PrimitiveType::Unit),
+  }
+}
+
+// The type of a statement that evaluates a value of type `x`: Never if the
+// value never finishes, and () otherwise.
+fn statement_type(ctx: &SemanticContext, x: TypeRef) -> TypeRef {
+  match shallow_resolve(ctx, x) {
+    TypeRef::Never => TypeRef::Never,
+    _ => TypeRef::Primitive(PrimitiveType::Unit),
+  }
+}
+
+fn check_range(pos: Span, value: i128, x: TypeRef) -> TypeResult<()> {
+  let x = match x {
+    TypeRef::Primitive(x) => x,
+    _ => return Ok(()),
+  };
+
+  match x.integer_range() {
+    Some((min, max)) if value < min || value > max => {
+      Err(TypeErrorCtx(pos, TypeError::LiteralOutOfRange { value, x }))
+    }
+    _ => Ok(()),
+  }
+}
+
+// Type checks a statement and returns its type, which is Never for a statement
+// that never finishes, like return, break and continue, and () otherwise.
+pub fn visit_statement(
+  ctx: &mut SemanticContext,
+  scope_id: ScopeId,
+  statement: &RastStatementCtx,
+) -> TypeResult<TypeRef> {
   let RastStatementCtx(pos, statement) = statement;
 
   match statement {
-    RastStatement::AssignLocal {
-      local_id, value, ..
+    RastStatement::DeclareLocal { local_id, value }
+    | RastStatement::AssignLocal { local_id, value } => {
+      let local_type = ctx.resolve_local(scope_id, *local_id).unwrap().type_;
+      let value_type = resolve_expression(ctx, scope_id, value);
+      if unify(ctx, local_type, value_type) {
+        Ok(statement_type(ctx, value_type))
+      } else {
+        Err(not_assignable(ctx, *pos, local_type, value_type))
+      }
+    }
+    RastStatement::AssignIndex {
+      array,
+      index,
+      value,
     } => {
-      let value_type = resolve_expression(ctx, scope_id, value).unwrap();
-      let local = ctx.resolve_local_mut(scope_id, *local_id).unwrap();
-
-      if local.initial_type == None {
-        local.initial_type = Some(value_type);
-      } else if let Some(annotated_type) = local.initial_type {
-        if !is_assignable(ctx, annotated_type, value_type) {
-          return Err(TypeErrorCtx(
-            *pos,
-            TypeError::NotAssignable {
-              target: annotated_type,
-              x: value_type,
-            },
-          ));
+      let element_type =
+        resolve_index(ctx, scope_id, array, index).unwrap_or_else(|error| report(ctx, error));
+      let value_type = resolve_expression(ctx, scope_id, value);
+      if unify(ctx, element_type, value_type) {
+        Ok(statement_type(ctx, value_type))
+      } else {
+        Err(not_assignable(ctx, *pos, element_type, value_type))
+      }
+    }
+    RastStatement::AssignField {
+      target,
+      field,
+      value,
+    } => {
+      let field_type = resolve_field(ctx, scope_id, *pos, target, field)
+        .unwrap_or_else(|error| report(ctx, error));
+      let value_type = resolve_expression(ctx, scope_id, value);
+      if unify(ctx, field_type, value_type) {
+        Ok(statement_type(ctx, value_type))
+      } else {
+        Err(not_assignable(ctx, *pos, field_type, value_type))
+      }
+    }
+    RastStatement::Expression { value } => {
+      let x = resolve_expression(ctx, scope_id, value);
+      Ok(statement_type(ctx, x))
+    }
+    RastStatement::While {
+      condition, body, ..
+    } => {
+      let condition_type = resolve_expression(ctx, scope_id, condition);
+      if !unify(ctx, condition_type, TypeRef::Primitive(PrimitiveType::Bool)) {
+        let x = type_name(ctx, condition_type);
+        report(
+          ctx,
+          TypeErrorCtx(condition.0, TypeError::InvalidCondition { x }),
+        );
+      }
+
+      resolve_block(ctx, body);
+      Ok(TypeRef::Primitive(PrimitiveType::Unit))
+    }
+    RastStatement::Break { loop_id, value } => {
+      let value_type = match value {
+        Some(value) => resolve_expression(ctx, scope_id, value),
+        None => TypeRef::Primitive(PrimitiveType::Unit),
+      };
+
+      let &Loop {
+        kind, break_type, ..
+      } = &ctx.loops[loop_id];
+      // Jumps never finish, even if they have errors, so those are reported
+      // here.
+      if kind == LoopKind::While && value.is_some() {
+        report(ctx, TypeErrorCtx(*pos, TypeError::BreakWithValueFromWhile));
+        return Ok(TypeRef::Never);
+      }
+
+      match break_type {
+        None => ctx.loops.get_mut(loop_id).unwrap().break_type = Some(value_type),
+        Some(expected) if unify(ctx, expected, value_type) => {}
+        Some(expected) => {
+          let error = TypeError::MismatchedBreak {
+            expected: type_name(ctx, expected),
+            x: type_name(ctx, value_type),
+          };
+          report(ctx, TypeErrorCtx(*pos, error));
         }
       }
+      Ok(TypeRef::Never)
+    }
+    RastStatement::Continue { .. } => Ok(TypeRef::Never),
+    RastStatement::Return { function_id, value } => {
+      let value_type = match value {
+        Some(value) => resolve_expression(ctx, scope_id, value),
+        None => TypeRef::Primitive(PrimitiveType::Unit),
+      };
 
-      Ok(())
+      let expected = ctx.functions[function_id].return_type;
+      if !unify(ctx, expected, value_type) {
+        let error = TypeError::MismatchedReturn {
+          expected: type_name(ctx, expected),
+          x: type_name(ctx, value_type),
+        };
+        report(ctx, TypeErrorCtx(*pos, error));
+      }
+      Ok(TypeRef::Never)
+    }
+  }
+}
+
+pub fn visit_function(ctx: &mut SemanticContext, function: &RastFunction) {
+  let body_type = resolve_block(ctx, &function.body);
+  let return_type = ctx.functions[&function.id].return_type;
+  if unify(ctx, return_type, body_type) {
+    return;
+  }
+
+  // Point at the result if there is one, and at the name of the function
+  // otherwise.
+  let pos = function
+    .body
+    .result
+    .as_ref()
+    .map_or(ctx.functions[&function.id].span, |result| result.0);
+  let error = TypeError::MismatchedReturn {
+    expected: type_name(ctx, return_type),
+    x: type_name(ctx, body_type),
+  };
+  report(ctx, TypeErrorCtx(pos, error));
+}
+
+// Once the whole program has been type checked, integer variables that nothing
+// decided become i32, and the operators that were waiting for the types of
+// their operands are checked. Types that are still unknown are only reported
+// if nothing else was, since an earlier error may be why they weren't decided.
+fn solve(ctx: &mut SemanticContext) {
+  let reported = !ctx.type_errors.is_empty();
+  for variable in ctx.type_variables.values_mut() {
+    if variable.kind == TypeVariableKind::Integer && variable.value.is_none() {
+      variable.value = Some(TypeRef::Primitive(PrimitiveType::I32));
+    }
+    if variable.kind == TypeVariableKind::General && variable.value.is_none() && variable.diverging
+    {
+      variable.value = Some(TypeRef::Primitive(PrimitiveType::Unit));
+    }
+  }
+
+  for operation in std::mem::take(&mut ctx.deferred_operations) {
+    let operands: Vec<TypeRef> = operation
+      .operands
+      .iter()
+      .map(|&x| resolve_type(ctx, x))
+      .collect();
+    // A variable that was only generalized because nothing decided it is
+    // still missing an annotation, unlike a declared type parameter.
+    let undecided = |x| match shallow_resolve(ctx, x) {
+      TypeRef::Variable(id) => ctx.type_variables[&id].origin != TypeVariableOrigin::TypeParameter,
+      _ => false,
+    };
+    if operands.contains(&TypeRef::Error) {
+      continue;
     }
-    RastStatement::Block { scope_id, inner } => {
-      for statement in inner {
-        visit_statement(ctx, *scope_id, statement)?;
+    if operands.iter().any(|&x| undecided(x)) {
+      if !reported {
+        report(ctx, TypeErrorCtx(operation.span, TypeError::CannotInfer));
       }
+    } else if operation_type(operation.op, &operands).is_none() {
+      let error = operation_error(ctx, operation.op, &operands);
+      report(ctx, TypeErrorCtx(operation.span, error));
+    }
+  }
+}
 
-      Ok(())
+// Makes a function generic over the type variables still left in its
+// signature once it has been type checked. Functions don't capture locals, so
+// nothing outside of them can decide those types any more. Integer variables
+// aren't generalized, since there's no way to be generic over integer types.
+fn generalize(ctx: &mut SemanticContext, function_id: FunctionId) {
+  let function = &ctx.functions[&function_id];
+  let mut types: Vec<TypeRef> = function
+    .parameters
+    .iter()
+    .map(|parameter| ctx.locals[parameter].type_)
+    .collect();
+  types.push(function.return_type);
+
+  let mut type_parameters = function.type_parameters.clone();
+  for x in types {
+    map_variables(ctx, x, &mut |ctx, id| {
+      if ctx.type_variables[&id].kind == TypeVariableKind::General && !type_parameters.contains(&id)
+      {
+        type_parameters.push(id);
+      }
+      TypeRef::Variable(id)
+    });
+  }
+
+  for id in &type_parameters {
+    ctx.type_variables.get_mut(id).unwrap().kind = TypeVariableKind::Generic;
+  }
+  ctx.functions.get_mut(&function_id).unwrap().type_parameters = type_parameters;
+}
+
+// Checks that every type variable has been decided, and replaces the
+// variables in the types of locals, literals, functions, loops and type
+// arguments with their values for the backends.
+fn finish(ctx: &mut SemanticContext) {
+  let reported = !ctx.type_errors.is_empty();
+  let mut literal_ids: Vec<_> = ctx.literals.keys().copied().collect();
+  literal_ids.sort_by_key(|id| id.0);
+  for id in literal_ids {
+    let literal = &ctx.literals[&id];
+    let (value, span) = (literal.value, literal.span);
+    let type_ = resolve_type(ctx, literal.type_);
+    if let Err(error) = check_range(span, value, type_) {
+      report(ctx, error);
+    }
+    ctx.literals.get_mut(&id).unwrap().type_ = type_;
+  }
+
+  let unknown: Vec<TypeErrorCtx> = ctx
+    .type_variables
+    .values()
+    .filter(|x| x.kind == TypeVariableKind::General && x.value.is_none())
+    .map(|variable| {
+      let error = match variable.origin {
+        TypeVariableOrigin::Local(local_id) => TypeError::UntypedLocal { local_id },
+        TypeVariableOrigin::EmptyArray => TypeError::EmptyArray,
+        TypeVariableOrigin::Expression | TypeVariableOrigin::TypeParameter => {
+          TypeError::CannotInfer
+        }
+      };
+      TypeErrorCtx(variable.span, error)
+    })
+    .collect();
+  if !reported {
+    ctx.type_errors.extend(unknown);
+  }
+
+  let local_ids: Vec<_> = ctx.locals.keys().copied().collect();
+  for id in local_ids {
+    let type_ = resolve_type(ctx, ctx.locals[&id].type_);
+    ctx.locals.get_mut(&id).unwrap().type_ = type_;
+  }
+  let function_ids: Vec<_> = ctx.functions.keys().copied().collect();
+  for id in function_ids {
+    let return_type = resolve_type(ctx, ctx.functions[&id].return_type);
+    ctx.functions.get_mut(&id).unwrap().return_type = return_type;
+  }
+  let loop_ids: Vec<_> = ctx.loops.keys().copied().collect();
+  for id in loop_ids {
+    if let Some(break_type) = ctx.loops[&id].break_type {
+      let break_type = resolve_type(ctx, break_type);
+      ctx.loops.get_mut(&id).unwrap().break_type = Some(break_type);
     }
-    _ => panic!(),
+  }
+  let type_arguments_ids: Vec<_> = ctx.type_arguments.keys().copied().collect();
+  for id in type_arguments_ids {
+    let arguments = ctx.type_arguments[&id].clone();
+    let arguments = arguments
+      .into_iter()
+      .map(|x| resolve_type(ctx, x))
+      .collect();
+    ctx.type_arguments.insert(id, arguments);
   }
 }
 
-pub fn visit_program(ctx: &mut SemanticContext, program: &mut RastProgram) -> TypeResult<()> {
-  for statement in &mut program.0 {
-    visit_statement(ctx, ScopeId(0), statement)?;
+// The functions used by a block, directly or through a closure.
+fn used_functions(block: &RastBlock, used: &mut Vec<FunctionId>) {
+  fn visit_expression(
+    RastExpressionCtx(_, expression): &RastExpressionCtx,
+    used: &mut Vec<FunctionId>,
+  ) {
+    use RastExpression::*;
+    match expression {
+      IntegerConstant(..) | FloatConstant(_) | BooleanConstant(_) | StringConstant(_)
+      | Local(_) => {}
+      UnaryOp(_, x) => visit_expression(x, used),
+      BinaryOp(_, args) => {
+        visit_expression(&args.0, used);
+        visit_expression(&args.1, used);
+      }
+      Block(block) => used_functions(block, used),
+      If {
+        condition,
+        then_branch,
+        else_branch,
+      } => {
+        visit_expression(condition, used);
+        visit_expression(then_branch, used);
+        if let Some(else_branch) = else_branch {
+          visit_expression(else_branch, used);
+        }
+      }
+      Function(function_id, _) => used.push(*function_id),
+      Closure(function) => used_functions(&function.body, used),
+      Call {
+        function_id,
+        arguments,
+        ..
+      } => {
+        used.push(*function_id);
+        arguments.iter().for_each(|x| visit_expression(x, used));
+      }
+      Builtin { arguments, .. } | Variant { arguments, .. } => {
+        arguments.iter().for_each(|x| visit_expression(x, used))
+      }
+      Array(elements, _) => elements.iter().for_each(|x| visit_expression(x, used)),
+      Index { array, index } => {
+        visit_expression(array, used);
+        visit_expression(index, used);
+      }
+      Struct { fields, .. } => fields.iter().for_each(|(_, x)| visit_expression(x, used)),
+      Field { value, .. } | Cast { value, .. } => visit_expression(value, used),
+      Match { scrutinee, arms } => {
+        visit_expression(scrutinee, used);
+        arms
+          .iter()
+          .for_each(|arm| visit_expression(&arm.body, used));
+      }
+      CallIndirect { callee, arguments } => {
+        visit_expression(callee, used);
+        arguments.iter().for_each(|x| visit_expression(x, used));
+      }
+      Loop { body, .. } => used_functions(body, used),
+    }
+  }
+
+  for RastStatementCtx(_, statement) in &block.statements {
+    match statement {
+      RastStatement::DeclareLocal { value, .. }
+      | RastStatement::AssignLocal { value, .. }
+      | RastStatement::Expression { value } => visit_expression(value, used),
+      RastStatement::AssignIndex {
+        array,
+        index,
+        value,
+      } => {
+        visit_expression(array, used);
+        visit_expression(index, used);
+        visit_expression(value, used);
+      }
+      RastStatement::AssignField { target, value, .. } => {
+        visit_expression(target, used);
+        visit_expression(value, used);
+      }
+      RastStatement::While {
+        condition, body, ..
+      } => {
+        visit_expression(condition, used);
+        used_functions(body, used);
+      }
+      RastStatement::Break { value, .. } | RastStatement::Return { value, .. } => {
+        if let Some(value) = value {
+          visit_expression(value, used);
+        }
+      }
+      RastStatement::Continue { .. } => {}
+    }
+  }
+  if let Some(result) = &block.result {
+    visit_expression(result, used);
+  }
+}
+
+// Groups functions that use each other, directly or not, with Tarjan's
+// algorithm. A group comes after the groups of every function it uses.
+fn function_groups(functions: &[RastFunction]) -> Vec<Vec<&RastFunction>> {
+  struct Tarjan {
+    edges: Vec<Vec<usize>>,
+    indices: Vec<Option<usize>>,
+    low_links: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    next_index: usize,
+    groups: Vec<Vec<usize>>,
+  }
+
+  impl Tarjan {
+    fn visit(&mut self, v: usize) {
+      let index = self.next_index;
+      self.next_index += 1;
+      self.indices[v] = Some(index);
+      self.low_links[v] = index;
+      self.stack.push(v);
+      self.on_stack[v] = true;
+
+      for i in 0..self.edges[v].len() {
+        let w = self.edges[v][i];
+        match self.indices[w] {
+          None => {
+            self.visit(w);
+            self.low_links[v] = self.low_links[v].min(self.low_links[w]);
+          }
+          Some(index) if self.on_stack[w] => {
+            self.low_links[v] = self.low_links[v].min(index);
+          }
+          Some(_) => {}
+        }
+      }
+
+      if self.low_links[v] == index {
+        let mut group = Vec::new();
+        loop {
+          let w = self.stack.pop().unwrap();
+          self.on_stack[w] = false;
+          group.push(w);
+          if w == v {
+            break;
+          }
+        }
+        group.reverse();
+        self.groups.push(group);
+      }
+    }
+  }
+
+  let positions: HashMap<FunctionId, usize> = functions
+    .iter()
+    .enumerate()
+    .map(|(i, function)| (function.id, i))
+    .collect();
+  let edges = functions
+    .iter()
+    .map(|function| {
+      let mut used = Vec::new();
+      used_functions(&function.body, &mut used);
+      used
+        .iter()
+        .filter_map(|id| positions.get(id).copied())
+        .collect()
+    })
+    .collect();
+
+  let mut tarjan = Tarjan {
+    edges,
+    indices: vec![None; functions.len()],
+    low_links: vec![0; functions.len()],
+    stack: Vec::new(),
+    on_stack: vec![false; functions.len()],
+    next_index: 0,
+    groups: Vec::new(),
+  };
+  for v in 0..functions.len() {
+    if tarjan.indices[v].is_none() {
+      tarjan.visit(v);
+    }
   }
 
-  Ok(())
+  tarjan
+    .groups
+    .into_iter()
+    .map(|group| group.into_iter().map(|i| &functions[i]).collect())
+    .collect()
+}
+
+// Functions are type checked before the functions that use them, so that they
+// are generalized by then, and those that use each other are checked together.
+// Every error in the program is returned, in the order of the source.
+pub fn visit_program(
+  ctx: &mut SemanticContext,
+  program: &RastProgram,
+) -> Result<(), Vec<TypeErrorCtx>> {
+  with_stack(PASS_STACK_SIZE, || {
+    for group in function_groups(&program.functions) {
+      for function in &group {
+        visit_function(ctx, function);
+      }
+      for function in &group {
+        generalize(ctx, function.id);
+      }
+    }
+
+    resolve_block(ctx, &program.body);
+    solve(ctx);
+    finish(ctx);
+
+    let mut errors = std::mem::take(&mut ctx.type_errors);
+    if errors.is_empty() {
+      return Ok(());
+    }
+    errors.sort_by_key(|TypeErrorCtx(span, _)| (span.start, span.end));
+    Err(errors)
+  })
 }
 
 #[cfg(test)]
@@ -157,10 +1949,167 @@ mod assignability_tests {
   #[test]
   fn primitive_resolved() {
     let mut ctx = SemanticContext::new();
-    assert!(is_assignable(
+    assert!(unify(
       &mut ctx,
       TypeRef::Primitive(PrimitiveType::I32),
       TypeRef::Primitive(PrimitiveType::I32)
     ));
   }
+
+  #[test]
+  fn integer_variables_only_unify_with_integers() {
+    let mut ctx = SemanticContext::new();
+    let x = ctx.declare_type_variable(
+      TypeVariableKind::Integer,
+      TypeVariableOrigin::Expression,
+      Span::default(),
+    );
+    assert!(!unify(&mut ctx, x, TypeRef::Primitive(PrimitiveType::Bool)));
+    assert!(unify(&mut ctx, x, TypeRef::Primitive(PrimitiveType::U8)));
+    assert_eq!(
+      TypeRef::Primitive(PrimitiveType::U8),
+      shallow_resolve(&ctx, x)
+    );
+  }
+
+  #[test]
+  fn arrays_unify_by_element_type() {
+    let mut ctx = SemanticContext::new();
+    let x = ctx.declare_type_variable(
+      TypeVariableKind::General,
+      TypeVariableOrigin::EmptyArray,
+      Span::default(),
+    );
+    let array = ctx.intern_type(UserTypeDef::Array(x));
+    let i64_array = ctx.intern_type(UserTypeDef::Array(TypeRef::Primitive(PrimitiveType::I64)));
+    assert!(unify(&mut ctx, array, i64_array));
+    assert_eq!(i64_array, resolve_type(&mut ctx, array));
+  }
+
+  #[test]
+  fn occurs_check() {
+    let mut ctx = SemanticContext::new();
+    let x = ctx.declare_type_variable(
+      TypeVariableKind::General,
+      TypeVariableOrigin::Expression,
+      Span::default(),
+    );
+    let array = ctx.intern_type(UserTypeDef::Array(x));
+    assert!(!unify(&mut ctx, x, array));
+  }
+
+  #[test]
+  fn error_unifies_with_everything() {
+    let mut ctx = SemanticContext::new();
+    let x = ctx.declare_type_variable(
+      TypeVariableKind::General,
+      TypeVariableOrigin::Expression,
+      Span::default(),
+    );
+    assert!(unify(
+      &mut ctx,
+      TypeRef::Primitive(PrimitiveType::Bool),
+      TypeRef::Error
+    ));
+    assert!(unify(&mut ctx, x, TypeRef::Error));
+    assert_eq!(TypeRef::Error, shallow_resolve(&ctx, x));
+  }
+
+  #[test]
+  fn never_unifies_without_deciding_variables() {
+    let mut ctx = SemanticContext::new();
+    let x = ctx.declare_type_variable(
+      TypeVariableKind::General,
+      TypeVariableOrigin::Expression,
+      Span::default(),
+    );
+    assert!(unify(&mut ctx, x, TypeRef::Never));
+    assert_eq!(x, shallow_resolve(&ctx, x));
+    let i32_type = TypeRef::Primitive(PrimitiveType::I32);
+    assert!(unify(&mut ctx, x, i32_type));
+    assert_eq!(i32_type, shallow_resolve(&ctx, x));
+  }
+}
+
+#[cfg(test)]
+mod expression_tests {
+  use super::*;
+  use crate::ast::{Expression, ExpressionCtx};
+  use crate::semantic::transform_expression;
+
+  fn resolve(expression: &Expression) -> Result<TypeRef, Vec<TypeErrorCtx>> {
+    let mut ctx = SemanticContext::new();
+    let scope_id = ctx.declare_scope(None);
+    let ast = ExpressionCtx(Span::default(), expression.clone());
+    let rast = transform_expression(&mut ctx, scope_id, &ast).unwrap();
+    let x = resolve_expression(&mut ctx, scope_id, &rast);
+    if ctx.type_errors.is_empty() {
+      Ok(x)
+    } else {
+      Err(ctx.type_errors)
+    }
+  }
+
+  fn binary(op: BinaryOperator, lhs: Expression, rhs: Expression) -> Expression {
+    Expression::BinaryOp(
+      op,
+      Box::new((
+        ExpressionCtx(Span::default(), lhs),
+        ExpressionCtx(Span::default(), rhs),
+      )),
+    )
+  }
+
+  #[test]
+  fn comparison_is_bool() {
+    let expression = binary(
+      BinaryOperator::LessOrEqual,
+      Expression::IntegerConstant(1, None),
+      Expression::IntegerConstant(2, None),
+    );
+    assert_eq!(
+      Ok(TypeRef::Primitive(PrimitiveType::Bool)),
+      resolve(&expression)
+    );
+  }
+
+  #[test]
+  fn logical_requires_bool() {
+    let expression = binary(
+      BinaryOperator::And,
+      Expression::BooleanConstant(true),
+      Expression::IntegerConstant(2, None),
+    );
+    assert_eq!(
+      Err(vec![TypeErrorCtx(
+        Span::default(),
+        TypeError::InvalidBinaryOpArgs {
+          op: BinaryOperator::And,
+          lhs: "bool".to_string(),
+          rhs: "i32".to_string(),
+        }
+      )]),
+      resolve(&expression)
+    );
+  }
+
+  #[test]
+  fn equality_requires_same_types() {
+    let expression = binary(
+      BinaryOperator::Equals,
+      Expression::BooleanConstant(true),
+      Expression::BooleanConstant(false),
+    );
+    assert_eq!(
+      Ok(TypeRef::Primitive(PrimitiveType::Bool)),
+      resolve(&expression)
+    );
+
+    let expression = binary(
+      BinaryOperator::NotEquals,
+      Expression::BooleanConstant(true),
+      Expression::IntegerConstant(1, None),
+    );
+    assert!(resolve(&expression).is_err());
+  }
 }
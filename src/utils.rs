@@ -18,3 +18,32 @@ impl<T, A> ResultExt<T, A> for Result<T, A> {
     self.map_err(|_| err)
   }
 }
+
+// The stack that the passes over a program get. They recurse on the stack for
+// every level of nesting, which takes tens of kilobytes per level in builds
+// without optimizations.
+pub(crate) const PASS_STACK_SIZE: usize = 64 << 20;
+
+// Lets a value move to the thread that with_stack starts and back. The thread
+// that starts it does nothing until it has finished, so values that aren't
+// Send, like Rcs, are never used by two threads at once.
+struct AssertSend<T>(T);
+unsafe impl<T> Send for AssertSend<T> {}
+
+// Runs `f` on a thread of its own with a stack of `stack_size` bytes, whatever
+// the stack of the caller is, and returns its result once it has finished.
+pub(crate) fn with_stack<T>(stack_size: usize, f: impl FnOnce() -> T) -> T {
+  let f = AssertSend(f);
+  std::thread::scope(|scope| {
+    std::thread::Builder::new()
+      .stack_size(stack_size)
+      .spawn_scoped(scope, move || {
+        let AssertSend(f) = f;
+        AssertSend(f())
+      })
+      .expect("failed to start a thread")
+      .join()
+      .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+      .0
+  })
+}
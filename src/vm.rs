@@ -0,0 +1,352 @@
+// Executes a program compiled to bytecode. It gives the same results as the
+// interpreter, including the runtime errors and where they happen.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::bytecode::{BytecodeFunction, BytecodeProgram, Instruction, Register};
+use crate::interpreter::{
+  binary_operation, cast, unary_operation, Cell, Closure, RuntimeError, RuntimeErrorCtx,
+  RuntimeResult, Value,
+};
+use crate::span::Span;
+
+// The deepest that calls can nest. Frames are kept on the heap rather than on
+// the stack of the host, so this is higher than in the interpreter, and only
+// stops runaway recursion before it uses up the memory of the host.
+pub const MAX_FRAMES: usize = 100_000;
+
+// Locals captured by reference are shared through a cell, the other values
+// are kept in the register directly.
+#[derive(Debug, Clone)]
+enum Slot {
+  Value(Value),
+  Cell(Cell),
+}
+
+// A call in progress. The frames of the callers hold where they continue once
+// the call returns.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+  function: usize,
+  ip: usize,
+  // Where the registers of the call start.
+  base: usize,
+  // The register of the caller that gets the value of the call.
+  dst: Register,
+}
+
+pub struct Vm<'a> {
+  program: &'a BytecodeProgram,
+  // The registers of every call in progress, one after the other.
+  registers: Vec<Slot>,
+  frames: Vec<Frame>,
+}
+
+impl<'a> Vm<'a> {
+  pub fn new(program: &'a BytecodeProgram) -> Vm<'a> {
+    Vm {
+      program,
+      registers: Vec::new(),
+      frames: Vec::new(),
+    }
+  }
+
+  // Makes the registers of a call, starting with the captures and the
+  // arguments.
+  fn enter(&mut self, function: usize, slots: Vec<Slot>, dst: Register) -> Frame {
+    let base = self.registers.len();
+    let register_count = self.program.functions[function].register_count;
+    self.registers.extend(slots);
+    self
+      .registers
+      .resize(base + register_count, Slot::Value(Value::Unit));
+
+    Frame {
+      function,
+      ip: 0,
+      base,
+      dst,
+    }
+  }
+
+  // Keeps the frame of a caller until the call returns.
+  fn push_frame(&mut self, pos: Span, frame: Frame) -> RuntimeResult<()> {
+    if self.frames.len() == MAX_FRAMES {
+      return Err(RuntimeErrorCtx(pos, RuntimeError::StackOverflow));
+    }
+    self.frames.push(frame);
+    Ok(())
+  }
+
+  fn get(&self, frame: &Frame, register: Register) -> Value {
+    match &self.registers[frame.base + register as usize] {
+      Slot::Value(value) => value.clone(),
+      Slot::Cell(cell) => cell.borrow().clone(),
+    }
+  }
+
+  // The values of consecutive registers.
+  fn get_all(&self, frame: &Frame, first: Register, count: usize) -> Vec<Value> {
+    (first..first + count as Register)
+      .map(|register| self.get(frame, register))
+      .collect()
+  }
+
+  // Runs the program and returns its value.
+  pub fn run(&mut self) -> RuntimeResult<Value> {
+    use Instruction::*;
+
+    let program = self.program;
+    let mut frame = self.enter(program.main(), Vec::new(), 0);
+    let mut function: &BytecodeFunction = &program.functions[frame.function];
+
+    loop {
+      let instruction = function.code[frame.ip];
+      let pos = function.spans[frame.ip];
+      frame.ip += 1;
+
+      let value = match instruction {
+        LoadConstant { dst, constant } => Some((dst, program.constants[constant as usize].clone())),
+        LoadUnit { dst } => Some((dst, Value::Unit)),
+        Move { dst, src } | LoadCell { dst, cell: src } => Some((dst, self.get(&frame, src))),
+        Copy { dst, src } => Some((dst, self.get(&frame, src).deep_copy())),
+        StoreCell { cell, src } => {
+          let value = self.get(&frame, src).deep_copy();
+          match &self.registers[frame.base + cell as usize] {
+            Slot::Cell(cell) => *cell.borrow_mut() = value,
+            Slot::Value(_) => unreachable!(),
+          }
+          None
+        }
+        DeclareCell { cell, src } => {
+          let value = self.get(&frame, src).deep_copy();
+          self.registers[frame.base + cell as usize] = Slot::Cell(Rc::new(RefCell::new(value)));
+          None
+        }
+        Jump { target } => {
+          frame.ip = target as usize;
+          None
+        }
+        JumpIfFalse { condition, target } => {
+          if let Value::Bool(false) = self.get(&frame, condition) {
+            frame.ip = target as usize;
+          }
+          None
+        }
+        Unary { op, dst, x } => {
+          let x = self.get(&frame, x);
+          let value = unary_operation(op, x).map_err(|error| RuntimeErrorCtx(pos, error))?;
+          Some((dst, value))
+        }
+        Binary { op, dst, lhs, rhs } => {
+          let lhs = self.get(&frame, lhs);
+          let rhs = self.get(&frame, rhs);
+          let value =
+            binary_operation(op, lhs, rhs).map_err(|error| RuntimeErrorCtx(pos, error))?;
+          Some((dst, value))
+        }
+        Cast { type_, dst, x } => Some((dst, cast(self.get(&frame, x), type_))),
+        Function { dst, function } => {
+          let function_id = program.functions[function as usize].id.unwrap();
+          Some((dst, Value::Function(function_id)))
+        }
+        Closure { dst, function } => {
+          let callee = &program.functions[function as usize];
+          let captures = callee
+            .captures
+            .iter()
+            .map(|capture| {
+              let cell = match &self.registers[frame.base + capture.register as usize] {
+                Slot::Cell(cell) => cell.clone(),
+                Slot::Value(value) => Rc::new(RefCell::new(value.clone())),
+              };
+              (capture.local_id, cell)
+            })
+            .collect();
+          let closure = self::Closure {
+            function_id: callee.id.unwrap(),
+            captures,
+          };
+          Some((dst, Value::Closure(Rc::new(closure))))
+        }
+        Call {
+          dst,
+          function: index,
+          arguments,
+        } => {
+          let count = program.functions[index as usize].parameter_count;
+          let arguments = self.get_all(&frame, arguments, count);
+          let slots = arguments
+            .into_iter()
+            .map(|x| Slot::Value(x.deep_copy()))
+            .collect();
+          self.push_frame(pos, frame)?;
+          frame = self.enter(index as usize, slots, dst);
+          function = &program.functions[frame.function];
+          None
+        }
+        CallIndirect {
+          dst,
+          callee,
+          arguments,
+          count,
+        } => {
+          let (index, mut slots) = match self.get(&frame, callee) {
+            Value::Function(function_id) => (program.function_indices[&function_id], Vec::new()),
+            Value::Closure(closure) => {
+              let index = program.function_indices[&closure.function_id];
+              let captures = program.functions[index as usize]
+                .captures
+                .iter()
+                .zip(&closure.captures)
+                .map(|(capture, (_, cell))| {
+                  if capture.is_cell {
+                    Slot::Cell(cell.clone())
+                  } else {
+                    Slot::Value(cell.borrow().clone())
+                  }
+                })
+                .collect();
+              (index, captures)
+            }
+            _ => unreachable!(),
+          };
+          let arguments = self.get_all(&frame, arguments, count as usize);
+          slots.extend(arguments.into_iter().map(|x| Slot::Value(x.deep_copy())));
+          self.push_frame(pos, frame)?;
+          frame = self.enter(index as usize, slots, dst);
+          function = &program.functions[frame.function];
+          None
+        }
+        Return { src } => {
+          let value = self.get(&frame, src);
+          self.registers.truncate(frame.base);
+          let dst = frame.dst;
+          match self.frames.pop() {
+            Some(caller) => {
+              frame = caller;
+              function = &program.functions[frame.function];
+              Some((dst, value))
+            }
+            None => return Ok(value),
+          }
+        }
+        Len { dst, x } => {
+          let len = match self.get(&frame, x) {
+            Value::Array(array) => array.borrow().len(),
+            // Like in Rust, the length of a string is in bytes.
+            Value::Str(s) => s.len(),
+            _ => unreachable!(),
+          };
+          Some((dst, Value::I32(len as i32)))
+        }
+        Array {
+          dst,
+          elements,
+          count,
+        } => {
+          let elements = self.get_all(&frame, elements, count as usize);
+          Some((dst, Value::Array(Rc::new(RefCell::new(elements)))))
+        }
+        Index { dst, array, index } => {
+          let array = self.get(&frame, array);
+          let index = self.get(&frame, index);
+          let index = check_index(&array, &index).map_err(|error| RuntimeErrorCtx(pos, error))?;
+          match array {
+            Value::Array(array) => Some((dst, array.borrow()[index].clone())),
+            _ => unreachable!(),
+          }
+        }
+        CheckIndex { array, index } => {
+          let array = self.get(&frame, array);
+          let index = self.get(&frame, index);
+          check_index(&array, &index).map_err(|error| RuntimeErrorCtx(pos, error))?;
+          None
+        }
+        SetIndex { array, index, src } => {
+          let value = self.get(&frame, src).deep_copy();
+          match (self.get(&frame, array), self.get(&frame, index)) {
+            (Value::Array(array), Value::I32(index)) => array.borrow_mut()[index as usize] = value,
+            _ => unreachable!(),
+          }
+          None
+        }
+        Struct {
+          dst,
+          layout,
+          fields: first,
+        } => {
+          let (type_id, indices) = &program.structs[layout as usize];
+          let mut fields = vec![Value::Unit; indices.len()];
+          let values = self.get_all(&frame, first, indices.len());
+          for (index, value) in indices.iter().zip(values) {
+            fields[*index] = value;
+          }
+          Some((dst, Value::Struct(*type_id, Rc::new(RefCell::new(fields)))))
+        }
+        Field { dst, x, field } => match self.get(&frame, x) {
+          Value::Struct(type_id, fields) => {
+            let index = program.fields[field as usize].1[&type_id];
+            let value = fields.borrow()[index].clone();
+            Some((dst, value))
+          }
+          _ => unreachable!(),
+        },
+        SetField { target, field, src } => {
+          let value = self.get(&frame, src).deep_copy();
+          match self.get(&frame, target) {
+            Value::Struct(type_id, fields) => {
+              let index = program.fields[field as usize].1[&type_id];
+              fields.borrow_mut()[index] = value;
+            }
+            _ => unreachable!(),
+          }
+          None
+        }
+        Variant {
+          dst,
+          variant,
+          fields,
+        } => {
+          let (type_id, variant, count) = program.variants[variant as usize];
+          let fields = self.get_all(&frame, fields, count);
+          Some((dst, Value::Enum(type_id, variant, Rc::new(fields))))
+        }
+        IsInteger { dst, x, integer } => {
+          let x = self.get(&frame, x).as_integer().map(|(_, x)| x);
+          let integer = program.integers[integer as usize];
+          Some((dst, Value::Bool(x == Some(integer))))
+        }
+        IsVariant { dst, x, variant } => match self.get(&frame, x) {
+          Value::Enum(_, x, _) => Some((dst, Value::Bool(x == variant as usize))),
+          _ => unreachable!(),
+        },
+        VariantField { dst, x, index } => match self.get(&frame, x) {
+          Value::Enum(_, _, fields) => Some((dst, fields[index as usize].clone())),
+          _ => unreachable!(),
+        },
+      };
+
+      // The value goes to a register of the frame that's current afterwards,
+      // which after a return is the caller's.
+      if let Some((dst, value)) = value {
+        self.registers[frame.base + dst as usize] = Slot::Value(value);
+      }
+    }
+  }
+}
+
+// The index into an array, if it's within its bounds.
+fn check_index(array: &Value, index: &Value) -> Result<usize, RuntimeError> {
+  match (array, index) {
+    (Value::Array(array), &Value::I32(index)) => {
+      let len = array.borrow().len();
+      if index < 0 || index as usize >= len {
+        return Err(RuntimeError::IndexOutOfBounds { index, len });
+      }
+      Ok(index as usize)
+    }
+    _ => unreachable!(),
+  }
+}
@@ -0,0 +1,238 @@
+use std::fs;
+use std::process::{Command, Output};
+
+use toylang::codegen::c::generate;
+use toylang::diagnostics::Report;
+use toylang::interpreter::Value;
+use toylang::parser::Parser;
+use toylang::semantic::transform_program;
+use toylang::source_map::SourceMap;
+use toylang::token_stream::TokenStream;
+use toylang::type_checker::visit_program;
+
+fn generate_c(src: &str, source_map: &SourceMap) -> String {
+  let mut token_stream = TokenStream::new(src);
+  let mut parser = Parser::new(&mut token_stream);
+  let (program, errors) = parser.parse_program();
+  assert!(errors.is_empty(), "{:?} doesn't parse", src);
+  let (mut ctx, program) = transform_program(program).unwrap();
+  visit_program(&mut ctx, &program).unwrap();
+  generate(&ctx, &program, source_map).unwrap()
+}
+
+// Compiles a C program with the system compiler and runs it, or returns None
+// if there is no compiler.
+fn compile_and_run(name: &str, c: &str) -> Option<Output> {
+  let dir = std::env::temp_dir().join(format!("toylang-{}-{}", name, std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let source = dir.join("program.c");
+  let executable = dir.join("program");
+  fs::write(&source, c).unwrap();
+
+  let status = Command::new("cc")
+    .arg("-std=c99")
+    .arg("-o")
+    .arg(&executable)
+    .arg(&source)
+    .arg("-lm")
+    .status()
+    .ok()?;
+  assert!(status.success(), "cc failed on:\n{}", c);
+  let output = Command::new(&executable).output().unwrap();
+  fs::remove_dir_all(&dir).unwrap();
+  Some(output)
+}
+
+// The compiled programs must print the value the interpreter gives, or the
+// same report of a runtime error.
+fn assert_same_output(name: &str, programs: &[&str]) {
+  for (i, src) in programs.iter().enumerate() {
+    let mut source_map = SourceMap::new();
+    source_map.add_file("<stdin>", src);
+    let c = generate_c(src, &source_map);
+    let output = match compile_and_run(&format!("{}-{}", name, i), &c) {
+      Some(output) => output,
+      None => {
+        eprintln!("skipping C backend tests, since there is no cc");
+        return;
+      }
+    };
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    match toylang::eval(src) {
+      Ok(value) => {
+        let expected = format!("{:?}\n", value.unwrap_or(Value::Unit));
+        assert!(output.status.success(), "{:?} failed: {}", src, stderr);
+        assert_eq!(expected, stdout, "wrong output for {:?}", src);
+      }
+      Err(error) => {
+        let expected = Report::new(&source_map, &error).to_string();
+        assert!(!output.status.success(), "{:?} should fail", src);
+        assert_eq!(expected, stderr, "wrong error for {:?}", src);
+      }
+    }
+  }
+}
+
+#[test]
+pub fn integers() {
+  assert_same_output(
+    "integers",
+    &[
+      "1 + 2 * 3 - 4",
+      "let x = 7; -x / 2 + x % 3",
+      "let a = 6u8; let b = 3u8; (a & b) | (a ^ b) << 1u8 >> 1u8",
+      "let x = -9223372036854775808i64; x + 1",
+      "18446744073709551615u64 / 3",
+      "let x = 1i16; !x",
+      "let x = 200u8; ~x",
+      "-128i8 >> 2u64",
+      "let x = 1; x << 31",
+      "let x = 300; x as u8 as i32 + 1e300 as i32 + -1.5 as i32",
+      "let x = -1i64; x as u64",
+      "true as u8 + 1",
+      "1 < 2 && 2 >= 2 || 1 / 0 == 1",
+    ],
+  );
+}
+
+#[test]
+pub fn floats_and_strings() {
+  assert_same_output(
+    "floats-strings",
+    &[
+      "let x = 1.5; x * 2.0 - 0.25 / x",
+      "0.1 + 0.2",
+      "1e16",
+      "1e15 + 0.5",
+      "0.00001",
+      "-1.5e300 * 10.0",
+      "let zero = 0.0; zero / zero",
+      "-0.0",
+      "7.5 % 2.0",
+      "\"con\" + \"cat\"",
+      "\"tab\\t \\\"quote\\\" \\u{0}\\u{1b} é?\"",
+      "let s = \"ab\"; s + \"c\" == \"abc\" && s != \"abc\"",
+      "len(\"hello\" + \"é\")",
+    ],
+  );
+}
+
+#[test]
+pub fn control_flow() {
+  assert_same_output(
+    "control-flow",
+    &[
+      "let x = 1; let x = x + 2; { let x = x * 2; x }",
+      "let mut x = 1; { let mut x = 2; x = 3; } x",
+      "let x = 5; if x < 3 { 1 } else if x < 10 { 2 } else { 3 }",
+      "let mut y = 0; if true { y = 7; } if false { y = 8; } y",
+      "let mut i = 0; let mut sum = 0; while i < 10 { i = i + 1; sum = sum + i; } sum",
+      "let mut x = 1; let y = loop { x = x * 2; if x > 50 { break x; } }; y",
+      "let mut i = 0; let mut s = 0; while i < 10 { i = i + 1; if i % 2 == 0 { continue; } s = s + i; } s",
+      "let mut i = 0; 1 + loop { i = i + 1; 2 * { if i < 3 { continue; } if i > 4 { break i * 10; } i }; }",
+      "let mut n = 0; loop { while { if n > 3 { break n; } true } { n = n + 1; } }",
+      "let mut x = 1; x + { x = 5; x }",
+      "let mut a = false; a = true && a; a",
+      "let b = false; let n = 2; match b { true => 0, false => match n { 1 => 1, -1 => 2, y => y * 3 } }",
+      "let x = 300i16; match x { 1 => 1, 300 => 2, _ => 3 }",
+      "let x = 1; x;",
+    ],
+  );
+}
+
+#[test]
+pub fn functions() {
+  assert_same_output(
+    "functions",
+    &[
+      "fn fib(n: i32) -> i32 { if n < 2 { return n; } fib(n - 1) + fib(n - 2) } fib(20)",
+      "fn even(n: i32) -> bool { if n == 0 { true } else { odd(n - 1) } } \
+       fn odd(n: i32) -> bool { if n == 0 { false } else { even(n - 1) } } even(10)",
+      "fn f(x: i32) -> i32 { 1 + { if x > 0 { return x; } 0 } } f(5) + f(-5)",
+      "fn f() -> str { loop { return \"done\"; } } f()",
+      "fn f() {} f()",
+      "fn f(n: u64) -> u64 { n * 4294967296 } f(4294967295) + f(1)",
+    ],
+  );
+}
+
+#[test]
+pub fn runtime_errors() {
+  assert_same_output(
+    "runtime-errors",
+    &[
+      "2147483647 + 1",
+      "let x: i8 = -128; -x",
+      "let x = 0; 1 / x",
+      "let x = 0; 1 % x",
+      "let x = -128i8; x / -1",
+      "0u8 - 1",
+      "let x = 1; x << 32",
+      "let x = 1u8; x >> -1",
+      "fn f(n: i32) -> i32 { n * 2147483647 } f(1) + f(2)",
+      "fn f(n: i32) -> i32 { f(n + 1) } f(0)",
+      "fn f(n: i32) -> i32 { if n == 0 { 0 } else { 1 + f(n - 1) } } f(9999) + f(10000)",
+      "fn f() { g(); } fn g() { f(); } f()",
+    ],
+  );
+}
+
+#[test]
+pub fn arrays() {
+  assert_same_output(
+    "arrays",
+    &[
+      "[1, 2, 3]",
+      "let a: [i64] = []; a",
+      "let a = [[1.5], [], [2.0, -0.0]]; a[2]",
+      "let a = [\"x\", \"y\" + \"z\"]; len(a) + len(a[1])",
+      "let mut a = [1, 2]; let b = a; a[0] = 5; [a, b]",
+      "let a = [[1], [2]]; let mut b = a; b[0][0] = 9; [a, b]",
+      "let a = [1]; let mut b = [a, a]; b[0][0] = 7; [a, b[1]]",
+      "fn f(a: [i32]) -> [i32] { a } let mut a = [1]; let b = [f(a)]; a[0] = 2; b",
+      "let mut a = [1, 2]; a[{ a = [5]; 1 }] = 3; a",
+      "let a = [true, false]; match a { b => b }",
+      "let a = [1, 2]; a[2]",
+      "let a = [[1], []]; a[1][-1]",
+      "let mut a = [0u8]; a[1] = 1; a",
+    ],
+  );
+}
+
+#[test]
+pub fn structs() {
+  assert_same_output(
+    "structs",
+    &[
+      "struct P { x: i32, y: i32 } let p = P { y: 1, x: 2 }; p.x * 10 + p.y",
+      "struct P { x: i32, s: str } P { s: \"s\", x: 1 }",
+      "struct E {} [E {}]",
+      "struct P { x: i32 } let mut p = P { x: 1 }; let q = p; p.x = 5; [p, q]",
+      "struct S { a: [i32] } let mut s = S { a: [1, 2] }; s.a[1] = 3; s",
+      "struct S { a: [i32] } let a = [1]; let mut s = S { a: a }; s.a[0] = 2; a",
+      "struct N { children: [N], value: f64 } N { children: [N { children: [], value: 2.5 }], value: 1.0 }",
+      "struct P { x: u16 } fn f(p: P) -> P { p } let mut p = P { x: 1 }; let q = [f(p)]; p.x = 2; q",
+    ],
+  );
+}
+
+#[test]
+pub fn generic_functions() {
+  assert_same_output(
+    "generic-functions",
+    &[
+      "fn id<T>(x: T) -> T { x } id(1) + id(2)",
+      "fn id<T>(x: T) -> T { x } if id(true) { id(\"yes\") } else { id(\"no\") }",
+      "fn first<T>(a: [T]) -> T { a[0] } first([first([1u8]) as f64, 2.0])",
+      "fn first<T>(a: [T]) -> T { a[0] } let a: [bool] = []; first(a)",
+      "struct Pair<A, B> { a: A, b: B } \
+       fn swap<A, B>(p: Pair<A, B>) -> Pair<B, A> { Pair { a: p.b, b: p.a } } \
+       [swap(Pair { a: 1, b: 2 }), swap(swap(Pair { a: 3, b: 4 }))]",
+      "struct Box<T> { value: T } fn get<T>(b: Box<T>) -> T { b.value } \
+       get(Box { value: [1] })[0] + len(get(Box { value: \"ab\" }))",
+      "fn twice<T>(x: T) -> [T] { [x, x] } fn nest<T>(x: T) -> [[T]] { twice(twice(x)) } nest(1.5)",
+    ],
+  );
+}
@@ -0,0 +1,199 @@
+use toylang::{eval_with, Backend};
+
+// Runs programs with both backends, which must give the same value or the same
+// error. Values are compared by their debug output, since closures are only
+// equal to themselves.
+fn assert_same_results(programs: &[&str]) {
+  for src in programs {
+    let interpreted = eval_with(src, Backend::Interpreter);
+    let compiled = eval_with(src, Backend::Vm);
+    assert_eq!(
+      format!("{:?}", interpreted),
+      format!("{:?}", compiled),
+      "backends disagree on {:?}",
+      src
+    );
+  }
+}
+
+#[test]
+pub fn arithmetic() {
+  assert_same_results(&[
+    "1 + 2 * 3 - 4",
+    "let x = 7; -x / 2 + x % 3",
+    "let a = 6u8; let b = 3u8; (a & b) | (a ^ b) << 1u8 >> 1u8",
+    "2147483647 + 1",
+    "let x: i8 = -128; -x",
+    "let x = 0; 1 / x",
+    "let x = 0; 1 % x",
+    "0u8 - 1",
+    "let x = 1.5; x * 2.0 - 0.25 / x",
+    "let x = 300; x as u8 as i32 + 1e300 as i32 + -1.5 as i32",
+    "\"con\" + \"cat\"",
+    "!true == false && 1 != 2 || 1 / 0 == 1",
+    "false && 1 / 0 == 1",
+    "let mut x = 1; false && { x = 2; true }; true || { x = 3; true }; x",
+  ]);
+}
+
+#[test]
+pub fn locals_and_blocks() {
+  assert_same_results(&[
+    "let x = 1; let x = x + 2; { let x = x * 2; x }",
+    "let mut x = 1; { let mut x = 2; x = 3; } x",
+    "let x = 2; let y = { let z = x + 1; z + 2 }; y",
+    "let x = 5; if x < 3 { 1 } else if x < 10 { 2 } else { 3 }",
+    "let mut y = 0; if true { y = 7; } if false { y = 8; } y",
+    "if false { 1; }",
+    "let x = 1; x;",
+  ]);
+}
+
+#[test]
+pub fn loops() {
+  assert_same_results(&[
+    "let mut i = 0; let mut sum = 0; while i < 10 { i = i + 1; sum = sum + i; } sum",
+    "let mut x = 1; let y = loop { x = x * 2; if x > 50 { break x; } }; y",
+    "let mut n = 0; loop { loop { break; } n = n + 1; if n == 3 { break n } }",
+    "let mut i = 0; let mut s = 0; while i < 10 { i = i + 1; if i % 2 == 0 { continue; } s = s + i; } s",
+    "let mut i = 0; while true { i = i + 1; if i == 5 { break; } } i",
+    // break and continue from within expressions that are being evaluated
+    "let mut i = 0; 1 + loop { i = i + 1; 2 * { if i < 3 { continue; } if i > 4 { break i * 10; } i }; }",
+    "let mut i = 0; let a = [1, loop { i = i + 1; [i, { if i > 2 { break i; } 0 }]; }]; a[1]",
+    "let mut i = 0; let mut s = 0; \
+     while i < 5 { i = i + 1; let a = [i, { if i == 2 { continue; } i }]; s = s + a[1]; } s",
+    "let mut i = 0; while i < 3 { i = i + 1; 1 + { if i == 2 { break; } i }; } i",
+    "let mut n = 0; loop { n = n + match n { 5 => { if true { break n * 2; } 0 }, _ => 1 }; }",
+    "let mut i = 0; loop { i = i + 1; let x = 2147483647; if i > 1 { x + i; } }",
+  ]);
+}
+
+#[test]
+pub fn functions() {
+  assert_same_results(&[
+    "fn fib(n: i32) -> i32 { if n < 2 { return n; } fib(n - 1) + fib(n - 2) } fib(15)",
+    "fn even(n: i32) -> bool { if n == 0 { true } else { odd(n - 1) } } \
+     fn odd(n: i32) -> bool { if n == 0 { false } else { even(n - 1) } } even(10)",
+    "fn f(a: i32, b: i32) -> i32 { let c = a * 10; return c + b; } f(1, 2) + f(3, 4)",
+    "fn f(x: i32) -> i32 { 1 + { if x > 0 { return x; } 0 } } f(5) + f(-5)",
+    "fn f(x: i32) -> i32 { let mut i = 0; loop { i = i + 1; if i == x { [i, { if true { return i * 2; } 0 }]; } } } f(4)",
+    "fn double(x: i32) -> i32 { x * 2 } let f = double; f(21)",
+    "fn f(n: i32) -> i32 { n * 2147483647 } f(1) + f(2)",
+    "fn f() {} f()",
+    "fn id<T>(x: T) -> T { x } if id(true) { id(1) + id(2) } else { 0 }",
+    "fn f(x: i32) -> i32 { if x > 0 { return 1; } else { return 2; } } f(1) + f(-1)",
+    "fn f(x: i32) -> i32 { let y = if x > 0 { x } else { return 0; }; y * 2 } f(3) + f(-3)",
+    "fn f(n: i32) -> i32 { f(n + 1) } f(0)",
+    "fn f(n: i32) -> i32 { if n == 0 { 0 } else { 1 + f(n - 1) } } f(300) + f(3000)",
+    "fn h() -> i32 { let g = h; g() } h()",
+    "let f = |n: i32| n; fn g(n: i32) -> i32 { let f = |m: i32| g(m); f(n) } g(1)",
+  ]);
+}
+
+#[test]
+pub fn closures() {
+  assert_same_results(&[
+    "let n = 3; let add = |x: i32| x + n; add(4)",
+    "let f = |x: i32| -> i32 { loop { if x > 0 { return x; } } }; f(3)",
+    "let n = 2; let f = |x: i32| { let y = x * n; if y > 5 { return y; } y + 1 }; f(1) + f(4)",
+    "let make_adder = |n: i32| |x: i32| x + n; let add = make_adder(10); add(5)",
+    "let sign = |x: i32| { if x < 0 { return -1; } 1 }; sign(-5)",
+    "let mut c = 0; let inc = || { c = c + 1; }; inc(); inc(); c",
+    "let mut n = 1; let get = || n; n = 10; get() + 1",
+    "let n = 1; let get = || n; let n = 10; get()",
+    "fn apply(x, f) -> i32 { f(x) } apply(21, |x| x * 2)",
+    "struct S { f: fn(i32) -> i32 } let s = S { f: |x: i32| x + 1 }; s.f(1) + [s.f][0](2)",
+    // Every iteration declares a new variable, so each closure has its own.
+    "let mut fs = [|| 0]; let mut i = 0; \
+     while i < 3 { let mut j = i; fs = [fs[0], || j]; j = j * 10; i = i + 1; } fs[1]()",
+    "let mut total = 0; let mut i = 0; \
+     let add = |x: i32| { total = total + x; total }; \
+     while i < 4 { i = i + 1; add(i); } total",
+    "let mut x = 1; let f = || { let g = || { x = x + 1; x }; g() + g() }; f() + x",
+    "let f = |x: i32| x; f",
+    "fn f(x: i32) -> i32 { x } [f, f]",
+  ]);
+}
+
+#[test]
+pub fn arrays() {
+  assert_same_results(&[
+    "let mut a = [1, 2, 3]; a[1] = 5; a[1] + len(a)",
+    "let mut a: [[i32]] = [[1], [2, 3]]; a[0][0] = a[1][1]; a[0][0]",
+    "let mut a = [1]; let mut b = a; b[0] = 7; a[0]",
+    "let a = [1, 2, 3]; let mut b = a; b[0] = 9; a[0]",
+    "let mut a = [2]; let b = a; a[0] = 9; b[0]",
+    "let mut a = [[3]]; let b = a[0]; a[0][0] = 9; [b[0], a[0][0]]",
+    "let a = [4]; let mut b = [[0]]; b[0] = a; b[0][0] = 9; a[0]",
+    "let mut a = [6]; let f = |b: [i32]| { a[0] = 9; b[0] }; f(a)",
+    "enum E { A([i32]) } let mut a = [5]; let e = E::A(a); a[0] = 9; match e { E::A(b) => b[0] }",
+    "let mut a = [7]; let mut b = [0]; b = a; b[0] = 9; let mut c = a; { c = b; } [a, b, c]",
+    "let a = [1, 2]; a[2]",
+    "let a = [1, 2]; a[-1]",
+    "let mut a = [1, 2]; a[5] = 1 / 0;",
+    "let mut a = [1, 2]; a[0] = 1 / 0;",
+    "let mut a = [[0]]; a[0][0] = 1; a",
+    "len(\"hello\") + len([true])",
+  ]);
+}
+
+#[test]
+pub fn structs_and_enums() {
+  assert_same_results(&[
+    "struct P { x: i32, y: i32 } let p = P { y: 1, x: 2 }; p.x * 10 + p.y",
+    "struct P { x: i32 } let mut p = P { x: 1 }; let q = p; p.x = 5; q",
+    "struct P { x: i32 } struct Q { y: bool, x: i32 } let p = P { x: 1 }; let q = Q { x: 2, y: true }; p.x + q.x",
+    "struct Pair<A, B> { a: A, b: B } fn swap<A, B>(p: Pair<A, B>) -> Pair<B, A> { Pair { a: p.b, b: p.a } } swap(Pair { a: 1, b: true })",
+    "struct S { a: [i32] } let mut s = S { a: [1, 2] }; s.a[1] = 3; s",
+    "struct P { x: i32 } let p = P { x: 1 }; let mut q = p; q.x = 9; [p, q]",
+    "struct P { x: i32 } let mut p = P { x: 2 }; let q = p; p.x = 9; [p, q]",
+    "struct L { a: P, b: P } struct P { x: i32 } let p = P { x: 3 }; let mut l = L { a: p, b: p }; \
+     l.a.x = 9; l.b = l.a; l.b.x = 10; [p.x, l.a.x, l.b.x]",
+    "struct S { a: [i32] } let a = [4]; let mut s = S { a: a }; s.a[0] = 9; a",
+    "struct P { x: i32 } fn f(p: P) -> P { p } let mut p = P { x: 5 }; let mut q = f(p); q.x = 9; p",
+    "enum E { A(bool), B(i32, i32), C } fn f(e: E) -> i32 { match e { E::A(true) => 1, E::A(_) => 2, E::B(x, 0) => x, E::B(_, y) => y, E::C => 0 } } \
+     [f(E::A(true)), f(E::A(false)), f(E::B(5, 0)), f(E::B(5, 6)), f(E::C)]",
+    "enum Option<T> { Some(T), None } let o = Option::Some(Option::Some(3)); \
+     match o { Option::Some(Option::Some(x)) => x, Option::Some(Option::None) => 1, Option::None => 0 }",
+    "enum List { Cons(i32, List), Nil } fn sum(l: List) -> i32 { match l { List::Cons(x, rest) => x + sum(rest), List::Nil => 0 } } \
+     sum(List::Cons(1, List::Cons(2, List::Cons(3, List::Nil))))",
+    "let b = false; let n = 2; match b { true => 0, false => match n { 1 => 1, -1 => 2, _ => 3 } }",
+    "let x = 300i16; match x { 1 => 1, 300 => 2, _ => 3 }",
+    "let x = 5; let mut fs = [|| 0]; match x { y => { fs = [|| y]; } } fs[0]()",
+    "enum E { A(i32) } E::A(1)",
+  ]);
+}
+
+// Operands are evaluated left to right, even when a later one assigns to a
+// local that an earlier one read.
+#[test]
+pub fn evaluation_order() {
+  assert_same_results(&[
+    "let mut x = 1; x + { x = 5; x }",
+    "let mut x = 2; let y = x * { x = 10; 1 }; [x, y]",
+    "let mut a = true; let b = false; a = b || a; a",
+    "let mut a = false; a = true && a; a",
+    "let mut a = [1, 2]; let mut i = 0; a[i] = { i = 1; 5 }; [a[0], a[1], i]",
+    "let mut a = [1, 2]; let b = a; a[0] = { a = [3, 4]; 5 }; [a, b]",
+    "let mut a = [1, 2]; let mut i = 0; a[i] + { while i < 1 { i = i + 1; } a[i] }",
+    "let mut f = |x: i32| x; f({ f = |x: i32| x * 2; 3 })",
+    "let mut f = |x: i32| x; let g = f; f = |x: i32| x * 2; [g(1), f(1)]",
+    "let mut x = 1; let y = if x > 0 { x = 5; x } else { 0 }; [x, y]",
+    "let f = |a: i32, b: i32| a - b; let mut i = 3; f(i, { i = 1; i })",
+    "struct P { x: i32, y: i32 } let mut n = 1; let p = P { y: { n = n + 1; n }, x: n }; [p.x, p.y]",
+    "let x = 1; let y = x; let x = 2; y * 10 + x",
+    "let mut i = 0; let mut fs = [|| 0]; \
+     while i < 3 { let x = i * 2; match x { y => { fs = [fs[0], || y + x]; } } i = i + 1; } \
+     fs[1]()",
+  ]);
+}
+
+#[test]
+pub fn errors() {
+  assert_same_results(&[
+    "let x = ;",
+    "let x = y;",
+    "let x: bool = 1;",
+    "fn f() -> i32 { true } f()",
+  ]);
+}
@@ -0,0 +1,1604 @@
+use toylang::eval;
+use toylang::interpreter::Value;
+
+#[test]
+pub fn program_result() {
+  assert_eq!(Ok(Some(Value::I32(6))), eval("let x = 2; x * 3"));
+}
+
+#[test]
+pub fn program_without_result() {
+  assert_eq!(Ok(None), eval("let x = 2; x * 3;"));
+}
+
+#[test]
+pub fn block_result() {
+  assert_eq!(
+    Ok(Some(Value::I32(5))),
+    eval("let x = 2; let y = { let z = x + 1; z + 2 }; y")
+  );
+}
+
+#[test]
+pub fn nested_block_result() {
+  assert_eq!(Ok(Some(Value::I32(3))), eval("{ { 1 + 2 } }"));
+}
+
+#[test]
+pub fn expression_statements() {
+  assert_eq!(
+    Ok(Some(Value::I32(10))),
+    eval("let mut x = 1; x + 1; { x = 10; } x")
+  );
+}
+
+#[test]
+pub fn unit_block() {
+  assert_eq!(Ok(None), eval("let x = { let y = 1; }; x"));
+}
+
+#[test]
+pub fn assign_to_immutable() {
+  use toylang::semantic::{SemanticError, SemanticErrorCtx};
+  use toylang::span::Span;
+  use toylang::EvalError;
+
+  assert_eq!(
+    Err(EvalError::SemanticError(SemanticErrorCtx(
+      Span::new(11, 17),
+      SemanticError::AssignToImmutable {
+        name: "x".to_string(),
+        declaration: Span::new(4, 5)
+      }
+    ))),
+    eval("let x = 1; x = 2; x")
+  );
+}
+
+#[test]
+pub fn shadowing() {
+  assert_eq!(
+    Ok(Some(Value::I32(1))),
+    eval("let mut x = 1; { let mut x = 2; x = 3; } x")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(6))),
+    eval("let x = 1; let x = x + 2; { let x = x * 2; x }")
+  );
+  assert_eq!(Ok(Some(Value::I32(2))), eval("{ let x = 1; } let x = 2; x"));
+}
+
+#[test]
+pub fn left_associative_operators() {
+  assert_eq!(Ok(Some(Value::I32(5))), eval("10 - 2 - 3"));
+}
+
+#[test]
+pub fn boolean_operators() {
+  assert_eq!(
+    Ok(Some(Value::Bool(true))),
+    eval("let x = 3; x > 2 && x <= 3")
+  );
+  assert_eq!(Ok(Some(Value::Bool(false))), eval("!(1 == 1) || 2 != 2"));
+  assert_eq!(
+    Ok(Some(Value::Bool(true))),
+    eval("let b: bool = true; b == (1 < 2)")
+  );
+}
+
+#[test]
+pub fn short_circuit() {
+  // Neither right hand side may run, or x would be reassigned.
+  assert_eq!(
+    Ok(Some(Value::Bool(false))),
+    eval("let mut x = 1; false && { x = 2; true }; true || { x = 3; true }; x != 1")
+  );
+}
+
+#[test]
+pub fn if_else() {
+  assert_eq!(
+    Ok(Some(Value::I32(2))),
+    eval("let x = 5; if x < 3 { 1 } else if x < 10 { 2 } else { 3 }")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(7))),
+    eval("let mut y = 0; if true { y = 7; } if false { y = 8; } y")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(3))),
+    eval("let x = if false { 1 } else { 3 }; x")
+  );
+}
+
+#[test]
+pub fn if_branch_scopes() {
+  assert_eq!(
+    Ok(Some(Value::I32(1))),
+    eval("let x = 1; if true { let x = 2; x } else { x }; x")
+  );
+}
+
+#[test]
+pub fn if_type_errors() {
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(3, 4),
+      TypeError::InvalidCondition {
+        x: "i32".to_string()
+      }
+    )])),
+    eval("if 1 { }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 28),
+      TypeError::MismatchedBranches {
+        then_type: "i32".to_string(),
+        else_type: "bool".to_string()
+      }
+    )])),
+    eval("if true { 1 } else { false }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(8, 13),
+      TypeError::MissingElse {
+        then_type: "i32".to_string()
+      }
+    )])),
+    eval("if true { 1 }")
+  );
+}
+
+#[test]
+pub fn while_loop() {
+  assert_eq!(
+    Ok(Some(Value::I32(55))),
+    eval("let mut i = 0; let mut sum = 0; while i < 10 { i = i + 1; sum = sum + i; } sum")
+  );
+}
+
+#[test]
+pub fn break_and_continue() {
+  // Sums the odd numbers below 10.
+  assert_eq!(
+    Ok(Some(Value::I32(25))),
+    eval(
+      "let mut i = 0; let mut sum = 0;
+      while true {
+        i = i + 1;
+        if i >= 10 { break; }
+        if i == 2 || i == 4 || i == 6 || i == 8 { continue }
+        sum = sum + i;
+      }
+      sum"
+    )
+  );
+}
+
+#[test]
+pub fn loop_break_value() {
+  assert_eq!(
+    Ok(Some(Value::I32(64))),
+    eval("let mut x = 1; let y = loop { x = x * 2; if x > 50 { break x; } }; y")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(3))),
+    eval("let mut n = 0; loop { loop { break; } n = n + 1; if n == 3 { break n } }")
+  );
+}
+
+#[test]
+pub fn loop_type_errors() {
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(13, 21),
+      TypeError::BreakWithValueFromWhile
+    )])),
+    eval("while true { break 1; }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(28, 40),
+      TypeError::MismatchedBreak {
+        expected: "i32".to_string(),
+        x: "bool".to_string()
+      }
+    )])),
+    eval("loop { if true { break 1; } break false; }")
+  );
+}
+
+#[test]
+pub fn recursion() {
+  assert_eq!(
+    Ok(Some(Value::I32(3628800))),
+    eval(
+      "fn factorial(n: i32) -> i32 { if n <= 1 { 1 } else { n * factorial(n - 1) } } factorial(10)"
+    )
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(610))),
+    eval("fn fib(n: i32) -> i32 { if n < 2 { return n; } fib(n - 1) + fib(n - 2) } fib(15)")
+  );
+}
+
+#[test]
+pub fn mutual_recursion() {
+  assert_eq!(
+    Ok(Some(Value::Bool(true))),
+    eval(
+      "fn is_even(n: i32) -> bool { if n == 0 { true } else { is_odd(n - 1) } }
+      fn is_odd(n: i32) -> bool { if n == 0 { false } else { is_even(n - 1) } }
+      is_even(10) && is_odd(7)"
+    )
+  );
+}
+
+#[test]
+pub fn return_from_loop() {
+  assert_eq!(
+    Ok(Some(Value::I32(4))),
+    eval(
+      "fn first_square_above(n: i32) -> i32 {
+        let mut i = 0;
+        loop { if i * i > n { return i; } i = i + 1; }
+      }
+      first_square_above(10)"
+    )
+  );
+}
+
+#[test]
+pub fn diverging_expressions() {
+  // Blocks that end in a jump, and loops without a break, never finish, so
+  // they can stand where a value of any type is expected.
+  assert_eq!(
+    Ok(Some(Value::I32(2))),
+    eval("fn f(x: i32) -> i32 { if x > 0 { return 1; } else { return 2; } } f(-1)")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(6))),
+    eval("fn f(x: i32) -> i32 { let y = if x > 0 { x } else { return 0; }; y * 2 } f(3) + f(-3)")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(11))),
+    eval("fn f(x: i32) -> i32 { match x { 0 => { return 10; } n => n } } f(0) + f(1)")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(9))),
+    eval(
+      "let mut i = 0; let mut s = 0;
+      while i < 5 { i = i + 1; let x: i32 = if i % 2 == 0 { continue; } else { i }; s = s + x; }
+      s"
+    )
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(5))),
+    eval("fn f() -> i32 { let x = { return 5; }; x } f()")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(1))),
+    eval("fn forever() -> str { loop {} } let f = || loop {}; 1")
+  );
+}
+
+#[test]
+pub fn call_frames() {
+  // Each call has its own copy of the parameters and locals.
+  assert_eq!(
+    Ok(Some(Value::I32(21))),
+    eval(
+      "fn sum_to(n: i32) -> i32 { let mut x = n; if n > 0 { x = x + sum_to(n - 1); } x }
+      let n = 1; sum_to(6) * n"
+    )
+  );
+  assert_eq!(Ok(None), eval("fn nothing() { } nothing()"));
+}
+
+#[test]
+pub fn stack_overflow() {
+  use toylang::interpreter::{RuntimeError, RuntimeErrorCtx, MAX_CALL_DEPTH};
+  use toylang::span::Span;
+  use toylang::EvalError;
+
+  // Runaway recursion is an error, not a crash of the interpreter.
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(22, 30),
+      RuntimeError::StackOverflow
+    ))),
+    eval("fn f(n: i32) -> i32 { f(n + 1) } f(0)")
+  );
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(27, 30),
+      RuntimeError::StackOverflow
+    ))),
+    eval("fn h() -> i32 { let g = h; g() } h()")
+  );
+  // Calls up to the limit are fine.
+  assert_eq!(
+    Ok(Some(Value::I32(MAX_CALL_DEPTH as i32))),
+    eval(&format!(
+      "fn depth(n: i32) -> i32 {{ if n == {} {{ n }} else {{ depth(n + 1) }} }} depth(1)",
+      MAX_CALL_DEPTH
+    ))
+  );
+  // Deep recursion over data runs on its own stack, whatever the stack of the
+  // thread that evaluates it.
+  let elements = vec!["1"; 3000].join(", ");
+  let src = format!(
+    "fn sum(a: [i32], i: i32) -> i32 {{ if i == len(a) {{ 0 }} else {{ a[i] + sum(a, i + 1) }} }} \
+     sum([{}], 0)",
+    elements
+  );
+  let summed = std::thread::Builder::new()
+    .stack_size(256 << 10)
+    .spawn(move || eval(&src) == Ok(Some(Value::I32(3000))))
+    .unwrap()
+    .join()
+    .unwrap();
+  assert!(summed);
+}
+
+#[test]
+pub fn vm_stack_overflow() {
+  use toylang::interpreter::{RuntimeError, RuntimeErrorCtx};
+  use toylang::span::Span;
+  use toylang::vm::MAX_FRAMES;
+  use toylang::{eval_with, Backend, EvalError};
+
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(22, 30),
+      RuntimeError::StackOverflow
+    ))),
+    eval_with("fn f(n: i32) -> i32 { f(n + 1) } f(0)", Backend::Vm)
+  );
+  // The frames of the VM are on the heap, so calls nest deeper than in the
+  // interpreter.
+  assert_eq!(
+    Ok(Some(Value::I32(MAX_FRAMES as i32 - 1))),
+    eval_with(
+      &format!(
+        "fn depth(n: i32) -> i32 {{ if n == {} {{ n }} else {{ depth(n + 1) }} }} depth(1)",
+        MAX_FRAMES - 1
+      ),
+      Backend::Vm
+    )
+  );
+}
+
+#[test]
+pub fn function_type_errors() {
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  let i32_type = "i32";
+  let bool_type = "bool";
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(17, 24),
+      TypeError::WrongArgumentCount {
+        expected: 1,
+        actual: 2
+      }
+    )])),
+    eval("fn f(a: i32) { } f(1, 2)")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(19, 23),
+      TypeError::NotAssignable {
+        target: i32_type.to_string(),
+        x: bool_type.to_string()
+      }
+    )])),
+    eval("fn f(a: i32) { } f(true)")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(16, 20),
+      TypeError::MismatchedReturn {
+        expected: i32_type.to_string(),
+        x: bool_type.to_string()
+      }
+    )])),
+    eval("fn f() -> i32 { true }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(27, 36),
+      TypeError::MismatchedReturn {
+        expected: bool_type.to_string(),
+        x: i32_type.to_string()
+      }
+    )])),
+    eval("fn f() -> bool { if true { return 1; } false }")
+  );
+  // Without an else, the if may finish without returning.
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(22, 44),
+      TypeError::MismatchedReturn {
+        expected: i32_type.to_string(),
+        x: "()".to_string()
+      }
+    )])),
+    eval("fn f(x: i32) -> i32 { if x > 0 { return 1; } }")
+  );
+}
+
+#[test]
+pub fn closures() {
+  assert_eq!(
+    Ok(Some(Value::I32(7))),
+    eval("let n = 3; let add = |x: i32| x + n; add(4)")
+  );
+  assert_eq!(Ok(Some(Value::I32(6))), eval("(|x: i32| x * 2)(3)"));
+  assert_eq!(
+    Ok(Some(Value::I32(15))),
+    eval("let make_adder = |n: i32| |x: i32| x + n; let add = make_adder(10); add(5)")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(-1))),
+    eval("let sign = |x: i32| { if x < 0 { return -1; } 1 }; sign(-5)")
+  );
+  // A block body that never finishes is fine, as in a function.
+  assert_eq!(
+    Ok(Some(Value::I32(1))),
+    eval("let f = || -> i32 { return 1; }; f()")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(3))),
+    eval("let f = |x: i32| -> i32 { loop { if x > 0 { return x; } } }; f(3)")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(2))),
+    eval("let f = |x: i32| { if x > 0 { return 1; } else { return 2; } }; f(-1)")
+  );
+}
+
+#[test]
+pub fn closure_captures() {
+  // Immutable locals are copied, mutable ones are shared.
+  assert_eq!(
+    Ok(Some(Value::I32(2))),
+    eval("let mut c = 0; let inc = || { c = c + 1; }; inc(); inc(); c")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(11))),
+    eval("let mut n = 1; let get = || n; n = 10; get() + 1")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(1))),
+    eval("let n = 1; let get = || n; let n = 10; get()")
+  );
+  // Each iteration declares a new local, so each closure sees its own.
+  assert_eq!(
+    Ok(Some(Value::I32(3))),
+    eval(
+      "let mut f = || 0; let mut i = 0;
+      while i < 3 { i = i + 1; let mut j = i; let g = || j; if i == 3 { f = g; } j = 0; }
+      f() + 3"
+    )
+  );
+}
+
+#[test]
+pub fn functions_as_values() {
+  assert_eq!(
+    Ok(Some(Value::I32(42))),
+    eval("fn double(x: i32) -> i32 { x * 2 } let f = double; f(21)")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(9))),
+    eval(
+      "fn inc(x: i32) -> i32 { x + 1 } let twice = |x: i32| inc(inc(x)); let mut f = twice; f(7)"
+    )
+  );
+}
+
+#[test]
+pub fn function_types() {
+  assert_eq!(
+    Ok(Some(Value::I32(8))),
+    eval("fn apply(f: fn(i32) -> i32, x: i32) -> i32 { f(x) } apply(|x: i32| x * 2, 4)")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(3))),
+    eval(
+      "fn inc(x: i32) -> i32 { x + 1 } struct S { f: fn(i32) -> i32 } let s = S { f: inc }; s.f(2)"
+    )
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(5))),
+    eval(
+      "fn compose(f: fn(i32) -> i32, g: fn(i32) -> i32) -> fn(i32) -> i32 { |x: i32| g(f(x)) }
+      let h: fn(i32) -> i32 = compose(|x: i32| x + 1, |x: i32| x * 2); h(1) + 1"
+    )
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(2))),
+    eval(
+      "let mut c = 0; let fs: [fn()] = [|| { c = c + 1; }, || { c = c + 1; }]; fs[0](); fs[1](); c"
+    )
+  );
+  // Without a return type, a function type returns ().
+  assert!(eval("let f: fn() = || 1;").is_err());
+  assert!(eval("let f: fn(i32) -> i32 = |x: bool| 1;").is_err());
+}
+
+#[test]
+pub fn closure_type_errors() {
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  let i32_type = "i32";
+  let bool_type = "bool";
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(11, 12),
+      TypeError::NotCallable {
+        x: i32_type.to_string()
+      }
+    )])),
+    eval("let x = 1; x(2)")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(26, 30),
+      TypeError::NotAssignable {
+        target: i32_type.to_string(),
+        x: bool_type.to_string()
+      }
+    )])),
+    eval("let f = |x: i32| x + 1; f(true)")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(20, 24),
+      TypeError::MismatchedReturn {
+        expected: i32_type.to_string(),
+        x: bool_type.to_string()
+      }
+    )])),
+    eval("let f = || -> i32 { true };")
+  );
+  assert!(eval("let mut f = |x: i32| x; f = |x: bool| 1;").is_err());
+}
+
+#[test]
+pub fn arrays() {
+  assert_eq!(
+    Ok(Some(Value::I32(8))),
+    eval("let mut a = [1, 2, 3]; a[1] = 5; a[1] + len(a)")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(3))),
+    eval("let mut a: [[i32]] = [[1], [2, 3]]; a[0][0] = a[1][1]; a[0][0]")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(6))),
+    eval(
+      "fn sum(a: [i32]) -> i32 {
+        let mut s = 0; let mut i = 0;
+        while i < len(a) { s = s + a[i]; i = i + 1; }
+        s
+      }
+      sum([1, 2, 3])"
+    )
+  );
+  // Binding an array to a local copies it, so changing one local never
+  // changes another.
+  assert_eq!(
+    Ok(Some(Value::I32(1))),
+    eval("let mut a = [1]; let mut b = a; b[0] = 7; a[0]")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(1))),
+    eval("let a = [1, 2, 3]; let mut b = a; b[0] = 9; a[0]")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(2))),
+    eval("let mut a = [2]; let b = a; a[0] = 9; b[0]")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(3))),
+    eval("let mut a = [[3]]; let b = a[0]; a[0][0] = 9; b[0]")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(4))),
+    eval("let a = [4]; let mut b = [[0]]; b[0] = a; b[0][0] = 9; a[0]")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(5))),
+    eval(
+      "enum E { A([i32]) }
+      let mut a = [5]; let e = E::A(a); a[0] = 9;
+      match e { E::A(b) => b[0] }"
+    )
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(6))),
+    eval("let mut a = [6]; let f = |b: [i32]| { a[0] = 9; b[0] }; f(a)")
+  );
+}
+
+#[test]
+pub fn array_index_out_of_bounds() {
+  use toylang::interpreter::{RuntimeError, RuntimeErrorCtx};
+  use toylang::span::Span;
+  use toylang::EvalError;
+
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(16, 20),
+      RuntimeError::IndexOutOfBounds { index: 2, len: 2 }
+    ))),
+    eval("let a = [1, 2]; a[2]")
+  );
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(19, 29),
+      RuntimeError::IndexOutOfBounds { index: -1, len: 1 }
+    ))),
+    eval("let mut a = [1]; { a[-1] = 0; }")
+  );
+}
+
+#[test]
+pub fn array_type_errors() {
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  let i32_type = "i32";
+  let bool_type = "bool";
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(4, 8),
+      TypeError::NotAssignable {
+        target: i32_type.to_string(),
+        x: bool_type.to_string()
+      }
+    )])),
+    eval("[1, true]")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(15, 19),
+      TypeError::InvalidIndex {
+        x: bool_type.to_string()
+      }
+    )])),
+    eval("let a = [1]; a[true]")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(15, 16),
+      TypeError::ExpectedArray {
+        x: i32_type.to_string()
+      }
+    )])),
+    eval("let x = 1; len(x)")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 2),
+      TypeError::EmptyArray
+    )])),
+    eval("[]")
+  );
+  assert!(eval("let mut a = [1]; a[0] = false;").is_err());
+  assert!(eval("let a: [bool] = [1];").is_err());
+}
+
+#[test]
+pub fn structs() {
+  assert_eq!(
+    Ok(Some(Value::I32(22))),
+    eval(
+      "struct Point { x: i32, y: i32 } let mut p = Point { y: 2, x: 1 }; p.x = p.x + 10; p.x * p.y"
+    )
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(4))),
+    eval(
+      "struct Point { x: i32, y: i32 }
+      fn manhattan(p: Point) -> i32 { p.x + p.y }
+      manhattan(Point { x: 1, y: 3 })"
+    )
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(9))),
+    eval(
+      "struct Line { from: Point, to: Point } struct Point { x: i32, y: i32 }
+      let mut line = Line { from: Point { x: 0, y: 0 }, to: Point { x: 1, y: 1 } };
+      line.to.y = 9;
+      line.to.y"
+    )
+  );
+  // Like arrays, structs are copied when they are bound to a local.
+  assert_eq!(
+    Ok(Some(Value::I32(1))),
+    eval("struct P { x: i32 } let p = P { x: 1 }; let mut q = p; q.x = 9; p.x")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(2))),
+    eval("struct P { x: i32 } let mut p = P { x: 2 }; let q = p; p.x = 9; q.x")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(3))),
+    eval(
+      "struct Line { from: P, to: P } struct P { x: i32 }
+      let p = P { x: 3 }; let mut line = Line { from: p, to: p };
+      line.from.x = 9; line.to = line.from; line.to.x = 10;
+      p.x + line.from.x - 9"
+    )
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(4))),
+    eval("struct S { a: [i32] } let a = [4]; let mut s = S { a: a }; s.a[0] = 9; a[0]")
+  );
+  // Struct literals aren't allowed in conditions, but are in parentheses.
+  assert_eq!(
+    Ok(Some(Value::Bool(true))),
+    eval("struct B { b: bool } if (B { b: true }).b { true } else { false }")
+  );
+}
+
+#[test]
+pub fn struct_type_errors() {
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  let i32_type = "i32";
+  let bool_type = "bool";
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(27, 31),
+      TypeError::NotAssignable {
+        target: i32_type.to_string(),
+        x: bool_type.to_string()
+      }
+    )])),
+    eval("struct P { x: i32 } P { x: true }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(11, 14),
+      TypeError::NoSuchField {
+        x: i32_type.to_string(),
+        field: "y".to_string()
+      }
+    )])),
+    eval("let x = 1; x.y")
+  );
+  // Structs with the same fields are still different types.
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(40, 62),
+      TypeError::NotAssignable {
+        target: "P".to_string(),
+        x: "Q".to_string()
+      }
+    )])),
+    eval("struct P { x: i32 } struct Q { x: i32 } let p: P = Q { x: 1 };")
+  );
+}
+
+#[test]
+pub fn type_names_in_errors() {
+  let message = |src| eval(src).unwrap_err().to_string();
+  let label = |src| {
+    let diagnostics = eval(src).unwrap_err().to_diagnostics();
+    diagnostics[0].primary.message.clone().unwrap()
+  };
+
+  assert_eq!(
+    "expected `P`, found `[i32]`",
+    label("struct P { x: i32 } let p: P = [1];")
+  );
+  assert_eq!(
+    "cannot apply binary operator `==` to types `[i32]` and `[i32]`",
+    message("[1] == [1]")
+  );
+  assert_eq!("casting `[i32]` as `i32` is invalid", message("[1] as i32"));
+  assert_eq!(
+    "expected `fn(i32)`, found `fn(i32) -> i32`",
+    label("fn f(x: i32) -> i32 { x } let g: fn(i32) = f;")
+  );
+  assert_eq!(
+    "expected `i32`, found `Option<[bool]>`",
+    label("enum Option<T> { Some(T), None } let x: i32 = Option::Some([true]);")
+  );
+  assert_eq!(
+    "expected `T`, found `bool`",
+    label("fn f<T>(x: T) -> T { if true { x } else { false } }")
+  );
+}
+
+#[test]
+pub fn enums() {
+  assert_eq!(
+    Ok(Some(Value::I32(22))),
+    eval(
+      "enum Shape { Circle(i32), Rect(i32, i32), Empty }
+      fn area(s: Shape) -> i32 {
+        match s {
+          Shape::Circle(r) => 3 * r * r,
+          Shape::Rect(w, h) => w * h,
+          Shape::Empty => 0,
+        }
+      }
+      area(Shape::Rect(2, 5)) + area(Shape::Circle(2)) + area(Shape::Empty)"
+    )
+  );
+  // Patterns nest, and the first arm that matches wins.
+  assert_eq!(
+    Ok(Some(Value::I32(7))),
+    eval(
+      "enum Shape { Circle(i32), Rect(i32, i32) } enum Maybe { Some(Shape), None }
+      let x = Maybe::Some(Shape::Rect(1, 7));
+      match x {
+        Maybe::Some(Shape::Rect(1, h)) => h,
+        Maybe::Some(_) => 1,
+        Maybe::None => 0,
+      }"
+    )
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(3))),
+    eval("let b = false; let n = 2; match b { true => 0, false => match n { 1 => 1, -1 => 2, _ => 3 } }")
+  );
+  // Bindings are only visible in their arm.
+  assert_eq!(
+    Ok(Some(Value::Bool(true))),
+    eval("let x = true; let y = match 5 { x => x + 1 }; x")
+  );
+}
+
+#[test]
+pub fn enum_type_errors() {
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  let i32_type = "i32";
+  let bool_type = "bool";
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(53, 54),
+      TypeError::NonExhaustive {
+        missing: "E::B(_)".to_string()
+      }
+    )])),
+    eval("enum E { A(bool), B(i32) } fn f(e: E) -> i32 { match e { E::A(_) => 1 } }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(53, 54),
+      TypeError::NonExhaustive {
+        missing: "E::A(false)".to_string()
+      }
+    )])),
+    eval(
+      "enum E { A(bool), B(i32) } fn f(e: E) -> i32 { match e { E::A(true) => 1, E::B(_) => 2 } }"
+    )
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(6, 7),
+      TypeError::NonExhaustive {
+        missing: "_".to_string()
+      }
+    )])),
+    eval("match 1 { 1 => 0 }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(21, 25),
+      TypeError::UnreachablePattern
+    )])),
+    eval("match true { _ => 1, true => 2 }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(18, 22),
+      TypeError::MismatchedPattern {
+        expected: i32_type.to_string(),
+        x: bool_type.to_string()
+      }
+    )])),
+    eval("match 1 { 1 => 0, true => 1 }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(34, 44),
+      TypeError::WrongFieldCount {
+        expected: 1,
+        actual: 2
+      }
+    )])),
+    eval("enum E { A(i32) } match E::A(1) { E::A(x, y) => x }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(29, 33),
+      TypeError::MismatchedArms {
+        expected: i32_type.to_string(),
+        x: bool_type.to_string()
+      }
+    )])),
+    eval("match true { true => 1, _ => true }")
+  );
+}
+
+#[test]
+pub fn strings() {
+  assert_eq!(
+    Ok(Some(Value::Str("say \"hi\"\n\tfrom \\ 😀".into()))),
+    eval(r#"let s: str = "say \"hi\"\n"; s + "\tfrom \\ " + "\u{1F600}""#)
+  );
+  assert_eq!(
+    Ok(Some(Value::Bool(true))),
+    eval(r#"let a = "ab"; let b = a + "c"; b == "abc" && a != b && "" == """#)
+  );
+  // The length is in bytes.
+  assert_eq!(
+    Ok(Some(Value::I32(8))),
+    eval(r#"fn twice(s: str) -> str { s + s } len(twice("ä") + "\u{1F600}" + "")"#)
+  );
+}
+
+#[test]
+pub fn string_type_errors() {
+  use toylang::ast_common::BinaryOperator;
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  let i32_type = "i32";
+  let str_type = "str";
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 7),
+      TypeError::InvalidBinaryOpArgs {
+        op: BinaryOperator::Add,
+        lhs: str_type.to_string(),
+        rhs: i32_type.to_string()
+      }
+    )])),
+    eval(r#""a" + 1"#)
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 9),
+      TypeError::InvalidBinaryOpArgs {
+        op: BinaryOperator::Less,
+        lhs: str_type.to_string(),
+        rhs: str_type.to_string()
+      }
+    )])),
+    eval(r#""a" < "b""#)
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 15),
+      TypeError::NotAssignable {
+        target: str_type.to_string(),
+        x: i32_type.to_string()
+      }
+    )])),
+    eval("let s: str = 1;")
+  );
+}
+
+#[test]
+pub fn integer_types() {
+  assert_eq!(Ok(Some(Value::U8(255))), eval("let x: u8 = 200; x + 55"));
+  assert_eq!(
+    Ok(Some(Value::I64(-9000000000))),
+    eval("fn triple(x: i64) -> i64 { x * 3 } triple(-3000000000)")
+  );
+  // Literals take the type of where they're used, or their suffix.
+  assert_eq!(
+    Ok(Some(Value::U64(18446744073709551615))),
+    eval("let mut a = [0u64, 1]; a[1] = 18446744073709551614; 1 + a[1]")
+  );
+  assert_eq!(
+    Ok(Some(Value::Bool(true))),
+    eval("let x = -128i8; let y: u16 = 65535; x < 0 && y > 65534 && x == -128")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(2))),
+    eval("let x = 300i16; match x { 1 => 1, 300 => 2, _ => 3 }")
+  );
+}
+
+#[test]
+pub fn integer_overflow() {
+  use toylang::ast_common::{BinaryOperator, Operator, UnaryOperator};
+  use toylang::interpreter::{RuntimeError, RuntimeErrorCtx};
+  use toylang::span::Span;
+  use toylang::EvalError;
+
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(20, 35),
+      RuntimeError::IntegerOverflow {
+        op: Operator::Binary(BinaryOperator::Add)
+      }
+    ))),
+    eval("let a = 2147483647; a + (a - a + 1)")
+  );
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(14, 19),
+      RuntimeError::IntegerOverflow {
+        op: Operator::Binary(BinaryOperator::Sub)
+      }
+    ))),
+    eval("let x = 1u32; x - 2")
+  );
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(16, 18),
+      RuntimeError::IntegerOverflow {
+        op: Operator::Unary(UnaryOperator::Negate)
+      }
+    ))),
+    eval("let x = -128i8; -x")
+  );
+}
+
+#[test]
+pub fn integer_type_errors() {
+  use toylang::ast_common::BinaryOperator;
+  use toylang::rast::PrimitiveType;
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  let i32_type = "i32";
+  let i64_type = "i64";
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(12, 15),
+      TypeError::LiteralOutOfRange {
+        value: 256,
+        x: PrimitiveType::U8
+      }
+    )])),
+    eval("let x: u8 = 256;")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(8, 12),
+      TypeError::LiteralOutOfRange {
+        value: -1,
+        x: PrimitiveType::U8
+      }
+    )])),
+    eval("let x = -1u8;")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 10),
+      TypeError::LiteralOutOfRange {
+        value: 2147483648,
+        x: PrimitiveType::I32
+      }
+    )])),
+    eval("2147483648")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(30, 35),
+      TypeError::InvalidBinaryOpArgs {
+        op: BinaryOperator::Add,
+        lhs: i64_type.to_string(),
+        rhs: i32_type.to_string()
+      }
+    )])),
+    eval("let x = 1i64; let y: i32 = 2; x + y")
+  );
+}
+
+#[test]
+pub fn division_and_bitwise_operators() {
+  assert_eq!(Ok(Some(Value::I32(-3))), eval("-7 / 2"));
+  assert_eq!(Ok(Some(Value::I32(-1))), eval("-7 % 2"));
+  assert_eq!(Ok(Some(Value::I32(7))), eval("1 + 12 / 2 % 4 * 3"));
+  assert_eq!(
+    Ok(Some(Value::U8(0b1110_0110))),
+    eval("let x = 240u8; x & 238 | 6u8 ^ 0")
+  );
+  assert_eq!(Ok(Some(Value::U8(14))), eval("12u8 | 6u8 ^ 4u8 & 5"));
+  assert_eq!(Ok(Some(Value::U8(0b1100_0000))), eval("255u8 << 6"));
+  assert_eq!(Ok(Some(Value::I8(-32))), eval("-128i8 >> 2u64"));
+  assert_eq!(Ok(Some(Value::U16(65280))), eval("let x = 255u16; ~x"));
+  assert_eq!(Ok(Some(Value::I32(-6))), eval("!5"));
+  assert_eq!(
+    Ok(Some(Value::Bool(true))),
+    eval("true ^ false & true | false")
+  );
+  assert_eq!(
+    Ok(Some(Value::Bool(true))),
+    eval("1 << 4 == 16 && 1 + 1 << 1 == 4")
+  );
+}
+
+#[test]
+pub fn division_by_zero() {
+  use toylang::ast_common::{BinaryOperator, Operator};
+  use toylang::interpreter::{RuntimeError, RuntimeErrorCtx};
+  use toylang::span::Span;
+  use toylang::EvalError;
+
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(13, 18),
+      RuntimeError::DivisionByZero {
+        op: BinaryOperator::Div
+      }
+    ))),
+    eval("let x = 0u8; 1 / x")
+  );
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(0, 5),
+      RuntimeError::DivisionByZero {
+        op: BinaryOperator::Rem
+      }
+    ))),
+    eval("7 % 0")
+  );
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(16, 22),
+      RuntimeError::IntegerOverflow {
+        op: Operator::Binary(BinaryOperator::Div)
+      }
+    ))),
+    eval("let x = -128i8; x / -1")
+  );
+  assert_eq!(
+    Err(EvalError::RuntimeError(RuntimeErrorCtx(
+      Span::new(15, 21),
+      RuntimeError::IntegerOverflow {
+        op: Operator::Binary(BinaryOperator::Shl)
+      }
+    ))),
+    eval("let x = 32u32; 1 << x")
+  );
+}
+
+#[test]
+pub fn bitwise_type_errors() {
+  use toylang::ast_common::{BinaryOperator, UnaryOperator};
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 5),
+      TypeError::InvalidUnaryOpArg {
+        op: UnaryOperator::BitNot,
+        x: "bool".to_string()
+      }
+    )])),
+    eval("~true")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 12),
+      TypeError::InvalidBinaryOpArgs {
+        op: BinaryOperator::Shl,
+        lhs: "i32".to_string(),
+        rhs: "bool".to_string()
+      }
+    )])),
+    eval("1 << (1 < 2)")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 9),
+      TypeError::InvalidBinaryOpArgs {
+        op: BinaryOperator::BitAnd,
+        lhs: "u8".to_string(),
+        rhs: "i8".to_string()
+      }
+    )])),
+    eval("1u8 & 1i8")
+  );
+}
+
+#[test]
+pub fn floats() {
+  assert_eq!(
+    Ok(Some(Value::F64(3.75))),
+    eval("let x = 1.5; x * 2.0 + 0.75")
+  );
+  assert_eq!(Ok(Some(Value::F64(-0.5))), eval("-1e-3 * 5e2"));
+  assert_eq!(Ok(Some(Value::F64(1.5))), eval("7.5 % 2f64"));
+  assert_eq!(
+    Ok(Some(Value::Bool(true))),
+    eval("let x: f64 = 0.1 + 0.2; x > 0.3 && x != 0.3 && 1.0 / 0.0 > 1e308")
+  );
+  assert_eq!(
+    Ok(Some(Value::F64(2.0))),
+    eval("fn mean(a: [f64]) -> f64 { (a[0] + a[1]) / len(a) as f64 } mean([1.5, 2.5])")
+  );
+}
+
+#[test]
+pub fn casts() {
+  assert_eq!(Ok(Some(Value::U8(44))), eval("300 as u8"));
+  assert_eq!(Ok(Some(Value::U8(255))), eval("-1 as u8"));
+  assert_eq!(Ok(Some(Value::I8(-128))), eval("128u64 as i8"));
+  assert_eq!(Ok(Some(Value::I32(-2))), eval("-2.9 as i32"));
+  assert_eq!(Ok(Some(Value::U8(255))), eval("1e10 as u8"));
+  assert_eq!(
+    Ok(Some(Value::I32(0))),
+    eval("let nan = 0.0 / 0.0; nan as i32")
+  );
+  assert_eq!(Ok(Some(Value::F64(-5.0))), eval("let x = -5i64; x as f64"));
+  assert_eq!(Ok(Some(Value::I16(1))), eval("true as i16"));
+  assert_eq!(
+    Ok(Some(Value::F64(0.5))),
+    eval("let x = 1; let f = || x as f64 / 2.0; f()")
+  );
+}
+
+#[test]
+pub fn float_type_errors() {
+  use toylang::ast_common::BinaryOperator;
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  let f64_type = "f64";
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 7),
+      TypeError::InvalidBinaryOpArgs {
+        op: BinaryOperator::Add,
+        lhs: f64_type.to_string(),
+        rhs: "i32".to_string()
+      }
+    )])),
+    eval("1.5 + 1")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 9),
+      TypeError::InvalidBinaryOpArgs {
+        op: BinaryOperator::BitAnd,
+        lhs: f64_type.to_string(),
+        rhs: f64_type.to_string()
+      }
+    )])),
+    eval("1.0 & 2.0")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 11),
+      TypeError::InvalidCast {
+        x: "bool".to_string(),
+        target: f64_type.to_string()
+      }
+    )])),
+    eval("true as f64")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(0, 10),
+      TypeError::InvalidCast {
+        x: "str".to_string(),
+        target: "i32".to_string()
+      }
+    )])),
+    eval("\"1\" as i32")
+  );
+}
+
+#[test]
+pub fn type_inference() {
+  assert_eq!(
+    Ok(Some(Value::U8(2))),
+    eval("let mut a = []; a = [1u8, 2]; a[1]")
+  );
+  assert_eq!(Ok(Some(Value::U8(255))), eval("let x: u8 = 200 + 55; x"));
+  assert_eq!(
+    Ok(Some(Value::I64(42))),
+    eval("let f = |x| x * 2; f(21i64)")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(4))),
+    eval("let f = |g| g(1) + 1; f(|x| x * 3)")
+  );
+  // Functions are generic over the parameter types that their bodies don't
+  // decide.
+  assert_eq!(
+    Ok(Some(Value::I32(45))),
+    eval("fn apply(x, f) -> i32 { f(x) } apply(21, |x| x * 2) + apply(\"abc\", |s| len(s))")
+  );
+  assert_eq!(
+    Ok(Some(Value::Bool(true))),
+    eval(
+      "fn even(n) -> bool { if n == 0 { true } else { odd(n - 1) } }
+       fn odd(n) -> bool { if n == 0 { false } else { even(n - 1) } }
+       even(10u8)"
+    )
+  );
+}
+
+#[test]
+pub fn type_inference_errors() {
+  use toylang::rast::PrimitiveType;
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  let u8_type = "u8";
+
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(26, 41),
+      TypeError::NotAssignable {
+        target: "i64".to_string(),
+        x: u8_type.to_string()
+      }
+    )])),
+    eval("let x = 1; let y: u8 = x; let z: i64 = x;")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(8, 11),
+      TypeError::LiteralOutOfRange {
+        value: 300,
+        x: PrimitiveType::U8
+      }
+    )])),
+    eval("let x = 300; let y: u8 = x;")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(23, 27),
+      TypeError::NotAssignable {
+        target: "i32".to_string(),
+        x: "bool".to_string()
+      }
+    )])),
+    eval("let f = |x| x; f(1); f(true)")
+  );
+  // Operators and fields need the types of their operands.
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(21, 27),
+      TypeError::CannotInfer
+    )])),
+    eval("fn f(a, b) -> bool { a == b }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(12, 13),
+      TypeError::CannotInfer
+    )])),
+    eval("let f = |x| x.y;")
+  );
+  match eval("let f = |x| x;") {
+    Err(EvalError::TypeError(errors)) => match errors.as_slice() {
+      [TypeErrorCtx(span, TypeError::UntypedLocal { .. })] => assert_eq!(Span::new(9, 10), *span),
+      errors => panic!("Unexpected errors: {:?}", errors),
+    },
+    result => panic!("Unexpected result: {:?}", result),
+  }
+  assert!(eval("let f = |x| x(x);").is_err());
+}
+
+#[test]
+pub fn generics() {
+  assert_eq!(
+    Ok(Some(Value::I32(3))),
+    eval("fn id<T>(x: T) -> T { x } if id(true) { id(1) + id(2) } else { 0 }")
+  );
+  assert_eq!(
+    Ok(Some(Value::U8(3))),
+    eval("fn id<T>(x: T) -> T { x } let a: u8 = id(3); a")
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(1))),
+    eval(
+      "struct Pair<A, B> { a: A, b: B }
+       fn swap<A, B>(p: Pair<A, B>) -> Pair<B, A> { Pair { a: p.b, b: p.a } }
+       let p = swap(Pair { a: true, b: 1 });
+       if p.b { p.a } else { 0 }"
+    )
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(1))),
+    eval(
+      "enum Option<T> { Some(T), None }
+       let x: Option<Option<i32>> = Option::Some(Option::None);
+       match x {
+         Option::Some(Option::Some(v)) => v,
+         Option::Some(Option::None) => 1,
+         Option::None => 0,
+       }"
+    )
+  );
+  assert_eq!(
+    Ok(Some(Value::I32(6))),
+    eval(
+      "enum List<T> { Cons(T, [List<T>]), Nil }
+       fn sum(list: List<i32>) -> i32 {
+         match list { List::Cons(x, rest) => x + sum(rest[0]), List::Nil => 0 }
+       }
+       sum(List::Cons(1, [List::Cons(2, [List::Cons(3, [List::Nil])])]))"
+    )
+  );
+  // Comparisons after a cast aren't type arguments.
+  assert_eq!(Ok(Some(Value::Bool(true))), eval("3 as u8 < 4 as u8"));
+}
+
+#[test]
+pub fn generic_type_errors() {
+  use toylang::semantic::{SemanticError, SemanticErrorCtx};
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  assert_eq!(
+    Err(EvalError::SemanticError(SemanticErrorCtx(
+      Span::new(40, 49),
+      SemanticError::WrongTypeArgumentCount {
+        name: "Pair".to_string(),
+        expected: 2,
+        actual: 1
+      }
+    ))),
+    eval("struct Pair<A, B> { a: A, b: B } let p: Pair<i32> = Pair { a: 1, b: 2 };")
+  );
+  assert_eq!(
+    Err(EvalError::SemanticError(SemanticErrorCtx(
+      Span::new(27, 33),
+      SemanticError::WrongTypeArgumentCount {
+        name: "S".to_string(),
+        expected: 0,
+        actual: 1
+      }
+    ))),
+    eval("struct S { a: i32 } let s: S<i32> = S { a: 1 };")
+  );
+  assert_eq!(
+    Err(EvalError::SemanticError(SemanticErrorCtx(
+      Span::new(8, 9),
+      SemanticError::DuplicateTypeParameter {
+        name: "T".to_string(),
+        previous: Span::new(5, 6)
+      }
+    ))),
+    eval("fn f<T, T>(x: T) -> T { x }")
+  );
+  // Inside of its declaration a type parameter is a type of its own.
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(21, 22),
+      TypeError::MismatchedReturn {
+        expected: "T".to_string(),
+        x: "i32".to_string()
+      }
+    )])),
+    eval("fn f<T>(x: T) -> T { 1 }")
+  );
+  match eval("fn f<T>(x: T, y: T) -> T { x + y }") {
+    Err(EvalError::TypeError(errors)) => match errors.as_slice() {
+      [TypeErrorCtx(span, TypeError::InvalidBinaryOpArgs { .. })] => {
+        assert_eq!(Span::new(27, 32), *span)
+      }
+      errors => panic!("Unexpected errors: {:?}", errors),
+    },
+    result => panic!("Unexpected result: {:?}", result),
+  }
+}
+
+#[test]
+pub fn all_type_errors() {
+  use toylang::span::Span;
+  use toylang::type_checker::{TypeError, TypeErrorCtx};
+  use toylang::EvalError;
+
+  let i32_type = "i32";
+  let bool_type = "bool";
+  assert_eq!(
+    Err(EvalError::TypeError(vec![
+      TypeErrorCtx(
+        Span::new(16, 20),
+        TypeError::MismatchedReturn {
+          expected: i32_type.to_string(),
+          x: bool_type.to_string()
+        }
+      ),
+      TypeErrorCtx(
+        Span::new(23, 39),
+        TypeError::NotAssignable {
+          target: bool_type.to_string(),
+          x: i32_type.to_string()
+        }
+      ),
+      TypeErrorCtx(
+        Span::new(40, 44),
+        TypeError::WrongArgumentCount {
+          expected: 0,
+          actual: 1
+        }
+      ),
+    ])),
+    eval("fn f() -> i32 { true } let x: bool = 1; f(2)")
+  );
+  // The value of an expression with an error can be used as anything, so the
+  // error isn't reported again where it's used.
+  assert_eq!(
+    Err(EvalError::TypeError(vec![TypeErrorCtx(
+      Span::new(8, 16),
+      TypeError::InvalidBinaryOpArgs {
+        op: toylang::ast_common::BinaryOperator::Add,
+        lhs: bool_type.to_string(),
+        rhs: i32_type.to_string()
+      }
+    )])),
+    eval("let a = true + 1; let b = a.x * 2; if b { a[0] } else { b(1) }")
+  );
+  assert_eq!(
+    Err(EvalError::TypeError(vec![
+      TypeErrorCtx(
+        Span::new(12, 16),
+        TypeError::NotAssignable {
+          target: i32_type.to_string(),
+          x: bool_type.to_string()
+        }
+      ),
+      TypeErrorCtx(
+        Span::new(24, 28),
+        TypeError::InvalidIndex {
+          x: bool_type.to_string()
+        }
+      ),
+    ])),
+    eval("let a = [1, true, 2]; a[true]")
+  );
+  // Types left unknown aren't reported along with other errors, since those
+  // may be why nothing decided them.
+  match eval("let a = []; let b: bool = 1;") {
+    Err(EvalError::TypeError(errors)) => match errors.as_slice() {
+      [TypeErrorCtx(_, TypeError::NotAssignable { .. })] => {}
+      errors => panic!("Unexpected errors: {:?}", errors),
+    },
+    result => panic!("Unexpected result: {:?}", result),
+  }
+}
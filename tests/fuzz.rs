@@ -0,0 +1,245 @@
+use std::panic;
+
+use toylang::{eval_with, Backend};
+
+// Feeds random token sequences through eval. Whatever the input, eval must
+// return an error instead of panicking, and both backends must agree.
+
+// xorshift64*, so that the harness has no dependencies and every failure can
+// be reproduced from the seed.
+struct Rng(u64);
+
+impl Rng {
+  fn next_u64(&mut self) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+  }
+
+  fn below(&mut self, n: usize) -> usize {
+    (self.next_u64() % n as u64) as usize
+  }
+
+  fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+    items[self.below(items.len())]
+  }
+}
+
+const TOKENS: &[&str] = &[
+  "let",
+  "mut",
+  "=",
+  "(",
+  ")",
+  "{",
+  "}",
+  ":",
+  ";",
+  "+",
+  "-",
+  "*",
+  "x",
+  "y",
+  "i32",
+  "bool",
+  "0",
+  "1",
+  "7",
+  "2147483647",
+  "2147483648",
+  "99999999999999999999999999999999999999999",
+  "true",
+  "false",
+  "==",
+  "!=",
+  "<",
+  "<=",
+  ">",
+  ">=",
+  "&&",
+  "||",
+  "!",
+  "if",
+  "else",
+  "break",
+  "fn",
+  "return",
+  ",",
+  "->",
+  "|",
+  "continue",
+  "@",
+  "[",
+  "]",
+  "len",
+  "struct",
+  ".",
+  "_",
+  "enum",
+  "match",
+  "=>",
+  "::",
+  "\"",
+  "\"a\\n\"",
+  "\"\\q\"",
+  "\"\\u{",
+  "str",
+  "255u8",
+  "128i8",
+  "18446744073709551616u64",
+  "u16",
+  "3x",
+  "/",
+  "%",
+  "&",
+  "^",
+  "~",
+  "<<",
+  ">>",
+  "1.5",
+  "2e-3",
+  "1e999",
+  "f64",
+  "as",
+  "T",
+  "Option",
+];
+
+// Well-formed statements, spliced in so that the generated programs also get
+// past the parser every now and then. `while` and `loop` only appear here,
+// since random tokens would mostly make loops that never end.
+const STATEMENTS: &[&str] = &[
+  "let x = 1;",
+  "let mut y: i32 = x * 2;",
+  "x = y - 1;",
+  "{ let x = 2; y = x; }",
+  "let x: bool = 1;",
+  "let z = 2147483647 + 1;",
+  "let z = -2147483648 * -1;",
+  "let b = x < y && !(y == 2) || false;",
+  "if x < 2 { y = 1; } else if y == 3 { x } else { 5 }",
+  "let mut i = 0; while i < 10 { i = i + 1; if i == 5 { continue; } }",
+  "let z = loop { break 3; };",
+  "fn f(a: i32, b: bool) -> i32 { if b { return a; } f(a - 1, a < 1) }",
+  "let w = f(2, false);",
+  "let g = |n: i32| -> i32 { n + y }; let v = g(x);",
+  "let mut a: [i32] = [x, 2, 3]; a[y] = len(a);",
+  "struct S { a: i32, b: [S] }",
+  "let mut s = S { b: [], a: 1 }; s.a = s.a + 1;",
+  "enum E { A(i32, bool), B }",
+  "let e = match x { 0 => E::B, n => E::A(n, true) };",
+  "match e { E::A(n, true) => n, E::A(_, false) => 1, E::B => 2 }",
+  "let q: u8 = 200; let r = q + 55; let s = -128i8 * -1;",
+  "let d = x / y % 3 << y >> 1 & ~x | 2 ^ x;",
+  "let f: f64 = 1.5 * x as f64 / 0.0; let i = f as u8 + 1;",
+  "let h = |p| p + x; let mut u = []; u = [h(1u8)];",
+  "let mut t: str = \"\\u{1F600}\\t\"; t = t + \"\\\"\"; let n = len(t);",
+  "enum Option<T> { Some(T), None }",
+  "fn id<T>(v: T) -> T { v } let o: Option<Option<i32>> = Option::Some(id(Option::None));",
+];
+
+fn random_program(rng: &mut Rng) -> String {
+  let length = rng.below(24);
+  let mut parts = Vec::with_capacity(length);
+  for _ in 0..length {
+    if rng.below(4) == 0 {
+      parts.push(rng.pick(STATEMENTS));
+    } else {
+      parts.push(rng.pick(TOKENS));
+    }
+  }
+  parts.join(" ")
+}
+
+fn assert_no_panic(src: &str) {
+  let results: Vec<String> = [Backend::Interpreter, Backend::Vm]
+    .iter()
+    .map(
+      |&backend| match panic::catch_unwind(|| eval_with(src, backend)) {
+        Ok(result) => format!("{:?}", result),
+        Err(_) => panic!("{:?} panicked on input {:?}", backend, src),
+      },
+    )
+    .collect();
+  assert_eq!(
+    results[0], results[1],
+    "backends disagree on input {:?}",
+    src
+  );
+}
+
+#[test]
+pub fn random_token_sequences() {
+  let mut rng = Rng(0x5eed_1234_abcd_9876);
+  for _ in 0..20_000 {
+    assert_no_panic(&random_program(&mut rng));
+  }
+}
+
+#[test]
+pub fn known_crashers() {
+  for src in &[
+    "5;",
+    "}",
+    ")",
+    "[",
+    "let x = x;",
+    "let x: i32 = x;",
+    "let x = 99999 * 99999;",
+    "let x = -2147483648; let y = -x;",
+    "{ let x = 1;",
+    "break;",
+    "{ continue } break 1",
+    "match 1 { }",
+    "enum E { } match 1 { E::A => 1 }",
+    "\"\\",
+    "\"\\u{ffffffffffffffffff}\"",
+    "1 / 0",
+    "-2147483648 % -1",
+    "1u64 << 64",
+    "-1 >> -1",
+    "1.0 as",
+    "match 1.5 { _ => 1 }",
+    "1e999999999999999999999",
+    "let a = []; a",
+    "let f = |x| x(x);",
+    "fn f(a) -> i32 { a.b } f(1)",
+    "fn f<T>(x: T) -> T { x.a }",
+    "struct S<T> { a: T } let s: S<S> = S { a: 1 };",
+    "enum E<T> { A(T) } match E::A(1) { E::A(E::A(x)) => x }",
+    "let x: i32<>> = 1;",
+    "fn f(n: i32) -> i32 { f(n + 1) } f(0)",
+  ] {
+    assert_no_panic(src);
+  }
+
+  // Nesting that overflowed the stack of the passes after parsing.
+  for depth in &[1000, 10_000] {
+    let open_close =
+      |open: &str, close: &str| format!("{}1{}", open.repeat(*depth), close.repeat(*depth));
+    for src in &[
+      open_close("(", ")"),
+      open_close("{", "}"),
+      open_close("[", "]"),
+      open_close("-", ""),
+      open_close("if true { 1 } else ", ""),
+      open_close("|| ", ""),
+      open_close("f(", ")"),
+      open_close("", ".a"),
+      open_close("", " + 1"),
+      format!(
+        "let x: {}i32{} = 1;",
+        "[".repeat(*depth),
+        "]".repeat(*depth)
+      ),
+      format!(
+        "match 1 {{ {}_{} => 1 }}",
+        "E::A(".repeat(*depth),
+        ")".repeat(*depth)
+      ),
+    ] {
+      assert_no_panic(src);
+    }
+  }
+}
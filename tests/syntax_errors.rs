@@ -1,4 +1,7 @@
+use toylang::interpreter::Value;
 use toylang::parser::{ParseError, ParseErrorCtx};
+use toylang::span::Span;
+use toylang::token_stream::LexerError;
 use toylang::tokens::TokenKind;
 use toylang::{eval, EvalError};
 
@@ -6,13 +9,197 @@ use toylang::{eval, EvalError};
 pub fn missing_semicolon() {
   let result = eval("let x = 9");
   assert_eq!(
-    Err(EvalError::ParseError(ParseErrorCtx(
-      9,
+    Err(EvalError::ParseError(vec![ParseErrorCtx(
+      Span::point(9),
       ParseError::UnexpectedToken {
         expected: vec![TokenKind::Semicolon],
         was: TokenKind::EOF
       }
-    ))),
+    )])),
+    result
+  );
+}
+
+#[test]
+pub fn missing_semicolon_report() {
+  use toylang::diagnostics::Report;
+  use toylang::source_map::SourceMap;
+
+  let src = "let x = 9";
+  let mut source_map = SourceMap::new();
+  source_map.add_file("main.toy", src);
+  let error = eval(src).unwrap_err();
+
+  assert_eq!("expected `;`, found end of file", error.to_string());
+  assert_eq!(
+    "error: expected `;`, found end of file
+ --> main.toy:1:10
+  |
+1 | let x = 9
+  |          ^ expected `;`
+",
+    Report::new(&source_map, &error).to_string()
+  );
+}
+
+#[test]
+pub fn multiple_errors() {
+  let result = eval("let x = ; let y = 2; let = 3; { let z 4; } let w = 5");
+  assert_eq!(
+    Err(EvalError::ParseError(vec![
+      ParseErrorCtx(
+        Span::new(8, 9),
+        ParseError::UnexpectedToken {
+          expected: vec![
+            TokenKind::Minus,
+            TokenKind::Bang,
+            TokenKind::Tilde,
+            TokenKind::Integer,
+            TokenKind::Float,
+            TokenKind::Str,
+            TokenKind::True,
+            TokenKind::False,
+            TokenKind::Identifier,
+            TokenKind::LParen,
+            TokenKind::LBrace,
+            TokenKind::LBracket,
+            TokenKind::If,
+            TokenKind::Loop,
+            TokenKind::Match,
+            TokenKind::Pipe,
+            TokenKind::DoublePipe,
+          ],
+          was: TokenKind::Semicolon
+        }
+      ),
+      ParseErrorCtx(
+        Span::new(25, 26),
+        ParseError::UnexpectedToken {
+          expected: vec![TokenKind::Identifier],
+          was: TokenKind::Equals
+        }
+      ),
+      ParseErrorCtx(
+        Span::new(38, 39),
+        ParseError::UnexpectedToken {
+          expected: vec![TokenKind::Equals],
+          was: TokenKind::Integer
+        }
+      ),
+      ParseErrorCtx(
+        Span::point(52),
+        ParseError::UnexpectedToken {
+          expected: vec![TokenKind::Semicolon],
+          was: TokenKind::EOF
+        }
+      ),
+    ])),
+    result
+  );
+}
+
+#[test]
+pub fn nested_too_deeply() {
+  use toylang::parser::MAX_NESTING;
+
+  let parens = |n| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+  assert_eq!(
+    Err(EvalError::ParseError(vec![ParseErrorCtx(
+      Span::new(MAX_NESTING, MAX_NESTING + 1),
+      ParseError::NestedTooDeeply
+    )])),
+    eval(&parens(1000))
+  );
+  assert_eq!(Ok(Some(Value::I32(1))), eval(&parens(MAX_NESTING - 1)));
+
+  // The rest of the program is still parsed.
+  let blocks = format!("{}{} let x = 1 @ 2;", "{".repeat(1000), "}".repeat(1000));
+  assert_eq!(
+    Err(EvalError::ParseError(vec![
+      ParseErrorCtx(
+        Span::new(MAX_NESTING, MAX_NESTING + 1),
+        ParseError::NestedTooDeeply
+      ),
+      ParseErrorCtx(
+        Span::new(2011, 2012),
+        ParseError::LexerError(LexerError::UnknownToken("@".to_string()))
+      ),
+    ])),
+    eval(&blocks)
+  );
+
+  // Operators nest their left hand side, so long chains of them are limited
+  // too.
+  let sum = |n| format!("0{}", " + 1".repeat(n));
+  assert_eq!(Ok(Some(Value::I32(200))), eval(&sum(200)));
+  assert!(matches!(
+    eval(&sum(1000)),
+    Err(EvalError::ParseError(errors)) if errors[0].1 == ParseError::NestedTooDeeply
+  ));
+}
+
+#[test]
+pub fn unknown_tokens() {
+  let result = eval("let x = 1 @ 2; let y = $;");
+  assert_eq!(
+    Err(EvalError::ParseError(vec![
+      ParseErrorCtx(
+        Span::new(10, 11),
+        ParseError::LexerError(LexerError::UnknownToken("@".to_string()))
+      ),
+      ParseErrorCtx(
+        Span::new(23, 24),
+        ParseError::LexerError(LexerError::UnknownToken("$".to_string()))
+      ),
+    ])),
+    result
+  );
+}
+
+#[test]
+pub fn string_errors() {
+  let result = eval(r#"let x = "a\qb"; let y = "\u{110000}"; let z = "c"#);
+  assert_eq!(
+    Err(EvalError::ParseError(vec![
+      ParseErrorCtx(
+        Span::new(10, 12),
+        ParseError::LexerError(LexerError::InvalidEscape("\\q".to_string()))
+      ),
+      ParseErrorCtx(
+        Span::new(25, 35),
+        ParseError::LexerError(LexerError::InvalidEscape("\\u{110000}".to_string()))
+      ),
+      ParseErrorCtx(
+        Span::new(46, 48),
+        ParseError::LexerError(LexerError::UnterminatedString)
+      ),
+    ])),
     result
   );
 }
+
+// An error in a statement is reported rather than the one it causes in the
+// result of the block.
+#[test]
+pub fn first_semantic_error() {
+  use toylang::semantic::{SemanticError, SemanticErrorCtx};
+
+  assert_eq!(
+    Err(EvalError::SemanticError(SemanticErrorCtx(
+      Span::new(8, 23),
+      SemanticError::UnknownLocal {
+        name: "undefined_thing".to_string()
+      }
+    ))),
+    eval("let a = undefined_thing; a")
+  );
+  assert_eq!(
+    Err(EvalError::SemanticError(SemanticErrorCtx(
+      Span::new(7, 10),
+      SemanticError::UnknownType {
+        name: "f32".to_string()
+      }
+    ))),
+    eval("let x: f32 = 0.5; x")
+  );
+}
@@ -0,0 +1,37 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn toylangi(args: &[&str], input: &str) -> Output {
+  let mut child = Command::new(env!("CARGO_BIN_EXE_toylangi"))
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+  child
+    .stdin
+    .take()
+    .unwrap()
+    .write_all(input.as_bytes())
+    .unwrap();
+  child.wait_with_output().unwrap()
+}
+
+#[test]
+pub fn emit_exit_status() {
+  for args in &[["--emit=c"], ["--emit=bytecode"]] {
+    assert!(toylangi(args, "1 + 2\n").status.success());
+    assert_eq!(Some(1), toylangi(args, "1 +\n").status.code());
+    // A program that fails fails the whole run, after the others are emitted.
+    let output = toylangi(args, "let x: bool = 1;\n1\n");
+    assert_eq!(Some(1), output.status.code());
+    assert!(!output.stdout.is_empty());
+  }
+  assert_eq!(
+    Some(1),
+    toylangi(&["--emit=c"], "let f = || 1; 1\n").status.code()
+  );
+  // Programs that are run report their errors and go on.
+  assert!(toylangi(&[], "1 +\n1\n").status.success());
+}